@@ -2,10 +2,15 @@
 use std::{path::PathBuf, sync::Arc};
 
 use async_trait::async_trait;
+use tokio::io::AsyncWrite;
 use tokio::sync::Semaphore;
 
-use crate::profiles::{Passphrase, PruneOptions, Repository, RepositoryOptions};
-use crate::types::{BorgResult, CommandResponseSender, RepositoryArchives};
+use crate::cancel::CancelToken;
+use crate::profiles::{ArchiveFilter, Passphrase, PruneOptions, Repository, RepositoryOptions};
+use crate::types::{
+    ArchiveDiffEntry, ArchiveEntry, BorgResult, CommandResponseSender, PruneDecision,
+    RepositoryArchives,
+};
 
 #[async_trait]
 pub(crate) trait BackupProvider: Send {
@@ -19,42 +24,136 @@ pub(crate) trait BackupProvider: Send {
         repo: Repository,
         progress_channel: CommandResponseSender,
         completion_semaphore: Arc<Semaphore>,
+        cancel: CancelToken,
     ) -> BorgResult<()>;
     async fn list_archives(&self, repo: &Repository) -> BorgResult<RepositoryArchives>;
+    /// Fill in archive sizes and file counts `list_archives` leaves out because they're
+    /// expensive to get. Only worth calling where they're shown.
+    async fn add_archive_stats(
+        &self,
+        repo: &Repository,
+        archives: &mut RepositoryArchives,
+    ) -> BorgResult<()>;
+    /// List the immediate children of `directory` (relative to the archive root,
+    /// empty for the root) in the archive named by `archive_spec` ("repo::archive").
+    async fn list_archive_directory(
+        &self,
+        repo: &Repository,
+        archive_spec: String,
+        directory: String,
+    ) -> BorgResult<Vec<ArchiveEntry>>;
+    /// Write the contents of the file at `path` (relative to the archive root) in the
+    /// archive named by `archive_spec` ("repo::archive") to `writer`.
+    async fn read_archive_file(
+        &self,
+        repo: &Repository,
+        archive_spec: String,
+        path: String,
+        writer: &mut (dyn AsyncWrite + Send + Unpin),
+    ) -> BorgResult<()>;
+    /// Write the archive named by `archive_spec` ("repo::archive") to `writer` as an
+    /// uncompressed tar stream, limited to `paths` (relative to the archive root) if any are given.
+    async fn export_tar(
+        &self,
+        repo: &Repository,
+        archive_spec: String,
+        paths: &[PathBuf],
+        writer: &mut (dyn AsyncWrite + Send + Unpin),
+        cancel: CancelToken,
+    ) -> BorgResult<()>;
+    /// Create the archive `archive_name` from the tarball at `tarball` ("-" for stdin).
+    async fn import_tar(
+        &self,
+        repo: &Repository,
+        archive_name: String,
+        tarball: PathBuf,
+        progress_channel: CommandResponseSender,
+        cancel: CancelToken,
+    ) -> BorgResult<()>;
+    /// List what changed going from the first to the second archive. Both specs are
+    /// "repo::archive" and belong to `repo`.
+    async fn diff(
+        &self,
+        repo: &Repository,
+        first_archive_spec: String,
+        second_archive_spec: String,
+    ) -> BorgResult<Vec<ArchiveDiffEntry>>;
     async fn init_repo(
         &self,
         repo_loc: String,
         passphrase: Option<Passphrase>,
         config: RepositoryOptions,
     ) -> BorgResult<()>;
+    /// Mount the repository or archive and record it in the mount registry.
     async fn mount(
         &self,
         repo: &Repository,
         given_repository_path: String,
         mountpoint: PathBuf,
     ) -> BorgResult<()>;
-    // TODO: Figure out unmounting
-    #[allow(unused)]
     async fn unmount(&self, mountpoint: PathBuf) -> BorgResult<()>;
+    /// Apply `prune_options` to the archives matching `archive_filter`.
     async fn prune(
         &self,
         repo: &Repository,
         prune_options: PruneOptions,
+        archive_filter: &ArchiveFilter,
+        progress_channel: CommandResponseSender,
+        cancel: CancelToken,
+    ) -> BorgResult<()>;
+    /// Work out which archives `prune` would keep or remove without deleting anything.
+    async fn prune_dry_run(
+        &self,
+        repo: &Repository,
+        prune_options: PruneOptions,
+        archive_filter: &ArchiveFilter,
+    ) -> BorgResult<Vec<PruneDecision>>;
+    /// Delete the archive named by `archive_spec` ("repo::archive"), freeing its space if
+    /// the provider can do so without a separate compaction.
+    async fn delete_archive(
+        &self,
+        repo: &Repository,
+        archive_spec: String,
+        progress_channel: CommandResponseSender,
+        cancel: CancelToken,
+    ) -> BorgResult<()>;
+    /// Rename the archive named by `archive_spec` ("repo::archive") to `new_name`.
+    async fn rename_archive(
+        &self,
+        repo: &Repository,
+        archive_spec: String,
+        new_name: String,
         progress_channel: CommandResponseSender,
     ) -> BorgResult<()>;
     async fn compact(
         &self,
         repo: &Repository,
         progress_channel: CommandResponseSender,
+        cancel: CancelToken,
     ) -> BorgResult<()>;
     async fn check(
         &self,
         repo: &Repository,
         progress_channel: CommandResponseSender,
+        cancel: CancelToken,
     ) -> BorgResult<bool>;
+    /// Repair the repository. With `dry_run`, only report what would be repaired.
     async fn repair(
         &self,
         repo: &Repository,
+        dry_run: bool,
         progress_channel: CommandResponseSender,
+        cancel: CancelToken,
     ) -> BorgResult<bool>;
+    /// Restore `include_paths` (or everything, if empty) from the archive named by
+    /// `archive_spec` ("repo::archive") into `target_directory`.
+    async fn restore(
+        &self,
+        repo: &Repository,
+        archive_spec: String,
+        include_paths: &[PathBuf],
+        target_directory: PathBuf,
+        strip_components: Option<u32>,
+        progress_channel: CommandResponseSender,
+    ) -> BorgResult<()>;
 }
@@ -1,61 +1,185 @@
-use std::{path::PathBuf, process::Stdio};
+use std::{
+    collections::HashMap,
+    path::PathBuf,
+    process::Stdio,
+    sync::{LazyLock, Mutex},
+};
 
 use anyhow::anyhow;
 use async_trait::async_trait;
 use borgbackup::{
     asynchronous as borg_async,
-    common::{
-        CommonOptions, EncryptionMode, InitOptions, MountOptions, MountSource,
-        PruneOptions as BorgLibPruneOptions,
-    },
-    output::list::ListRepository as BorgLibListRepository,
+    common::{CommonOptions, EncryptionMode, InitOptions, MountOptions, MountSource},
+    output::logging::{LevelName, LoggingMessage},
 };
+use serde::Deserialize;
+use tokio::io::AsyncWrite;
 use tracing::info;
 
 use crate::{
     borgtui::CommandResponse,
-    profiles::{Passphrase, Repository, RepositoryOptions},
+    cancel::{
+        copy_stdout, is_cancelled, keep_ctrl_c_away, output_or_cancel, wait_or_cancel, CancelToken,
+    },
+    mounts::{self, MountRecord},
+    profiles::{
+        ArchiveFilter, BorgV1Options, Passphrase, PruneOptions, Repository, RepositoryOptions,
+    },
+    tarball::{self, TarCompression},
     types::{
-        send_check_complete, send_check_progress, send_error, send_info, take_repo_lock, Archive,
-        BackupCreateProgress, BackupCreationProgress, BorgResult, CommandResponseSender,
-        RepositoryArchives,
+        is_direct_child, send_check_complete, send_check_progress, send_create_cancelled,
+        send_error, send_info, take_repo_lock, Archive, ArchiveDiffChange, ArchiveDiffEntry,
+        ArchiveEntry, BackupCreateProgress, BackupCreationProgress, BorgResult,
+        CommandResponseSender, PruneDecision, RepositoryArchives,
     },
 };
 
-impl From<BorgLibListRepository> for RepositoryArchives {
-    fn from(value: BorgLibListRepository) -> Self {
-        RepositoryArchives {
-            path: value.repository.location,
-            archives: value
-                .archives
-                .into_iter()
-                .map(|archive| Archive {
-                    name: archive.name,
-                    creation_date: archive.start,
-                })
-                .collect(),
-        }
+const LOGGING_THROTTLE_TIME: std::time::Duration = std::time::Duration::from_millis(40);
+
+const BORG_TIMESTAMP_FORMAT: &str = "%Y-%m-%dT%H:%M:%S%.f";
+
+#[derive(Deserialize)]
+struct BorgListArchives {
+    archives: Vec<BorgListArchive>,
+}
+
+#[derive(Deserialize)]
+struct BorgListArchive {
+    id: String,
+    name: String,
+    start: String,
+    end: Option<String>,
+    hostname: Option<String>,
+    username: Option<String>,
+}
+
+impl TryFrom<BorgListArchive> for Archive {
+    type Error = anyhow::Error;
+
+    fn try_from(value: BorgListArchive) -> Result<Self, Self::Error> {
+        let creation_date =
+            chrono::NaiveDateTime::parse_from_str(&value.start, BORG_TIMESTAMP_FORMAT)?;
+        let duration = value
+            .end
+            .and_then(|end| chrono::NaiveDateTime::parse_from_str(&end, BORG_TIMESTAMP_FORMAT).ok())
+            .and_then(|end| (end - creation_date).to_std().ok());
+        // Sizes and file counts are only available from `borg info`, see `add_archive_stats`
+        Ok(Archive {
+            name: value.name,
+            creation_date,
+            id: Some(value.id),
+            hostname: value.hostname,
+            username: value.username,
+            duration,
+            ..Default::default()
+        })
     }
 }
 
-impl From<borg_async::CreateProgress> for BackupCreationProgress {
-    fn from(value: borg_async::CreateProgress) -> Self {
-        match value {
-            borg_async::CreateProgress::Progress {
-                original_size,
-                compressed_size,
-                deduplicated_size,
-                nfiles,
-                path,
-            } => BackupCreationProgress::InProgress {
-                original_size,
-                compressed_size,
-                deduplicated_size,
-                num_files: nfiles,
-                current_path: path,
-            },
-            borg_async::CreateProgress::Finished => BackupCreationProgress::Finished,
+#[derive(Deserialize)]
+struct BorgInfoArchives {
+    archives: Vec<BorgInfoArchive>,
+}
+
+#[derive(Deserialize)]
+struct BorgInfoArchive {
+    id: String,
+    stats: BorgArchiveStats,
+}
+
+#[derive(Clone, Copy, Deserialize)]
+struct BorgArchiveStats {
+    original_size: u64,
+    compressed_size: u64,
+    deduplicated_size: u64,
+    nfiles: u64,
+}
+
+/// Archive stats by archive id. Archives never change, so their stats only need fetching once.
+static ARCHIVE_STATS_CACHE: LazyLock<Mutex<HashMap<String, BorgArchiveStats>>> =
+    LazyLock::new(Default::default);
+
+/// Copy the cached stats onto `archives`, returning how many had none cached.
+fn fill_cached_archive_stats(archives: &mut [Archive]) -> usize {
+    let cache = ARCHIVE_STATS_CACHE.lock().unwrap();
+    let mut missing = 0;
+    for archive in archives {
+        let Some(stats) = archive.id.as_ref().and_then(|id| cache.get(id)) else {
+            missing += 1;
+            continue;
+        };
+        archive.original_size = Some(stats.original_size);
+        archive.compressed_size = Some(stats.compressed_size);
+        archive.deduplicated_size = Some(stats.deduplicated_size);
+        archive.num_files = Some(stats.nfiles);
+    }
+    missing
+}
+
+/// Fill in the sizes and file counts `borg list` can't provide from `borg info`.
+/// `borg info` reads every archive it reports on, so only archives without cached
+/// stats are asked for.
+async fn add_archive_stats(repo: &Repository, archives: &mut [Archive]) -> BorgResult<()> {
+    if fill_cached_archive_stats(archives) == 0 {
+        return Ok(());
+    }
+    // `archives` are sorted oldest first like `--last`, and new archives are the ones
+    // usually missing, so only ask for everything from the oldest uncached archive on.
+    let cache_miss_count = {
+        let cache = ARCHIVE_STATS_CACHE.lock().unwrap();
+        let first_missing = archives
+            .iter()
+            .position(|archive| archive.id.as_ref().is_none_or(|id| !cache.contains_key(id)))
+            .unwrap_or(archives.len());
+        archives.len() - first_missing
+    };
+    let output = borg_command(repo)?
+        .arg("info")
+        .arg("--json")
+        .arg("--last")
+        .arg(cache_miss_count.to_string())
+        .arg(repo.path())
+        .spawn()?
+        .wait_with_output()
+        .await?;
+    if !output.status.success() {
+        anyhow::bail!(
+            "Failed to get archive stats for {}: {}",
+            repo.path(),
+            String::from_utf8_lossy(&output.stderr).trim()
+        );
+    }
+    let info: BorgInfoArchives = serde_json::from_slice(&output.stdout)?;
+    ARCHIVE_STATS_CACHE.lock().unwrap().extend(
+        info.archives
+            .into_iter()
+            .map(|archive| (archive.id, archive.stats)),
+    );
+    fill_cached_archive_stats(archives);
+    Ok(())
+}
+
+/// The backup progress in a `borg create --progress --log-json` line, if it has any.
+fn create_progress_from_log(message: LoggingMessage) -> Option<BackupCreationProgress> {
+    match message {
+        LoggingMessage::ArchiveProgress { finished: true, .. } => {
+            Some(BackupCreationProgress::Finished)
         }
+        LoggingMessage::ArchiveProgress {
+            original_size,
+            compressed_size,
+            deduplicated_size,
+            nfiles,
+            path,
+            ..
+        } => Some(BackupCreationProgress::InProgress {
+            original_size: original_size.unwrap_or_default(),
+            compressed_size: compressed_size.unwrap_or_default(),
+            deduplicated_size: deduplicated_size.unwrap_or_default(),
+            num_files: nfiles.unwrap_or_default(),
+            current_path: path.unwrap_or_default(),
+        }),
+        _ => None,
     }
 }
 
@@ -68,12 +192,349 @@ fn make_common_options(repo: &Repository) -> BorgResult<CommonOptions> {
     })
 }
 
-/// TODO: tie this into the repo which was mounted!
-pub(crate) async fn hack_unmount(mountpoint: PathBuf) -> BorgResult<()> {
-    let mut exit = tokio::process::Command::new("umount")
-        .arg(mountpoint)
-        .spawn()?;
-    tracing::info!("unmount finished with exitcode {:?}", exit.wait().await?);
+/// Build a `borg` invocation with the repo's passphrase and connection options set.
+fn borg_command(repo: &Repository) -> BorgResult<tokio::process::Command> {
+    let borg_options = repo.borg_options()?;
+    let mut command = tokio::process::Command::new("borg");
+    keep_ctrl_c_away(&mut command)
+        .stdin(Stdio::null())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .env(
+            "BORG_PASSPHRASE",
+            repo.get_passphrase()?
+                .map(|p| p.inner())
+                .unwrap_or_default(),
+        );
+    if let Some(rsh) = borg_options.rsh {
+        command.arg("--rsh").arg(rsh);
+    }
+    if let Some(remote_path) = borg_options.remote_path {
+        command.arg("--remote-path").arg(remote_path);
+    }
+    if let Some(upload_ratelimit) = borg_options.upload_ratelimit {
+        command
+            .arg("--upload-ratelimit")
+            .arg(upload_ratelimit.to_string());
+    }
+    Ok(command)
+}
+
+/// The `borg create` flags for the repository's options.
+fn borg_create_args(borg_options: &BorgV1Options) -> Vec<String> {
+    let mut args = Vec::new();
+    if let Some(compression) = &borg_options.compression {
+        args.push(format!("--compression={}", compression));
+    }
+    if let Some(checkpoint_interval) = borg_options.checkpoint_interval_seconds {
+        args.push(format!("--checkpoint-interval={}", checkpoint_interval));
+    }
+    if borg_options.one_file_system {
+        args.push("--one-file-system".to_string());
+    }
+    if borg_options.numeric_ids {
+        args.push("--numeric-ids".to_string());
+    }
+    if let Some(chunker_params) = &borg_options.chunker_params {
+        args.push(format!("--chunker-params={}", chunker_params));
+    }
+    args
+}
+
+/// Forward the `message` field of each `--log-json` line on `reader` as an info message.
+fn forward_borg_log_messages<R>(
+    reader: R,
+    progress_channel: CommandResponseSender,
+    repo_loc: String,
+) -> tokio::task::JoinHandle<Vec<String>>
+where
+    R: tokio::io::AsyncRead + Unpin + Send + 'static,
+{
+    tokio::spawn(async move {
+        use tokio::io::AsyncBufReadExt;
+        let mut errors = Vec::new();
+        let mut last_update: Option<std::time::Instant> = None;
+        let mut lines = tokio::io::BufReader::new(reader).lines();
+        while let Ok(Some(line)) = lines.next_line().await {
+            let json = match line.parse::<serde_json::Value>() {
+                Ok(json) => json,
+                Err(_) => {
+                    errors.push(line);
+                    continue;
+                }
+            };
+            let is_error = json
+                .get("levelname")
+                .and_then(|level| level.as_str())
+                .map(|level| level == "ERROR" || level == "CRITICAL")
+                .unwrap_or(false);
+            let msg = match json.get("message").and_then(|msg| msg.as_str()) {
+                Some(msg) if !msg.is_empty() => msg.to_string(),
+                _ => continue,
+            };
+            if is_error {
+                errors.push(msg);
+                continue;
+            }
+            if let Some(last_update) = last_update {
+                if last_update.elapsed() < LOGGING_THROTTLE_TIME {
+                    continue;
+                }
+            }
+            last_update = Some(std::time::Instant::now());
+            send_info!(progress_channel, format!("[{}] {}", repo_loc, msg));
+        }
+        errors
+    })
+}
+
+/// Forward the `archive_progress` lines of `borg create` on `reader`, returning the errors it logged.
+fn forward_borg_create_progress<R>(
+    reader: R,
+    progress_channel: CommandResponseSender,
+    repo_loc: String,
+) -> tokio::task::JoinHandle<Vec<String>>
+where
+    R: tokio::io::AsyncRead + Unpin + Send + 'static,
+{
+    tokio::spawn(async move {
+        use tokio::io::AsyncBufReadExt;
+        let mut errors = Vec::new();
+        let mut lines = tokio::io::BufReader::new(reader).lines();
+        while let Ok(Some(line)) = lines.next_line().await {
+            let message = match serde_json::from_str::<LoggingMessage>(&line) {
+                Ok(message) => message,
+                Err(_) => {
+                    errors.push(line);
+                    continue;
+                }
+            };
+            if let LoggingMessage::LogMessage {
+                level_name: LevelName::Error | LevelName::Critical,
+                message,
+                ..
+            } = message
+            {
+                errors.push(message);
+                continue;
+            }
+            if let Some(create_progress) = create_progress_from_log(message) {
+                let create_progress = BackupCreateProgress {
+                    repository: repo_loc.clone(),
+                    create_progress,
+                };
+                if let Err(e) = progress_channel
+                    .send(CommandResponse::CreateProgress(create_progress))
+                    .await
+                {
+                    tracing::error!("Failed to send CreateProgress update: {}", e);
+                }
+            }
+        }
+        errors
+    })
+}
+
+/// Run a `borg create` command once the repo lock is free, forwarding its progress.
+async fn borg_create(
+    mut command: tokio::process::Command,
+    repo: &Repository,
+    progress_channel: &CommandResponseSender,
+    cancel: &CancelToken,
+) -> BorgResult<()> {
+    take_repo_lock!(
+        progress_channel,
+        repo,
+        "A backup is already in progress for {}, waiting..."
+    );
+    cancel.check()?;
+    send_info!(
+        progress_channel,
+        format!("Grabbed repo lock, starting the backup for {}", repo)
+    );
+    let mut process = command.spawn()?;
+    let stderr = process
+        .stderr
+        .take()
+        .ok_or_else(|| anyhow!("Failed to get stderr of borg create"))?;
+    let progress_handle =
+        forward_borg_create_progress(stderr, progress_channel.clone(), repo.path());
+    let output = match output_or_cancel(process, cancel).await {
+        Ok(output) => output,
+        Err(e) => {
+            progress_handle.abort();
+            return Err(e);
+        }
+    };
+    let errors = progress_handle.await?;
+    // Borg exits with 1 when it finished with warnings
+    match output.status.code() {
+        Some(0 | 1) => {}
+        _ => anyhow::bail!("{} ({})", errors.join("\n"), output.status),
+    }
+    let created: borgbackup::output::create::Create = serde_json::from_slice(&output.stdout)?;
+    info!(
+        "Archive created successfully in repo {}: {:?}",
+        created.repository.location, created.archive.stats
+    );
+    Ok(())
+}
+
+#[derive(Deserialize)]
+struct BorgListItem {
+    #[serde(rename = "type")]
+    item_type: String,
+    path: String,
+    #[serde(default)]
+    size: u64,
+    mtime: Option<String>,
+}
+
+impl From<BorgListItem> for ArchiveEntry {
+    fn from(value: BorgListItem) -> Self {
+        ArchiveEntry {
+            path: value.path,
+            is_dir: value.item_type == "d",
+            size: value.size,
+            modified: value.mtime.and_then(|mtime| {
+                chrono::NaiveDateTime::parse_from_str(&mtime, BORG_TIMESTAMP_FORMAT).ok()
+            }),
+        }
+    }
+}
+
+#[derive(Deserialize)]
+struct BorgDiffItem {
+    path: String,
+    changes: Vec<BorgDiffChange>,
+}
+
+#[derive(Deserialize)]
+struct BorgDiffChange {
+    #[serde(rename = "type")]
+    change_type: String,
+    size: Option<u64>,
+    added: Option<u64>,
+    removed: Option<u64>,
+}
+
+impl From<BorgDiffItem> for ArchiveDiffEntry {
+    fn from(value: BorgDiffItem) -> Self {
+        let mut entry = ArchiveDiffEntry {
+            path: value.path,
+            change: ArchiveDiffChange::MetadataChanged,
+            bytes_added: None,
+            bytes_removed: None,
+        };
+        // Borg reports content and metadata changes separately, keep the most significant.
+        for change in value.changes {
+            let (change_kind, bytes_added, bytes_removed) = match change.change_type.as_str() {
+                added if added.starts_with("added") => {
+                    (ArchiveDiffChange::Added, change.size, None)
+                }
+                removed if removed.starts_with("removed") => {
+                    (ArchiveDiffChange::Removed, None, change.size)
+                }
+                "modified" | "changed link" => {
+                    (ArchiveDiffChange::Modified, change.added, change.removed)
+                }
+                _ => continue,
+            };
+            if change_kind <= entry.change {
+                entry.change = change_kind;
+                entry.bytes_added = bytes_added;
+                entry.bytes_removed = bytes_removed;
+            }
+        }
+        entry
+    }
+}
+
+/// The keep-* flags understood by `borg prune`.
+fn borg_prune_args(prune_options: &PruneOptions, archive_filter: &ArchiveFilter) -> Vec<String> {
+    let mut args = Vec::new();
+    // The hostname is part of the glob when it's part of the archive names
+    if let Some(glob) = &archive_filter.glob {
+        args.push("--glob-archives".to_string());
+        args.push(glob.clone());
+    }
+    if let Some(keep_within) = prune_options.keep_within {
+        args.push("--keep-within".to_string());
+        args.push(keep_within.to_string());
+    }
+    for (flag, keep) in [
+        ("--keep-last", prune_options.keep_last),
+        ("--keep-hourly", prune_options.keep_hourly),
+        ("--keep-daily", prune_options.keep_daily),
+        ("--keep-weekly", prune_options.keep_weekly),
+        ("--keep-monthly", prune_options.keep_monthly),
+        ("--keep-yearly", prune_options.keep_yearly),
+    ] {
+        if let Some(keep) = keep {
+            args.push(flag.to_string());
+            args.push(keep.to_string());
+        }
+    }
+    args
+}
+
+/// Parse a `borg prune --list` line, e.g.
+/// "Keeping archive (rule: daily #1):  name  Sun, 2023-06-11 22:15:31 [id]"
+fn parse_borg_prune_list_line(message: &str) -> Option<PruneDecision> {
+    let (keep, reason, rest) = if let Some(rest) = message.strip_prefix("Keeping archive (rule: ") {
+        let (rule, rest) = rest.split_once("):")?;
+        (true, Some(rule.to_string()), rest)
+    } else if let Some(rest) = message.strip_prefix("Keeping checkpoint archive:") {
+        (true, Some("checkpoint".to_string()), rest)
+    } else if let Some(rest) = message.strip_prefix("Would prune:") {
+        (false, None, rest)
+    } else {
+        return None;
+    };
+    // Drop the trailing timestamp ("Sun, 2023-06-11 22:15:31") and archive id
+    let mut archive = rest.trim();
+    for _ in 0..4 {
+        archive = archive.rsplit_once(char::is_whitespace)?.0.trim_end();
+    }
+    Some(PruneDecision {
+        archive: archive.to_string(),
+        keep,
+        reason,
+    })
+}
+
+fn regex_escape(input: &str) -> String {
+    let mut escaped = String::with_capacity(input.len());
+    for c in input.chars() {
+        if "\\.^$|?*+()[]{}".contains(c) {
+            escaped.push('\\');
+        }
+        escaped.push(c);
+    }
+    escaped
+}
+
+/// Free the space of deleted archives. Borg only compacts segments where at least
+/// `threshold` percent (10 by default) would be freed.
+async fn borg_compact(
+    repo: &Repository,
+    threshold: Option<u8>,
+    cancel: &CancelToken,
+) -> BorgResult<()> {
+    let mut command = borg_command(repo)?;
+    command.arg("compact");
+    if let Some(threshold) = threshold {
+        command.arg(format!("--threshold={}", threshold));
+    }
+    let process = command.arg(repo.path()).spawn()?;
+    let output = output_or_cancel(process, cancel).await?;
+    if !output.status.success() {
+        anyhow::bail!(
+            "Failed to compact repo {}: {}",
+            repo.path(),
+            String::from_utf8_lossy(&output.stderr)
+        );
+    }
     Ok(())
 }
 
@@ -82,14 +543,17 @@ async fn borg_check(
     passphrase: Option<Passphrase>,
     progress_channel: CommandResponseSender,
     repair: bool,
+    cancel: CancelToken,
 ) -> BorgResult<bool> {
+    cancel.check()?;
     let repo_path = repo.path();
     let rsh = repo.borg_options()?.rsh.clone();
     let mut extra_args = vec![];
     if repair {
         extra_args.push("--repair");
     }
-    let mut process = tokio::process::Command::new("borg")
+    let mut command = tokio::process::Command::new("borg");
+    let mut process = keep_ctrl_c_away(&mut command)
         .stdin(Stdio::null())
         .stdout(Stdio::piped())
         .stderr(Stdio::piped())
@@ -129,7 +593,13 @@ async fn borg_check(
         });
     }
 
-    let exit = process.wait().await?;
+    let exit = match wait_or_cancel(&mut process, &cancel).await {
+        Ok(exit) => exit,
+        Err(e) => {
+            send_check_complete!(progress_channel, repo_path, Some(e.to_string()));
+            return Err(e);
+        }
+    };
     if !exit.success() {
         let err = format!("Borg check failed for {repo_path}");
         send_check_complete!(progress_channel, repo_path, Some(err));
@@ -160,97 +630,267 @@ impl BackupProvider for BorgProvider {
         repo: Repository,
         progress_channel: CommandResponseSender,
         completion_semaphore: std::sync::Arc<tokio::sync::Semaphore>,
+        cancel: CancelToken,
     ) -> BorgResult<()> {
-        // CreateOptions
-        let backup_paths = backup_paths
-            .iter()
-            .map(|path| format!("'{}'", path.to_string_lossy()))
-            .collect::<Vec<String>>();
-
-        let mut create_option = borgbackup::common::CreateOptions::new(
-            repo.path(),
-            archive_name.clone(),
-            backup_paths.to_vec(),
-            vec![],
-        );
-        create_option.passphrase = repo.get_passphrase()?.map(|p| p.inner());
-        create_option.excludes = exclude_patterns
-            .iter()
-            .cloned()
-            .map(borgbackup::common::Pattern::Shell)
-            .collect();
-        create_option.exclude_caches = exclude_caches;
-
-        // Convert borgs create progress into ours
-
-        let (create_progress_send, mut create_progress_recv) =
-            tokio::sync::mpsc::channel::<borg_async::CreateProgress>(200);
-
-        let common_options = make_common_options(&repo)?;
-
-        let repo_name_clone = repo.path();
-        let progress_channel_task = progress_channel.clone();
-        tokio::spawn(async move {
-            take_repo_lock!(
-                progress_channel_task,
-                repo,
-                "A backup is already in progress for {}, waiting..."
-            );
-            send_info!(
-                progress_channel_task,
-                format!("Grabbed repo lock, starting the backup for {}", repo)
-            );
-            // TODO: I think the UI doesn't update if you issue two backups in a row
-            while let Some(progress) = create_progress_recv.recv().await {
-                let create_progress = BackupCreateProgress {
-                    repository: repo_name_clone.clone(),
-                    create_progress: progress.into(),
-                };
-                if let Err(e) = progress_channel_task
-                    .send(CommandResponse::CreateProgress(create_progress))
-                    .await
-                {
-                    tracing::error!("Failed to send CreateProgress update: {}", e);
-                }
-            }
-        });
-
-        // Actually spawn the borg backup
+        let mut command = borg_command(&repo)?;
+        command
+            .arg("--progress")
+            .arg("--log-json")
+            .arg("create")
+            .arg("--json")
+            .args(borg_create_args(&repo.borg_options()?));
+        if exclude_caches {
+            command.arg("--exclude-caches");
+        }
+        for pattern in exclude_patterns {
+            command.arg(format!(
+                "--exclude={}",
+                borgbackup::common::Pattern::Shell(pattern.clone())
+            ));
+        }
+        command
+            .arg(format!("{}::{}", repo.path(), archive_name))
+            .args(backup_paths);
 
-        let progress_channel_clone = progress_channel.clone();
-        let completion_semaphore_clone = completion_semaphore.clone();
         tokio::spawn(async move {
-            let res =
-                borg_async::create_progress(&create_option, &common_options, create_progress_send)
-                    .await;
-            completion_semaphore_clone.add_permits(1);
+            let res = borg_create(command, &repo, &progress_channel, &cancel).await;
+            completion_semaphore.add_permits(1);
             match res {
-                Ok(c) => info!(
-                    "Archive created successfully in repo {}: {:?}",
-                    c.repository.location, c.archive.stats
-                ),
+                Ok(()) => {}
+                Err(e) if is_cancelled(&e) => {
+                    let checkpoint = cancel.wants_checkpoint();
+                    send_create_cancelled!(progress_channel, repo.path(), checkpoint);
+                    send_info!(
+                        progress_channel,
+                        format!(
+                            "Cancelled the backup to {}{}",
+                            repo,
+                            if checkpoint {
+                                " (borg was asked to save a checkpoint archive)"
+                            } else {
+                                ""
+                            }
+                        )
+                    );
+                }
                 Err(e) => send_error!(
-                    progress_channel_clone,
+                    progress_channel,
                     format!(
-                        "Failed to create archive {} in repo {}: {:?}",
-                        create_option.archive, create_option.repository, e
+                        "Failed to create archive {} in repo {}: {}",
+                        archive_name,
+                        repo.path(),
+                        e
                     )
                 ),
-            };
+            }
         });
         Ok(())
     }
 
     async fn list_archives(&self, repo: &Repository) -> BorgResult<RepositoryArchives> {
-        let list_options = borgbackup::common::ListOptions {
-            repository: repo.path(),
-            passphrase: repo.get_passphrase()?.map(|p| p.inner()),
+        // Keys used in --format are added to the json output
+        let output = borg_command(repo)?
+            .arg("list")
+            .arg("--json")
+            .arg("--format")
+            .arg("{hostname}{username}{end}")
+            .arg(repo.path())
+            .spawn()?
+            .wait_with_output()
+            .await?;
+        if !output.status.success() {
+            anyhow::bail!(
+                "Failed to list archives in repo {}: {}",
+                repo.path(),
+                String::from_utf8_lossy(&output.stderr)
+            );
+        }
+        let list: BorgListArchives = serde_json::from_slice(&output.stdout)?;
+        Ok(RepositoryArchives {
+            path: repo.path(),
+            archives: list
+                .archives
+                .into_iter()
+                .map(Archive::try_from)
+                .collect::<BorgResult<_>>()?,
+        })
+    }
+    async fn add_archive_stats(
+        &self,
+        repo: &Repository,
+        archives: &mut RepositoryArchives,
+    ) -> BorgResult<()> {
+        add_archive_stats(repo, &mut archives.archives).await
+    }
+    async fn list_archive_directory(
+        &self,
+        repo: &Repository,
+        archive_spec: String,
+        directory: String,
+    ) -> BorgResult<Vec<ArchiveEntry>> {
+        let directory = directory.trim_matches('/').to_string();
+        // Borg lists recursively, so exclude anything nested deeper than one level.
+        let nested_pattern = if directory.is_empty() {
+            "re:^[^/]+/".to_string()
+        } else {
+            format!("re:^{}/[^/]+/", regex_escape(&directory))
         };
-        let res = borg_async::list(&list_options, &make_common_options(repo)?)
-            .await
-            .map_err(|e| anyhow!("Failed to list archives in repo {}: {:?}", repo.path(), e))?;
-        Ok(res.into())
+        let mut command = borg_command(repo)?;
+        command
+            .arg("list")
+            .arg("--json-lines")
+            .arg("--exclude")
+            .arg(nested_pattern)
+            .arg(&archive_spec);
+        if !directory.is_empty() {
+            command.arg(&directory);
+        }
+        let output = command.spawn()?.wait_with_output().await?;
+        if !output.status.success() {
+            anyhow::bail!(
+                "Failed to list {} in {}: {}",
+                directory,
+                archive_spec,
+                String::from_utf8_lossy(&output.stderr)
+            );
+        }
+        let mut entries = Vec::new();
+        for line in output.stdout.split(|b| *b == b'\n') {
+            if line.is_empty() {
+                continue;
+            }
+            let item: BorgListItem = serde_json::from_slice(line)?;
+            if is_direct_child(&directory, &item.path) {
+                entries.push(item.into());
+            }
+        }
+        Ok(entries)
+    }
+
+    async fn read_archive_file(
+        &self,
+        repo: &Repository,
+        archive_spec: String,
+        path: String,
+        writer: &mut (dyn AsyncWrite + Send + Unpin),
+    ) -> BorgResult<()> {
+        let mut command = borg_command(repo)?;
+        command
+            .arg("extract")
+            .arg("--stdout")
+            .arg(&archive_spec)
+            .arg(path.trim_matches('/'));
+        let context = format!("Failed to read {} from {}", path, archive_spec);
+        // Nothing cancels reads, they stop when the reader hangs up
+        copy_stdout(command, writer, context, &CancelToken::default()).await
+    }
+
+    async fn export_tar(
+        &self,
+        repo: &Repository,
+        archive_spec: String,
+        paths: &[PathBuf],
+        writer: &mut (dyn AsyncWrite + Send + Unpin),
+        cancel: CancelToken,
+    ) -> BorgResult<()> {
+        let mut command = borg_command(repo)?;
+        // Borg stores paths without a leading slash
+        command.arg("export-tar").arg(&archive_spec).arg("-").args(
+            paths
+                .iter()
+                .map(|path| path.strip_prefix("/").unwrap_or(path)),
+        );
+        let context = format!("Failed to export {}", archive_spec);
+        copy_stdout(command, writer, context, &cancel).await
+    }
+
+    async fn import_tar(
+        &self,
+        repo: &Repository,
+        archive_name: String,
+        tarball: PathBuf,
+        progress_channel: CommandResponseSender,
+        cancel: CancelToken,
+    ) -> BorgResult<()> {
+        let mut command = borg_command(repo)?;
+        command
+            .stdin(tarball::tarball_stdin(&tarball)?)
+            .arg("--log-json")
+            .arg("import-tar");
+        if let Some(compression) = &repo.borg_options()?.compression {
+            command.arg(format!("--compression={}", compression));
+        }
+        // Borg only guesses the filter from file names, and it's reading stdin
+        if let Some(compression) = TarCompression::from_path(&tarball) {
+            command.arg(format!("--tar-filter={}", compression.decompress_command()));
+        }
+        command
+            .arg(format!("{}::{}", repo.path(), archive_name))
+            .arg("-");
+        take_repo_lock!(
+            progress_channel,
+            repo,
+            "A backup is already in progress for {}, waiting..."
+        );
+        cancel.check()?;
+        let mut process = command.spawn()?;
+        let stderr = process
+            .stderr
+            .take()
+            .ok_or_else(|| anyhow!("Failed to get stderr of borg import-tar"))?;
+        let log_handle = forward_borg_log_messages(stderr, progress_channel.clone(), repo.path());
+        let output = match output_or_cancel(process, &cancel).await {
+            Ok(output) => output,
+            Err(e) => {
+                log_handle.abort();
+                return Err(e);
+            }
+        };
+        let errors = log_handle.await?;
+        // Borg exits with 1 when it finished with warnings
+        match output.status.code() {
+            Some(0 | 1) => Ok(()),
+            _ => anyhow::bail!("{} ({})", errors.join("\n"), output.status),
+        }
     }
+
+    async fn diff(
+        &self,
+        repo: &Repository,
+        first_archive_spec: String,
+        second_archive_spec: String,
+    ) -> BorgResult<Vec<ArchiveDiffEntry>> {
+        // borg diff takes repo::first second
+        let (_, second_archive) = second_archive_spec.split_once("::").ok_or_else(|| {
+            anyhow!("Expected an archive (repo::archive), got {second_archive_spec}")
+        })?;
+        let output = borg_command(repo)?
+            .arg("diff")
+            .arg("--json-lines")
+            .arg(&first_archive_spec)
+            .arg(second_archive)
+            .spawn()?
+            .wait_with_output()
+            .await?;
+        if !output.status.success() {
+            anyhow::bail!(
+                "Failed to diff {} and {}: {}",
+                first_archive_spec,
+                second_archive_spec,
+                String::from_utf8_lossy(&output.stderr)
+            );
+        }
+        let mut entries = Vec::new();
+        for line in output.stdout.split(|b| *b == b'\n') {
+            if line.is_empty() {
+                continue;
+            }
+            let item: BorgDiffItem = serde_json::from_slice(line)?;
+            entries.push(item.into());
+        }
+        Ok(entries)
+    }
+
     async fn init_repo(
         &self,
         repo_loc: String,
@@ -291,7 +931,6 @@ impl BackupProvider for BorgProvider {
             );
             tokio::fs::create_dir_all(&mountpoint).await?;
         }
-        // TODO: Check if this is already mounted!
         let mount_source = if given_repository_path.contains("::") {
             MountSource::Archive {
                 archive_name: given_repository_path.clone(),
@@ -310,6 +949,14 @@ impl BackupProvider for BorgProvider {
         borg_async::mount(&mount_options, &make_common_options(repo)?)
             .await
             .map_err(|e| anyhow!("Failed to mount repo {}: {}", repo.path(), e))?;
+        // Borg daemonizes, so there's no process to keep track of
+        mounts::register_mount(MountRecord::new(
+            repo,
+            &given_repository_path,
+            &mountpoint,
+            None,
+        )?)
+        .await?;
         info!(
             "Successfully mounted {} at {:?}",
             given_repository_path, mountpoint
@@ -317,8 +964,6 @@ impl BackupProvider for BorgProvider {
         Ok(())
     }
 
-    // TODO: Figure out unused
-    #[allow(unused)]
     async fn unmount(&self, mountpoint: PathBuf) -> BorgResult<()> {
         borg_async::umount(
             mountpoint.to_string_lossy().to_string(),
@@ -331,50 +976,214 @@ impl BackupProvider for BorgProvider {
     async fn prune(
         &self,
         repo: &Repository,
-        prune_options: crate::profiles::PruneOptions,
+        prune_options: PruneOptions,
+        archive_filter: &ArchiveFilter,
         progress_channel: CommandResponseSender,
+        cancel: CancelToken,
     ) -> BorgResult<()> {
         take_repo_lock!(progress_channel, repo);
-        let mut compact_options = BorgLibPruneOptions::new(repo.path());
-        compact_options.passphrase = repo.get_passphrase()?.map(|p| p.inner());
-        compact_options.keep_daily = Some(prune_options.keep_daily);
-        compact_options.keep_weekly = Some(prune_options.keep_weekly);
-        compact_options.keep_monthly = Some(prune_options.keep_monthly);
-        compact_options.keep_yearly = Some(prune_options.keep_yearly);
-        borg_async::prune(&compact_options, &make_common_options(repo)?)
-            .await
-            .map_err(|e| anyhow!("Failed to prune repo {}: {:?}", repo.path(), e))?;
+        cancel.check()?;
+        let process = borg_command(repo)?
+            .arg("prune")
+            .args(borg_prune_args(&prune_options, archive_filter))
+            .arg(repo.path())
+            .spawn()?;
+        let output = output_or_cancel(process, &cancel).await?;
+        if !output.status.success() {
+            anyhow::bail!(
+                "Failed to prune repo {}: {}",
+                repo.path(),
+                String::from_utf8_lossy(&output.stderr)
+            );
+        }
         Ok(())
     }
-    async fn compact(
+    async fn prune_dry_run(
+        &self,
+        repo: &Repository,
+        prune_options: PruneOptions,
+        archive_filter: &ArchiveFilter,
+    ) -> BorgResult<Vec<PruneDecision>> {
+        let output = borg_command(repo)?
+            .arg("--log-json")
+            .arg("prune")
+            .arg("--dry-run")
+            .arg("--list")
+            .args(borg_prune_args(&prune_options, archive_filter))
+            .arg(repo.path())
+            .spawn()?
+            .wait_with_output()
+            .await?;
+        if !output.status.success() {
+            anyhow::bail!(
+                "Failed to preview pruning {}: {}",
+                repo,
+                String::from_utf8_lossy(&output.stderr)
+            );
+        }
+        // The --list output is logged to stderr
+        let mut decisions = Vec::new();
+        for line in output.stderr.split(|b| *b == b'\n') {
+            let Ok(log_line) = serde_json::from_slice::<serde_json::Value>(line) else {
+                continue;
+            };
+            if let Some(decision) = log_line
+                .get("message")
+                .and_then(|message| message.as_str())
+                .and_then(parse_borg_prune_list_line)
+            {
+                decisions.push(decision);
+            }
+        }
+        Ok(decisions)
+    }
+    async fn delete_archive(
         &self,
         repo: &Repository,
+        archive_spec: String,
         progress_channel: CommandResponseSender,
+        cancel: CancelToken,
     ) -> BorgResult<()> {
-        let compact_options = borgbackup::common::CompactOptions {
-            repository: repo.path(),
-        };
         take_repo_lock!(progress_channel, repo);
-        borg_async::compact(&compact_options, &make_common_options(repo)?)
-            .await
-            .map_err(|e| anyhow!("Failed to compact repo {}: {:?}", repo.path(), e))?;
+        cancel.check()?;
+        let process = borg_command(repo)?
+            .arg("delete")
+            .arg(&archive_spec)
+            .spawn()?;
+        let output = output_or_cancel(process, &cancel).await?;
+        if !output.status.success() {
+            anyhow::bail!(
+                "Failed to delete {}: {}",
+                archive_spec,
+                String::from_utf8_lossy(&output.stderr).trim()
+            );
+        }
+        // Borg keeps the archive's data until the segments holding it are compacted. Any
+        // freed space at all is enough, so none of it is left behind.
+        cancel.check()?;
+        send_info!(progress_channel, format!("Compacting {}...", repo.path()));
+        borg_compact(repo, Some(0), &cancel).await
+    }
+    async fn rename_archive(
+        &self,
+        repo: &Repository,
+        archive_spec: String,
+        new_name: String,
+        progress_channel: CommandResponseSender,
+    ) -> BorgResult<()> {
+        take_repo_lock!(progress_channel, repo);
+        let output = borg_command(repo)?
+            .arg("rename")
+            .arg(&archive_spec)
+            .arg(&new_name)
+            .spawn()?
+            .wait_with_output()
+            .await?;
+        if !output.status.success() {
+            anyhow::bail!(
+                "Failed to rename {} to {}: {}",
+                archive_spec,
+                new_name,
+                String::from_utf8_lossy(&output.stderr).trim()
+            );
+        }
         Ok(())
     }
+    async fn compact(
+        &self,
+        repo: &Repository,
+        progress_channel: CommandResponseSender,
+        cancel: CancelToken,
+    ) -> BorgResult<()> {
+        take_repo_lock!(progress_channel, repo);
+        cancel.check()?;
+        borg_compact(repo, None, &cancel).await
+    }
     async fn check(
         &self,
         repo: &Repository,
-        // TODO: Use this
         progress_channel: CommandResponseSender,
+        cancel: CancelToken,
     ) -> BorgResult<bool> {
         take_repo_lock!(progress_channel, repo);
-        borg_check(repo, repo.get_passphrase()?, progress_channel, false).await
+        borg_check(
+            repo,
+            repo.get_passphrase()?,
+            progress_channel,
+            false,
+            cancel,
+        )
+        .await
     }
     async fn repair(
         &self,
         repo: &Repository,
+        dry_run: bool,
         progress_channel: CommandResponseSender,
+        cancel: CancelToken,
     ) -> BorgResult<bool> {
         take_repo_lock!(progress_channel, repo);
-        borg_check(repo, repo.get_passphrase()?, progress_channel, true).await
+        // A plain check reports everything `--repair` would fix
+        borg_check(
+            repo,
+            repo.get_passphrase()?,
+            progress_channel,
+            !dry_run,
+            cancel,
+        )
+        .await
+    }
+
+    async fn restore(
+        &self,
+        repo: &Repository,
+        archive_spec: String,
+        include_paths: &[PathBuf],
+        target_directory: PathBuf,
+        strip_components: Option<u32>,
+        progress_channel: CommandResponseSender,
+    ) -> BorgResult<()> {
+        if !archive_spec.contains("::") {
+            anyhow::bail!("Expected an archive (repo::archive) to restore, got {archive_spec}");
+        }
+        tokio::fs::create_dir_all(&target_directory).await?;
+        let mut command = borg_command(repo)?;
+        command
+            .current_dir(&target_directory)
+            .arg("--progress")
+            .arg("--log-json")
+            .arg("extract")
+            .arg("--list");
+        if let Some(strip_components) = strip_components {
+            command
+                .arg("--strip-components")
+                .arg(strip_components.to_string());
+        }
+        // Borg stores paths without a leading slash
+        command.arg(&archive_spec).args(
+            include_paths
+                .iter()
+                .map(|path| path.strip_prefix("/").unwrap_or(path)),
+        );
+        send_info!(
+            progress_channel,
+            format!(
+                "Restoring {} to {}",
+                archive_spec,
+                target_directory.to_string_lossy()
+            )
+        );
+        let mut process = command.spawn()?;
+        let stderr = process
+            .stderr
+            .take()
+            .ok_or_else(|| anyhow!("Failed to get stderr of borg extract"))?;
+        let log_handle = forward_borg_log_messages(stderr, progress_channel.clone(), repo.path());
+        let exit = process.wait().await?;
+        let errors = log_handle.await?;
+        if !exit.success() {
+            anyhow::bail!("Failed to restore {}: {}", archive_spec, errors.join("\n"));
+        }
+        Ok(())
     }
 }
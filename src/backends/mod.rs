@@ -5,3 +5,6 @@ pub(crate) mod restic_provider;
 mod rustic_mount;
 #[cfg(feature = "rustic")]
 pub(crate) mod rustic_provider;
+// Plain std, so it's also tested without rustic
+#[cfg(any(feature = "rustic", test))]
+mod rustic_tar;
@@ -1,17 +1,25 @@
 use crate::{
     borgtui::CommandResponse,
-    profiles::{Passphrase, PruneOptions, Repository, RepositoryOptions},
+    cancel::{copy_stdout, is_cancelled, keep_ctrl_c_away, output_or_cancel, CancelToken},
+    mounts::{self, MountRecord},
+    profiles::{
+        ArchiveFilter, KeepWithin, KeepWithinUnit, Passphrase, PruneOptions, Repository,
+        RepositoryOptions, ResticOptions,
+    },
+    tarball,
     types::{
-        Archive, BackupCreateProgress, BackupCreationProgress, BorgResult, CheckComplete,
-        CommandResponseSender, RepositoryArchives,
+        is_direct_child, send_create_cancelled, send_error, send_info, take_repo_lock, Archive,
+        ArchiveDiffChange, ArchiveDiffEntry, ArchiveEntry, BackupCreateProgress,
+        BackupCreationProgress, BorgResult, CheckComplete, CommandResponseSender, PrettyBytes,
+        PruneDecision, RepositoryArchives,
     },
 };
 use anyhow::anyhow;
 use async_trait::async_trait;
 use serde::Deserialize;
-use std::{path::PathBuf, process::Stdio, sync::Arc};
+use std::{collections::HashMap, path::PathBuf, process::Stdio, sync::Arc};
 use tokio::{
-    io::{AsyncBufReadExt, BufReader},
+    io::{AsyncBufReadExt, AsyncWrite, BufReader},
     sync::Semaphore,
 };
 
@@ -22,9 +30,105 @@ const LOGGING_THROTTLE_TIME: std::time::Duration = std::time::Duration::from_mil
 #[derive(Deserialize)]
 struct ResticSnapshot {
     time: String,
+    id: String,
+    hostname: Option<String>,
+    username: Option<String>,
+    #[serde(default)]
+    tags: Vec<String>,
+    /// Only present for snapshots made with restic 0.17 or newer
+    summary: Option<ResticSnapshotSummary>,
     // ignore the rest of the fields
 }
 
+#[derive(Deserialize)]
+struct ResticSnapshotSummary {
+    backup_start: String,
+    backup_end: String,
+    total_files_processed: u64,
+    total_bytes_processed: u64,
+    data_added: u64,
+}
+
+impl TryFrom<ResticSnapshot> for Archive {
+    type Error = anyhow::Error;
+
+    fn try_from(snapshot: ResticSnapshot) -> Result<Self, Self::Error> {
+        // Local time, like borg's and rustic's archive dates, so ages and sorting agree
+        let creation_date = chrono::DateTime::parse_from_rfc3339(&snapshot.time)?
+            .with_timezone(&chrono::Local)
+            .naive_local();
+        let mut archive = Archive {
+            name: snapshot.time,
+            creation_date,
+            id: Some(snapshot.id),
+            hostname: snapshot.hostname,
+            username: snapshot.username,
+            tags: snapshot.tags,
+            ..Default::default()
+        };
+        if let Some(summary) = snapshot.summary {
+            archive.original_size = Some(summary.total_bytes_processed);
+            // restic only reports the packed size of the data a snapshot added, not the
+            // compressed size of the whole snapshot, so compressed_size stays empty
+            archive.deduplicated_size = Some(summary.data_added);
+            archive.num_files = Some(summary.total_files_processed);
+            let backup_start = chrono::DateTime::parse_from_rfc3339(&summary.backup_start);
+            let backup_end = chrono::DateTime::parse_from_rfc3339(&summary.backup_end);
+            if let (Ok(backup_start), Ok(backup_end)) = (backup_start, backup_end) {
+                archive.duration = (backup_end - backup_start).to_std().ok();
+            }
+        }
+        Ok(archive)
+    }
+}
+
+#[derive(Deserialize)]
+struct ResticLsNode {
+    #[serde(default)]
+    struct_type: String,
+    #[serde(rename = "type", default)]
+    node_type: String,
+    #[serde(default)]
+    path: String,
+    #[serde(default)]
+    size: u64,
+    mtime: Option<String>,
+}
+
+#[derive(Deserialize)]
+struct ResticDiffLine {
+    message_type: String,
+    #[serde(default)]
+    path: String,
+    #[serde(default)]
+    modifier: String,
+}
+
+#[derive(Deserialize)]
+struct ResticForgetGroup {
+    keep: Option<Vec<ResticSnapshot>>,
+    remove: Option<Vec<ResticSnapshot>>,
+    #[serde(default)]
+    reasons: Vec<ResticForgetReason>,
+    // ignore the rest of the fields
+}
+
+#[derive(Deserialize)]
+struct ResticForgetReason {
+    snapshot: ResticSnapshot,
+    matches: Vec<String>,
+}
+
+#[derive(Deserialize)]
+struct ResticRestoreProgress<'a> {
+    message_type: &'a str,
+    percent_done: Option<f64>,
+    files_restored: Option<u64>,
+    total_files: Option<u64>,
+    bytes_restored: Option<u64>,
+    total_bytes: Option<u64>,
+}
+
 #[derive(Deserialize)]
 struct ResticProgress<'a> {
     message_type: &'a str,
@@ -34,6 +138,343 @@ struct ResticProgress<'a> {
     current_files: Option<Vec<&'a str>>,
 }
 
+/// The global flags for the repository's options.
+fn restic_option_args(options: &ResticOptions) -> Vec<String> {
+    let mut args = Vec::new();
+    for (key, value) in &options.backend_options {
+        args.push("-o".to_string());
+        args.push(format!("{key}={value}"));
+    }
+    if let Some(cache_dir) = &options.cache_dir {
+        args.push("--cache-dir".to_string());
+        args.push(cache_dir.to_string_lossy().to_string());
+    }
+    if options.no_cache {
+        args.push("--no-cache".to_string());
+    }
+    if let Some(compression) = options.compression {
+        args.push(format!("--compression={compression}"));
+    }
+    if let Some(pack_size) = options.pack_size_mib {
+        args.push(format!("--pack-size={pack_size}"));
+    }
+    args
+}
+
+fn restic_command(
+    repo: &Repository,
+    passphrase: &Passphrase,
+) -> BorgResult<tokio::process::Command> {
+    let mut command = tokio::process::Command::new("restic");
+    keep_ctrl_c_away(&mut command)
+        .arg("--repo")
+        .arg(repo.path_ref())
+        .args(restic_option_args(&repo.restic_options()?))
+        .env("RESTIC_PASSWORD", passphrase.inner())
+        .stdin(Stdio::null())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped());
+    Ok(command)
+}
+
+/// A restic command which only reads the repository. Read-only repositories don't lock
+/// them, since read-only storage can't hold a lock.
+fn restic_read_command(
+    repo: &Repository,
+    passphrase: &Passphrase,
+) -> BorgResult<tokio::process::Command> {
+    let mut command = restic_command(repo, passphrase)?;
+    if repo.read_only() {
+        command.arg("--no-lock");
+    }
+    Ok(command)
+}
+
+async fn restic_snapshots(repo: &Repository) -> BorgResult<Vec<ResticSnapshot>> {
+    let passphrase = repo
+        .get_passphrase()?
+        .ok_or_else(|| anyhow!("Restic requires a password to list archives."))?;
+
+    let output = restic_read_command(repo, &passphrase)?
+        .arg("snapshots")
+        .arg("--json")
+        .spawn()?
+        .wait_with_output()
+        .await?;
+
+    if !output.status.success() {
+        let stderr = String::from_utf8_lossy(&output.stderr);
+        return Err(anyhow!("Failed to list restic snapshots: {}", stderr));
+    }
+
+    Ok(serde_json::from_slice(&output.stdout)?)
+}
+
+/// Resolve the archive part of a "repo::archive" spec to a restic snapshot id.
+///
+/// BorgTUI names restic archives after their creation time, but snapshot ids
+/// (or a unique prefix of one) and "latest" are accepted as well.
+/// Size of every file in a snapshot, keyed by its path relative to the snapshot root.
+async fn restic_file_sizes(
+    repo: &Repository,
+    passphrase: &Passphrase,
+    snapshot_id: &str,
+) -> BorgResult<HashMap<String, u64>> {
+    let output = restic_read_command(repo, passphrase)?
+        .arg("ls")
+        .arg("--json")
+        .arg(snapshot_id)
+        .spawn()?
+        .wait_with_output()
+        .await?;
+    if !output.status.success() {
+        let stderr = String::from_utf8_lossy(&output.stderr);
+        return Err(anyhow!(
+            "Failed to list the files in snapshot {}: {}",
+            snapshot_id,
+            stderr
+        ));
+    }
+    let mut sizes = HashMap::new();
+    for line in output.stdout.split(|b| *b == b'\n') {
+        if line.is_empty() {
+            continue;
+        }
+        let node: ResticLsNode = serde_json::from_slice(line)?;
+        if node.struct_type == "node" && node.node_type == "file" {
+            sizes.insert(node.path.trim_matches('/').to_string(), node.size);
+        }
+    }
+    Ok(sizes)
+}
+
+async fn resolve_snapshot_id(repo: &Repository, archive_spec: &str) -> BorgResult<String> {
+    let archive = archive_spec
+        .split_once("::")
+        .map(|(_, archive)| archive)
+        .ok_or_else(|| anyhow!("Expected an archive (repo::archive), got {archive_spec}"))?;
+    if archive == "latest" {
+        return Ok(archive.to_string());
+    }
+    let snapshots = restic_snapshots(repo).await?;
+    snapshot_id(&snapshots, archive).map_err(|e| anyhow!("{} in {}", e, repo))
+}
+
+/// The ID of the snapshot `archive` names by its time, ID or a unique prefix of its ID.
+fn snapshot_id(snapshots: &[ResticSnapshot], archive: &str) -> BorgResult<String> {
+    if archive.is_empty() {
+        anyhow::bail!("Expected a snapshot time or ID, got an empty archive name");
+    }
+    if snapshots
+        .iter()
+        .any(|snapshot| snapshot.time == archive || snapshot.id == archive)
+    {
+        return exact_snapshot_id(snapshots, archive);
+    }
+    only_snapshot(
+        archive,
+        snapshots
+            .iter()
+            .filter(|snapshot| snapshot.id.starts_with(archive))
+            .collect(),
+    )
+}
+
+/// The ID of the snapshot `archive_spec` names exactly, by its time or full ID. Fails if
+/// several snapshots share the time, since guessing could delete the wrong one.
+async fn resolve_exact_snapshot_id(repo: &Repository, archive_spec: &str) -> BorgResult<String> {
+    let archive = archive_spec
+        .split_once("::")
+        .map(|(_, archive)| archive)
+        .ok_or_else(|| anyhow!("Expected an archive (repo::archive), got {archive_spec}"))?;
+    let snapshots = restic_snapshots(repo).await?;
+    exact_snapshot_id(&snapshots, archive).map_err(|e| anyhow!("{} in {}", e, repo))
+}
+
+fn exact_snapshot_id(snapshots: &[ResticSnapshot], archive: &str) -> BorgResult<String> {
+    only_snapshot(
+        archive,
+        snapshots
+            .iter()
+            .filter(|snapshot| snapshot.time == archive || snapshot.id == archive)
+            .collect(),
+    )
+}
+
+/// The ID of the one snapshot `archive` matched, failing if it matched none or several.
+fn only_snapshot(archive: &str, matches: Vec<&ResticSnapshot>) -> BorgResult<String> {
+    match matches.as_slice() {
+        [] => Err(anyhow!("Could not find snapshot {}", archive)),
+        [snapshot] => Ok(snapshot.id.clone()),
+        _ => Err(anyhow!(
+            "{} names {} snapshots, use the ID of one ({})",
+            archive,
+            matches.len(),
+            matches
+                .iter()
+                .map(|snapshot| snapshot.id.as_str())
+                .collect::<Vec<_>>()
+                .join(", ")
+        )),
+    }
+}
+
+/// Remove data which is no longer referenced by any snapshot. Restic leaves packs which are
+/// mostly still in use alone unless `max_unused` (e.g. "0") says otherwise.
+async fn restic_prune(
+    repo: &Repository,
+    passphrase: &Passphrase,
+    max_unused: Option<&str>,
+    cancel: &CancelToken,
+) -> BorgResult<()> {
+    let mut command = restic_command(repo, passphrase)?;
+    command.arg("prune");
+    if let Some(max_unused) = max_unused {
+        command.args(["--max-unused", max_unused]);
+    }
+    let process = command.spawn()?;
+    let output = output_or_cancel(process, cancel).await?;
+    if !output.status.success() {
+        let stderr = String::from_utf8_lossy(&output.stderr);
+        return Err(anyhow!("Failed to prune restic repository: {}", stderr));
+    }
+    Ok(())
+}
+
+/// Run `restic forget` on the snapshots BorgTUI created using the retention policy.
+async fn restic_forget(
+    repo: &Repository,
+    passphrase: &Passphrase,
+    prune_options: &PruneOptions,
+    archive_filter: &ArchiveFilter,
+    dry_run: bool,
+    cancel: &CancelToken,
+) -> BorgResult<Vec<ResticForgetGroup>> {
+    // A dry run only reads, and restic doesn't lock for it with --no-lock
+    let mut command = if dry_run {
+        restic_read_command(repo, passphrase)?
+    } else {
+        restic_command(repo, passphrase)?
+    };
+    command
+        .arg("forget")
+        .arg("--json")
+        .args(["--tag", "borgtui"])
+        // Apply the policy to all snapshots at once (like borg) instead of per host and paths
+        .args(["--group-by", ""]);
+    // Snapshots have no name to match the glob against, but they do record their host
+    if let Some(hostname) = &archive_filter.hostname {
+        command.args(["--host", hostname]);
+    }
+    if dry_run {
+        command.arg("--dry-run");
+    }
+    for (flag, keep) in [
+        ("--keep-last", prune_options.keep_last),
+        ("--keep-hourly", prune_options.keep_hourly),
+        ("--keep-daily", prune_options.keep_daily),
+        ("--keep-weekly", prune_options.keep_weekly),
+        ("--keep-monthly", prune_options.keep_monthly),
+        ("--keep-yearly", prune_options.keep_yearly),
+    ] {
+        if let Some(keep) = keep {
+            command.arg(flag).arg(keep.to_string());
+        }
+    }
+    if let Some(keep_within) = prune_options.keep_within {
+        command
+            .arg("--keep-within")
+            .arg(restic_keep_within(keep_within));
+    }
+    let output = output_or_cancel(command.spawn()?, cancel).await?;
+    if !output.status.success() {
+        let stderr = String::from_utf8_lossy(&output.stderr);
+        return Err(anyhow!("Failed to forget restic snapshots: {}", stderr));
+    }
+    Ok(serde_json::from_slice(&output.stdout)?)
+}
+
+/// Run a `restic backup --json` command once the repo lock is free, forwarding its progress.
+async fn restic_backup(
+    mut command: tokio::process::Command,
+    repo: &Repository,
+    progress_channel: &CommandResponseSender,
+    cancel: &CancelToken,
+) -> BorgResult<()> {
+    take_repo_lock!(
+        progress_channel,
+        repo,
+        "A backup is already in progress for {}, waiting..."
+    );
+    cancel.check()?;
+    let mut child = command.spawn()?;
+
+    let stdout = child
+        .stdout
+        .take()
+        .ok_or_else(|| anyhow!("Failed to get stdout"))?;
+    let reader = BufReader::new(stdout);
+    let mut lines = reader.lines();
+
+    let repo_path = repo.path();
+    let progress_channel_clone = progress_channel.clone();
+
+    let progress_handle = tokio::spawn(async move {
+        let mut last_update = std::time::Instant::now();
+        while let Ok(Some(line)) = lines.next_line().await {
+            if last_update.elapsed() < LOGGING_THROTTLE_TIME {
+                continue;
+            }
+            last_update = std::time::Instant::now();
+            if let Ok(progress) = serde_json::from_str::<ResticProgress>(&line) {
+                if progress.message_type == "status" {
+                    let create_progress = BackupCreateProgress {
+                        repository: repo_path.clone(),
+                        create_progress: BackupCreationProgress::InProgress {
+                            original_size: progress.total_bytes.unwrap_or(0),
+                            compressed_size: progress.bytes_done.unwrap_or(0),
+                            deduplicated_size: progress.bytes_done.unwrap_or(0),
+                            num_files: progress.total_files.unwrap_or(0),
+                            current_path: progress.current_files.unwrap_or_default().join(", "),
+                        },
+                    };
+                    if let Err(e) = progress_channel_clone
+                        .send(CommandResponse::CreateProgress(create_progress))
+                        .await
+                    {
+                        tracing::error!("Failed to send progress: {}", e);
+                    }
+                }
+            }
+        }
+    });
+
+    let output = match output_or_cancel(child, cancel).await {
+        Ok(output) => output,
+        Err(e) => {
+            progress_handle.abort();
+            return Err(e);
+        }
+    };
+    progress_handle.await?;
+    if !output.status.success() {
+        anyhow::bail!("{}", String::from_utf8_lossy(&output.stderr));
+    }
+    Ok(())
+}
+
+/// Restic durations don't support weeks or hours with an upper case H.
+fn restic_keep_within(keep_within: KeepWithin) -> String {
+    let quantifier = keep_within.quantifier.get() as u32;
+    match keep_within.unit {
+        KeepWithinUnit::Hour => format!("{quantifier}h"),
+        KeepWithinUnit::Day => format!("{quantifier}d"),
+        KeepWithinUnit::Week => format!("{}d", quantifier * 7),
+        KeepWithinUnit::Month => format!("{quantifier}m"),
+        KeepWithinUnit::Year => format!("{quantifier}y"),
+    }
+}
+
 pub(crate) struct ResticProvider;
 
 #[async_trait]
@@ -47,20 +488,18 @@ impl BackupProvider for ResticProvider {
         repo: Repository,
         progress_channel: CommandResponseSender,
         completion_semaphore: Arc<Semaphore>,
+        cancel: CancelToken,
     ) -> BorgResult<()> {
         let passphrase = repo
             .get_passphrase()?
             .ok_or_else(|| anyhow!("Restic requires a password to create a backup."))?;
 
-        let mut command = tokio::process::Command::new("restic");
+        let mut command = restic_command(&repo, &passphrase)?;
         command
             .arg("backup")
-            .arg("--repo")
-            .arg(repo.path())
             .arg("--json")
             .args(backup_paths)
-            .args(["--tag", "borgtui"])
-            .env("RESTIC_PASSWORD", passphrase.inner());
+            .args(["--tag", "borgtui"]);
 
         for pattern in exclude_patterns {
             command.arg("--exclude").arg(pattern);
@@ -70,105 +509,288 @@ impl BackupProvider for ResticProvider {
             command.arg("--exclude-caches");
         }
 
-        let mut child = command
-            .stdin(Stdio::null())
-            .stdout(Stdio::piped())
-            .stderr(Stdio::piped())
-            .spawn()?;
-
-        let stdout = child
-            .stdout
-            .take()
-            .ok_or_else(|| anyhow!("Failed to get stdout"))?;
-        let reader = BufReader::new(stdout);
-        let mut lines = reader.lines();
-
-        let repo_path = repo.path();
-        let progress_channel_clone = progress_channel.clone();
-
         tokio::spawn(async move {
-            let mut last_update = std::time::Instant::now();
-            while let Ok(Some(line)) = lines.next_line().await {
-                if last_update.elapsed() < LOGGING_THROTTLE_TIME {
-                    continue;
-                }
-                last_update = std::time::Instant::now();
-                if let Ok(progress) = serde_json::from_str::<ResticProgress>(&line) {
-                    if progress.message_type == "status" {
-                        let create_progress = BackupCreateProgress {
-                            repository: repo_path.clone(),
-                            create_progress: BackupCreationProgress::InProgress {
-                                original_size: progress.total_bytes.unwrap_or(0),
-                                compressed_size: progress.bytes_done.unwrap_or(0),
-                                deduplicated_size: progress.bytes_done.unwrap_or(0),
-                                num_files: progress.total_files.unwrap_or(0),
-                                current_path: progress.current_files.unwrap_or_default().join(", "),
-                            },
-                        };
-                        if let Err(e) = progress_channel_clone
-                            .send(CommandResponse::CreateProgress(create_progress))
-                            .await
-                        {
-                            tracing::error!("Failed to send progress: {}", e);
-                        }
+            let res = restic_backup(command, &repo, &progress_channel, &cancel).await;
+            completion_semaphore.add_permits(1);
+            match res {
+                Ok(()) => {
+                    let create_progress = BackupCreateProgress {
+                        repository: repo.path(),
+                        create_progress: BackupCreationProgress::Finished,
+                    };
+                    if let Err(e) = progress_channel
+                        .send(CommandResponse::CreateProgress(create_progress))
+                        .await
+                    {
+                        tracing::error!("Failed to send progress: {}", e);
                     }
                 }
+                Err(e) if is_cancelled(&e) => {
+                    send_create_cancelled!(progress_channel, repo.path(), false);
+                    send_info!(
+                        progress_channel,
+                        format!("Cancelled the backup to {}", repo)
+                    );
+                }
+                Err(e) => send_error!(
+                    progress_channel,
+                    format!("Restic backup to {} failed: {}", repo, e)
+                ),
             }
-            completion_semaphore.add_permits(1);
         });
 
         Ok(())
     }
 
     async fn list_archives(&self, repo: &Repository) -> BorgResult<RepositoryArchives> {
+        let snapshots = restic_snapshots(repo).await?;
+
+        let archives = snapshots
+            .into_iter()
+            .map(Archive::try_from)
+            .collect::<BorgResult<_>>()?;
+
+        Ok(RepositoryArchives {
+            path: repo.path(),
+            archives,
+        })
+    }
+
+    async fn add_archive_stats(
+        &self,
+        _repo: &Repository,
+        _archives: &mut RepositoryArchives,
+    ) -> BorgResult<()> {
+        // Snapshot summaries already hold the stats, see `list_archives`
+        Ok(())
+    }
+
+    async fn list_archive_directory(
+        &self,
+        repo: &Repository,
+        archive_spec: String,
+        directory: String,
+    ) -> BorgResult<Vec<ArchiveEntry>> {
         let passphrase = repo
             .get_passphrase()?
             .ok_or_else(|| anyhow!("Restic requires a password to list archives."))?;
+        let snapshot_id = resolve_snapshot_id(repo, &archive_spec).await?;
+        let directory = directory.trim_matches('/').to_string();
 
-        let mut command = tokio::process::Command::new("restic");
+        let output = restic_read_command(repo, &passphrase)?
+            .arg("ls")
+            .arg("--json")
+            .arg(&snapshot_id)
+            .arg(format!("/{directory}"))
+            .spawn()?
+            .wait_with_output()
+            .await?;
+        if !output.status.success() {
+            let stderr = String::from_utf8_lossy(&output.stderr);
+            return Err(anyhow!(
+                "Failed to list {} in {}: {}",
+                directory,
+                archive_spec,
+                stderr
+            ));
+        }
+
+        let mut entries = Vec::new();
+        for line in output.stdout.split(|b| *b == b'\n') {
+            if line.is_empty() {
+                continue;
+            }
+            let node: ResticLsNode = serde_json::from_slice(line)?;
+            if node.struct_type != "node" || !is_direct_child(&directory, &node.path) {
+                continue;
+            }
+            entries.push(ArchiveEntry {
+                path: node.path.trim_start_matches('/').to_string(),
+                is_dir: node.node_type == "dir",
+                size: node.size,
+                modified: node.mtime.and_then(|mtime| {
+                    chrono::DateTime::parse_from_rfc3339(&mtime)
+                        .ok()
+                        .map(|mtime| mtime.naive_local())
+                }),
+            });
+        }
+        Ok(entries)
+    }
+
+    async fn read_archive_file(
+        &self,
+        repo: &Repository,
+        archive_spec: String,
+        path: String,
+        writer: &mut (dyn AsyncWrite + Send + Unpin),
+    ) -> BorgResult<()> {
+        let passphrase = repo
+            .get_passphrase()?
+            .ok_or_else(|| anyhow!("Restic requires a password to read archives."))?;
+        let snapshot_id = resolve_snapshot_id(repo, &archive_spec).await?;
+        let mut command = restic_read_command(repo, &passphrase)?;
         command
-            .arg("snapshots")
-            .arg("--repo")
-            .arg(repo.path())
+            .arg("dump")
+            .arg(&snapshot_id)
+            .arg(format!("/{}", path.trim_matches('/')));
+        let context = format!("Failed to read {} from {}", path, archive_spec);
+        // Nothing cancels reads, they stop when the reader hangs up
+        copy_stdout(command, writer, context, &CancelToken::default()).await
+    }
+
+    async fn export_tar(
+        &self,
+        repo: &Repository,
+        archive_spec: String,
+        paths: &[PathBuf],
+        writer: &mut (dyn AsyncWrite + Send + Unpin),
+        cancel: CancelToken,
+    ) -> BorgResult<()> {
+        let passphrase = repo
+            .get_passphrase()?
+            .ok_or_else(|| anyhow!("Restic requires a password to export archives."))?;
+        let path = match paths {
+            [] => String::new(),
+            [path] => path.to_string_lossy().trim_matches('/').to_string(),
+            _ => anyhow::bail!("Restic can only export one path at a time"),
+        };
+        // Restic dumps the contents of files instead of a tar containing them
+        if !path.is_empty() {
+            let (parent, name) = path.rsplit_once('/').unwrap_or(("", &path));
+            let is_file = self
+                .list_archive_directory(repo, archive_spec.clone(), parent.to_string())
+                .await?
+                .iter()
+                .any(|entry| entry.name() == name && !entry.is_dir);
+            if is_file {
+                anyhow::bail!(
+                    "Restic can only export directories, use restore for {}",
+                    path
+                );
+            }
+        }
+        let snapshot_id = resolve_snapshot_id(repo, &archive_spec).await?;
+        let mut command = restic_read_command(repo, &passphrase)?;
+        command
+            .arg("dump")
+            .arg("--archive")
+            .arg("tar")
+            .arg(&snapshot_id)
+            .arg(format!("/{}", path));
+        let context = format!("Failed to export {}", archive_spec);
+        copy_stdout(command, writer, context, &cancel).await
+    }
+
+    async fn import_tar(
+        &self,
+        repo: &Repository,
+        archive_name: String,
+        tarball: PathBuf,
+        progress_channel: CommandResponseSender,
+        cancel: CancelToken,
+    ) -> BorgResult<()> {
+        let passphrase = repo
+            .get_passphrase()?
+            .ok_or_else(|| anyhow!("Restic requires a password to import a tarball."))?;
+        // Restic can't unpack tarballs or name snapshots, so the tarball is stored as a single
+        // file named after the archive if it's read from stdin
+        let mut command = restic_command(repo, &passphrase)?;
+        command
+            .stdin(tarball::tarball_stdin(&tarball)?)
+            .arg("backup")
             .arg("--json")
-            .env("RESTIC_PASSWORD", passphrase.inner())
-            .stdin(Stdio::null())
-            .stdout(Stdio::piped())
-            .stderr(Stdio::piped());
+            .arg("--stdin")
+            .arg("--stdin-filename")
+            .arg(tarball::stored_file_name(&tarball, &archive_name))
+            .args(["--tag", "borgtui"]);
+        restic_backup(command, repo, &progress_channel, &cancel).await
+    }
 
-        let output = command.spawn()?.wait_with_output().await?;
+    async fn diff(
+        &self,
+        repo: &Repository,
+        first_archive_spec: String,
+        second_archive_spec: String,
+    ) -> BorgResult<Vec<ArchiveDiffEntry>> {
+        let passphrase = repo
+            .get_passphrase()?
+            .ok_or_else(|| anyhow!("Restic requires a password to diff snapshots."))?;
+        let first_snapshot_id = resolve_snapshot_id(repo, &first_archive_spec).await?;
+        let second_snapshot_id = resolve_snapshot_id(repo, &second_archive_spec).await?;
 
+        let output = restic_read_command(repo, &passphrase)?
+            .arg("diff")
+            .arg("--json")
+            .arg("--metadata")
+            .arg(&first_snapshot_id)
+            .arg(&second_snapshot_id)
+            .spawn()?
+            .wait_with_output()
+            .await?;
         if !output.status.success() {
             let stderr = String::from_utf8_lossy(&output.stderr);
-            return Err(anyhow!("Failed to list restic snapshots: {}", stderr));
+            return Err(anyhow!(
+                "Failed to diff {} and {}: {}",
+                first_archive_spec,
+                second_archive_spec,
+                stderr
+            ));
         }
 
-        let snapshots: Vec<ResticSnapshot> = serde_json::from_slice(&output.stdout)?;
+        let mut entries = Vec::new();
+        for line in output.stdout.split(|b| *b == b'\n') {
+            if line.is_empty() {
+                continue;
+            }
+            let diff_line: ResticDiffLine = serde_json::from_slice(line)?;
+            if diff_line.message_type != "change" {
+                continue;
+            }
+            let change = match diff_line.modifier.as_str() {
+                "+" => ArchiveDiffChange::Added,
+                "-" => ArchiveDiffChange::Removed,
+                "U" => ArchiveDiffChange::MetadataChanged,
+                _ => ArchiveDiffChange::Modified,
+            };
+            entries.push(ArchiveDiffEntry {
+                path: diff_line.path.trim_matches('/').to_string(),
+                change,
+                bytes_added: None,
+                bytes_removed: None,
+            });
+        }
 
-        let archives = snapshots
-            .into_iter()
-            .map(|snapshot| {
-                let creation_date = chrono::DateTime::parse_from_rfc3339(&snapshot.time)
-                    .unwrap()
-                    .naive_utc();
-                Archive {
-                    name: snapshot.time,
-                    creation_date,
+        // Restic doesn't report per-file sizes in diffs, so look them up in each snapshot.
+        // Like rustic, a modified file's size is how much it grew or shrank.
+        if entries.is_empty() {
+            return Ok(entries);
+        }
+        let first_sizes = restic_file_sizes(repo, &passphrase, &first_snapshot_id).await?;
+        let second_sizes = restic_file_sizes(repo, &passphrase, &second_snapshot_id).await?;
+        for entry in &mut entries {
+            let first_size = first_sizes.get(&entry.path).copied();
+            let second_size = second_sizes.get(&entry.path).copied();
+            match entry.change {
+                ArchiveDiffChange::Added => entry.bytes_added = second_size,
+                ArchiveDiffChange::Removed => entry.bytes_removed = first_size,
+                ArchiveDiffChange::Modified => {
+                    if let (Some(first_size), Some(second_size)) = (first_size, second_size) {
+                        entry.bytes_added = Some(second_size.saturating_sub(first_size));
+                        entry.bytes_removed = Some(first_size.saturating_sub(second_size));
+                    }
                 }
-            })
-            .collect();
-
-        Ok(RepositoryArchives {
-            path: repo.path(),
-            archives,
-        })
+                ArchiveDiffChange::MetadataChanged => {}
+            }
+        }
+        Ok(entries)
     }
 
     async fn init_repo(
         &self,
         repo_loc: String,
         passphrase: Option<Passphrase>,
-        _config: RepositoryOptions,
+        config: RepositoryOptions,
     ) -> BorgResult<()> {
         let passphrase =
             passphrase.ok_or_else(|| anyhow!("Restic requires a password for initialization."))?;
@@ -178,6 +800,7 @@ impl BackupProvider for ResticProvider {
             .arg("init")
             .arg("--repo")
             .arg(repo_loc)
+            .args(restic_option_args(&config.restic_options()?))
             .env("RESTIC_PASSWORD", passphrase.inner())
             .stdin(Stdio::null())
             .stdout(Stdio::piped())
@@ -199,28 +822,41 @@ impl BackupProvider for ResticProvider {
     async fn mount(
         &self,
         repo: &Repository,
-        _given_repository_path: String,
+        given_repository_path: String,
         mountpoint: PathBuf,
     ) -> BorgResult<()> {
         let passphrase = repo
             .get_passphrase()?
             .ok_or_else(|| anyhow!("Restic requires a password to mount."))?;
-        let repo_path = repo.path();
 
-        tokio::task::spawn_blocking(move || {
-            let mut command = std::process::Command::new("restic");
-            command
-                .arg("mount")
-                .arg(&mountpoint)
-                .arg("--repo")
-                .arg(repo_path)
-                .env("RESTIC_PASSWORD", passphrase.inner());
-
-            let status = command.status()?;
-            if !status.success() {
-                return Err(anyhow!("Failed to mount restic repository"));
+        // Restic serves the mount until it's unmounted, even if BorgTUI exits first
+        let mut process = restic_read_command(repo, &passphrase)?
+            .arg("mount")
+            .arg(&mountpoint)
+            .stdout(Stdio::null())
+            .stderr(mounts::open_mount_log().await?)
+            .spawn()?;
+        mounts::register_mount(MountRecord::new(
+            repo,
+            &given_repository_path,
+            &mountpoint,
+            process.id(),
+        )?)
+        .await?;
+        tokio::spawn(async move {
+            match process.wait().await {
+                Ok(status) if !status.success() => tracing::error!(
+                    "Restic mount at {} failed with {}, see {}",
+                    mountpoint.to_string_lossy(),
+                    status,
+                    mounts::mount_log_path()
+                        .map(|path| path.to_string_lossy().to_string())
+                        .unwrap_or_default()
+                ),
+                Ok(_) => tracing::info!("Restic unmounted {}", mountpoint.to_string_lossy()),
+                Err(e) => tracing::error!("Failed to wait for restic mount: {}", e),
             }
-            Ok(())
+            mounts::forget_finished_mount(mountpoint).await;
         });
         Ok(())
     }
@@ -239,59 +875,179 @@ impl BackupProvider for ResticProvider {
     async fn prune(
         &self,
         repo: &Repository,
-        _prune_options: PruneOptions,
-        _progress_channel: CommandResponseSender,
+        prune_options: PruneOptions,
+        archive_filter: &ArchiveFilter,
+        progress_channel: CommandResponseSender,
+        cancel: CancelToken,
     ) -> BorgResult<()> {
+        take_repo_lock!(progress_channel, repo);
+        cancel.check()?;
         let passphrase = repo
             .get_passphrase()?
             .ok_or_else(|| anyhow!("Restic requires a password to prune."))?;
 
-        let mut command = tokio::process::Command::new("restic");
-        command
-            .arg("prune")
-            .arg("--repo")
-            .arg(repo.path())
-            .env("RESTIC_PASSWORD", passphrase.inner());
+        let groups = restic_forget(
+            repo,
+            &passphrase,
+            &prune_options,
+            archive_filter,
+            false,
+            &cancel,
+        )
+        .await?;
+        let num_forgotten: usize = groups
+            .iter()
+            .map(|group| group.remove.as_ref().map(Vec::len).unwrap_or(0))
+            .sum();
+        send_info!(
+            progress_channel,
+            format!(
+                "Removed {} restic snapshots in {}.",
+                num_forgotten,
+                repo.path()
+            )
+        );
 
-        let output = command.spawn()?.wait_with_output().await?;
+        if num_forgotten > 0 {
+            cancel.check()?;
+            send_info!(progress_channel, format!("Pruning {}...", repo.path()));
+            restic_prune(repo, &passphrase, None, &cancel).await?;
+        }
+        send_info!(
+            progress_channel,
+            format!("Successfully pruned {}", repo.path())
+        );
+        Ok(())
+    }
 
+    async fn prune_dry_run(
+        &self,
+        repo: &Repository,
+        prune_options: PruneOptions,
+        archive_filter: &ArchiveFilter,
+    ) -> BorgResult<Vec<PruneDecision>> {
+        let passphrase = repo
+            .get_passphrase()?
+            .ok_or_else(|| anyhow!("Restic requires a password to prune."))?;
+        // Nothing cancels previews, they only read
+        let groups = restic_forget(
+            repo,
+            &passphrase,
+            &prune_options,
+            archive_filter,
+            true,
+            &CancelToken::default(),
+        )
+        .await?;
+        let mut decisions = Vec::new();
+        for group in groups {
+            for snapshot in group.keep.unwrap_or_default() {
+                let reason = group
+                    .reasons
+                    .iter()
+                    .find(|reason| reason.snapshot.id == snapshot.id)
+                    .map(|reason| reason.matches.join(", "));
+                decisions.push(PruneDecision {
+                    archive: snapshot.time,
+                    keep: true,
+                    reason,
+                });
+            }
+            for snapshot in group.remove.unwrap_or_default() {
+                decisions.push(PruneDecision {
+                    archive: snapshot.time,
+                    keep: false,
+                    reason: None,
+                });
+            }
+        }
+        Ok(decisions)
+    }
+
+    async fn delete_archive(
+        &self,
+        repo: &Repository,
+        archive_spec: String,
+        progress_channel: CommandResponseSender,
+        cancel: CancelToken,
+    ) -> BorgResult<()> {
+        take_repo_lock!(progress_channel, repo);
+        cancel.check()?;
+        let passphrase = repo
+            .get_passphrase()?
+            .ok_or_else(|| anyhow!("Restic requires a password to delete snapshots."))?;
+        let snapshot_id = resolve_exact_snapshot_id(repo, &archive_spec).await?;
+        let output = restic_command(repo, &passphrase)?
+            .arg("forget")
+            .arg(&snapshot_id)
+            .spawn()?
+            .wait_with_output()
+            .await?;
         if !output.status.success() {
             let stderr = String::from_utf8_lossy(&output.stderr);
-            return Err(anyhow!("Failed to prune restic repository: {}", stderr));
+            return Err(anyhow!(
+                "Failed to forget {}: {}",
+                archive_spec,
+                stderr.trim()
+            ));
         }
+        // Forgetting only drops the snapshot, its data stays around until it's pruned. Packs
+        // which are only partly the snapshot's are repacked too, so none of it is left.
+        cancel.check()?;
+        send_info!(progress_channel, format!("Pruning {}...", repo.path()));
+        restic_prune(repo, &passphrase, Some("0"), &cancel).await
+    }
 
-        Ok(())
+    async fn rename_archive(
+        &self,
+        _repo: &Repository,
+        _archive_spec: String,
+        _new_name: String,
+        _progress_channel: CommandResponseSender,
+    ) -> BorgResult<()> {
+        anyhow::bail!("Restic snapshots are named after when they were taken and can't be renamed")
     }
 
     async fn compact(
         &self,
         repo: &Repository,
         progress_channel: CommandResponseSender,
+        cancel: CancelToken,
     ) -> BorgResult<()> {
-        self.prune(repo, PruneOptions::default(), progress_channel)
-            .await
+        take_repo_lock!(progress_channel, repo);
+        cancel.check()?;
+        let passphrase = repo
+            .get_passphrase()?
+            .ok_or_else(|| anyhow!("Restic requires a password to compact."))?;
+        restic_prune(repo, &passphrase, None, &cancel).await
     }
 
     async fn check(
         &self,
         repo: &Repository,
         progress_channel: CommandResponseSender,
+        cancel: CancelToken,
     ) -> BorgResult<bool> {
         let passphrase = repo
             .get_passphrase()?
             .ok_or_else(|| anyhow!("Restic requires a password to check."))?;
 
-        let mut command = tokio::process::Command::new("restic");
-        command
-            .arg("check")
-            .arg("--repo")
-            .arg(repo.path())
-            .stdin(Stdio::null())
-            .stdout(Stdio::piped())
-            .stderr(Stdio::piped())
-            .env("RESTIC_PASSWORD", passphrase.inner());
-
-        let output = command.spawn()?.wait_with_output().await?;
+        let process = restic_command(repo, &passphrase)?.arg("check").spawn()?;
+        let output = match output_or_cancel(process, &cancel).await {
+            Ok(output) => output,
+            Err(e) => {
+                if let Err(e) = progress_channel
+                    .send(CommandResponse::CheckComplete(CheckComplete::new(
+                        repo.path(),
+                        Some(e.to_string()),
+                    )))
+                    .await
+                {
+                    tracing::error!("Failed to send CheckComplete: {e}");
+                }
+                return Err(e);
+            }
+        };
 
         if !output.status.success() {
             let stderr = String::from_utf8_lossy(&output.stderr);
@@ -328,26 +1084,25 @@ impl BackupProvider for ResticProvider {
     async fn repair(
         &self,
         repo: &Repository,
+        dry_run: bool,
         progress_channel: CommandResponseSender,
+        cancel: CancelToken,
     ) -> BorgResult<bool> {
         let passphrase = repo
             .get_passphrase()?
             .ok_or_else(|| anyhow!("Restic requires a password to check."))?;
 
-        let commands = &[["repair", "index"], ["repair", "snapshots"]];
+        // `repair index` has no dry run, but `check` reports the index problems it would fix
+        let commands: &[&[&str]] = if dry_run {
+            &[&["check"], &["repair", "snapshots", "--dry-run"]]
+        } else {
+            &[&["repair", "index"], &["repair", "snapshots"]]
+        };
         let mut error_occured = false;
 
         for command in commands {
-            let mut cmd = tokio::process::Command::new("restic");
-            cmd.args(command)
-                .arg("--repo")
-                .arg(repo.path())
-                .stdin(Stdio::null())
-                .stdout(Stdio::piped())
-                .stderr(Stdio::piped())
-                .env("RESTIC_PASSWORD", passphrase.inner());
-
-            let output = cmd.spawn()?.wait_with_output().await?;
+            let process = restic_command(repo, &passphrase)?.args(*command).spawn()?;
+            let output = output_or_cancel(process, &cancel).await?;
             let mut error = None;
 
             if !output.status.success() {
@@ -376,4 +1131,149 @@ impl BackupProvider for ResticProvider {
 
         Ok(!error_occured)
     }
+
+    async fn restore(
+        &self,
+        repo: &Repository,
+        archive_spec: String,
+        include_paths: &[PathBuf],
+        target_directory: PathBuf,
+        strip_components: Option<u32>,
+        progress_channel: CommandResponseSender,
+    ) -> BorgResult<()> {
+        if strip_components.unwrap_or(0) > 0 {
+            anyhow::bail!("Restic does not support --strip-components when restoring");
+        }
+        let passphrase = repo
+            .get_passphrase()?
+            .ok_or_else(|| anyhow!("Restic requires a password to restore."))?;
+        let snapshot_id = resolve_snapshot_id(repo, &archive_spec).await?;
+
+        let mut command = restic_read_command(repo, &passphrase)?;
+        command
+            .arg("restore")
+            .arg(&snapshot_id)
+            .arg("--target")
+            .arg(&target_directory)
+            .arg("--json");
+        // Restic stores absolute paths
+        for include_path in include_paths {
+            command
+                .arg("--include")
+                .arg(PathBuf::from("/").join(include_path));
+        }
+        let mut child = command.spawn()?;
+
+        let stdout = child
+            .stdout
+            .take()
+            .ok_or_else(|| anyhow!("Failed to get stdout"))?;
+        let mut lines = BufReader::new(stdout).lines();
+        let repo_path = repo.path();
+        let progress_channel_clone = progress_channel.clone();
+        let progress_handle = tokio::spawn(async move {
+            let mut last_update: Option<std::time::Instant> = None;
+            while let Ok(Some(line)) = lines.next_line().await {
+                let Ok(progress) = serde_json::from_str::<ResticRestoreProgress>(&line) else {
+                    continue;
+                };
+                // Status updates are throttled, but the final summary always gets through
+                match progress.message_type {
+                    "summary" => {}
+                    "status"
+                        if last_update
+                            .is_some_and(|last| last.elapsed() < LOGGING_THROTTLE_TIME) =>
+                    {
+                        continue
+                    }
+                    "status" => last_update = Some(std::time::Instant::now()),
+                    _ => continue,
+                }
+                let msg = format!(
+                    "[{}] Restored {}/{} files, {} / {} ({:.0}%)",
+                    repo_path,
+                    progress.files_restored.unwrap_or(0),
+                    progress.total_files.unwrap_or(0),
+                    PrettyBytes(progress.bytes_restored.unwrap_or(0)),
+                    PrettyBytes(progress.total_bytes.unwrap_or(0)),
+                    progress.percent_done.unwrap_or(0.0) * 100.0
+                );
+                if let Err(e) = progress_channel_clone
+                    .send(CommandResponse::Info(msg))
+                    .await
+                {
+                    tracing::error!("Failed to send progress: {}", e);
+                }
+            }
+        });
+
+        let output = child.wait_with_output().await?;
+        progress_handle.await?;
+        if !output.status.success() {
+            let stderr = String::from_utf8_lossy(&output.stderr);
+            return Err(anyhow!("Failed to restore {}: {}", archive_spec, stderr));
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn deleted_snapshots_are_named_exactly() {
+        let snapshots: Vec<ResticSnapshot> = serde_json::from_str(
+            r#"[
+                {"time": "2024-05-06T07:08:09.123456789+02:00", "id": "aaaa1111", "hostname": "laptop"},
+                {"time": "2024-05-06T07:08:09.123456789+02:00", "id": "aaaa2222", "hostname": "desktop"},
+                {"time": "2024-05-07T07:08:09.123456789+02:00", "id": "bbbb3333", "hostname": "laptop"}
+            ]"#,
+        )
+        .unwrap();
+        let id = |archive: &str| exact_snapshot_id(&snapshots, archive).map_err(|e| e.to_string());
+
+        assert_eq!(
+            id("2024-05-07T07:08:09.123456789+02:00"),
+            Ok("bbbb3333".to_string())
+        );
+        assert_eq!(id("aaaa2222"), Ok("aaaa2222".to_string()));
+        // Unlike when reading, there's no guessing
+        for loose in ["latest", "bbbb", "a", "2024-05-07"] {
+            assert!(
+                id(loose).unwrap_err().starts_with("Could not find"),
+                "{}",
+                loose
+            );
+        }
+        let error = id("2024-05-06T07:08:09.123456789+02:00").unwrap_err();
+        assert!(error.contains("names 2 snapshots"), "{}", error);
+        assert!(error.contains("aaaa1111, aaaa2222"), "{}", error);
+    }
+
+    #[test]
+    fn read_snapshots_are_never_guessed() {
+        let snapshots: Vec<ResticSnapshot> = serde_json::from_str(
+            r#"[
+                {"time": "2024-05-06T07:08:09.123456789+02:00", "id": "aaaa1111", "hostname": "laptop"},
+                {"time": "2024-05-06T07:08:09.123456789+02:00", "id": "aaaa2222", "hostname": "desktop"},
+                {"time": "2024-05-07T07:08:09.123456789+02:00", "id": "bbbb3333", "hostname": "laptop"}
+            ]"#,
+        )
+        .unwrap();
+        let id = |archive: &str| snapshot_id(&snapshots, archive).map_err(|e| e.to_string());
+
+        assert_eq!(
+            id("2024-05-07T07:08:09.123456789+02:00"),
+            Ok("bbbb3333".to_string())
+        );
+        assert_eq!(id("aaaa2222"), Ok("aaaa2222".to_string()));
+        assert_eq!(id("bb"), Ok("bbbb3333".to_string()));
+        assert!(id("").unwrap_err().contains("empty archive name"));
+        assert!(id("cccc").unwrap_err().starts_with("Could not find"));
+        for ambiguous in ["aaaa", "2024-05-06T07:08:09.123456789+02:00"] {
+            let error = id(ambiguous).unwrap_err();
+            assert!(error.contains("names 2 snapshots"), "{}", error);
+        }
+    }
 }
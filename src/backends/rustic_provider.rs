@@ -1,5 +1,7 @@
 #![cfg(feature = "rustic")]
 use std::{
+    collections::BTreeMap,
+    num::NonZeroU16,
     path::PathBuf,
     sync::{
         atomic::{AtomicBool, AtomicU64},
@@ -7,26 +9,94 @@ use std::{
     },
 };
 
+use anyhow::Context;
 use async_trait::async_trait;
 use fuse_mt::FuseMT;
-use rustic_core::repofile::SnapshotFile;
+use rustic_core::repofile::{NodeType, SnapshotFile};
 use rustic_core::vfs::{FilePolicy, IdenticalSnapshot, Latest, Vfs};
+use tokio::io::{AsyncWrite, AsyncWriteExt};
 use tokio::sync::Semaphore;
+use tracing_subscriber::{filter::Targets, layer::SubscriberExt, Layer};
 
 use crate::{
-    backends::rustic_mount::FuseFS,
+    backends::{
+        rustic_mount::FuseFS,
+        rustic_tar::{TarMetadata, TarWriter},
+    },
     borgtui::CommandResponse,
-    profiles::{Passphrase, PruneOptions, Repository},
+    cancel::{is_cancelled, CancelToken},
+    mounts::{self, MountRecord},
+    profiles::{
+        ArchiveFilter, Passphrase, PruneOptions, Repository, RusticOptions, ARCHIVE_TIME_FORMAT,
+    },
+    tarball,
     types::{
-        send_check_complete, send_error, send_info, take_repo_lock, Archive, BackupCreateProgress,
-        BackupCreationProgress, BorgResult, CheckProgress, CommandResponseSender, PrettyBytes,
-        RepositoryArchives,
+        send_check_complete, send_check_progress, send_create_cancelled, send_error, send_info,
+        take_repo_lock, Archive, ArchiveDiffChange, ArchiveDiffEntry, ArchiveEntry,
+        BackupCreateProgress, BackupCreationProgress, BorgResult, CheckProgress,
+        CommandResponseSender, PrettyBytes, PruneDecision, RepositoryArchives,
     },
     RepositoryOptions,
 };
 
 use super::backup_provider::BackupProvider;
 
+/// How much of a file to read from the repository at a time when serving or exporting it
+const READ_CHUNK_SIZE: usize = 1 << 20;
+
+/// Hands what's written on a blocking thread over to the async side.
+struct ChunkWriter(tokio::sync::mpsc::Sender<Vec<u8>>);
+
+impl std::io::Write for ChunkWriter {
+    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+        self.0
+            .blocking_send(buf.to_vec())
+            // Nobody is reading anymore
+            .map_err(|_| std::io::Error::from(std::io::ErrorKind::BrokenPipe))?;
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        Ok(())
+    }
+}
+
+/// Collects what's written to it, see `with_rustic_logs`.
+#[derive(Clone, Default)]
+struct LogBuffer(Arc<std::sync::Mutex<Vec<u8>>>);
+
+impl std::io::Write for LogBuffer {
+    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+        self.0.lock().unwrap().extend_from_slice(buf);
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        Ok(())
+    }
+}
+
+/// Run `f`, returning the lines rustic logged on this thread instead of logging them.
+/// Rustic only reports some things, like what a repair dry run would change, in its logs.
+fn with_rustic_logs<T>(f: impl FnOnce() -> T) -> (T, Vec<String>) {
+    let logs = LogBuffer::default();
+    let writer = logs.clone();
+    let subscriber = tracing_subscriber::registry().with(
+        tracing_subscriber::fmt::layer()
+            .with_writer(move || writer.clone())
+            .with_ansi(false)
+            .without_time()
+            .with_target(false)
+            .with_filter(Targets::new().with_target("rustic_core", tracing::Level::INFO)),
+    );
+    let result = tracing::subscriber::with_default(subscriber, f);
+    let logs = String::from_utf8_lossy(&logs.0.lock().unwrap())
+        .lines()
+        .map(|line| line.trim().to_string())
+        .collect();
+    (result, logs)
+}
+
 const RESTIC_PASSPHRASE_REQUIRED: &str = "Restic Repositories require a password! Please check your configuration using `borgtui config-path`";
 
 fn passphrase_from_repo(repo: &Repository) -> BorgResult<Passphrase> {
@@ -34,6 +104,81 @@ fn passphrase_from_repo(repo: &Repository) -> BorgResult<Passphrase> {
         .ok_or_else(|| anyhow::anyhow!(RESTIC_PASSPHRASE_REQUIRED))
 }
 
+/// The one snapshot named `name`, by its label or ID (short, as listed, or full). Several snapshots can share a label
+/// (e.g. one per host), and guessing between them could act on the wrong one.
+fn find_snapshot<P: rustic_core::ProgressBars, S: rustic_core::Open>(
+    rustic_repo: &rustic_core::Repository<P, S>,
+    name: &str,
+) -> BorgResult<SnapshotFile> {
+    only_snapshot(
+        rustic_repo.get_matching_snapshots(|sn: &SnapshotFile| is_named(sn, name))?,
+        name,
+    )
+}
+
+fn is_named(snapshot: &SnapshotFile, name: &str) -> bool {
+    snapshot.label == name
+        || snapshot.id.to_string() == name
+        || snapshot.id.to_hex().as_str() == name
+}
+
+/// The one snapshot of `snapshots` named `name`, failing if there are none or several.
+fn only_snapshot(snapshots: Vec<SnapshotFile>, name: &str) -> BorgResult<SnapshotFile> {
+    let mut matches = snapshots
+        .into_iter()
+        .filter(|snapshot| is_named(snapshot, name))
+        .collect::<Vec<_>>();
+    match matches.len() {
+        0 => anyhow::bail!("Could not find snapshot {name}"),
+        1 => Ok(matches.remove(0)),
+        n => anyhow::bail!(
+            "{name} names {n} snapshots, use the ID of one ({})",
+            matches
+                .iter()
+                .map(|snapshot| snapshot.id.to_hex().to_string())
+                .collect::<Vec<_>>()
+                .join(", ")
+        ),
+    }
+}
+
+fn keep_options(prune_options: &PruneOptions) -> rustic_core::KeepOptions {
+    // Rustic uses 0 to disable a bucket
+    let keep_count = |keep: Option<NonZeroU16>| keep.map(|n| n.get() as i32).unwrap_or(0);
+    let mut keep_options = rustic_core::KeepOptions::default()
+        .keep_last(keep_count(prune_options.keep_last))
+        .keep_hourly(keep_count(prune_options.keep_hourly))
+        .keep_daily(keep_count(prune_options.keep_daily))
+        .keep_weekly(keep_count(prune_options.keep_weekly))
+        .keep_monthly(keep_count(prune_options.keep_monthly))
+        .keep_yearly(keep_count(prune_options.keep_yearly));
+    keep_options.keep_within = prune_options
+        .keep_within
+        .map(|keep_within| keep_within.as_duration().into());
+    keep_options
+}
+
+/// Whether `snapshot` is one of the archives `archive_filter` lets prune remove
+fn matches_archive_filter(snapshot: &SnapshotFile, archive_filter: &ArchiveFilter) -> bool {
+    let glob_matches = archive_filter.glob.as_ref().is_none_or(|glob| {
+        glob::Pattern::new(glob).is_ok_and(|glob| glob.matches(&snapshot.label))
+    });
+    let hostname_matches = archive_filter
+        .hostname
+        .as_ref()
+        .is_none_or(|hostname| snapshot.hostname == *hostname);
+    glob_matches && hostname_matches
+}
+
+/// Apply the retention policy to all snapshots at once (like borg) instead of per host and paths
+fn forget_group_criterion() -> rustic_core::SnapshotGroupCriterion {
+    rustic_core::SnapshotGroupCriterion::default()
+        .hostname(false)
+        .label(false)
+        .paths(false)
+        .tags(false)
+}
+
 #[derive(Debug, Clone)]
 enum ProgressEmitterKind {
     Backup,
@@ -246,6 +391,62 @@ impl rustic_core::ProgressBars for ProgressEmitter {
     }
 }
 
+/// The backends for `repo_loc`, passing along the repository's backend options.
+fn rustic_backends(
+    repo_loc: &str,
+    options: &RusticOptions,
+) -> BorgResult<rustic_core::RepositoryBackends> {
+    let mut backend_opts = rustic_backend::BackendOptions::default().repository(repo_loc);
+    backend_opts.options = options.backend_options.clone();
+    Ok(backend_opts.to_backends()?)
+}
+
+fn rustic_repository_options(
+    options: &RusticOptions,
+    passphrase: Option<&Passphrase>,
+) -> rustic_core::RepositoryOptions {
+    let mut repo_opts = rustic_core::RepositoryOptions::default();
+    if let Some(passphrase) = passphrase {
+        repo_opts = repo_opts.password(passphrase.inner());
+    }
+    if options.no_cache {
+        repo_opts.no_cache = true;
+    } else if let Some(cache_dir) = options.cache_dir.clone().or_else(rustic_cache_dir) {
+        repo_opts = repo_opts.cache_dir(cache_dir);
+    }
+    repo_opts
+}
+
+/// The repository config changes the options ask for, if any.
+fn rustic_config_options(options: &RusticOptions) -> Option<rustic_core::ConfigOptions> {
+    if options.compression.is_none() && options.pack_size_mib.is_none() {
+        return None;
+    }
+    let mut config_opts = rustic_core::ConfigOptions::default();
+    config_opts.set_compression = options.compression;
+    config_opts.set_datapack_size = options
+        .pack_size_mib
+        .map(|size| bytesize::ByteSize::mib(size.get().into()));
+    Some(config_opts)
+}
+
+/// Apply the options' config changes, writing the repository config only if they change it.
+fn apply_rustic_config(
+    rustic_repo: &rustic_core::Repository<ProgressEmitter, rustic_core::OpenStatus>,
+    options: &RusticOptions,
+) -> BorgResult<()> {
+    let Some(config_opts) = rustic_config_options(options) else {
+        return Ok(());
+    };
+    let mut config = rustic_repo.config().clone();
+    config_opts.apply(&mut config)?;
+    if &config != rustic_repo.config() {
+        tracing::info!("Updating the repository config");
+        rustic_repo.apply_config(&config_opts)?;
+    }
+    Ok(())
+}
+
 fn rustic_cache_dir() -> Option<PathBuf> {
     dirs::cache_dir().map(|mut p| {
         p.push("borgtui");
@@ -267,6 +468,7 @@ impl BackupProvider for RusticProvider {
         repo: Repository,
         progress_channel: CommandResponseSender,
         completion_semaphore: Arc<Semaphore>,
+        cancel: CancelToken,
     ) -> BorgResult<()> {
         let backup_paths: Vec<_> = backup_paths
             .iter()
@@ -290,26 +492,23 @@ impl BackupProvider for RusticProvider {
             format!("Starting rustic backup of {fully_qualified_name}")
         );
         let pb = ProgressEmitter::create_backup(progress_channel.clone(), repo.path());
+        let repo_loc = repo.path();
+        // Rustic can't be interrupted mid-backup, so cancelling stops it between steps
+        let cancel_clone = cancel.clone();
         let handle = tokio::task::spawn_blocking(move || -> BorgResult<()> {
+            let cancel = cancel_clone;
             // Backend
-            let repo_loc = repo.path();
-            let backends = rustic_backend::BackendOptions::default()
-                .repository(repo_loc)
-                .to_backends()?;
-
-            // Passphrase
+            let options = repo.rustic_options()?;
+            let backends = rustic_backends(&repo.path(), &options)?;
             let passphrase = passphrase_from_repo(&repo)?;
             // Actually open the connection
-            let mut repo_opts =
-                rustic_core::RepositoryOptions::default().password(passphrase.inner());
-            if let Some(cache_dir) = rustic_cache_dir() {
-                repo_opts = repo_opts.cache_dir(cache_dir);
-                repo_opts.no_cache = false;
-            }
+            let repo_opts = rustic_repository_options(&options, Some(&passphrase));
             let rustic_repo =
                 rustic_core::Repository::new_with_progress(&repo_opts, &backends, pb.clone())?
-                    .open()?
-                    .to_indexed_ids()?;
+                    .open()?;
+            apply_rustic_config(&rustic_repo, &options)?;
+            let rustic_repo = rustic_repo.to_indexed_ids()?;
+            cancel.check()?;
             let backup_opts = rustic_core::BackupOptions::default().ignore_filter_opts(filter_opts);
             let sources = rustic_core::PathList::from_iter(backup_paths);
             let mut snap = rustic_core::SnapshotOptions::default()
@@ -330,6 +529,13 @@ impl BackupProvider for RusticProvider {
                         format!("Completed rustic backup for {}", fully_qualified_name)
                     );
                 }
+                Ok(Err(e)) if is_cancelled(&e) => {
+                    send_create_cancelled!(progress_channel, repo_loc, false);
+                    send_info!(
+                        progress_channel,
+                        format!("Cancelled rustic backup for {}", fully_qualified_name)
+                    );
+                }
                 Ok(Err(e)) => send_error!(progress_channel, format!("Rustic backup failed: {e}")),
                 Err(e) => send_error!(
                     progress_channel,
@@ -343,23 +549,33 @@ impl BackupProvider for RusticProvider {
     async fn list_archives(&self, repo: &Repository) -> BorgResult<RepositoryArchives> {
         let repo_loc = repo.path();
         let passphrase = repo.get_passphrase()?;
-        let backends = rustic_backend::BackendOptions::default()
-            .repository(&repo_loc)
-            .to_backends()?;
-
-        let mut repo_opts = rustic_core::RepositoryOptions::default();
-        if let Some(passphrase) = passphrase {
-            repo_opts = repo_opts.password(passphrase.inner())
-        }
+        let options = repo.rustic_options()?;
+        let backends = rustic_backends(&repo_loc, &options)?;
+        let repo_opts = rustic_repository_options(&options, passphrase.as_ref());
         let res = tokio::task::spawn_blocking(move || -> BorgResult<RepositoryArchives> {
             let snapshots = rustic_core::Repository::new(&repo_opts, &backends)?
                 .open()?
                 .get_all_snapshots()?;
             let mut archives: Vec<Archive> = snapshots
                 .iter()
-                .map(|snapshot| Archive {
-                    name: snapshot.label.clone(),
-                    creation_date: snapshot.time.date_naive().into(),
+                .map(|snapshot| {
+                    let summary = snapshot.summary.as_ref();
+                    Archive {
+                        name: snapshot.label.clone(),
+                        creation_date: snapshot.time.naive_local(),
+                        id: Some(snapshot.id.to_string()),
+                        hostname: Some(snapshot.hostname.clone()),
+                        username: Some(snapshot.username.clone()),
+                        tags: snapshot.tags.iter().cloned().collect(),
+                        original_size: summary.map(|s| s.total_bytes_processed),
+                        // Like restic, rustic only knows the packed size of the added data
+                        compressed_size: None,
+                        deduplicated_size: summary.map(|s| s.data_added),
+                        num_files: summary.map(|s| s.total_files_processed),
+                        duration: summary.and_then(|s| {
+                            std::time::Duration::try_from_secs_f64(s.backup_duration).ok()
+                        }),
+                    }
                 })
                 .collect();
             // Sort so the most recent archive is the last (borg behaviour)
@@ -375,11 +591,378 @@ impl BackupProvider for RusticProvider {
         Ok(res)
     }
 
+    async fn add_archive_stats(
+        &self,
+        _repo: &Repository,
+        _archives: &mut RepositoryArchives,
+    ) -> BorgResult<()> {
+        // Snapshot summaries already hold the stats, see `list_archives`
+        Ok(())
+    }
+
+    async fn list_archive_directory(
+        &self,
+        repo: &Repository,
+        archive_spec: String,
+        directory: String,
+    ) -> BorgResult<Vec<ArchiveEntry>> {
+        let (_repo_path, snapshot_label) = archive_spec.split_once("::").ok_or_else(|| {
+            anyhow::anyhow!("Expected an archive (repo::archive), got {archive_spec}")
+        })?;
+        let snapshot_label = snapshot_label.to_string();
+        let directory = directory.trim_matches('/').to_string();
+        let options = repo.rustic_options()?;
+        let backends = rustic_backends(&repo.path(), &options)?;
+        let passphrase = passphrase_from_repo(repo)?;
+        tokio::task::spawn_blocking(move || -> BorgResult<Vec<ArchiveEntry>> {
+            let repo_opts = rustic_repository_options(&options, Some(&passphrase));
+            let rustic_repo = rustic_core::Repository::new(&repo_opts, &backends)?
+                .open()?
+                .to_indexed()?;
+            let snapshot = find_snapshot(&rustic_repo, &snapshot_label)?;
+            let root = rustic_repo.node_from_snapshot_path(&snapshot.id.to_string(), |_| true)?;
+            let vfs = Vfs::from_dir_node(&root);
+            let nodes =
+                vfs.dir_entries_from_path(&rustic_repo, &PathBuf::from("/").join(&directory))?;
+            Ok(nodes
+                .into_iter()
+                .map(|node| {
+                    let name = node.name().to_string_lossy().to_string();
+                    let path = if directory.is_empty() {
+                        name
+                    } else {
+                        format!("{directory}/{name}")
+                    };
+                    ArchiveEntry {
+                        path,
+                        is_dir: node.is_dir(),
+                        size: node.meta.size,
+                        modified: node.meta.mtime.map(|mtime| mtime.naive_local()),
+                    }
+                })
+                .collect())
+        })
+        .await?
+    }
+
+    async fn read_archive_file(
+        &self,
+        repo: &Repository,
+        archive_spec: String,
+        path: String,
+        writer: &mut (dyn AsyncWrite + Send + Unpin),
+    ) -> BorgResult<()> {
+        let (_repo_path, snapshot_label) = archive_spec.split_once("::").ok_or_else(|| {
+            anyhow::anyhow!("Expected an archive (repo::archive), got {archive_spec}")
+        })?;
+        let snapshot_label = snapshot_label.to_string();
+        let path = path.trim_matches('/').to_string();
+        let options = repo.rustic_options()?;
+        let backends = rustic_backends(&repo.path(), &options)?;
+        let passphrase = passphrase_from_repo(repo)?;
+        // Read on a blocking thread, handing over each chunk as soon as it's read
+        let (chunk_send, mut chunk_recv) = tokio::sync::mpsc::channel::<Vec<u8>>(4);
+        let reader = tokio::task::spawn_blocking(move || -> BorgResult<()> {
+            let repo_opts = rustic_repository_options(&options, Some(&passphrase));
+            let rustic_repo = rustic_core::Repository::new(&repo_opts, &backends)?
+                .open()?
+                .to_indexed()?;
+            let snapshot = find_snapshot(&rustic_repo, &snapshot_label)?;
+            let root = rustic_repo.node_from_snapshot_path(&snapshot.id.to_string(), |_| true)?;
+            let vfs = Vfs::from_dir_node(&root);
+            let node = vfs.node_from_path(&rustic_repo, &PathBuf::from("/").join(&path))?;
+            let open_file = rustic_repo.open_file(&node)?;
+            let mut offset = 0;
+            while offset < node.meta.size as usize {
+                let chunk = rustic_repo.read_file_at(&open_file, offset, READ_CHUNK_SIZE)?;
+                if chunk.is_empty() {
+                    break;
+                }
+                offset += chunk.len();
+                if chunk_send.blocking_send(chunk.to_vec()).is_err() {
+                    // Nobody is reading anymore
+                    break;
+                }
+            }
+            Ok(())
+        });
+        while let Some(chunk) = chunk_recv.recv().await {
+            writer.write_all(&chunk).await?;
+        }
+        reader.await?
+    }
+
+    async fn export_tar(
+        &self,
+        repo: &Repository,
+        archive_spec: String,
+        paths: &[PathBuf],
+        writer: &mut (dyn AsyncWrite + Send + Unpin),
+        cancel: CancelToken,
+    ) -> BorgResult<()> {
+        let (_repo_path, snapshot_label) = archive_spec.split_once("::").ok_or_else(|| {
+            anyhow::anyhow!("Expected an archive (repo::archive), got {archive_spec}")
+        })?;
+        let snapshot_label = snapshot_label.to_string();
+        let options = repo.rustic_options()?;
+        let backends = rustic_backends(&repo.path(), &options)?;
+        let passphrase = passphrase_from_repo(repo)?;
+        // Rustic stores paths relative to the snapshot root
+        let include_paths: Vec<PathBuf> = paths
+            .iter()
+            .map(|path| path.strip_prefix("/").unwrap_or(path).to_path_buf())
+            .collect();
+        // Build the tar on a blocking thread, handing over each chunk as soon as it's written
+        let (chunk_send, mut chunk_recv) = tokio::sync::mpsc::channel::<Vec<u8>>(4);
+        let exporter = tokio::task::spawn_blocking(move || -> BorgResult<()> {
+            let repo_opts = rustic_repository_options(&options, Some(&passphrase));
+            let rustic_repo = rustic_core::Repository::new(&repo_opts, &backends)?
+                .open()?
+                .to_indexed()?;
+            let snapshot = find_snapshot(&rustic_repo, &snapshot_label)?;
+            let root = rustic_repo.node_from_snapshot_path(&snapshot.id.to_string(), |_| true)?;
+            let mut tar = TarWriter::new(std::io::BufWriter::with_capacity(
+                READ_CHUNK_SIZE,
+                ChunkWriter(chunk_send),
+            ));
+            for entry in rustic_repo.ls(&root, &rustic_core::LsOptions::default())? {
+                cancel.check()?;
+                let (path, node) = entry?;
+                if !include_paths.is_empty()
+                    && !include_paths
+                        .iter()
+                        .any(|include| path.starts_with(include))
+                {
+                    continue;
+                }
+                let metadata = TarMetadata {
+                    mode: node
+                        .meta
+                        .mode
+                        .unwrap_or(if node.is_dir() { 0o755 } else { 0o644 }),
+                    uid: node.meta.uid.unwrap_or(0),
+                    gid: node.meta.gid.unwrap_or(0),
+                    user: node.meta.user.clone().unwrap_or_default(),
+                    group: node.meta.group.clone().unwrap_or_default(),
+                    mtime: node.meta.mtime.map_or(0, |mtime| mtime.timestamp()),
+                };
+                match &node.node_type {
+                    NodeType::Dir => tar.append_directory(&path, &metadata)?,
+                    NodeType::Symlink { .. } => {
+                        tar.append_symlink(&path, node.node_type.to_link(), &metadata)?
+                    }
+                    NodeType::File => {
+                        let size = node.meta.size as usize;
+                        tar.start_file(&path, node.meta.size, &metadata)?;
+                        let open_file = rustic_repo.open_file(&node)?;
+                        let mut offset = 0;
+                        while offset < size {
+                            let length = READ_CHUNK_SIZE.min(size - offset);
+                            let chunk = rustic_repo.read_file_at(&open_file, offset, length)?;
+                            if chunk.is_empty() {
+                                anyhow::bail!(
+                                    "{} is shorter than its recorded size",
+                                    path.to_string_lossy()
+                                );
+                            }
+                            tar.write_file_data(&chunk)?;
+                            offset += chunk.len();
+                        }
+                        tar.finish_file(node.meta.size)?;
+                    }
+                    _ => tracing::warn!(
+                        "Skipping {}, only files, directories and symlinks are exported",
+                        path.to_string_lossy()
+                    ),
+                }
+            }
+            tar.finish()?;
+            Ok(())
+        });
+        while let Some(chunk) = chunk_recv.recv().await {
+            writer.write_all(&chunk).await?;
+        }
+        exporter.await?
+    }
+
+    async fn import_tar(
+        &self,
+        repo: &Repository,
+        archive_name: String,
+        tarball: PathBuf,
+        progress_channel: CommandResponseSender,
+        cancel: CancelToken,
+    ) -> BorgResult<()> {
+        let from_stdin = tarball::is_stdio(&tarball);
+        let source = if from_stdin {
+            tarball::check_piped_stdin()?;
+            "-".to_string()
+        } else {
+            std::path::absolute(&tarball)?.to_string_lossy().to_string()
+        };
+        let stored_file_name = tarball::stored_file_name(&tarball, &archive_name);
+        take_repo_lock!(
+            progress_channel,
+            repo,
+            "A backup is already in progress for {}, waiting..."
+        );
+        cancel.check()?;
+        let pb = ProgressEmitter::create_backup(progress_channel.clone(), repo.path());
+        let repo = repo.clone();
+        tokio::task::spawn_blocking(move || -> BorgResult<()> {
+            let options = repo.rustic_options()?;
+            let backends = rustic_backends(&repo.path(), &options)?;
+            let passphrase = passphrase_from_repo(&repo)?;
+            let repo_opts = rustic_repository_options(&options, Some(&passphrase));
+            let rustic_repo =
+                rustic_core::Repository::new_with_progress(&repo_opts, &backends, pb.clone())?
+                    .open()?;
+            apply_rustic_config(&rustic_repo, &options)?;
+            let rustic_repo = rustic_repo.to_indexed_ids()?;
+            cancel.check()?;
+            // Rustic can't unpack tarballs, so the tarball is stored as a single file
+            let mut backup_opts = rustic_core::BackupOptions::default();
+            if from_stdin {
+                backup_opts.stdin_filename = stored_file_name;
+            } else {
+                backup_opts.as_path = Some(PathBuf::from("/").join(stored_file_name));
+            }
+            let sources = rustic_core::PathList::from_iter([source]);
+            let mut snap = rustic_core::SnapshotOptions::default()
+                .add_tags("borgtui")?
+                .to_snapshot()?;
+            snap.label = archive_name;
+            let snap = rustic_repo.backup(&backup_opts, &sources, snap)?;
+            tracing::info!("Snapshot taken! {}", snap.label);
+            pb.maybe_send_backup_create_finished();
+            Ok(())
+        })
+        .await?
+    }
+
+    async fn diff(
+        &self,
+        repo: &Repository,
+        first_archive_spec: String,
+        second_archive_spec: String,
+    ) -> BorgResult<Vec<ArchiveDiffEntry>> {
+        let mut snapshot_labels = Vec::new();
+        for archive_spec in [&first_archive_spec, &second_archive_spec] {
+            let (_repo_path, snapshot_label) = archive_spec.split_once("::").ok_or_else(|| {
+                anyhow::anyhow!("Expected an archive (repo::archive), got {archive_spec}")
+            })?;
+            snapshot_labels.push(snapshot_label.to_string());
+        }
+        let options = repo.rustic_options()?;
+        let backends = rustic_backends(&repo.path(), &options)?;
+        let passphrase = passphrase_from_repo(repo)?;
+        tokio::task::spawn_blocking(move || -> BorgResult<Vec<ArchiveDiffEntry>> {
+            let repo_opts = rustic_repository_options(&options, Some(&passphrase));
+            let rustic_repo = rustic_core::Repository::new(&repo_opts, &backends)?
+                .open()?
+                .to_indexed()?;
+            let mut trees = Vec::new();
+            for snapshot_label in &snapshot_labels {
+                let snapshot = find_snapshot(&rustic_repo, snapshot_label)?;
+                let root =
+                    rustic_repo.node_from_snapshot_path(&snapshot.id.to_string(), |_| true)?;
+                let tree = rustic_repo
+                    .ls(&root, &rustic_core::LsOptions::default())?
+                    .collect::<Result<BTreeMap<_, _>, _>>()?;
+                trees.push(tree);
+            }
+            let (first_tree, second_tree) = (&trees[0], &trees[1]);
+
+            let mut entries = Vec::new();
+            for (path, first_node) in first_tree {
+                let path_str = path.to_string_lossy().to_string();
+                let Some(second_node) = second_tree.get(path) else {
+                    entries.push(ArchiveDiffEntry {
+                        path: path_str,
+                        change: ArchiveDiffChange::Removed,
+                        bytes_added: None,
+                        bytes_removed: Some(first_node.meta.size),
+                    });
+                    continue;
+                };
+                let content_changed = first_node.node_type != second_node.node_type
+                    || (!first_node.is_dir() && first_node.content != second_node.content);
+                let (first_meta, second_meta) = (&first_node.meta, &second_node.meta);
+                let metadata_changed = first_meta.mode != second_meta.mode
+                    || first_meta.mtime != second_meta.mtime
+                    || first_meta.uid != second_meta.uid
+                    || first_meta.gid != second_meta.gid;
+                if content_changed {
+                    // Rustic doesn't track changed chunks here, so report the change in size.
+                    entries.push(ArchiveDiffEntry {
+                        path: path_str,
+                        change: ArchiveDiffChange::Modified,
+                        bytes_added: Some(second_meta.size.saturating_sub(first_meta.size)),
+                        bytes_removed: Some(first_meta.size.saturating_sub(second_meta.size)),
+                    });
+                } else if metadata_changed {
+                    entries.push(ArchiveDiffEntry {
+                        path: path_str,
+                        change: ArchiveDiffChange::MetadataChanged,
+                        bytes_added: None,
+                        bytes_removed: None,
+                    });
+                }
+            }
+            for (path, second_node) in second_tree {
+                if !first_tree.contains_key(path) {
+                    entries.push(ArchiveDiffEntry {
+                        path: path.to_string_lossy().to_string(),
+                        change: ArchiveDiffChange::Added,
+                        bytes_added: Some(second_node.meta.size),
+                        bytes_removed: None,
+                    });
+                }
+            }
+            Ok(entries)
+        })
+        .await?
+    }
+
+    async fn prune_dry_run(
+        &self,
+        repo: &Repository,
+        prune_options: PruneOptions,
+        archive_filter: &ArchiveFilter,
+    ) -> BorgResult<Vec<PruneDecision>> {
+        let archive_filter = archive_filter.clone();
+        let options = repo.rustic_options()?;
+        let backends = rustic_backends(&repo.path(), &options)?;
+        let passphrase = passphrase_from_repo(repo)?;
+        tokio::task::spawn_blocking(move || -> BorgResult<Vec<PruneDecision>> {
+            let repo_opts = rustic_repository_options(&options, Some(&passphrase));
+            let rustic_repo = rustic_core::Repository::new(&repo_opts, &backends)?.open()?;
+            let groups = rustic_repo.get_forget_snapshots(
+                &keep_options(&prune_options),
+                forget_group_criterion(),
+                |snapshot| matches_archive_filter(snapshot, &archive_filter),
+            )?;
+            let decisions = groups
+                .0
+                .into_iter()
+                .flat_map(|group| group.snapshots)
+                .map(|forget_snapshot| PruneDecision {
+                    archive: forget_snapshot.snapshot.label,
+                    keep: forget_snapshot.keep,
+                    reason: (!forget_snapshot.reasons.is_empty())
+                        .then(|| forget_snapshot.reasons.join(", ")),
+                })
+                .collect();
+            Ok(decisions)
+        })
+        .await?
+    }
+
     async fn init_repo(
         &self,
         repo_loc: String,
         passphrase: Option<Passphrase>,
-        _config: RepositoryOptions,
+        config: RepositoryOptions,
     ) -> BorgResult<()> {
         let passphrase = match passphrase {
             Some(passphrase) => passphrase,
@@ -387,13 +970,12 @@ impl BackupProvider for RusticProvider {
                 "Restic repositories require a password. Please provide one. See `borgtui init -h`."
             ),
         };
-        let backends = rustic_backend::BackendOptions::default()
-            .repository(&repo_loc)
-            .to_backends()?;
+        let options = config.rustic_options()?;
+        let backends = rustic_backends(&repo_loc, &options)?;
 
-        let repo_opts = rustic_core::RepositoryOptions::default().password(passphrase.inner());
+        let repo_opts = rustic_repository_options(&options, Some(&passphrase));
         let key_opts = rustic_core::KeyOptions::default();
-        let config_opts = rustic_core::ConfigOptions::default();
+        let config_opts = rustic_config_options(&options).unwrap_or_default();
         tracing::info!("Initializing rustic repo: {repo_loc}");
         rustic_core::Repository::new(&repo_opts, &backends)?.init(&key_opts, &config_opts)?;
         tracing::info!("Successfully initialized rustic repo: {repo_loc}");
@@ -421,18 +1003,25 @@ impl BackupProvider for RusticProvider {
 
         let passphrase = passphrase_from_repo(repo)?;
         let repo_loc = repo.path();
-        let backends = rustic_backend::BackendOptions::default()
-            .repository(&repo_loc)
-            .to_backends()?;
+        let options = repo.rustic_options()?;
+        let backends = rustic_backends(&repo_loc, &options)?;
 
-        let repo_opts = rustic_core::RepositoryOptions::default().password(passphrase.inner());
+        let repo_opts = rustic_repository_options(&options, Some(&passphrase));
 
-        // TODO: do something with this join handle
-        let _join_handle = tokio::task::spawn_blocking(move || -> BorgResult<()> {
+        // The filesystem is served from this process, so it goes away when BorgTUI exits
+        mounts::register_mount(MountRecord::new(
+            repo,
+            &given_repository_path,
+            &mountpoint,
+            Some(std::process::id()),
+        )?)
+        .await?;
+        let mountpoint_clone = mountpoint.clone();
+        let join_handle = tokio::task::spawn_blocking(move || -> BorgResult<()> {
             let rustic_repo = rustic_core::Repository::new(&repo_opts, &backends)?.open()?;
 
             let (_repo_path, snapshot_label) =
-                given_repository_path.split_once("::").unwrap_or_default();
+                given_repository_path.rsplit_once("::").unwrap_or_default();
 
             let sn_filter = |sn: &SnapshotFile| {
                 if snapshot_label.is_empty() {
@@ -444,8 +1033,7 @@ impl BackupProvider for RusticProvider {
             let vfs = Vfs::from_snapshots(
                 rustic_repo.get_matching_snapshots(sn_filter)?,
                 "[{hostname}]/[{label}]",
-                // TODO: Make this a borgtui constant (we use it in the archive name)
-                "%Y-%m-%d:%H:%M:%S",
+                ARCHIVE_TIME_FORMAT,
                 Latest::AsLink,
                 IdenticalSnapshot::AsLink,
             )?;
@@ -462,17 +1050,26 @@ impl BackupProvider for RusticProvider {
             fuse_mt::mount(fuse_mt, &mountpoint, &[])?;
             Ok(())
         });
+        tokio::spawn(async move {
+            match join_handle.await {
+                Ok(Ok(())) => tracing::info!("Rustic unmounted {mountpoint_clone:?}"),
+                Ok(Err(e)) => tracing::error!("Rustic mount at {mountpoint_clone:?} failed: {e}"),
+                Err(e) => tracing::error!("Rustic mount thread failed: {e}"),
+            }
+            mounts::forget_finished_mount(mountpoint_clone).await;
+        });
         Ok(())
     }
 
-    // TODO: Wire unmounting in with repositories
     async fn unmount(&self, mountpoint: PathBuf) -> BorgResult<()> {
         let exit = tokio::process::Command::new("umount")
-            .arg(mountpoint)
+            .arg(&mountpoint)
             .spawn()?
             .wait()
             .await?;
-        tracing::info!("umount finished with exitcode {exit:?}");
+        if !exit.success() {
+            anyhow::bail!("umount {mountpoint:?} failed with {exit}");
+        }
         Ok(())
     }
 
@@ -480,37 +1077,34 @@ impl BackupProvider for RusticProvider {
         &self,
         repo: &Repository,
         prune_options: PruneOptions,
+        archive_filter: &ArchiveFilter,
         progress_channel: CommandResponseSender,
+        cancel: CancelToken,
     ) -> BorgResult<()> {
         take_repo_lock!(progress_channel, repo);
+        cancel.check()?;
 
         let repo_loc = repo.path();
+        let archive_filter = archive_filter.clone();
 
-        let backends = rustic_backend::BackendOptions::default()
-            .repository(&repo_loc)
-            .to_backends()?;
+        let options = repo.rustic_options()?;
+        let backends = rustic_backends(&repo_loc, &options)?;
         let passphrase = passphrase_from_repo(repo)?;
 
         let pb = ProgressEmitter::info(progress_channel.clone(), repo_loc.clone());
-        let handle = tokio::task::spawn_blocking(move || {
+        let repo_loc_clone = repo_loc.clone();
+        // Awaited while the repo lock is held, so nothing else touches the repo until it's done
+        tokio::task::spawn_blocking(move || {
+            let repo_loc = repo_loc_clone;
             // Actually open the connection
-            let repo_opts = rustic_core::RepositoryOptions::default().password(passphrase.inner());
+            let repo_opts = rustic_repository_options(&options, Some(&passphrase));
             let rustic_repo =
                 rustic_core::Repository::new_with_progress(&repo_opts, &backends, pb)?.open()?;
-            let keep_options = rustic_core::KeepOptions::default()
-                .keep_daily(prune_options.keep_daily.get() as i32)
-                .keep_weekly(prune_options.keep_weekly.get() as i32)
-                .keep_monthly(prune_options.keep_monthly.get() as i32)
-                .keep_yearly(prune_options.keep_yearly.get() as i32);
             let forget_ids = rustic_repo
                 .get_forget_snapshots(
-                    &keep_options,
-                    rustic_core::SnapshotGroupCriterion::default()
-                        .hostname(false)
-                        .label(false)
-                        .paths(false)
-                        .tags(false),
-                    |_| true,
+                    &keep_options(&prune_options),
+                    forget_group_criterion(),
+                    |snapshot| matches_archive_filter(snapshot, &archive_filter),
                 )?
                 .into_forget_ids();
             // TODO: use send_info_blocking (and write that macro)
@@ -519,31 +1113,100 @@ impl BackupProvider for RusticProvider {
                 forget_ids.len(),
                 repo_loc,
             );
+            // Cancelling fails the prune with `Cancelled` (kept through the context below), but
+            // rustic can only be stopped between steps
+            cancel.check()?;
             rustic_repo.delete_snapshots(&forget_ids)?;
+            // Stop before the slow part, the forgotten snapshots will be pruned next time
+            cancel.check()?;
             let prune_opts = rustic_core::PruneOptions::default().ignore_snaps(forget_ids);
             let prune_plan = rustic_repo.prune_plan(&prune_opts)?;
             // TODO: use send_info_blocking
             tracing::info!("Pruning {}...", repo_loc);
             rustic_repo.prune(&prune_opts, prune_plan)?;
             Ok::<(), anyhow::Error>(())
-        });
-        let repo_loc_clone = repo.path();
-        tokio::spawn(async move {
-            match handle.await {
-                Ok(Ok(_)) => send_info!(
-                    progress_channel,
-                    format!("Successfully pruned {repo_loc_clone}")
-                ),
-                Ok(Err(e)) => send_error!(progress_channel, format!("Rustic prune failed: {e}")),
-                Err(e) => send_error!(progress_channel, format!("Failed to spawn thread: {e}")),
-            }
-        });
+        })
+        .await?
+        .with_context(|| format!("Rustic prune of {repo_loc} failed"))?;
+        send_info!(progress_channel, format!("Successfully pruned {repo_loc}"));
+        Ok(())
+    }
+    async fn delete_archive(
+        &self,
+        repo: &Repository,
+        archive_spec: String,
+        progress_channel: CommandResponseSender,
+        cancel: CancelToken,
+    ) -> BorgResult<()> {
+        let (_repo_path, snapshot_label) = archive_spec.split_once("::").ok_or_else(|| {
+            anyhow::anyhow!("Expected an archive (repo::archive), got {archive_spec}")
+        })?;
+        let snapshot_label = snapshot_label.to_string();
+        take_repo_lock!(progress_channel, repo);
+        cancel.check()?;
+
+        let repo_loc = repo.path();
+        let options = repo.rustic_options()?;
+        let backends = rustic_backends(&repo_loc, &options)?;
+        let passphrase = passphrase_from_repo(repo)?;
+
+        let pb = ProgressEmitter::info(progress_channel.clone(), repo_loc.clone());
+        tokio::task::spawn_blocking(move || -> BorgResult<()> {
+            let repo_opts = rustic_repository_options(&options, Some(&passphrase));
+            let rustic_repo =
+                rustic_core::Repository::new_with_progress(&repo_opts, &backends, pb)?.open()?;
+            let snapshot = find_snapshot(&rustic_repo, &snapshot_label)?;
+            rustic_repo.delete_snapshots(&[snapshot.id])?;
+            // Prune right away so the snapshot's data is really gone. Packs only partly used
+            // by it are repacked, and packs are deleted now instead of marked for deletion.
+            cancel.check()?;
+            let mut prune_opts =
+                rustic_core::PruneOptions::default().ignore_snaps(vec![snapshot.id]);
+            prune_opts.max_unused = "0".parse()?;
+            prune_opts.instant_delete = true;
+            let prune_plan = rustic_repo.prune_plan(&prune_opts)?;
+            tracing::info!("Pruning {}...", repo_loc);
+            rustic_repo.prune(&prune_opts, prune_plan)?;
+            Ok(())
+        })
+        .await??;
+        Ok(())
+    }
+    async fn rename_archive(
+        &self,
+        repo: &Repository,
+        archive_spec: String,
+        new_name: String,
+        progress_channel: CommandResponseSender,
+    ) -> BorgResult<()> {
+        let (_repo_path, snapshot_label) = archive_spec.split_once("::").ok_or_else(|| {
+            anyhow::anyhow!("Expected an archive (repo::archive), got {archive_spec}")
+        })?;
+        let snapshot_label = snapshot_label.to_string();
+        take_repo_lock!(progress_channel, repo);
+
+        let options = repo.rustic_options()?;
+        let backends = rustic_backends(&repo.path(), &options)?;
+        let passphrase = passphrase_from_repo(repo)?;
+        tokio::task::spawn_blocking(move || -> BorgResult<()> {
+            let repo_opts = rustic_repository_options(&options, Some(&passphrase));
+            let rustic_repo = rustic_core::Repository::new(&repo_opts, &backends)?.open()?;
+            let mut snapshot = find_snapshot(&rustic_repo, &snapshot_label)?;
+            // Snapshot files are immutable, so save a relabelled copy and delete the original
+            let original_id = snapshot.id;
+            snapshot.label = new_name;
+            rustic_repo.save_snapshots(vec![snapshot])?;
+            rustic_repo.delete_snapshots(&[original_id])?;
+            Ok(())
+        })
+        .await??;
         Ok(())
     }
     async fn compact(
         &self,
         _repo: &Repository,
         _progress_channel: CommandResponseSender,
+        _cancel: CancelToken,
     ) -> BorgResult<()> {
         tracing::warn!(
             "BorgTUI's implementation of Rustic repositories automatically compact when pruning!"
@@ -555,19 +1218,20 @@ impl BackupProvider for RusticProvider {
         &self,
         repo: &Repository,
         progress_channel: CommandResponseSender,
+        cancel: CancelToken,
     ) -> BorgResult<bool> {
         take_repo_lock!(progress_channel, repo);
+        cancel.check()?;
         let repo_loc = repo.path();
 
-        let backends = rustic_backend::BackendOptions::default()
-            .repository(&repo_loc)
-            .to_backends()?;
+        let options = repo.rustic_options()?;
+        let backends = rustic_backends(&repo_loc, &options)?;
         let passphrase = passphrase_from_repo(repo)?;
 
         let progress_channel_clone = progress_channel.clone();
         let res = tokio::task::spawn_blocking(move || {
             let pb = ProgressEmitter::check(progress_channel_clone, repo_loc.clone());
-            let repo_opts = rustic_core::RepositoryOptions::default().password(passphrase.inner());
+            let repo_opts = rustic_repository_options(&options, Some(&passphrase));
             let rustic_repo =
                 rustic_core::Repository::new_with_progress(&repo_opts, &backends, pb)?.open()?;
             let check_options = rustic_core::CheckOptions::default();
@@ -593,10 +1257,207 @@ impl BackupProvider for RusticProvider {
     }
     async fn repair(
         &self,
-        _repo: &Repository,
-        _progress_channel: CommandResponseSender,
+        repo: &Repository,
+        dry_run: bool,
+        progress_channel: CommandResponseSender,
+        cancel: CancelToken,
     ) -> BorgResult<bool> {
-        // TODO: Implement this!
-        anyhow::bail!("Unimplemented")
+        take_repo_lock!(progress_channel, repo);
+        let repo_loc = repo.path();
+        // Every way out sends CheckComplete, so the UI doesn't think the repair still runs
+        let res: BorgResult<Vec<String>> = async {
+            cancel.check()?;
+            let options = repo.rustic_options()?;
+            let backends = rustic_backends(&repo_loc, &options)?;
+            let passphrase = passphrase_from_repo(repo)?;
+            let pb = ProgressEmitter::check(progress_channel.clone(), repo_loc.clone());
+            let repo_loc = repo_loc.clone();
+            tokio::task::spawn_blocking(move || -> BorgResult<Vec<String>> {
+                let repair = || -> BorgResult<()> {
+                    let repo_opts = rustic_repository_options(&options, Some(&passphrase));
+                    let rustic_repo =
+                        rustic_core::Repository::new_with_progress(&repo_opts, &backends, pb)?
+                            .open()?;
+                    // Snapshots can only be repaired against a complete index
+                    tracing::info!("Repairing the index of {repo_loc} (dry run: {dry_run})");
+                    rustic_repo
+                        .repair_index(&rustic_core::RepairIndexOptions::default(), dry_run)?;
+                    cancel.check()?;
+                    let rustic_repo = rustic_repo.to_indexed()?;
+                    let snapshots = rustic_repo.get_all_snapshots()?;
+                    tracing::info!(
+                        "Repairing {} snapshots of {repo_loc} (dry run: {dry_run})",
+                        snapshots.len()
+                    );
+                    rustic_repo.repair_snapshots(
+                        &rustic_core::RepairSnapshotsOptions::default(),
+                        snapshots,
+                        dry_run,
+                    )?;
+                    Ok(())
+                };
+                if !dry_run {
+                    return repair().map(|()| Vec::new());
+                }
+                // What a dry run would change is only in rustic's logs
+                let (res, findings) = with_rustic_logs(repair);
+                res.map(|()| findings)
+            })
+            .await?
+        }
+        .await;
+        match res {
+            Ok(findings) => {
+                let num_findings = findings.len();
+                for finding in findings {
+                    send_check_progress!(progress_channel, repo_loc.clone(), finding);
+                }
+                send_check_complete!(progress_channel, repo_loc.clone(), None);
+                let message = if !dry_run {
+                    format!("Repair succeeded for repository: {repo}")
+                } else if num_findings == 0 {
+                    format!("Repair dry run found nothing to repair in repository: {repo}")
+                } else {
+                    format!(
+                        "Repair dry run found {num_findings} thing(s) to repair in repository: {repo}"
+                    )
+                };
+                send_info!(progress_channel, message.clone());
+                Ok(true)
+            }
+            Err(e) if is_cancelled(&e) => {
+                send_check_complete!(progress_channel, repo_loc, Some(e.to_string()));
+                Err(e)
+            }
+            Err(e) => {
+                let err_msg = format!("Rustic repair failed: {e}");
+                send_check_complete!(progress_channel, repo_loc, Some(err_msg.clone()));
+                send_error!(progress_channel, err_msg.clone());
+                Ok(false)
+            }
+        }
+    }
+
+    async fn restore(
+        &self,
+        repo: &Repository,
+        archive_spec: String,
+        include_paths: &[PathBuf],
+        target_directory: PathBuf,
+        strip_components: Option<u32>,
+        progress_channel: CommandResponseSender,
+    ) -> BorgResult<()> {
+        let (_repo_path, snapshot_label) = archive_spec.split_once("::").ok_or_else(|| {
+            anyhow::anyhow!("Expected an archive (repo::archive), got {archive_spec}")
+        })?;
+        let snapshot_label = snapshot_label.to_string();
+        let repo_loc = repo.path();
+        let options = repo.rustic_options()?;
+        let backends = rustic_backends(&repo_loc, &options)?;
+        let passphrase = passphrase_from_repo(repo)?;
+        // Rustic stores paths relative to the snapshot root
+        let include_paths: Vec<PathBuf> = include_paths
+            .iter()
+            .map(|path| path.strip_prefix("/").unwrap_or(path).to_path_buf())
+            .collect();
+        let strip_components = strip_components.unwrap_or(0) as usize;
+
+        let pb = ProgressEmitter::info(progress_channel.clone(), repo_loc.clone());
+        send_info!(
+            progress_channel,
+            format!(
+                "Restoring {} to {}",
+                archive_spec,
+                target_directory.to_string_lossy()
+            )
+        );
+        tokio::task::spawn_blocking(move || -> BorgResult<()> {
+            let repo_opts = rustic_repository_options(&options, Some(&passphrase));
+            let rustic_repo =
+                rustic_core::Repository::new_with_progress(&repo_opts, &backends, pb)?
+                    .open()?
+                    .to_indexed()?;
+            let snapshot = find_snapshot(&rustic_repo, &snapshot_label)?;
+            let node = rustic_repo.node_from_snapshot_path(&snapshot.id.to_string(), |_| true)?;
+            let node_streamer = rustic_repo
+                .ls(&node, &rustic_core::LsOptions::default())?
+                .filter(move |entry| match entry {
+                    Ok((path, _)) => {
+                        include_paths.is_empty()
+                            || include_paths
+                                .iter()
+                                .any(|include| path.starts_with(include))
+                    }
+                    Err(_) => true,
+                })
+                .filter_map(move |entry| match entry {
+                    Ok((path, node)) => {
+                        let stripped: PathBuf = path.components().skip(strip_components).collect();
+                        (!stripped.as_os_str().is_empty()).then_some(Ok((stripped, node)))
+                    }
+                    Err(e) => Some(Err(e)),
+                });
+            let dest = rustic_core::LocalDestination::new(
+                &target_directory.to_string_lossy(),
+                true,
+                !node.is_dir(),
+            )?;
+            let restore_opts = rustic_core::RestoreOptions::default();
+            let restore_plan =
+                rustic_repo.prepare_restore(&restore_opts, node_streamer.clone(), &dest, false)?;
+            rustic_repo.restore(restore_plan, &restore_opts, node_streamer, &dest)?;
+            Ok(())
+        })
+        .await??;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn snapshot(label: &str, id: &str) -> SnapshotFile {
+        SnapshotFile {
+            label: label.to_string(),
+            id: id.parse().unwrap(),
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn snapshots_are_never_guessed() {
+        let laptop = "a".repeat(64);
+        let desktop = "b".repeat(64);
+        let older = "c".repeat(64);
+        let snapshots = vec![
+            snapshot("2024-05-06T07:08:09", &laptop),
+            snapshot("2024-05-06T07:08:09", &desktop),
+            snapshot("2024-05-05T07:08:09", &older),
+        ];
+        let id = |name: &str| {
+            only_snapshot(snapshots.clone(), name)
+                .map(|snapshot| snapshot.id.to_hex().to_string())
+                .map_err(|e| e.to_string())
+        };
+
+        assert_eq!(id("2024-05-05T07:08:09"), Ok(older.clone()));
+        assert_eq!(id(&desktop), Ok(desktop.clone()));
+        // The short IDs shown in archive lists work too
+        assert_eq!(id("bbbbbbbb"), Ok(desktop.clone()));
+        for missing in ["", "2024-05-07T07:08:09", "aaaa"] {
+            assert!(
+                id(missing).unwrap_err().starts_with("Could not find"),
+                "{}",
+                missing
+            );
+        }
+        let error = id("2024-05-06T07:08:09").unwrap_err();
+        assert!(error.contains("names 2 snapshots"), "{}", error);
+        assert!(
+            error.contains(&laptop) && error.contains(&desktop),
+            "{}",
+            error
+        );
     }
 }
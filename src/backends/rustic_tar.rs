@@ -0,0 +1,314 @@
+// Just enough of the tar format to export rustic snapshots, since rustic_core only dumps the
+// contents of single files.
+use std::io::{self, Write};
+use std::os::unix::ffi::OsStrExt;
+use std::path::Path;
+
+const BLOCK_SIZE: usize = 512;
+/// Longer names are written in a GNU long name entry before the header
+const NAME_FIELD_SIZE: usize = 100;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum EntryType {
+    File,
+    Directory,
+    Symlink,
+    LongName,
+    LongLinkName,
+}
+
+impl EntryType {
+    fn flag(self) -> u8 {
+        match self {
+            EntryType::File => b'0',
+            EntryType::Symlink => b'2',
+            EntryType::Directory => b'5',
+            EntryType::LongLinkName => b'K',
+            EntryType::LongName => b'L',
+        }
+    }
+}
+
+/// What goes in an entry's header besides its name and size.
+#[derive(Debug, Clone, Default)]
+pub(crate) struct TarMetadata {
+    pub(crate) mode: u32,
+    pub(crate) uid: u32,
+    pub(crate) gid: u32,
+    pub(crate) user: String,
+    pub(crate) group: String,
+    /// Seconds since the epoch
+    pub(crate) mtime: i64,
+}
+
+pub(crate) struct TarWriter<W: Write> {
+    writer: W,
+}
+
+impl<W: Write> TarWriter<W> {
+    pub(crate) fn new(writer: W) -> Self {
+        Self { writer }
+    }
+
+    pub(crate) fn append_directory(
+        &mut self,
+        path: &Path,
+        metadata: &TarMetadata,
+    ) -> io::Result<()> {
+        let mut name = entry_name(path);
+        name.push(b'/');
+        self.write_header(&name, EntryType::Directory, 0, b"", metadata)
+    }
+
+    pub(crate) fn append_symlink(
+        &mut self,
+        path: &Path,
+        target: &Path,
+        metadata: &TarMetadata,
+    ) -> io::Result<()> {
+        let target = target.as_os_str().as_bytes();
+        self.write_header(&entry_name(path), EntryType::Symlink, 0, target, metadata)
+    }
+
+    /// Write the header of a `size` byte file. Its contents follow with `write_file_data`, and
+    /// then `finish_file`.
+    pub(crate) fn start_file(
+        &mut self,
+        path: &Path,
+        size: u64,
+        metadata: &TarMetadata,
+    ) -> io::Result<()> {
+        self.write_header(&entry_name(path), EntryType::File, size, b"", metadata)
+    }
+
+    pub(crate) fn write_file_data(&mut self, data: &[u8]) -> io::Result<()> {
+        self.writer.write_all(data)
+    }
+
+    /// Pad the contents of the `size` byte file to a whole block.
+    pub(crate) fn finish_file(&mut self, size: u64) -> io::Result<()> {
+        self.pad(size)
+    }
+
+    /// End the archive, handing back the writer.
+    pub(crate) fn finish(mut self) -> io::Result<W> {
+        self.writer.write_all(&[0; 2 * BLOCK_SIZE])?;
+        self.writer.flush()?;
+        Ok(self.writer)
+    }
+
+    fn pad(&mut self, size: u64) -> io::Result<()> {
+        let remainder = (size % BLOCK_SIZE as u64) as usize;
+        if remainder != 0 {
+            self.writer.write_all(&[0; BLOCK_SIZE][remainder..])?;
+        }
+        Ok(())
+    }
+
+    fn write_long_name(&mut self, entry_type: EntryType, name: &[u8]) -> io::Result<()> {
+        let size = name.len() as u64 + 1;
+        let metadata = TarMetadata::default();
+        self.write_raw_header(b"././@LongLink", entry_type, size, b"", &metadata)?;
+        self.writer.write_all(name)?;
+        self.writer.write_all(&[0])?;
+        self.pad(size)
+    }
+
+    fn write_header(
+        &mut self,
+        name: &[u8],
+        entry_type: EntryType,
+        size: u64,
+        link_name: &[u8],
+        metadata: &TarMetadata,
+    ) -> io::Result<()> {
+        if name.len() > NAME_FIELD_SIZE {
+            self.write_long_name(EntryType::LongName, name)?;
+        }
+        if link_name.len() > NAME_FIELD_SIZE {
+            self.write_long_name(EntryType::LongLinkName, link_name)?;
+        }
+        self.write_raw_header(name, entry_type, size, link_name, metadata)
+    }
+
+    fn write_raw_header(
+        &mut self,
+        name: &[u8],
+        entry_type: EntryType,
+        size: u64,
+        link_name: &[u8],
+        metadata: &TarMetadata,
+    ) -> io::Result<()> {
+        let mut header = [0u8; BLOCK_SIZE];
+        copy_truncated(&mut header[0..100], name);
+        write_number(&mut header[100..108], (metadata.mode & 0o7777) as u64);
+        write_number(&mut header[108..116], metadata.uid as u64);
+        write_number(&mut header[116..124], metadata.gid as u64);
+        write_number(&mut header[124..136], size);
+        write_number(&mut header[136..148], metadata.mtime.max(0) as u64);
+        header[156] = entry_type.flag();
+        copy_truncated(&mut header[157..257], link_name);
+        // The GNU magic, since long names are a GNU extension
+        header[257..265].copy_from_slice(b"ustar  \0");
+        copy_truncated(&mut header[265..297], metadata.user.as_bytes());
+        copy_truncated(&mut header[297..329], metadata.group.as_bytes());
+        // The checksum is summed with its own field set to spaces, and is followed by a NUL
+        // and one of those spaces
+        header[148..156].fill(b' ');
+        let checksum: u64 = header.iter().map(|byte| *byte as u64).sum();
+        write_number(&mut header[148..155], checksum);
+        self.writer.write_all(&header)
+    }
+}
+
+/// Entries are relative to the archive root.
+fn entry_name(path: &Path) -> Vec<u8> {
+    let path = path.strip_prefix("/").unwrap_or(path);
+    path.as_os_str().as_bytes().to_vec()
+}
+
+fn copy_truncated(field: &mut [u8], value: &[u8]) {
+    let len = value.len().min(field.len());
+    field[..len].copy_from_slice(&value[..len]);
+}
+
+/// A NUL terminated octal number, or GNU's base-256 encoding if it doesn't fit.
+fn write_number(field: &mut [u8], value: u64) {
+    let digits = format!("{:0width$o}", value, width = field.len() - 1);
+    if digits.len() < field.len() {
+        field[..digits.len()].copy_from_slice(digits.as_bytes());
+        field[digits.len()] = 0;
+        return;
+    }
+    field.fill(0);
+    let bytes = value.to_be_bytes();
+    let len = bytes.len().min(field.len() - 1);
+    let start = field.len() - len;
+    field[start..].copy_from_slice(&bytes[bytes.len() - len..]);
+    field[0] = 0x80;
+}
+
+#[cfg(test)]
+mod tests {
+    use std::ffi::OsStr;
+    use std::fs;
+    use std::io::{Seek, SeekFrom};
+    use std::os::unix::fs::{MetadataExt, PermissionsExt};
+    use std::path::PathBuf;
+    use std::process::Command;
+
+    use super::*;
+
+    fn scratch_dir(name: &str) -> PathBuf {
+        let dir = std::env::temp_dir().join(format!("borgtui-tar-{}-{}", name, std::process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(&dir).unwrap();
+        dir
+    }
+
+    /// Run GNU tar, failing on any complaint it has about the archive.
+    fn gnu_tar(args: &[&OsStr]) -> String {
+        let output = Command::new("tar").args(args).output().unwrap();
+        let stderr = String::from_utf8_lossy(&output.stderr);
+        assert!(output.status.success() && stderr.is_empty(), "{}", stderr);
+        String::from_utf8_lossy(&output.stdout).to_string()
+    }
+
+    fn metadata(mode: u32) -> TarMetadata {
+        TarMetadata {
+            mode,
+            uid: 1000,
+            gid: 1000,
+            user: "user".to_string(),
+            group: "group".to_string(),
+            mtime: 1_700_000_000,
+        }
+    }
+
+    fn append_file(tar: &mut TarWriter<Vec<u8>>, path: &Path, contents: &[u8], mode: u32) {
+        tar.start_file(path, contents.len() as u64, &metadata(mode))
+            .unwrap();
+        // In pieces, like rustic hands them over
+        for piece in contents.chunks(300) {
+            tar.write_file_data(piece).unwrap();
+        }
+        tar.finish_file(contents.len() as u64).unwrap();
+    }
+
+    #[test]
+    fn archives_round_trip_through_gnu_tar() {
+        let dir = scratch_dir("round-trip");
+        let long_dir = PathBuf::from("a".repeat(70)).join("b".repeat(70));
+        let long_file = long_dir.join(format!("{}.txt", "c".repeat(120)));
+        let long_target = format!("../{}", "d".repeat(150));
+        let non_utf8 = PathBuf::from(OsStr::from_bytes(b"caf\xe9.txt"));
+        let long_contents = (0..1000u32).map(|i| i as u8).collect::<Vec<_>>();
+
+        let mut tar = TarWriter::new(Vec::new());
+        tar.append_directory(Path::new("/docs"), &metadata(0o755))
+            .unwrap();
+        append_file(&mut tar, Path::new("docs/readme.txt"), b"hello", 0o640);
+        append_file(&mut tar, Path::new("empty"), b"", 0o600);
+        tar.append_directory(&long_dir, &metadata(0o700)).unwrap();
+        append_file(&mut tar, &long_file, &long_contents, 0o644);
+        append_file(&mut tar, &non_utf8, b"latin1", 0o644);
+        tar.append_symlink(Path::new("link"), Path::new(&long_target), &metadata(0o777))
+            .unwrap();
+        let archive = tar.finish().unwrap();
+        assert_eq!(archive.len() % BLOCK_SIZE, 0);
+
+        let archive_path = dir.join("archive.tar");
+        fs::write(&archive_path, &archive).unwrap();
+        let out = dir.join("out");
+        fs::create_dir(&out).unwrap();
+        gnu_tar(&[
+            "-xf".as_ref(),
+            archive_path.as_os_str(),
+            "-C".as_ref(),
+            out.as_os_str(),
+        ]);
+
+        assert_eq!(fs::read(out.join("docs/readme.txt")).unwrap(), b"hello");
+        let readme = fs::metadata(out.join("docs/readme.txt")).unwrap();
+        assert_eq!(readme.permissions().mode() & 0o7777, 0o640);
+        assert_eq!(readme.mtime(), 1_700_000_000);
+        assert!(fs::read(out.join("empty")).unwrap().is_empty());
+        assert!(out.join(&long_dir).is_dir());
+        assert_eq!(fs::read(out.join(&long_file)).unwrap(), long_contents);
+        assert_eq!(fs::read(out.join(&non_utf8)).unwrap(), b"latin1");
+        assert_eq!(
+            fs::read_link(out.join("link")).unwrap(),
+            PathBuf::from(&long_target)
+        );
+
+        let listing = gnu_tar(&["-tvf".as_ref(), archive_path.as_os_str()]);
+        assert!(listing.contains("user/group"), "{}", listing);
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn large_files_round_trip_through_gnu_tar() {
+        // Too large for the 11 octal digits of the size field
+        let size = 8 * 1024 * 1024 * 1024 + 1;
+        let mut tar = TarWriter::new(Vec::new());
+        tar.start_file(Path::new("large.img"), size, &metadata(0o644))
+            .unwrap();
+        let header = tar.finish().unwrap();
+        assert_eq!(header[124], 0x80);
+
+        // The contents are left as a hole, so the archive takes no space
+        let dir = scratch_dir("large");
+        let archive_path = dir.join("large.tar");
+        let mut archive = fs::File::create(&archive_path).unwrap();
+        archive.write_all(&header[..BLOCK_SIZE]).unwrap();
+        let padded_size = size.div_ceil(BLOCK_SIZE as u64) * BLOCK_SIZE as u64;
+        archive.seek(SeekFrom::Current(padded_size as i64)).unwrap();
+        archive.write_all(&header[BLOCK_SIZE..]).unwrap();
+        drop(archive);
+
+        let listing = gnu_tar(&["-tvf".as_ref(), archive_path.as_os_str()]);
+        assert!(listing.contains(&format!(" {} ", size)), "{}", listing);
+        assert!(listing.contains("large.img"), "{}", listing);
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
@@ -1,8 +1,10 @@
+use crate::history::{RunKind, RunOutcome, RunRecord};
 use crate::profiles::Profile;
-use crate::profiles::{ProfileOperation, Repository};
+use crate::profiles::{check_archive_name, ProfileOperation, Repository, RepositoryKind};
 use crate::types::{
-    BackupCreateProgress, BackupCreationProgress, BorgResult, CheckComplete, CheckProgress,
-    PrettyBytes, RepositoryArchives, RingBuffer,
+    ArchiveDiff, ArchiveDiffChange, ArchiveDirectoryListing, ArchiveEntry, BackupCreateProgress,
+    BackupCreationProgress, BorgResult, CheckComplete, CheckProgress, PrettyBytes, PrunePreview,
+    RepositoryArchives, RingBuffer,
 };
 use crossterm::event::{KeyEvent, KeyModifiers};
 use crossterm::{
@@ -14,7 +16,10 @@ use ratatui::layout::Rect;
 use ratatui::style::{Color, Modifier, Style};
 use ratatui::symbols;
 use ratatui::text::{Line, Span};
-use ratatui::widgets::{Axis, Cell, Chart, Dataset, GraphType, Paragraph, Row, Table, Tabs, Wrap};
+use ratatui::widgets::{
+    Axis, Cell, Chart, Dataset, GraphType, Paragraph, Row, Table, TableState, Tabs, Wrap,
+};
+use serde::{Deserialize, Serialize};
 use tokio::sync::mpsc::{Receiver, Sender};
 use tracing::{error, info};
 
@@ -42,34 +47,60 @@ const NUM_RECENTLY_BACKED_UP_FILES: usize = 5;
 // How lines of check stats to remember
 const CHECK_STATS_RETENTION_AMOUNT: usize = 7;
 
-#[derive(Debug)]
+/// Also sent to a running daemon over its control socket (see control.rs). The progress
+/// handles are skipped, since they only mean something within one process.
+#[derive(Serialize, Deserialize, Debug)]
 pub(crate) enum Command {
     CreateBackup(Profile),
-    CheckRepository(Repository),
+    /// Cancel the running operations (and the daemon's), saving a checkpoint if true
+    Cancel(Profile, bool),
+    CheckProfile(Profile),
     SaveProfile(Profile),
-    UpdateProfileAndSave(Profile, ProfileOperation, Arc<AtomicBool>),
+    UpdateProfileAndSave(Profile, ProfileOperation, #[serde(skip)] Arc<AtomicBool>),
     ListArchives(Repository),
     Compact(Repository),
-    Prune(Repository, crate::profiles::PruneOptions),
-    DetermineDirectorySize(PathBuf, Arc<AtomicU64>, Vec<String>),
+    Prune(
+        Repository,
+        crate::profiles::PruneOptions,
+        crate::profiles::ArchiveFilter,
+    ),
+    PruneDryRun(
+        Repository,
+        crate::profiles::PruneOptions,
+        crate::profiles::ArchiveFilter,
+    ),
+    DetermineDirectorySize(PathBuf, #[serde(skip)] Arc<AtomicU64>, Vec<String>),
     GetDirectorySuggestionsFor(String),
     Mount(Repository, String, String),
     Unmount(String),
+    ListArchiveDirectory(Repository, String, String),
+    Restore(Repository, String, Vec<PathBuf>, PathBuf),
+    DiffArchives(Repository, String, String),
+    DeleteArchive(Repository, String),
+    /// Rename the archive (repo::archive) to the new name
+    RenameArchive(Repository, String, String),
+    LoadHistory,
     Quit,
 }
 
-#[derive(Debug)]
+#[derive(Serialize, Deserialize, Debug, Clone)]
 pub(crate) enum CommandResponse {
     CreateProgress(BackupCreateProgress),
     CheckProgress(CheckProgress),
     CheckComplete(CheckComplete),
     ListArchiveResult(RepositoryArchives),
-    ProfileUpdated(Profile),
+    ProfileUpdated(Box<Profile>),
     Info(String),
     Error(String),
     // TODO: Why is this a tuple :thinking:
     SuggestionResults((Vec<PathBuf>, usize)),
     MountResult(String, String),
+    ArchiveDirectoryListing(ArchiveDirectoryListing),
+    ArchiveDiff(ArchiveDiff),
+    PrunePreview(PrunePreview),
+    /// The prune preview of a repository (its path) failed with the given error.
+    PrunePreviewFailed(String, String),
+    History(Vec<RunRecord>),
 }
 
 #[derive(Copy, Clone, Debug)]
@@ -131,6 +162,7 @@ struct BackupState {
     backup_stats: HashMap<String, RingBuffer<BackupStat, BACKUP_STATS_RETENTION_AMOUNT>>,
     recently_backed_up_files: HashMap<String, RingBuffer<String, NUM_RECENTLY_BACKED_UP_FILES>>,
     finished_backing_up: HashSet<String>,
+    cancelled_backing_up: HashSet<String>,
 }
 
 impl BackupState {
@@ -141,8 +173,18 @@ impl BackupState {
     fn is_finished(&self, repo: &str) -> bool {
         self.finished_backing_up.contains(repo)
     }
+
+    fn mark_cancelled(&mut self, repo: String) {
+        self.cancelled_backing_up.insert(repo);
+    }
+
+    fn is_cancelled(&self, repo: &str) -> bool {
+        self.cancelled_backing_up.contains(repo)
+    }
+
     fn clear_finished(&mut self) {
         self.finished_backing_up.clear();
+        self.cancelled_backing_up.clear();
     }
 }
 
@@ -162,6 +204,94 @@ enum UIState {
     BackingUp,
     ListAllArchives,
     CheckingRepos,
+    BrowsingArchive,
+    ViewingArchiveDiff,
+    PrunePreview,
+    History,
+}
+
+#[derive(Default)]
+struct ArchiveBrowserState {
+    archive: String,
+    current_directory: String,
+    directory_listings: HashMap<String, Vec<ArchiveEntry>>,
+    cursor: usize,
+    marked: BTreeSet<String>,
+}
+
+impl ArchiveBrowserState {
+    fn new(archive: String) -> Self {
+        Self {
+            archive,
+            ..Default::default()
+        }
+    }
+
+    fn current_entries(&self) -> Option<&Vec<ArchiveEntry>> {
+        self.directory_listings.get(&self.current_directory)
+    }
+
+    fn selected_entry(&self) -> Option<&ArchiveEntry> {
+        self.current_entries()
+            .and_then(|entries| entries.get(self.cursor))
+    }
+
+    fn move_cursor(&mut self, delta: isize) {
+        let num_entries = self.current_entries().map(|e| e.len()).unwrap_or(0);
+        self.cursor = self
+            .cursor
+            .saturating_add_signed(delta)
+            .min(num_entries.saturating_sub(1));
+    }
+
+    /// Enter the selected directory. Returns true if its contents need to be fetched.
+    fn enter_selected_directory(&mut self) -> bool {
+        match self.selected_entry() {
+            Some(entry) if entry.is_dir => {
+                self.current_directory = entry.path.clone();
+                self.cursor = 0;
+                !self
+                    .directory_listings
+                    .contains_key(&self.current_directory)
+            }
+            _ => false,
+        }
+    }
+
+    fn go_to_parent_directory(&mut self) {
+        let parent = match self.current_directory.rsplit_once('/') {
+            Some((parent, _)) => parent.to_string(),
+            None => String::new(),
+        };
+        let previous_directory = std::mem::replace(&mut self.current_directory, parent);
+        self.cursor = self
+            .current_entries()
+            .and_then(|entries| {
+                entries
+                    .iter()
+                    .position(|entry| entry.path == previous_directory)
+            })
+            .unwrap_or(0);
+    }
+
+    fn toggle_mark_selected(&mut self) {
+        if let Some(path) = self.selected_entry().map(|entry| entry.path.clone()) {
+            if !self.marked.remove(&path) {
+                self.marked.insert(path);
+            }
+        }
+    }
+
+    /// The paths to restore: the marked paths, or the selected entry if nothing is marked.
+    fn paths_to_restore(&self) -> Vec<PathBuf> {
+        if self.marked.is_empty() {
+            self.selected_entry()
+                .map(|entry| vec![PathBuf::from(&entry.path)])
+                .unwrap_or_default()
+        } else {
+            self.marked.iter().map(PathBuf::from).collect()
+        }
+    }
 }
 
 #[derive(Debug)]
@@ -429,14 +559,7 @@ impl MountPopup {
     fn handle_key_repo_or_archive(&mut self, key: KeyEvent) {
         let res = self.input.handle_key(
             key,
-            |suggestions, input_buffer| {
-                if let Some(res) = suggestions.range(input_buffer.to_string()..).next() {
-                    if res != input_buffer {
-                        return Some(res.clone());
-                    }
-                }
-                None
-            },
+            complete_from_suggestions,
             |suggestions, input_buffer| suggestions.contains(input_buffer),
         );
         if let Some(value) = res {
@@ -527,6 +650,69 @@ impl Popup for MountPopup {
     }
 }
 
+/// Complete to the first suggestion the input buffer is a prefix of
+fn complete_from_suggestions(suggestions: &BTreeSet<String>, input_buffer: &str) -> Option<String> {
+    if let Some(res) = suggestions.range(input_buffer.to_string()..).next() {
+        if res != input_buffer {
+            return Some(res.clone());
+        }
+    }
+    None
+}
+
+#[derive(Debug)]
+struct UnmountPopup {
+    input: InputFieldWithSuggestions,
+    is_done: bool,
+}
+
+impl UnmountPopup {
+    fn new(mountpoints: Vec<String>) -> Self {
+        let mut input =
+            InputFieldWithSuggestions::new("".into(), "Mount Point to Unmount".to_string());
+        input.update_suggestions(mountpoints.into_iter());
+        UnmountPopup {
+            input,
+            is_done: false,
+        }
+    }
+}
+
+impl Popup for UnmountPopup {
+    fn on_tick(
+        &mut self,
+        _command_channel: &Sender<Command>,
+        _directory_suggestions: &[PathBuf],
+        _list_archives: &HashMap<String, RepositoryArchives>,
+    ) -> BorgResult<()> {
+        Ok(())
+    }
+
+    fn handle_key(&mut self, key: KeyEvent, borgtui: &mut BorgTui) {
+        let res = self.input.handle_key(
+            key,
+            complete_from_suggestions,
+            |suggestions, input_buffer| suggestions.contains(input_buffer),
+        );
+        if let Some(mountpoint) = res {
+            if let Err(e) = borgtui.unmount(mountpoint) {
+                borgtui.add_error(format!("{}", e));
+            }
+            self.is_done = true;
+        }
+    }
+
+    fn is_done(&self) -> bool {
+        self.input.is_done() || self.is_done
+    }
+
+    fn draw(&self, frame: &mut Frame, area: Rect) {
+        self.input.draw(frame, area, |suggestions, input_buffer| {
+            suggestions.contains(input_buffer)
+        })
+    }
+}
+
 #[derive(Debug)]
 struct AddFileToProfilePopup {
     path_successfully_added: Arc<AtomicBool>,
@@ -618,6 +804,138 @@ impl Popup for AddFileToProfilePopup {
     }
 }
 
+#[derive(Debug)]
+struct RestorePopup {
+    archive: String,
+    paths: Vec<PathBuf>,
+    input: InputFieldWithSuggestions,
+    is_done: bool,
+}
+
+impl RestorePopup {
+    fn new(archive: String, paths: Vec<PathBuf>) -> Self {
+        let initial_target = dirs::home_dir()
+            .map(|mut p| {
+                p.push("borg-restore");
+                p.to_string_lossy().to_string()
+            })
+            .unwrap_or_default();
+        let title = format!("Restore {} item(s) from {} to", paths.len(), archive);
+        RestorePopup {
+            archive,
+            paths,
+            input: InputFieldWithSuggestions::new(initial_target, title),
+            is_done: false,
+        }
+    }
+}
+
+impl Popup for RestorePopup {
+    fn handle_key(&mut self, key: KeyEvent, borgtui: &mut BorgTui) {
+        let res = self.input.handle_key(
+            key,
+            filter_directory_suggestions,
+            |_suggestions, _input_buffer| true,
+        );
+        if let Some(target) = res {
+            if let Err(e) = borgtui.restore(
+                self.archive.clone(),
+                self.paths.clone(),
+                PathBuf::from(target),
+            ) {
+                borgtui.add_error(format!("{}", e));
+            }
+            self.is_done = true;
+        }
+    }
+
+    fn on_tick(
+        &mut self,
+        command_channel: &Sender<Command>,
+        directory_suggestions: &[PathBuf],
+        _list_archives: &HashMap<String, RepositoryArchives>,
+    ) -> BorgResult<()> {
+        self.input.update_suggestions(
+            directory_suggestions
+                .iter()
+                .map(|path| path.to_string_lossy().to_string()),
+        );
+        self.input.on_input_buffer_changed(
+            |input_buffer| {
+                let command = Command::GetDirectorySuggestionsFor(input_buffer.to_string());
+                command_channel.blocking_send(command)?;
+                Ok(())
+            },
+            filter_directory_suggestions,
+        )?;
+        Ok(())
+    }
+
+    fn is_done(&self) -> bool {
+        self.input.is_done() || self.is_done
+    }
+
+    fn draw(&self, frame: &mut Frame, area: Rect) {
+        self.input.draw(frame, area, |_, _| true)
+    }
+}
+
+#[derive(Debug)]
+struct RenameArchivePopup {
+    /// The archive being renamed (repo::archive)
+    archive: String,
+    input: InputFieldWithSuggestions,
+    is_done: bool,
+}
+
+impl RenameArchivePopup {
+    fn new(archive: String) -> Self {
+        let current_name = archive
+            .rsplit_once("::")
+            .map(|(_, name)| name.to_string())
+            .unwrap_or_default();
+        let title = format!("Rename {} to", archive);
+        RenameArchivePopup {
+            archive,
+            input: InputFieldWithSuggestions::new(current_name, title),
+            is_done: false,
+        }
+    }
+}
+
+impl Popup for RenameArchivePopup {
+    fn handle_key(&mut self, key: KeyEvent, borgtui: &mut BorgTui) {
+        let res = self.input.handle_key(
+            key,
+            |_suggestions, _input_buffer| None,
+            |_suggestions, input_buffer| check_archive_name(input_buffer).is_ok(),
+        );
+        if let Some(new_name) = res {
+            borgtui.confirm_rename_archive(self.archive.clone(), new_name);
+            self.is_done = true;
+        }
+    }
+
+    fn on_tick(
+        &mut self,
+        _command_channel: &Sender<Command>,
+        _directory_suggestions: &[PathBuf],
+        _list_archives: &HashMap<String, RepositoryArchives>,
+    ) -> BorgResult<()> {
+        Ok(())
+    }
+
+    fn is_done(&self) -> bool {
+        self.input.is_done() || self.is_done
+    }
+
+    fn draw(&self, frame: &mut Frame, area: Rect) {
+        self.input.draw(frame, area, |_, input_buffer| {
+            check_archive_name(input_buffer).is_ok()
+        })
+    }
+}
+
 #[derive(Debug, Clone, Copy)]
 enum ConfirmationButtonState {
     Yes,
@@ -631,6 +949,9 @@ struct ConfirmationPopup {
     button_state: ConfirmationButtonState,
     on_confirmation_fn: OnConfirmationFn,
     is_dismissed: bool,
+    /// What has to be typed back to confirm, instead of picking yes or no
+    expected_input: Option<String>,
+    input_buffer: String,
 }
 
 impl std::fmt::Debug for ConfirmationPopup {
@@ -639,6 +960,8 @@ impl std::fmt::Debug for ConfirmationPopup {
             .field("text", &self.text)
             .field("button_state", &self.button_state)
             .field("is_dismissed", &self.is_dismissed)
+            .field("expected_input", &self.expected_input)
+            .field("input_buffer", &self.input_buffer)
             .finish()
     }
 }
@@ -663,12 +986,47 @@ impl ConfirmationPopup {
             button_state: inital_button_state,
             is_dismissed: false,
             on_confirmation_fn,
+            expected_input: None,
+            input_buffer: String::new(),
+        }
+    }
+
+    /// Only confirm once `expected_input` is typed back and Enter is pressed, for things
+    /// which can't be undone.
+    fn typed(text: String, expected_input: String, on_confirmation_fn: OnConfirmationFn) -> Self {
+        Self {
+            expected_input: Some(expected_input),
+            ..Self::new(text, ConfirmationButtonState::No, on_confirmation_fn)
+        }
+    }
+
+    fn input_matches(&self) -> bool {
+        self.expected_input.as_deref() == Some(self.input_buffer.as_str())
+    }
+
+    fn handle_typed_key(&mut self, key: KeyEvent, borgtui: &mut BorgTui) {
+        match (key.code, key.modifiers) {
+            (KeyCode::Char('g'), KeyModifiers::CONTROL) | (KeyCode::Esc, _) => {
+                self.is_dismissed = true;
+            }
+            (KeyCode::Enter, _) if self.input_matches() => {
+                (self.on_confirmation_fn)(ConfirmationButtonState::Yes, borgtui);
+                self.is_dismissed = true;
+            }
+            (KeyCode::Backspace, _) => {
+                self.input_buffer.pop();
+            }
+            (KeyCode::Char(c), _) => self.input_buffer.push(c),
+            _ => (),
         }
     }
 }
 
 impl Popup for ConfirmationPopup {
     fn handle_key(&mut self, key: KeyEvent, borgtui: &mut BorgTui) {
+        if self.expected_input.is_some() {
+            return self.handle_typed_key(key, borgtui);
+        }
         match (key.code, key.modifiers) {
             (KeyCode::Char('g'), KeyModifiers::CONTROL) | (KeyCode::Char('q'), _) => {
                 self.is_dismissed = true;
@@ -719,6 +1077,21 @@ impl Popup for ConfirmationPopup {
                     .title("Confirmation Dialog"),
             );
         frame.render_widget(text_panel, text_area);
+        if let Some(expected_input) = &self.expected_input {
+            let style = if self.input_matches() {
+                Style::default().fg(Color::Green)
+            } else {
+                Style::default().fg(Color::Red)
+            };
+            let input_panel = Paragraph::new(Span::styled(self.input_buffer.clone(), style)).block(
+                Block::default().borders(Borders::ALL).title(format!(
+                    "Type \"{}\" and press Enter to confirm, Esc to cancel",
+                    expected_input
+                )),
+            );
+            frame.render_widget(input_panel, button_area);
+            return;
+        }
         // Render Buttons
         let selected = if matches!(self.button_state, ConfirmationButtonState::Yes) {
             0
@@ -797,6 +1170,10 @@ trait Popup {
 
 enum UserIntent {
     UnmountAllRepos,
+    PruneAllRepos,
+    CancelOperations { checkpoint: bool },
+    DeleteArchive(String),
+    RenameArchive { archive: String, new_name: String },
 }
 
 // TODO: Consider encapsulating these different states into their own struct
@@ -815,6 +1192,19 @@ pub(crate) struct BorgTui {
     // This is not an enum field to make it easier to tab while a backup is in progress.
     backup_state: BackupState,
     list_archives_state: HashMap<String, RepositoryArchives>,
+    archive_list_cursor: usize,
+    archive_browser_state: ArchiveBrowserState,
+    /// The first archive picked with 'd' in the archive list
+    diff_base_archive: Option<String>,
+    archive_diff: Option<ArchiveDiff>,
+    archive_diff_cursor: usize,
+    /// Dry-run prune results keyed by repository path
+    prune_previews: HashMap<String, PrunePreview>,
+    /// Why the prune preview failed, keyed by repository path. These repos aren't pruned.
+    prune_preview_errors: HashMap<String, String>,
+    /// Past runs, oldest first. None until loaded.
+    run_history: Option<Vec<RunRecord>>,
+    run_history_cursor: usize,
     directory_suggestions: Vec<PathBuf>,
     directory_suggestions_update_num: usize,
     info_logs: RingBuffer<String, 10>,
@@ -844,6 +1234,15 @@ impl BorgTui {
             check_progress_state: CheckProgressState::default(),
             backup_state: BackupState::default(),
             list_archives_state: HashMap::new(),
+            archive_list_cursor: 0,
+            archive_browser_state: ArchiveBrowserState::default(),
+            diff_base_archive: None,
+            archive_diff: None,
+            archive_diff_cursor: 0,
+            prune_previews: HashMap::new(),
+            prune_preview_errors: HashMap::new(),
+            run_history: None,
+            run_history_cursor: 0,
             directory_suggestions: Vec::new(),
             directory_suggestions_update_num: 0,
             info_logs: RingBuffer::new(),
@@ -876,6 +1275,17 @@ impl BorgTui {
     }
 
     fn handle_keyboard_input(&mut self, key: KeyEvent) -> BorgResult<()> {
+        let handled = match self.ui_state {
+            UIState::ListAllArchives => self.handle_archive_list_key(key)?,
+            UIState::BrowsingArchive => self.handle_archive_browser_key(key)?,
+            UIState::ViewingArchiveDiff => self.handle_archive_diff_key(key),
+            UIState::PrunePreview => self.handle_prune_preview_key(key),
+            UIState::History => self.handle_history_key(key),
+            _ => false,
+        };
+        if handled {
+            return Ok(());
+        }
         match key.code {
             KeyCode::Char('q') => {
                 self.done = true;
@@ -894,6 +1304,14 @@ impl BorgTui {
                     self.send_list_archives_command()?;
                 });
             }
+            KeyCode::Char('h') => {
+                toggle_to_previous_state_or_run!(self, UIState::History, {
+                    self.run_history = None;
+                    self.run_history_cursor = 0;
+                    self.switch_ui_state(UIState::History);
+                    self.command_channel.blocking_send(Command::LoadHistory)?;
+                });
+            }
             KeyCode::Char('p') => {
                 toggle_to_previous_state_or_run!(self, UIState::ProfileView, {
                     self.switch_ui_state(UIState::ProfileView);
@@ -934,6 +1352,15 @@ impl BorgTui {
                 self.send_list_archives_command()?;
                 self.add_popup(MountPopup::new(true));
             }
+            KeyCode::Char('g') => {
+                if let Some(currently_mounted_items) = self.currently_mounted_items.as_ref() {
+                    let mountpoints = currently_mounted_items
+                        .iter()
+                        .map(|(_, mountpoint)| mountpoint.clone())
+                        .collect::<Vec<_>>();
+                    self.add_popup(UnmountPopup::new(mountpoints));
+                }
+            }
             KeyCode::Char('G') => {
                 if let Some(currently_mounted_items) = self.currently_mounted_items.as_ref() {
                     let mut text_description =
@@ -953,10 +1380,29 @@ impl BorgTui {
                     ))
                 }
             }
+            KeyCode::Char(c @ ('x' | 'X')) => {
+                let checkpoint = c == 'X';
+                let description = if checkpoint {
+                    "Cancel the running backups, checks and prunes? Borg will save what it has backed up so far as a checkpoint archive."
+                } else {
+                    "Cancel the running backups, checks and prunes?"
+                };
+                self.add_popup(ConfirmationPopup::new(
+                    description.to_string(),
+                    ConfirmationButtonState::No,
+                    Box::new(move |state, borgtui| {
+                        if let ConfirmationButtonState::Yes = state {
+                            borgtui
+                                .user_intent
+                                .push(UserIntent::CancelOperations { checkpoint });
+                        }
+                    }),
+                ))
+            }
             KeyCode::Char('\\') => {
-                self.add_info("Pruning each repo...");
-                if let Err(e) = self.send_prune_command() {
-                    let err_msg = format!("Failed to start pruning: {}", e);
+                self.add_info("Checking what pruning would remove...");
+                if let Err(e) = self.send_prune_dry_run_command() {
+                    let err_msg = format!("Failed to start prune dry run: {}", e);
                     error!(err_msg);
                     self.add_error(err_msg);
                 }
@@ -966,6 +1412,201 @@ impl BorgTui {
         Ok(())
     }
 
+    /// Returns true if the key was handled by the archive list.
+    fn handle_archive_list_key(&mut self, key: KeyEvent) -> BorgResult<bool> {
+        match key.code {
+            KeyCode::Up | KeyCode::Char('k') => {
+                self.archive_list_cursor = self.archive_list_cursor.saturating_sub(1);
+            }
+            KeyCode::Down | KeyCode::Char('j') => {
+                self.archive_list_cursor = self
+                    .archive_list_cursor
+                    .saturating_add(1)
+                    .min(self.selectable_archives().len().saturating_sub(1));
+            }
+            KeyCode::Enter => {
+                if let Some(archive) = self.selectable_archives().get(self.archive_list_cursor) {
+                    self.open_archive_browser(archive.clone())?;
+                }
+            }
+            KeyCode::Char('d') => {
+                let Some(archive) = self
+                    .selectable_archives()
+                    .get(self.archive_list_cursor)
+                    .cloned()
+                else {
+                    return Ok(true);
+                };
+                match self.diff_base_archive.take() {
+                    Some(base) if base == archive => {}
+                    Some(base) => self.diff_archives(base, archive)?,
+                    None => {
+                        self.add_info(format!(
+                            "Selected {} to diff. Press 'd' on another archive to compare.",
+                            archive
+                        ));
+                        self.diff_base_archive = Some(archive);
+                    }
+                }
+            }
+            KeyCode::Char('D') => {
+                if let Some(archive) = self.selectable_archives().get(self.archive_list_cursor) {
+                    self.confirm_delete_archive(archive.clone())?;
+                }
+            }
+            KeyCode::Char('R') => {
+                if let Some(archive) = self.selectable_archives().get(self.archive_list_cursor) {
+                    let repo = self.profile.find_repo_from_mount_src(archive)?;
+                    // Asking for a name only to refuse it afterwards would waste the user's time
+                    if matches!(repo.kind(), RepositoryKind::Restic) {
+                        self.add_error(
+                            "Restic snapshots are named after when they were taken and can't be renamed"
+                                .to_string(),
+                        );
+                    } else {
+                        self.add_popup(RenameArchivePopup::new(archive.clone()));
+                    }
+                }
+            }
+            _ => return Ok(false),
+        }
+        Ok(true)
+    }
+
+    /// Returns true if the key was handled by the archive browser.
+    fn handle_archive_browser_key(&mut self, key: KeyEvent) -> BorgResult<bool> {
+        match key.code {
+            KeyCode::Up | KeyCode::Char('k') => self.archive_browser_state.move_cursor(-1),
+            KeyCode::Down | KeyCode::Char('j') => self.archive_browser_state.move_cursor(1),
+            KeyCode::Enter | KeyCode::Right => {
+                if self.archive_browser_state.enter_selected_directory() {
+                    self.send_list_archive_directory_command()?;
+                }
+            }
+            KeyCode::Backspace | KeyCode::Left => {
+                self.archive_browser_state.go_to_parent_directory()
+            }
+            KeyCode::Char(' ') => {
+                self.archive_browser_state.toggle_mark_selected();
+                self.archive_browser_state.move_cursor(1);
+            }
+            KeyCode::Char('r') => {
+                let paths = self.archive_browser_state.paths_to_restore();
+                if paths.is_empty() {
+                    self.add_error("Nothing selected to restore!".to_string());
+                } else {
+                    let archive = self.archive_browser_state.archive.clone();
+                    self.add_popup(RestorePopup::new(archive, paths));
+                }
+            }
+            KeyCode::Esc => self.switch_ui_state(UIState::ListAllArchives),
+            _ => return Ok(false),
+        }
+        Ok(true)
+    }
+
+    /// Returns true if the key was handled by the archive diff screen.
+    fn handle_archive_diff_key(&mut self, key: KeyEvent) -> bool {
+        let num_entries = self
+            .archive_diff
+            .as_ref()
+            .map(|diff| diff.entries.len())
+            .unwrap_or(0);
+        match key.code {
+            KeyCode::Up | KeyCode::Char('k') => {
+                self.archive_diff_cursor = self.archive_diff_cursor.saturating_sub(1)
+            }
+            KeyCode::Down | KeyCode::Char('j') => {
+                self.archive_diff_cursor = self
+                    .archive_diff_cursor
+                    .saturating_add(1)
+                    .min(num_entries.saturating_sub(1))
+            }
+            KeyCode::Esc => self.switch_ui_state(UIState::ListAllArchives),
+            _ => return false,
+        }
+        true
+    }
+
+    /// Returns true if the key was handled by the prune preview screen.
+    fn handle_prune_preview_key(&mut self, key: KeyEvent) -> bool {
+        match key.code {
+            KeyCode::Enter => {
+                let waiting_on = self
+                    .profile
+                    .writable_repositories()
+                    .filter(|repo| {
+                        let path = repo.path();
+                        !self.prune_previews.contains_key(&path)
+                            && !self.prune_preview_errors.contains_key(&path)
+                    })
+                    .count();
+                if waiting_on > 0 {
+                    self.add_error(format!(
+                        "Still waiting on the prune preview for {} repo(s)",
+                        waiting_on
+                    ));
+                    return true;
+                }
+                if self.prune_previews.is_empty() {
+                    self.add_error(
+                        "No repo could be previewed, so there's nothing to prune".to_string(),
+                    );
+                    return true;
+                }
+                let num_removed: usize = self
+                    .prune_previews
+                    .values()
+                    .map(|preview| preview.num_removed())
+                    .sum();
+                let skipped = if self.prune_preview_errors.is_empty() {
+                    String::new()
+                } else {
+                    format!(
+                        " {} repo(s) whose preview failed will be skipped.",
+                        self.prune_preview_errors.len()
+                    )
+                };
+                self.add_popup(ConfirmationPopup::new(
+                    format!(
+                        "Prune {} archive(s) across {} repo(s)?{} This cannot be undone.",
+                        num_removed,
+                        self.prune_previews.len(),
+                        skipped
+                    ),
+                    ConfirmationButtonState::No,
+                    Box::new(|state, borgtui| {
+                        if let ConfirmationButtonState::Yes = state {
+                            borgtui.user_intent.push(UserIntent::PruneAllRepos);
+                        }
+                    }),
+                ))
+            }
+            KeyCode::Esc => self.switch_ui_state(UIState::ProfileView),
+            _ => return false,
+        }
+        true
+    }
+
+    /// Returns true if the key was handled by the history screen.
+    fn handle_history_key(&mut self, key: KeyEvent) -> bool {
+        let num_records = self.run_history.as_ref().map(Vec::len).unwrap_or(0);
+        match key.code {
+            KeyCode::Up | KeyCode::Char('k') => {
+                self.run_history_cursor = self.run_history_cursor.saturating_sub(1)
+            }
+            KeyCode::Down | KeyCode::Char('j') => {
+                self.run_history_cursor = self
+                    .run_history_cursor
+                    .saturating_add(1)
+                    .min(num_records.saturating_sub(1))
+            }
+            KeyCode::Esc => self.switch_ui_state(UIState::ProfileView),
+            _ => return false,
+        }
+        true
+    }
+
     fn run_app(&mut self, terminal: &mut Terminal<CrosstermBackend<Stdout>>) -> BorgResult<()> {
         let mut last_tick = Instant::now();
         self.profile
@@ -1053,6 +1694,119 @@ impl BorgTui {
         Ok(())
     }
 
+    fn restore(&mut self, archive: String, paths: Vec<PathBuf>, target: PathBuf) -> BorgResult<()> {
+        let repo = self.profile.find_repo_from_mount_src(&archive)?;
+        self.add_info(format!(
+            "Restoring {} item(s) from {} to {}",
+            paths.len(),
+            archive,
+            target.to_string_lossy()
+        ));
+        self.command_channel
+            .blocking_send(Command::Restore(repo, archive, paths, target))?;
+        Ok(())
+    }
+
+    fn diff_archives(&mut self, first: String, second: String) -> BorgResult<()> {
+        let repo = self.profile.find_repo_from_mount_src(&first)?;
+        self.add_info(format!("Comparing {} and {}", first, second));
+        self.archive_diff = None;
+        self.archive_diff_cursor = 0;
+        self.switch_ui_state(UIState::ViewingArchiveDiff);
+        self.command_channel
+            .blocking_send(Command::DiffArchives(repo, first, second))?;
+        Ok(())
+    }
+
+    /// Ask for the archive's name to be typed back before deleting it.
+    fn confirm_delete_archive(&mut self, archive: String) -> BorgResult<()> {
+        self.profile.find_repo_from_mount_src(&archive)?;
+        let Some((_, archive_name)) = archive.rsplit_once("::") else {
+            return Ok(());
+        };
+        let text = format!("Delete {}? This cannot be undone.", archive);
+        let archive_name = archive_name.to_string();
+        self.add_popup(ConfirmationPopup::typed(
+            text,
+            archive_name,
+            Box::new(move |state, borgtui| {
+                if let ConfirmationButtonState::Yes = state {
+                    borgtui
+                        .user_intent
+                        .push(UserIntent::DeleteArchive(archive.clone()));
+                }
+            }),
+        ));
+        Ok(())
+    }
+
+    /// Ask for the archive's current name to be typed back before renaming it.
+    fn confirm_rename_archive(&mut self, archive: String, new_name: String) {
+        let Some((_, archive_name)) = archive.rsplit_once("::") else {
+            return;
+        };
+        let text = format!("Rename {} to {}?", archive, new_name);
+        let archive_name = archive_name.to_string();
+        self.add_popup(ConfirmationPopup::typed(
+            text,
+            archive_name,
+            Box::new(move |state, borgtui| {
+                if let ConfirmationButtonState::Yes = state {
+                    borgtui.user_intent.push(UserIntent::RenameArchive {
+                        archive: archive.clone(),
+                        new_name: new_name.clone(),
+                    });
+                }
+            }),
+        ))
+    }
+
+    fn delete_archive(&mut self, archive: String) -> BorgResult<()> {
+        let repo = self.profile.find_repo_from_mount_src(&archive)?;
+        self.add_info(format!("Deleting {}", archive));
+        self.command_channel
+            .blocking_send(Command::DeleteArchive(repo, archive))?;
+        Ok(())
+    }
+
+    fn rename_archive(&mut self, archive: String, new_name: String) -> BorgResult<()> {
+        let repo = self.profile.find_repo_from_mount_src(&archive)?;
+        self.add_info(format!("Renaming {} to {}", archive, new_name));
+        self.command_channel
+            .blocking_send(Command::RenameArchive(repo, archive, new_name))?;
+        Ok(())
+    }
+
+    fn open_archive_browser(&mut self, archive: String) -> BorgResult<()> {
+        self.archive_browser_state = ArchiveBrowserState::new(archive);
+        self.switch_ui_state(UIState::BrowsingArchive);
+        self.send_list_archive_directory_command()
+    }
+
+    fn send_list_archive_directory_command(&mut self) -> BorgResult<()> {
+        let archive = self.archive_browser_state.archive.clone();
+        let repo = self.profile.find_repo_from_mount_src(&archive)?;
+        let command = Command::ListArchiveDirectory(
+            repo,
+            archive,
+            self.archive_browser_state.current_directory.clone(),
+        );
+        self.command_channel.blocking_send(command)?;
+        Ok(())
+    }
+
+    fn unmount(&mut self, mountpoint: String) -> BorgResult<()> {
+        self.command_channel
+            .blocking_send(Command::Unmount(mountpoint.clone()))?;
+        if let Some(items) = self.currently_mounted_items.as_mut() {
+            items.retain(|(_, item_mountpoint)| *item_mountpoint != mountpoint);
+            if items.is_empty() {
+                self.currently_mounted_items = None;
+            }
+        }
+        Ok(())
+    }
+
     fn unmount_all(&mut self) -> BorgResult<()> {
         let mount_points: Vec<String> = self
             .currently_mounted_items
@@ -1098,16 +1852,40 @@ impl BorgTui {
     }
 
     fn send_compact_command(&mut self) -> BorgResult<()> {
-        for repo in self.profile.active_repositories() {
+        for repo in self.profile.writable_repositories() {
             let command = Command::Compact(repo.clone());
             self.command_channel.blocking_send(command)?;
         }
         Ok(())
     }
 
+    /// Prune the repos the prune preview succeeded for.
     fn send_prune_command(&mut self) -> BorgResult<()> {
-        for repo in self.profile.active_repositories() {
-            let command = Command::Prune(repo.clone(), self.profile.prune_options());
+        for repo in self
+            .profile
+            .writable_repositories()
+            .filter(|repo| self.prune_previews.contains_key(&repo.path()))
+        {
+            let command = Command::Prune(
+                repo.clone(),
+                self.profile.prune_options_for(repo),
+                self.profile.archive_filter_for(repo),
+            );
+            self.command_channel.blocking_send(command)?;
+        }
+        Ok(())
+    }
+
+    fn send_prune_dry_run_command(&mut self) -> BorgResult<()> {
+        self.prune_previews.clear();
+        self.prune_preview_errors.clear();
+        self.switch_ui_state(UIState::PrunePreview);
+        for repo in self.profile.writable_repositories() {
+            let command = Command::PruneDryRun(
+                repo.clone(),
+                self.profile.prune_options_for(repo),
+                self.profile.archive_filter_for(repo),
+            );
             self.command_channel.blocking_send(command)?;
         }
         Ok(())
@@ -1128,10 +1906,8 @@ impl BorgTui {
     }
 
     fn send_checking_command(&mut self) -> BorgResult<()> {
-        for repo in self.profile.active_repositories() {
-            let command = Command::CheckRepository(repo.clone());
-            self.command_channel.blocking_send(command)?;
-        }
+        let command = Command::CheckProfile(self.profile.clone());
+        self.command_channel.blocking_send(command)?;
         Ok(())
     }
 
@@ -1225,6 +2001,18 @@ impl BorgTui {
                         self.add_info(format!("Finished backing up {}", repo));
                         tracing::info!("Finished backing up {}", repo);
                     }
+                    BackupCreationProgress::Cancelled { checkpoint } => {
+                        self.backup_state.mark_cancelled(repo.clone());
+                        if checkpoint {
+                            self.add_info(format!(
+                                "Cancelled backing up {} (keeping a checkpoint archive)",
+                                repo
+                            ));
+                        } else {
+                            self.add_info(format!("Cancelled backing up {}", repo));
+                        }
+                        tracing::info!("Cancelled backing up {}", repo);
+                    }
                 }
             }
             CommandResponse::CheckProgress(check_progress) => {
@@ -1246,6 +2034,10 @@ impl BorgTui {
             CommandResponse::ListArchiveResult(list_archive_result) => {
                 self.list_archives_state
                     .insert(list_archive_result.path.clone(), list_archive_result);
+                // Deleting the last archive would otherwise leave the cursor past the end
+                self.archive_list_cursor = self
+                    .archive_list_cursor
+                    .min(self.selectable_archives().len().saturating_sub(1));
             }
             CommandResponse::SuggestionResults((suggestions, update_num)) => {
                 if self.directory_suggestions_update_num < update_num {
@@ -1260,11 +2052,37 @@ impl BorgTui {
                     mounted_items.push((repo_or_archive, mountpoint))
                 }
             }
+            CommandResponse::ArchiveDirectoryListing(listing) => {
+                if listing.archive == self.archive_browser_state.archive {
+                    self.archive_browser_state
+                        .directory_listings
+                        .insert(listing.directory, listing.entries);
+                }
+            }
+            CommandResponse::ArchiveDiff(diff) => {
+                self.archive_diff_cursor = 0;
+                self.archive_diff = Some(diff);
+            }
+            CommandResponse::History(records) => {
+                self.run_history_cursor = 0;
+                self.run_history = Some(records);
+            }
+            CommandResponse::PrunePreview(preview) => {
+                self.prune_previews
+                    .insert(preview.repository.clone(), preview);
+            }
+            CommandResponse::PrunePreviewFailed(repository, error) => {
+                self.add_error(format!(
+                    "Failed to preview prune of {}: {}",
+                    repository, error
+                ));
+                self.prune_preview_errors.insert(repository, error);
+            }
             CommandResponse::Error(error_message) => self.add_error(error_message),
             CommandResponse::ProfileUpdated(profile) => {
                 self.add_info("Profile updated.");
                 // TODO: Refactor this to be nicer.
-                self.profile = profile;
+                self.profile = *profile;
                 let paths_to_add: Vec<_> = self
                     .profile
                     .backup_paths()
@@ -1315,6 +2133,19 @@ impl BorgTui {
         for user_intent in user_intentions {
             match user_intent {
                 UserIntent::UnmountAllRepos => self.unmount_all()?,
+                UserIntent::PruneAllRepos => {
+                    self.add_info("Pruning each repo...");
+                    self.send_prune_command()?;
+                    self.switch_ui_state(UIState::ProfileView);
+                }
+                UserIntent::CancelOperations { checkpoint } => {
+                    self.command_channel
+                        .blocking_send(Command::Cancel(self.profile.clone(), checkpoint))?;
+                }
+                UserIntent::DeleteArchive(archive) => self.delete_archive(archive)?,
+                UserIntent::RenameArchive { archive, new_name } => {
+                    self.rename_archive(archive, new_name)?
+                }
             }
         }
         Ok(())
@@ -1623,6 +2454,7 @@ impl BorgTui {
                     items.insert(0, ListItem::new("# Repo disabled, not backing up..."));
                 }
                 let is_finished = self.backup_state.is_finished(&repo.path);
+                let is_cancelled = self.backup_state.is_cancelled(&repo.path);
                 let is_disabled = repo.disabled();
                 let backup_span = if is_finished {
                     Span::styled(
@@ -1631,6 +2463,13 @@ impl BorgTui {
                             .fg(Color::Green)
                             .add_modifier(Modifier::BOLD),
                     )
+                } else if is_cancelled {
+                    Span::styled(
+                        format!("CANCELLED Backup {}", repo),
+                        Style::default()
+                            .fg(Color::Yellow)
+                            .add_modifier(Modifier::BOLD),
+                    )
                 } else if is_disabled {
                     Span::styled(
                         format!("DISABLED Backup {}", repo),
@@ -1652,6 +2491,21 @@ impl BorgTui {
             })
     }
 
+    /// Archives in the order they're drawn in the archive list, as "repo::archive".
+    fn selectable_archives(&self) -> Vec<String> {
+        self.repos_with_archives()
+            .into_iter()
+            .flat_map(|(repo_name, list_archive, _)| {
+                list_archive
+                    .map(|list_archive| list_archive.archives)
+                    .unwrap_or_default()
+                    .into_iter()
+                    .rev()
+                    .map(move |archive| format!("{}::{}", repo_name, archive.name))
+            })
+            .collect()
+    }
+
     fn repos_with_archives(&self) -> Vec<(String, Option<RepositoryArchives>, bool)> {
         self.profile
             .repositories()
@@ -1682,22 +2536,59 @@ impl BorgTui {
             .direction(Direction::Vertical)
             .constraints(backup_constraints)
             .split(area);
+        let mut archives_before_repo = 0;
         for ((repo_name, list_archive, repo_disabled), area) in
             repos_with_archives.into_iter().zip(areas.as_ref())
         {
+            let num_archives = list_archive
+                .as_ref()
+                .map(|list_archive| list_archive.archives.len())
+                .unwrap_or(0);
+            let selected = self
+                .archive_list_cursor
+                .checked_sub(archives_before_repo)
+                .filter(|index| *index < num_archives);
+            archives_before_repo += num_archives;
             let archive_rows = match list_archive {
                 Some(list_archive) => list_archive
                     .archives
                     .iter()
                     .rev()
                     .map(|archive| {
-                        Row::new([
+                        let optional_bytes = |bytes: Option<u64>| {
+                            bytes
+                                .map(|bytes| format!("{}", PrettyBytes(bytes)))
+                                .unwrap_or_default()
+                        };
+                        let row = Row::new([
                             Cell::from(format!(
                                 "{}",
                                 archive.creation_date.format("%b %d %Y %H:%M:%S")
                             )),
                             Cell::from(archive.name.clone()),
-                        ])
+                            Cell::from(archive.hostname.clone().unwrap_or_default()),
+                            Cell::from(
+                                archive
+                                    .num_files
+                                    .map(|num_files| num_files.to_string())
+                                    .unwrap_or_default(),
+                            ),
+                            Cell::from(optional_bytes(archive.original_size)),
+                            Cell::from(optional_bytes(archive.deduplicated_size)),
+                            Cell::from(
+                                archive
+                                    .duration
+                                    .map(|duration| format!("{}s", duration.as_secs()))
+                                    .unwrap_or_default(),
+                            ),
+                        ]);
+                        let is_diff_base = self.diff_base_archive.as_ref()
+                            == Some(&format!("{}::{}", repo_name, archive.name));
+                        if is_diff_base {
+                            row.style(Style::default().fg(Color::Yellow))
+                        } else {
+                            row
+                        }
                     })
                     .collect::<Vec<_>>(),
                 None => {
@@ -1711,27 +2602,341 @@ impl BorgTui {
             };
             let archive_table = Table::new(
                 archive_rows,
-                [Constraint::Percentage(30), Constraint::Percentage(70)],
+                [
+                    Constraint::Length(20),
+                    Constraint::Min(20),
+                    Constraint::Length(16),
+                    Constraint::Length(8),
+                    Constraint::Length(10),
+                    Constraint::Length(10),
+                    Constraint::Length(6),
+                ],
             )
-            .block(Block::default().borders(Borders::ALL).title(repo_name));
-            frame.render_widget(archive_table, *area)
+            .header(
+                Row::new(["Date", "Name", "Host", "Files", "Size", "Added", "Took"])
+                    .style(Style::default().add_modifier(Modifier::BOLD)),
+            )
+            .block(Block::default().borders(Borders::ALL).title(repo_name))
+            .highlight_style(Style::default().add_modifier(Modifier::REVERSED));
+            let mut table_state = TableState::default().with_selected(selected);
+            frame.render_stateful_widget(archive_table, *area, &mut table_state)
         }
     }
 
+    fn draw_archive_browser(&self, frame: &mut Frame, area: Rect) {
+        let browser = &self.archive_browser_state;
+        let chunks = Layout::default()
+            .direction(Direction::Vertical)
+            .constraints([Constraint::Length(4), Constraint::Min(0)])
+            .split(area);
+        let header = Paragraph::new(vec![
+            Line::from(format!("/{}", browser.current_directory)),
+            Line::from(format!(
+                "{} marked • Space to mark • 'r' to restore • Esc to go back",
+                browser.marked.len()
+            )),
+        ])
+        .block(
+            Block::default()
+                .borders(Borders::ALL)
+                .title(browser.archive.clone()),
+        );
+        frame.render_widget(header, chunks[0]);
+
+        let rows = match browser.current_entries() {
+            Some(entries) if entries.is_empty() => {
+                vec![Row::new([Cell::from(""), Cell::from("Empty directory")])]
+            }
+            Some(entries) => entries
+                .iter()
+                .map(|entry| {
+                    let mark = if browser.marked.contains(&entry.path) {
+                        "*"
+                    } else {
+                        ""
+                    };
+                    let size = if entry.is_dir {
+                        String::new()
+                    } else {
+                        format!("{}", PrettyBytes(entry.size))
+                    };
+                    let modified = entry
+                        .modified
+                        .map(|modified| format!("{}", modified.format("%b %d %Y %H:%M:%S")))
+                        .unwrap_or_default();
+                    let name = if entry.is_dir {
+                        format!("{}/", entry.name())
+                    } else {
+                        entry.name().to_string()
+                    };
+                    Row::new([
+                        Cell::from(mark),
+                        Cell::from(size),
+                        Cell::from(modified),
+                        Cell::from(name),
+                    ])
+                })
+                .collect(),
+            None => vec![Row::new([Cell::from(""), Cell::from("Still fetching...")])],
+        };
+        let table = Table::new(
+            rows,
+            [
+                Constraint::Length(1),
+                Constraint::Length(10),
+                Constraint::Length(20),
+                Constraint::Min(0),
+            ],
+        )
+        .header(
+            Row::new(["", "Size", "Modified", "Name"])
+                .style(Style::default().add_modifier(Modifier::BOLD)),
+        )
+        .block(Block::default().borders(Borders::ALL))
+        .highlight_style(Style::default().add_modifier(Modifier::REVERSED));
+        let mut table_state = TableState::default().with_selected(Some(browser.cursor));
+        frame.render_stateful_widget(table, chunks[1], &mut table_state)
+    }
+
+    fn draw_archive_diff(&self, frame: &mut Frame, area: Rect) {
+        let Some(diff) = self.archive_diff.as_ref() else {
+            let waiting = Paragraph::new("Still comparing archives...")
+                .block(Block::default().borders(Borders::ALL).title("Archive Diff"));
+            frame.render_widget(waiting, area);
+            return;
+        };
+        let chunks = Layout::default()
+            .direction(Direction::Vertical)
+            .constraints([Constraint::Length(4), Constraint::Min(0)])
+            .split(area);
+        let header = Paragraph::new(vec![
+            Line::from(format!("{} -> {}", diff.first, diff.second)),
+            Line::from(diff.summary()),
+        ])
+        .block(Block::default().borders(Borders::ALL).title("Archive Diff"));
+        frame.render_widget(header, chunks[0]);
+
+        let rows = diff
+            .entries
+            .iter()
+            .map(|entry| {
+                let color = match entry.change {
+                    ArchiveDiffChange::Added => Color::Green,
+                    ArchiveDiffChange::Removed => Color::Red,
+                    ArchiveDiffChange::Modified => Color::Yellow,
+                    ArchiveDiffChange::MetadataChanged => Color::Gray,
+                };
+                Row::new([
+                    Cell::from(entry.change.to_string()),
+                    Cell::from(entry.size_change()),
+                    Cell::from(entry.path.clone()),
+                ])
+                .style(Style::default().fg(color))
+            })
+            .collect::<Vec<_>>();
+        let table = Table::new(
+            rows,
+            [
+                Constraint::Length(16),
+                Constraint::Length(22),
+                Constraint::Min(0),
+            ],
+        )
+        .header(
+            Row::new(["Change", "Size", "Path"])
+                .style(Style::default().add_modifier(Modifier::BOLD)),
+        )
+        .block(Block::default().borders(Borders::ALL))
+        .highlight_style(Style::default().add_modifier(Modifier::REVERSED));
+        let mut table_state = TableState::default().with_selected(Some(self.archive_diff_cursor));
+        frame.render_stateful_widget(table, chunks[1], &mut table_state)
+    }
+
+    fn draw_prune_preview(&self, frame: &mut Frame, area: Rect) {
+        // Read-only repos are never previewed or pruned
+        let repos = self.profile.writable_repositories().collect::<Vec<_>>();
+        let mut constraints = vec![Constraint::Length(3)];
+        constraints.extend(std::iter::repeat_n(
+            Constraint::Percentage(100_u16.checked_div(repos.len() as u16).unwrap_or(100)),
+            repos.len(),
+        ));
+        let areas = Layout::default()
+            .direction(Direction::Vertical)
+            .constraints(constraints)
+            .split(area);
+        let header = Paragraph::new("Press Enter to prune, Esc to cancel").block(
+            Block::default()
+                .borders(Borders::ALL)
+                .title("Prune Preview"),
+        );
+        frame.render_widget(header, areas[0]);
+
+        for (repo, area) in repos.into_iter().zip(areas.iter().skip(1)) {
+            let repo_path = repo.path();
+            if let Some(error) = self.prune_preview_errors.get(&repo_path) {
+                let failed = Paragraph::new(format!("Preview failed, not pruning: {}", error))
+                    .style(Style::default().fg(Color::Red))
+                    .wrap(Wrap { trim: true })
+                    .block(Block::default().borders(Borders::ALL).title(repo_path));
+                frame.render_widget(failed, *area);
+                continue;
+            }
+            let Some(preview) = self.prune_previews.get(&repo_path) else {
+                let waiting = Paragraph::new("Still checking...")
+                    .block(Block::default().borders(Borders::ALL).title(repo_path));
+                frame.render_widget(waiting, *area);
+                continue;
+            };
+            let rows = preview
+                .decisions
+                .iter()
+                .map(|decision| {
+                    let (label, color) = if decision.keep {
+                        ("keep", Color::Green)
+                    } else {
+                        ("remove", Color::Red)
+                    };
+                    Row::new([
+                        Cell::from(label),
+                        Cell::from(decision.reason.clone().unwrap_or_default()),
+                        Cell::from(decision.archive.clone()),
+                    ])
+                    .style(Style::default().fg(color))
+                })
+                .collect::<Vec<_>>();
+            let title = format!(
+                "{} ({} to remove, {} to keep)",
+                repo_path,
+                preview.num_removed(),
+                preview.decisions.len() - preview.num_removed()
+            );
+            let table = Table::new(
+                rows,
+                [
+                    Constraint::Length(8),
+                    Constraint::Length(24),
+                    Constraint::Min(0),
+                ],
+            )
+            .header(
+                Row::new(["Action", "Rule", "Archive"])
+                    .style(Style::default().add_modifier(Modifier::BOLD)),
+            )
+            .block(Block::default().borders(Borders::ALL).title(title));
+            frame.render_widget(table, *area);
+        }
+    }
+
+    fn draw_history(&self, frame: &mut Frame, area: Rect) {
+        let Some(records) = self.run_history.as_ref() else {
+            let waiting = Paragraph::new("Still loading history...")
+                .block(Block::default().borders(Borders::ALL).title("History"));
+            frame.render_widget(waiting, area);
+            return;
+        };
+        let last_successful_backups = self
+            .profile
+            .repositories()
+            .iter()
+            .map(|repo| {
+                let repo_path = repo.path();
+                let last_success = records
+                    .iter()
+                    .rev()
+                    .find(|record| {
+                        record.repository == repo_path
+                            && record.kind == RunKind::Create
+                            && record.outcome == RunOutcome::Success
+                    })
+                    .map(|record| record.finished_at.format("%b %d %Y %H:%M:%S").to_string())
+                    .unwrap_or_else(|| "never".to_string());
+                Line::from(format!(
+                    "{}: last successful backup {}",
+                    repo_path, last_success
+                ))
+            })
+            .collect::<Vec<_>>();
+        let chunks = Layout::default()
+            .direction(Direction::Vertical)
+            .constraints([
+                Constraint::Length(last_successful_backups.len() as u16 + 2),
+                Constraint::Min(0),
+            ])
+            .split(area);
+        let header = Paragraph::new(last_successful_backups)
+            .block(Block::default().borders(Borders::ALL).title("History"));
+        frame.render_widget(header, chunks[0]);
+
+        let rows = records
+            .iter()
+            .rev()
+            .map(|record| {
+                let color = match record.outcome {
+                    RunOutcome::Success => Color::Green,
+                    RunOutcome::Failure => Color::Red,
+                    RunOutcome::Cancelled => Color::Yellow,
+                };
+                Row::new([
+                    Cell::from(record.started_at.format("%b %d %Y %H:%M:%S").to_string()),
+                    Cell::from(format!("{}s", record.duration().num_seconds())),
+                    Cell::from(record.kind.to_string()),
+                    Cell::from(record.repository.clone()),
+                    Cell::from(record.archive.clone().unwrap_or_default()),
+                    Cell::from(record.details()),
+                ])
+                .style(Style::default().fg(color))
+            })
+            .collect::<Vec<_>>();
+        let table = Table::new(
+            rows,
+            [
+                Constraint::Length(20),
+                Constraint::Length(8),
+                Constraint::Length(8),
+                Constraint::Percentage(25),
+                Constraint::Percentage(25),
+                Constraint::Min(0),
+            ],
+        )
+        .header(
+            Row::new([
+                "Started",
+                "Took",
+                "Kind",
+                "Repository",
+                "Archive",
+                "Details",
+            ])
+            .style(Style::default().add_modifier(Modifier::BOLD)),
+        )
+        .block(Block::default().borders(Borders::ALL))
+        .highlight_style(Style::default().add_modifier(Modifier::REVERSED));
+        let mut table_state = TableState::default().with_selected(Some(self.run_history_cursor));
+        frame.render_stateful_widget(table, chunks[1], &mut table_state)
+    }
+
     fn draw_info_panel(&mut self, frame: &mut Frame, area: Rect) {
         let text = vec![
             Line::from("• Press 'q' to quit"),
             Line::from("• Press 'u' to backup"),
             Line::from("• Press 'p' to toggle profile"),
+            Line::from("• Press 'h' to view run history"),
             Line::from("• Press 'l' to list archives"),
+            Line::from("• Press Enter on an archive to browse it"),
+            Line::from("• Press 'd' on two archives to diff them"),
+            Line::from("• Press 'D' on an archive to delete it"),
+            Line::from("• Press 'R' on an archive to rename it"),
             Line::from("• Press 'a' to add a backup path"),
             Line::from("• Press 's' to save profile"),
             Line::from("• Press 'y' to check"),
             Line::from("• Press 'c' to compact"),
             Line::from("• Press 'm' to mount"),
             Line::from("• Press 'M' to mount a repo"),
+            Line::from("• Press 'g' to unmount one"),
             Line::from("• Press 'G' to unmount all"),
-            Line::from("• Press '\\' to prune"),
+            Line::from("• Press '\\' to preview and prune"),
+            Line::from("• Press 'x' to cancel running operations"),
+            Line::from("• Press 'X' to cancel, keeping a checkpoint"),
         ];
         let info_panel = Paragraph::new(text)
             .wrap(Wrap { trim: true })
@@ -1833,7 +3038,24 @@ impl BorgTui {
             .iter()
             .map(|repo| {
                 let text = if repo.disabled() { " -- DISABLED" } else { "" };
-                ListItem::new(format!("{}{}", repo.path.clone(), text))
+                let mut lines = vec![Line::from(format!("{}{}", repo.path.clone(), text))];
+                // Repos without overrides back up the sources listed below
+                if repo.has_backup_overrides() {
+                    for path in self.profile.backup_paths_for(repo) {
+                        lines.push(Line::from(Span::styled(
+                            format!("  {}", path.display()),
+                            Style::default().fg(Color::Gray),
+                        )));
+                    }
+                    let excludes = self.profile.exclude_patterns_for(repo);
+                    if !excludes.is_empty() {
+                        lines.push(Line::from(Span::styled(
+                            format!("  excluding {}", excludes.join(", ")),
+                            Style::default().fg(Color::Gray),
+                        )));
+                    }
+                }
+                ListItem::new(lines)
             })
             .collect();
         let repo_list = List::new(repo_items)
@@ -1892,6 +3114,18 @@ impl BorgTui {
             UIState::ListAllArchives => {
                 self.draw_all_archive_lists(frame, right_area);
             }
+            UIState::BrowsingArchive => {
+                self.draw_archive_browser(frame, right_area);
+            }
+            UIState::ViewingArchiveDiff => {
+                self.draw_archive_diff(frame, right_area);
+            }
+            UIState::PrunePreview => {
+                self.draw_prune_preview(frame, right_area);
+            }
+            UIState::History => {
+                self.draw_history(frame, right_area);
+            }
             UIState::CheckingRepos => {
                 let check_chunks = Layout::default()
                     .direction(Direction::Vertical)
@@ -1937,3 +3171,165 @@ impl BorgTui {
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use ratatui::backend::TestBackend;
+
+    use super::*;
+    use crate::types::Archive;
+
+    const PROFILE: &str = r#"
+{
+  "name": "dev",
+  "backup_paths": ["/tmp"],
+  "repos": [
+    {"path": "/borg-repo", "encryption": "None", "config": {"BorgV1": {"rsh": null, "remote_path": null}}},
+    {"path": "/restic-repo", "encryption": "None", "config": {"Restic": {}}}
+  ]
+}
+"#;
+
+    fn borgtui_with_archive(repo_path: &str) -> BorgTui {
+        let (command_send, _) = tokio::sync::mpsc::channel(1);
+        let (_, response_recv) = tokio::sync::mpsc::channel(1);
+        let mut borgtui = BorgTui::new(
+            serde_json::from_str(PROFILE).unwrap(),
+            command_send,
+            response_recv,
+        );
+        borgtui.list_archives_state.insert(
+            repo_path.to_string(),
+            RepositoryArchives {
+                path: repo_path.to_string(),
+                archives: vec![Archive {
+                    name: "2024-05-06T07:08:09".to_string(),
+                    ..Default::default()
+                }],
+            },
+        );
+        borgtui
+    }
+
+    fn key(code: KeyCode) -> KeyEvent {
+        KeyEvent::new(code, KeyModifiers::NONE)
+    }
+
+    fn type_text(popup: &mut ConfirmationPopup, text: &str, borgtui: &mut BorgTui) {
+        for c in text.chars() {
+            popup.handle_key(key(KeyCode::Char(c)), borgtui);
+        }
+    }
+
+    fn drawn_text(popup: &dyn Popup) -> String {
+        let mut terminal = Terminal::new(TestBackend::new(80, 10)).unwrap();
+        terminal
+            .draw(|frame| popup.draw(frame, frame.size()))
+            .unwrap();
+        terminal
+            .backend()
+            .buffer()
+            .content
+            .iter()
+            .map(|cell| cell.symbol())
+            .collect()
+    }
+
+    fn delete_confirmation() -> ConfirmationPopup {
+        ConfirmationPopup::typed(
+            "Delete it?".to_string(),
+            "archive".to_string(),
+            Box::new(|state, borgtui| {
+                if let ConfirmationButtonState::Yes = state {
+                    borgtui
+                        .user_intent
+                        .push(UserIntent::DeleteArchive("/borg-repo::archive".to_string()));
+                }
+            }),
+        )
+    }
+
+    #[test]
+    fn typed_confirmations_need_the_exact_input() {
+        let mut borgtui = borgtui_with_archive("/borg-repo");
+        let mut popup = delete_confirmation();
+
+        // Picking yes like in other confirmations doesn't work
+        for code in [KeyCode::Enter, KeyCode::Right, KeyCode::Enter] {
+            popup.handle_key(key(code), &mut borgtui);
+        }
+        type_text(&mut popup, "y", &mut borgtui);
+        popup.handle_key(key(KeyCode::Enter), &mut borgtui);
+        assert!(!popup.is_done());
+        assert!(borgtui.user_intent.is_empty());
+
+        popup.handle_key(key(KeyCode::Backspace), &mut borgtui);
+        type_text(&mut popup, "archiv", &mut borgtui);
+        assert!(!popup.input_matches());
+        popup.handle_key(key(KeyCode::Enter), &mut borgtui);
+        type_text(&mut popup, "ee", &mut borgtui);
+        assert!(!popup.input_matches());
+        popup.handle_key(key(KeyCode::Enter), &mut borgtui);
+        assert!(borgtui.user_intent.is_empty());
+
+        popup.handle_key(key(KeyCode::Backspace), &mut borgtui);
+        assert!(popup.input_matches());
+        popup.handle_key(key(KeyCode::Enter), &mut borgtui);
+        assert!(popup.is_done());
+        assert!(matches!(
+            borgtui.user_intent.as_slice(),
+            [UserIntent::DeleteArchive(archive)] if archive == "/borg-repo::archive"
+        ));
+
+        // Escaping dismisses without confirming, even once the input matches
+        let mut borgtui = borgtui_with_archive("/borg-repo");
+        let mut popup = delete_confirmation();
+        type_text(&mut popup, "archive", &mut borgtui);
+        popup.handle_key(key(KeyCode::Esc), &mut borgtui);
+        assert!(popup.is_done());
+        assert!(borgtui.user_intent.is_empty());
+    }
+
+    #[test]
+    fn restic_archives_arent_offered_for_renaming() {
+        let mut borgtui = borgtui_with_archive("/restic-repo");
+        assert!(borgtui
+            .handle_archive_list_key(key(KeyCode::Char('R')))
+            .unwrap());
+        assert_eq!(borgtui.popup_stack.len(), 1);
+        let drawn = drawn_text(borgtui.popup_stack[0].as_ref());
+        assert!(drawn.contains("can't be renamed"), "{}", drawn);
+
+        let mut borgtui = borgtui_with_archive("/borg-repo");
+        assert!(borgtui
+            .handle_archive_list_key(key(KeyCode::Char('R')))
+            .unwrap());
+        assert_eq!(borgtui.popup_stack.len(), 1);
+        let drawn = drawn_text(borgtui.popup_stack[0].as_ref());
+        assert!(drawn.contains("Rename /borg-repo::"), "{}", drawn);
+    }
+
+    #[test]
+    fn failed_prune_previews_dont_block_pruning_the_rest() {
+        let mut borgtui = borgtui_with_archive("/borg-repo");
+        borgtui.switch_ui_state(UIState::PrunePreview);
+        borgtui.handle_command(CommandResponse::PrunePreview(PrunePreview {
+            repository: "/borg-repo".to_string(),
+            decisions: Vec::new(),
+        }));
+        assert!(borgtui.handle_prune_preview_key(key(KeyCode::Enter)));
+        let drawn = drawn_text(borgtui.popup_stack[0].as_ref());
+        assert!(drawn.contains("Still waiting"), "{}", drawn);
+
+        borgtui.popup_stack.clear();
+        borgtui.handle_command(CommandResponse::PrunePreviewFailed(
+            "/restic-repo".to_string(),
+            "locked".to_string(),
+        ));
+        borgtui.popup_stack.clear();
+        assert!(borgtui.handle_prune_preview_key(key(KeyCode::Enter)));
+        assert_eq!(borgtui.popup_stack.len(), 1);
+        let drawn = drawn_text(borgtui.popup_stack[0].as_ref());
+        assert!(drawn.contains("across 1 repo(s)"), "{}", drawn);
+    }
+}
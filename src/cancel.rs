@@ -0,0 +1,389 @@
+use std::process::{ExitStatus, Output};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+use std::time::Duration;
+
+use tokio::io::{AsyncRead, AsyncReadExt, AsyncWrite};
+use tokio::process::{Child, Command};
+use tokio_util::sync::CancellationToken;
+
+use crate::types::{log_on_error, BorgResult};
+
+/// Set when Ctrl-C cancels operations through a `CancelToken` rather than stopping BorgTUI.
+static CTRL_C_CANCELS: AtomicBool = AtomicBool::new(false);
+
+/// How long a cancelled borg or restic process gets to write a checkpoint and release its
+/// repository lock before it's killed.
+const CANCEL_GRACE_PERIOD: Duration = Duration::from_secs(120);
+
+/// The error returned by operations which stopped because they were cancelled.
+#[derive(Debug, Clone, Copy)]
+pub(crate) struct Cancelled;
+
+impl std::fmt::Display for Cancelled {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "Cancelled")
+    }
+}
+
+impl std::error::Error for Cancelled {}
+
+pub(crate) fn is_cancelled(error: &anyhow::Error) -> bool {
+    error.downcast_ref::<Cancelled>().is_some()
+}
+
+/// Shared by every operation started together, cancelling one clone cancels them all.
+#[derive(Debug, Clone)]
+pub(crate) struct CancelToken {
+    token: CancellationToken,
+    /// Skips the grace period of processes which are already stopping
+    kill: CancellationToken,
+    /// This token's checkpoint flag followed by its parents'
+    checkpoints: Vec<Arc<AtomicBool>>,
+}
+
+impl Default for CancelToken {
+    fn default() -> Self {
+        Self {
+            token: CancellationToken::new(),
+            kill: CancellationToken::new(),
+            checkpoints: vec![Arc::new(AtomicBool::new(false))],
+        }
+    }
+}
+
+impl CancelToken {
+    /// A token which is also cancelled (or killed) along with this one, but not vice versa.
+    pub(crate) fn child(&self) -> Self {
+        let mut checkpoints = vec![Arc::new(AtomicBool::new(false))];
+        checkpoints.extend(self.checkpoints.iter().cloned());
+        Self {
+            token: self.token.child_token(),
+            kill: self.kill.child_token(),
+            checkpoints,
+        }
+    }
+
+    /// Stop the operations. With `checkpoint`, borg saves what it has backed up so far as a
+    /// checkpoint archive before stopping. Restic and rustic don't have checkpoints.
+    pub(crate) fn cancel(&self, checkpoint: bool) {
+        if checkpoint {
+            self.checkpoints[0].store(true, Ordering::SeqCst);
+        }
+        self.token.cancel();
+    }
+
+    /// Cancel, and kill processes right away instead of letting them clean up.
+    pub(crate) fn kill(&self) {
+        self.token.cancel();
+        self.kill.cancel();
+    }
+
+    pub(crate) fn is_cancelled(&self) -> bool {
+        self.token.is_cancelled()
+    }
+
+    pub(crate) fn wants_checkpoint(&self) -> bool {
+        self.checkpoints
+            .iter()
+            .any(|checkpoint| checkpoint.load(Ordering::SeqCst))
+    }
+
+    pub(crate) async fn cancelled(&self) {
+        self.token.cancelled().await
+    }
+
+    /// Fail with `Cancelled` once cancelled. For operations which can only stop between steps.
+    pub(crate) fn check(&self) -> BorgResult<()> {
+        if self.is_cancelled() {
+            return Err(Cancelled.into());
+        }
+        Ok(())
+    }
+}
+
+/// Cancel `cancel` on Ctrl-C, or kill its operations on a second Ctrl-C. Processes started
+/// from now on are kept out of the terminal's Ctrl-C (see `keep_ctrl_c_away`).
+pub(crate) async fn cancel_on_ctrl_c(cancel: CancelToken, checkpoint: bool) {
+    CTRL_C_CANCELS.store(true, Ordering::SeqCst);
+    log_on_error!(
+        tokio::signal::ctrl_c().await,
+        "Failed to listen for Ctrl-C: {}"
+    );
+    tracing::warn!(
+        "Cancelling, press Ctrl-C again to stop without waiting for borg or restic to clean up"
+    );
+    cancel.cancel(checkpoint);
+    log_on_error!(
+        tokio::signal::ctrl_c().await,
+        "Failed to listen for Ctrl-C: {}"
+    );
+    tracing::warn!("Killing running operations");
+    cancel.kill();
+}
+
+/// Start `command` in its own process group if Ctrl-C is cancelling operations, so the
+/// terminal's SIGINT doesn't reach it and it can be stopped gracefully instead. Otherwise it
+/// stays in BorgTUI's group and a Ctrl-C in the terminal stops both.
+pub(crate) fn keep_ctrl_c_away(command: &mut Command) -> &mut Command {
+    if CTRL_C_CANCELS.load(Ordering::SeqCst) {
+        command.process_group(0);
+    }
+    command
+}
+
+/// Wait for `child` to exit, failing with `Cancelled` if `cancel` fires first.
+///
+/// A cancelled child is sent SIGINT if a checkpoint was asked for (borg then writes one) and
+/// SIGTERM otherwise, both of which make borg and restic clean up and release their locks.
+/// It's killed if it's still running after `CANCEL_GRACE_PERIOD`, or once `cancel` is killed.
+pub(crate) async fn wait_or_cancel(
+    child: &mut Child,
+    cancel: &CancelToken,
+) -> BorgResult<ExitStatus> {
+    tokio::select! {
+        status = child.wait() => return Ok(status?),
+        _ = cancel.cancelled() => {}
+    }
+    if let Some(pid) = child.id() {
+        let signal = if cancel.wants_checkpoint() {
+            libc::SIGINT
+        } else {
+            libc::SIGTERM
+        };
+        // Safety: The child hasn't been reaped, so the pid still belongs to it
+        unsafe { libc::kill(pid as libc::pid_t, signal) };
+    }
+    tokio::select! {
+        status = child.wait() => {
+            status?;
+        }
+        _ = tokio::time::sleep(CANCEL_GRACE_PERIOD) => {
+            tracing::warn!(
+                "Process {:?} didn't stop within {}s of being cancelled, killing it",
+                child.id(),
+                CANCEL_GRACE_PERIOD.as_secs()
+            );
+            child.kill().await?;
+        }
+        _ = cancel.kill.cancelled() => child.kill().await?,
+    }
+    Err(Cancelled.into())
+}
+
+/// Run `command`, copying its stdout to `writer`. Fails with `context` and the command's
+/// stderr if it exits unsuccessfully, or with `Cancelled` (see `wait_or_cancel`) if `cancel`
+/// fires first. If writing fails, the command is stopped like a cancelled one before the
+/// write error is returned.
+pub(crate) async fn copy_stdout(
+    mut command: Command,
+    writer: &mut (dyn AsyncWrite + Send + Unpin),
+    context: String,
+    cancel: &CancelToken,
+) -> BorgResult<()> {
+    // Don't leave the process holding its repository's lock if this future is dropped
+    let mut process = command.kill_on_drop(true).spawn()?;
+    let stderr = tokio::spawn(read_to_end(process.stderr.take()));
+    if let Some(mut stdout) = process.stdout.take() {
+        tokio::select! {
+            copied = tokio::io::copy(&mut stdout, writer) => {
+                if let Err(e) = copied {
+                    drop(stdout);
+                    let stop = cancel.child();
+                    stop.cancel(false);
+                    if let Err(stop_error) = wait_or_cancel(&mut process, &stop).await {
+                        if !is_cancelled(&stop_error) {
+                            tracing::warn!("Failed to stop {}: {}", context, stop_error);
+                        }
+                    }
+                    return Err(e.into());
+                }
+            }
+            _ = cancel.cancelled() => {}
+        }
+    }
+    let status = wait_or_cancel(&mut process, cancel).await?;
+    if !status.success() {
+        anyhow::bail!(
+            "{}: {}",
+            context,
+            String::from_utf8_lossy(&stderr.await??).trim()
+        );
+    }
+    Ok(())
+}
+
+/// Like `Child::wait_with_output`, but fails with `Cancelled` if `cancel` fires first.
+pub(crate) async fn output_or_cancel(mut child: Child, cancel: &CancelToken) -> BorgResult<Output> {
+    let stdout = tokio::spawn(read_to_end(child.stdout.take()));
+    let stderr = tokio::spawn(read_to_end(child.stderr.take()));
+    let status = wait_or_cancel(&mut child, cancel).await?;
+    Ok(Output {
+        status,
+        stdout: stdout.await??,
+        stderr: stderr.await??,
+    })
+}
+
+pub(crate) async fn read_to_end<R: AsyncRead + Unpin>(
+    reader: Option<R>,
+) -> std::io::Result<Vec<u8>> {
+    let mut buf = Vec::new();
+    if let Some(mut reader) = reader {
+        reader.read_to_end(&mut buf).await?;
+    }
+    Ok(buf)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::path::Path;
+    use std::process::Stdio;
+    use std::time::{Duration, Instant};
+
+    use tokio::process::{Child, Command};
+
+    use super::{copy_stdout, is_cancelled, output_or_cancel, wait_or_cancel, CancelToken};
+
+    /// A shell which writes the signal it gets to `marker` and exits. With `stubborn`, it
+    /// ignores signals instead and has to be killed.
+    fn spawn_sleeper(marker: &Path, stubborn: bool) -> Child {
+        let traps = if stubborn {
+            "trap '' INT TERM".to_string()
+        } else {
+            let marker = marker.display();
+            format!(
+                "trap 'echo INT > {marker}; exit 0' INT; trap 'echo TERM > {marker}; exit 0' TERM"
+            )
+        };
+        Command::new("sh")
+            .arg("-c")
+            .arg(format!("{traps}; echo ready; while :; do sleep 0.1; done"))
+            .stdout(Stdio::piped())
+            .spawn()
+            .unwrap()
+    }
+
+    /// Wait for the sleeper to install its traps
+    async fn wait_until_ready(child: &mut Child) {
+        use tokio::io::AsyncBufReadExt;
+        let stdout = child.stdout.take().unwrap();
+        let mut lines = tokio::io::BufReader::new(stdout).lines();
+        assert_eq!(lines.next_line().await.unwrap().unwrap(), "ready");
+    }
+
+    fn marker(name: &str) -> std::path::PathBuf {
+        std::env::temp_dir().join(format!("borgtui-cancel-{}-{}", name, std::process::id()))
+    }
+
+    #[tokio::test]
+    async fn finished_processes_are_not_cancelled() {
+        let cancel = CancelToken::default();
+        let child = Command::new("echo")
+            .arg("hello")
+            .stdout(Stdio::piped())
+            .stderr(Stdio::piped())
+            .spawn()
+            .unwrap();
+        let output = output_or_cancel(child, &cancel).await.unwrap();
+        assert!(output.status.success());
+        assert_eq!(output.stdout, b"hello\n");
+
+        let mut child = Command::new("false").spawn().unwrap();
+        let status = wait_or_cancel(&mut child, &cancel).await.unwrap();
+        assert!(!status.success());
+    }
+
+    #[tokio::test]
+    async fn cancelling_asks_the_process_to_stop() {
+        for (checkpoint, signal) in [(false, "TERM"), (true, "INT")] {
+            let marker = marker(signal);
+            let mut child = spawn_sleeper(&marker, false);
+            wait_until_ready(&mut child).await;
+            // Cancelling a parent cancels its children, but not the other way around
+            let parent = CancelToken::default();
+            let operation = parent.child();
+            operation.child().cancel(false);
+            assert!(!operation.is_cancelled());
+            parent.cancel(checkpoint);
+            let error = wait_or_cancel(&mut child, &operation).await.unwrap_err();
+            assert!(is_cancelled(&error));
+            let received = std::fs::read_to_string(&marker).unwrap();
+            assert_eq!(received.trim(), signal);
+            std::fs::remove_file(&marker).unwrap();
+        }
+    }
+
+    #[tokio::test]
+    async fn killing_skips_the_grace_period() {
+        let mut child = spawn_sleeper(&marker("stubborn"), true);
+        wait_until_ready(&mut child).await;
+        let cancel = CancelToken::default();
+        cancel.cancel(false);
+        let killer = cancel.clone();
+        tokio::spawn(async move {
+            tokio::time::sleep(Duration::from_millis(200)).await;
+            killer.kill();
+        });
+        let start = Instant::now();
+        let error = wait_or_cancel(&mut child, &cancel).await.unwrap_err();
+        assert!(is_cancelled(&error));
+        assert!(start.elapsed() < Duration::from_secs(10));
+        assert!(child.try_wait().unwrap().is_some());
+    }
+
+    /// A writer whose reader went away, like a disconnected HTTP client.
+    struct ClosedWriter;
+
+    impl tokio::io::AsyncWrite for ClosedWriter {
+        fn poll_write(
+            self: std::pin::Pin<&mut Self>,
+            _cx: &mut std::task::Context<'_>,
+            _buf: &[u8],
+        ) -> std::task::Poll<std::io::Result<usize>> {
+            std::task::Poll::Ready(Err(std::io::ErrorKind::BrokenPipe.into()))
+        }
+
+        fn poll_flush(
+            self: std::pin::Pin<&mut Self>,
+            _cx: &mut std::task::Context<'_>,
+        ) -> std::task::Poll<std::io::Result<()>> {
+            std::task::Poll::Ready(Ok(()))
+        }
+
+        fn poll_shutdown(
+            self: std::pin::Pin<&mut Self>,
+            _cx: &mut std::task::Context<'_>,
+        ) -> std::task::Poll<std::io::Result<()>> {
+            std::task::Poll::Ready(Ok(()))
+        }
+    }
+
+    #[tokio::test]
+    async fn failed_writes_stop_the_process() {
+        let pid_file = marker("copy-pid");
+        let mut command = Command::new("sh");
+        command
+            .arg("-c")
+            .arg(format!("echo $$ > {}; exec yes", pid_file.display()))
+            .stdout(Stdio::piped())
+            .stderr(Stdio::piped());
+        let error = copy_stdout(
+            command,
+            &mut ClosedWriter,
+            "yes".to_string(),
+            &CancelToken::default(),
+        )
+        .await
+        .unwrap_err();
+        assert!(!is_cancelled(&error));
+        let pid: libc::pid_t = std::fs::read_to_string(&pid_file)
+            .unwrap()
+            .trim()
+            .parse()
+            .unwrap();
+        std::fs::remove_file(&pid_file).unwrap();
+        // Safety: Signal 0 only checks whether the process exists
+        assert_eq!(unsafe { libc::kill(pid, 0) }, -1);
+    }
+}
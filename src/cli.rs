@@ -1,12 +1,15 @@
+use std::net::SocketAddr;
 use std::path::{Path, PathBuf};
 
+use anyhow::bail;
 use async_recursion::async_recursion;
-use clap::{CommandFactory, Parser, Subcommand};
+use clap::{CommandFactory, Parser, Subcommand, ValueEnum};
 use clap_complete::{generate, shells};
 use tokio::io::AsyncWriteExt;
 
 use crate::{
-    profiles::{Passphrase, RepositoryKind},
+    profiles::{passcommand_passphrase, Passphrase, RepositoryKind},
+    tarball,
     types::BorgResult,
 };
 
@@ -32,6 +35,19 @@ pub(crate) struct Args {
     /// This feature is experimental.
     #[arg(short, long)]
     pub(crate) watch_profile: bool,
+
+    /// The output format for commands that list things (list, list-repos, ls-repo, diff).
+    /// Logs are written to stderr when using json.
+    #[arg(global = true, long, value_enum, default_value_t = OutputFormat::Text)]
+    pub(crate) output: OutputFormat,
+}
+
+#[derive(ValueEnum, Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) enum OutputFormat {
+    /// Human readable output
+    Text,
+    /// Machine readable JSON output
+    Json,
 }
 
 #[derive(Parser, Debug, Clone)]
@@ -47,17 +63,35 @@ pub(crate) struct PassphraseSource {
     /// Implied if no other borg passphrase sources are specified.
     #[arg(short, long)]
     pub(crate) none: bool,
+    /// Obtain the borg passphrase by running a command, e.g. "pass show borg/laptop".
+    ///
+    /// The first line the command prints is used. It's run once per BorgTUI process.
+    #[arg(long, conflicts_with_all = ["keyfile", "raw", "none"])]
+    pub(crate) passcommand: Option<String>,
     /// Obtain the borg passphrase from the environment.
     #[arg(env)]
     pub(crate) borg_passphrase: Option<Passphrase>,
 }
 
 impl PassphraseSource {
+    /// The passcommand split into a program and its arguments, if one was given.
+    pub(crate) fn passcommand_argv(&self) -> BorgResult<Option<Vec<String>>> {
+        let Some(passcommand) = &self.passcommand else {
+            return Ok(None);
+        };
+        match shlex::split(passcommand) {
+            Some(argv) if !argv.is_empty() => Ok(Some(argv)),
+            _ => bail!("Failed to parse passcommand `{}`", passcommand),
+        }
+    }
+
     pub(crate) fn get_passphrase(&self) -> BorgResult<Option<Passphrase>> {
         if self.none {
             return Ok(None);
         }
-        if let Some(borg_passphrase) = &self.borg_passphrase {
+        if let Some(passcommand) = self.passcommand_argv()? {
+            Ok(Some(passcommand_passphrase(&passcommand)?))
+        } else if let Some(borg_passphrase) = &self.borg_passphrase {
             Ok(Some(borg_passphrase.clone()))
         } else if let Some(keyfile) = &self.keyfile {
             let passphrase = std::fs::read_to_string(keyfile)?.trim().to_string();
@@ -91,7 +125,14 @@ pub(crate) enum Action {
         passphrase_loc: PassphraseSource,
     },
     /// Create a new backup
-    Create,
+    ///
+    /// Press Ctrl-C to cancel the backup, and again to stop borg or restic without waiting
+    /// for them to clean up.
+    Create {
+        /// When cancelled, have borg save what it backed up so far as a checkpoint archive
+        #[arg(long)]
+        checkpoint_on_cancel: bool,
+    },
     /// Add a directory to the profile to backup
     Add {
         /// The directory or file path to add to backup
@@ -140,7 +181,102 @@ pub(crate) enum Action {
         #[arg(short, long)]
         do_not_open_in_gui_file_manager: bool,
     },
-    /// Unmount a mounted Borg repo or archive
+    /// Restore files from an archive into a target directory.
+    ///
+    /// Use `borgtui list` to find the archive to restore from.
+    Restore {
+        /// The archive to restore from (repo::archive)
+        archive: String,
+        /// If specified, only restore these paths from the archive
+        paths: Vec<PathBuf>,
+        /// The directory to restore into. It will be created if it doesn't exist.
+        #[arg(short, long, default_value = ".")]
+        target: PathBuf,
+        /// Remove the specified number of leading path elements when restoring.
+        #[arg(long)]
+        strip_components: Option<u32>,
+    },
+    /// Export an archive as a tarball, for handing backups to people without borg or restic.
+    ///
+    /// The tarball is compressed if its name ends in .gz, .bz2, .xz, .zst or .lz4, using the
+    /// matching command line tool.
+    ExportTar {
+        /// The archive to export (repo::archive)
+        archive: String,
+        /// Only export these paths (relative to the archive root). Restic only takes one.
+        paths: Vec<PathBuf>,
+        /// Where to write the tarball, or "-" for stdout
+        #[arg(short = 'o', long)]
+        output_file: PathBuf,
+    },
+    /// Create an archive from a tarball, named like the profile's backups.
+    ///
+    /// Borg unpacks the tarball into the archive. Restic and rustic can't, so they store the
+    /// tarball as a single file.
+    ImportTar {
+        /// The tarball, or "-" for stdin. Borg decompresses it going by its extension.
+        tarball: PathBuf,
+        /// Only import into this repository instead of every writable one
+        #[arg(short, long)]
+        repository: Option<String>,
+    },
+    /// Delete a single archive, such as one which captured something it shouldn't have.
+    ///
+    /// The repository is compacted (borg) or pruned (restic and rustic) right afterwards, so
+    /// the archive's data is gone once this finishes. Restic snapshots have to be named by
+    /// their exact time or full ID.
+    Delete {
+        /// The archive to delete (repo::archive)
+        archive: String,
+    },
+    /// Rename an archive. Restic snapshots are named after when they were taken, so they
+    /// can't be renamed.
+    Rename {
+        /// The archive to rename (repo::archive)
+        archive: String,
+        /// The archive's new name, without the repository
+        new_name: String,
+    },
+    /// Show what changed between two archives in the same repository.
+    Diff {
+        /// The older archive (repo::archive)
+        first: String,
+        /// The newer archive (repo::archive)
+        second: String,
+    },
+    /// Run the backups, prunes, compactions and checks in the profile's `schedule` section
+    /// until stopped. Use this instead of the systemd units if you'd prefer.
+    Daemon,
+    /// Show how old each repository's newest archive is and the last check result.
+    ///
+    /// Exits non-zero if an active repository's newest archive is older than the
+    /// profile's `max_age`, or its archives can't be listed.
+    Status,
+    /// Show past create, prune, compact, check and delete runs, most recent last.
+    History {
+        /// If specified, only show runs against this repository. Consider using `list-repos` to get repo urls.
+        repository: Option<String>,
+        /// How many runs to show
+        #[arg(short, long, default_value_t = 20)]
+        limit: usize,
+    },
+    /// Browse a repository's archives, or a single archive, read-only over HTTP.
+    ///
+    /// Every URL starts with a secret token, so only those given the printed URL can browse.
+    Serve {
+        /// The repository or archive (repo::archive) to serve
+        repository_path: String,
+        /// The address to listen on. Anything but localhost exposes your backups to the network.
+        #[arg(long, default_value = "127.0.0.1:8080")]
+        bind: SocketAddr,
+        /// The token URLs start with, at least 16 characters. A random one is generated if unset.
+        #[arg(long, env = "BORGTUI_SERVE_TOKEN")]
+        token: Option<String>,
+        /// Also answer WebDAV PROPFIND requests so the archives can be opened in a file manager
+        #[arg(long)]
+        webdav: bool,
+    },
+    /// Unmount a mounted repo or archive, falling back to `fusermount -u` if needed
     Umount {
         /// The mount point
         mountpoint: PathBuf,
@@ -167,8 +303,15 @@ pub(crate) enum Action {
     },
     /// Update the configuration file format. No-op if you're on the latest config version.
     UpdateConfig,
+    /// Check the profile for mistakes: fields that don't parse, missing backup paths, invalid
+    /// exclude patterns, duplicate repositories and passphrases that can't be found.
+    /// Exits non-zero if there are any.
+    ValidateConfig {
+        /// Check this profile file instead of the profile's own (e.g. before installing it)
+        path: Option<PathBuf>,
+    },
     /// Set the password for a repository. By default it will read
-    /// BORG_PASSPHRASE from the environment unless --keyfile is specified.
+    /// BORG_PASSPHRASE from the environment unless --keyfile or --passcommand is specified.
     SetPassword {
         /// Name of the repository (use `borgtui list-repos` to list)
         repo: String,
@@ -179,7 +322,11 @@ pub(crate) enum Action {
     /// Compact a borg repo
     Compact,
     /// Prune a borg repo
-    Prune,
+    Prune {
+        /// Show which archives would be kept or removed (and why) without deleting anything
+        #[arg(long)]
+        dry_run: bool,
+    },
     /// Verify the integrity of all active repositories. This usually takes a long time.
     ///
     /// It will display a notification and log on failure.
@@ -189,6 +336,9 @@ pub(crate) enum Action {
     },
     /// Repair backups. This is potentially dangerous - use with caution!
     Repair {
+        /// Report what would be repaired without changing anything
+        #[arg(long)]
+        dry_run: bool,
         /// If specified, only repair these repositories. Consider using `list-repos` to get repo urls.
         only_these_repos: Option<Vec<String>>,
     },
@@ -240,6 +390,36 @@ pub(crate) enum Action {
     },
 }
 
+impl Action {
+    /// Whether the action writes something other than logs to stdout.
+    pub(crate) fn writes_to_stdout(&self) -> bool {
+        matches!(self, Action::ExportTar { output_file, .. } if tarball::is_stdio(output_file))
+    }
+
+    /// Whether any error should make borgtui exit non-zero, so scripts can rely on the exit
+    /// status. `status` only does for unhealthy repositories (see `UnhealthyRepositories`).
+    pub(crate) fn fails_with_exit_status(&self) -> bool {
+        matches!(self, Action::ValidateConfig { .. })
+    }
+
+    /// Whether Ctrl-C should cancel the action gracefully rather than exit right away.
+    pub(crate) fn is_cancellable(&self) -> bool {
+        matches!(
+            self,
+            Action::Create { .. }
+                | Action::Compact
+                | Action::Prune { dry_run: false }
+                | Action::Check { .. }
+                | Action::Repair { .. }
+                | Action::Daemon
+                | Action::Serve { .. }
+                | Action::ImportTar { .. }
+                | Action::ExportTar { .. }
+                | Action::Delete { .. }
+        )
+    }
+}
+
 pub(crate) async fn print_manpage(man_root: PathBuf) -> BorgResult<()> {
     // Adapted from https://github.com/clap-rs/clap/discussions/3603#discussioncomment-3641542
     #[async_recursion]
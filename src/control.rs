@@ -0,0 +1,426 @@
+use std::os::unix::fs::{DirBuilderExt, MetadataExt};
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+
+use anyhow::{anyhow, bail};
+use serde::{Deserialize, Serialize};
+use tokio::io::{AsyncBufReadExt, AsyncWriteExt, BufReader, Lines};
+use tokio::net::unix::{OwnedReadHalf, OwnedWriteHalf};
+use tokio::net::{UnixListener, UnixStream};
+use tokio::sync::{broadcast, Mutex};
+use tracing::{error, info};
+
+use crate::{
+    borgtui::{Command, CommandResponse},
+    cancel::CancelToken,
+    profiles::Profile,
+    types::{BorgResult, CommandResponseSender, RepositoryStatus},
+};
+
+/// Sent by a client, one per connection. Messages are newline delimited JSON.
+#[derive(Serialize, Deserialize, Debug)]
+pub(crate) enum ControlRequest {
+    /// Stream everything the daemon reports until the client disconnects
+    Subscribe,
+    Status,
+    /// Run a TUI command. The daemon runs `CreateBackup` (streaming its progress until it's
+    /// done) and `Cancel` for its own profile, and refuses everything else.
+    Command(Box<Command>),
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+pub(crate) enum ControlReply {
+    Response(Box<CommandResponse>),
+    Status(Vec<RepositoryStatus>),
+    Done,
+    Failed(String),
+}
+
+/// The daemon's socket for `profile_name`, under $XDG_RUNTIME_DIR if it's set.
+pub(crate) fn socket_path(profile_name: &str) -> BorgResult<PathBuf> {
+    let mut path = match dirs::runtime_dir() {
+        Some(mut runtime_dir) => {
+            runtime_dir.push("borgtui");
+            create_private_dir(&runtime_dir)?;
+            runtime_dir
+        }
+        None => {
+            // Safety: getuid never fails
+            let uid = unsafe { libc::getuid() };
+            let path = std::env::temp_dir().join(format!("borgtui-{}", uid));
+            create_private_dir(&path)?;
+            check_private_dir(&path, uid)?;
+            path
+        }
+    };
+    path.push(format!("{}.sock", profile_name));
+    Ok(path)
+}
+
+fn create_private_dir(path: &Path) -> BorgResult<()> {
+    std::fs::DirBuilder::new()
+        .recursive(true)
+        .mode(0o700)
+        .create(path)?;
+    Ok(())
+}
+
+/// Make sure nobody else can get at the sockets in `path`. Anyone can create it first in a
+/// shared directory like /tmp.
+fn check_private_dir(path: &Path, uid: u32) -> BorgResult<()> {
+    let metadata = std::fs::symlink_metadata(path)?;
+    if !metadata.is_dir() {
+        bail!("{} is not a directory", path.display());
+    }
+    if metadata.uid() != uid {
+        bail!(
+            "{} is owned by uid {}, not {}",
+            path.display(),
+            metadata.uid(),
+            uid
+        );
+    }
+    if metadata.mode() & 0o777 != 0o700 {
+        bail!(
+            "{} has mode {:o}, expected 700",
+            path.display(),
+            metadata.mode() & 0o777
+        );
+    }
+    Ok(())
+}
+
+pub(crate) struct ControlClient {
+    reader: Lines<BufReader<OwnedReadHalf>>,
+    writer: OwnedWriteHalf,
+}
+
+impl ControlClient {
+    /// Connect to the daemon running `profile_name`, if there is one.
+    pub(crate) async fn connect(profile_name: &str) -> BorgResult<Option<Self>> {
+        let path = socket_path(profile_name)?;
+        if !tokio::fs::try_exists(&path).await? {
+            return Ok(None);
+        }
+        let stream = match UnixStream::connect(&path).await {
+            Ok(stream) => stream,
+            Err(e) => {
+                tracing::debug!("Daemon socket {} is stale: {}", path.display(), e);
+                return Ok(None);
+            }
+        };
+        let (reader, writer) = stream.into_split();
+        Ok(Some(Self {
+            reader: BufReader::new(reader).lines(),
+            writer,
+        }))
+    }
+
+    pub(crate) async fn send(&mut self, request: &ControlRequest) -> BorgResult<()> {
+        write_message(&mut self.writer, request).await
+    }
+
+    /// The next reply, or None once the daemon hangs up.
+    pub(crate) async fn next_reply(&mut self) -> BorgResult<Option<ControlReply>> {
+        match self.reader.next_line().await? {
+            Some(line) => Ok(Some(serde_json::from_str(&line)?)),
+            None => Ok(None),
+        }
+    }
+
+    /// Run `request` on the daemon, forwarding its progress to `progress_channel`.
+    pub(crate) async fn run_until_done(
+        &mut self,
+        request: &ControlRequest,
+        progress_channel: &CommandResponseSender,
+    ) -> BorgResult<()> {
+        self.send(request).await?;
+        while let Some(reply) = self.next_reply().await? {
+            match reply {
+                ControlReply::Response(response) => progress_channel.send(*response).await?,
+                ControlReply::Done => return Ok(()),
+                ControlReply::Failed(e) => bail!(e),
+                ControlReply::Status(_) => {}
+            }
+        }
+        bail!("The daemon hung up before finishing")
+    }
+}
+
+async fn write_message<T: Serialize>(writer: &mut OwnedWriteHalf, message: &T) -> BorgResult<()> {
+    let mut line = serde_json::to_string(message)?;
+    line.push('\n');
+    writer.write_all(line.as_bytes()).await?;
+    Ok(())
+}
+
+/// What the daemon shares with its control socket connections.
+#[derive(Clone)]
+pub(crate) struct DaemonState {
+    pub(crate) profile_name: Option<String>,
+    /// Everything sent here is logged and forwarded to subscribers
+    pub(crate) progress_channel: CommandResponseSender,
+    pub(crate) responses: broadcast::Sender<CommandResponse>,
+    /// Held for the duration of each operation so they never overlap
+    pub(crate) operation_lock: Arc<Mutex<()>>,
+    /// Cancelled when the daemon is shutting down
+    pub(crate) shutdown: CancelToken,
+    current_operation: Arc<std::sync::Mutex<CancelToken>>,
+}
+
+impl DaemonState {
+    pub(crate) fn new(
+        profile_name: Option<String>,
+        progress_channel: CommandResponseSender,
+        responses: broadcast::Sender<CommandResponse>,
+        shutdown: CancelToken,
+    ) -> Self {
+        Self {
+            profile_name,
+            progress_channel,
+            responses,
+            operation_lock: Default::default(),
+            current_operation: Arc::new(std::sync::Mutex::new(shutdown.child())),
+            shutdown,
+        }
+    }
+
+    /// The token for the operation about to run. Call with `operation_lock` held.
+    pub(crate) fn start_operation(&self) -> CancelToken {
+        let cancel = self.shutdown.child();
+        *self.current_operation.lock().unwrap() = cancel.clone();
+        cancel
+    }
+
+    fn cancel_operation(&self, checkpoint: bool) {
+        self.current_operation.lock().unwrap().cancel(checkpoint);
+    }
+}
+
+/// Listen on the profile's control socket until the daemon shuts down.
+pub(crate) async fn serve(profile_name: &str, state: DaemonState) -> BorgResult<()> {
+    let path = socket_path(profile_name)?;
+    if tokio::fs::try_exists(&path).await? {
+        if UnixStream::connect(&path).await.is_ok() {
+            bail!("A daemon is already running for profile {}", profile_name);
+        }
+        tokio::fs::remove_file(&path).await?;
+    }
+    let listener = UnixListener::bind(&path)?;
+    info!("Listening for control connections on {}", path.display());
+    tokio::spawn(async move {
+        loop {
+            let accepted = tokio::select! {
+                accepted = listener.accept() => accepted,
+                _ = state.shutdown.cancelled() => break,
+            };
+            match accepted {
+                Ok((stream, _)) => {
+                    let state = state.clone();
+                    tokio::spawn(async move {
+                        if let Err(e) = handle_connection(stream, state).await {
+                            error!("Control connection failed: {}", e);
+                        }
+                    });
+                }
+                Err(e) => error!("Failed to accept control connection: {}", e),
+            }
+        }
+        if let Err(e) = tokio::fs::remove_file(&path).await {
+            error!("Failed to remove control socket {}: {}", path.display(), e);
+        }
+    });
+    Ok(())
+}
+
+async fn handle_connection(stream: UnixStream, state: DaemonState) -> BorgResult<()> {
+    let (reader, mut writer) = stream.into_split();
+    let request = BufReader::new(reader)
+        .lines()
+        .next_line()
+        .await?
+        .ok_or_else(|| anyhow!("Client hung up before sending a request"))?;
+    let request: ControlRequest = serde_json::from_str(&request)?;
+    tracing::debug!("Control request: {:?}", request);
+    match request {
+        ControlRequest::Subscribe => {
+            let mut responses = state.responses.subscribe();
+            loop {
+                match responses.recv().await {
+                    Ok(response) => {
+                        write_message(&mut writer, &ControlReply::Response(Box::new(response)))
+                            .await?
+                    }
+                    Err(broadcast::error::RecvError::Lagged(skipped)) => {
+                        tracing::warn!("Subscriber fell behind, skipped {} messages", skipped)
+                    }
+                    Err(broadcast::error::RecvError::Closed) => break,
+                }
+            }
+        }
+        ControlRequest::Status => {
+            let statuses = match Profile::open_or_create(&state.profile_name).await {
+                Ok(profile) => profile.repository_statuses().await,
+                Err(e) => Err(e),
+            };
+            let reply = match statuses {
+                Ok(statuses) => ControlReply::Status(statuses),
+                Err(e) => ControlReply::Failed(e.to_string()),
+            };
+            write_message(&mut writer, &reply).await?;
+        }
+        ControlRequest::Command(command) => match *command {
+            Command::CreateBackup(profile) => {
+                let mut responses = state.responses.subscribe();
+                let reply = match create_backup(&state, profile.name(), &mut responses, &mut writer)
+                    .await
+                {
+                    Ok(()) => ControlReply::Done,
+                    Err(e) => ControlReply::Failed(e.to_string()),
+                };
+                write_message(&mut writer, &reply).await?;
+            }
+            Command::Cancel(_, checkpoint) => {
+                info!("Cancelling the running operation on request");
+                state.cancel_operation(checkpoint);
+                write_message(&mut writer, &ControlReply::Done).await?;
+            }
+            command => {
+                let reply = ControlReply::Failed(format!("The daemon can't run {:?}", command));
+                write_message(&mut writer, &reply).await?;
+            }
+        },
+    }
+    Ok(())
+}
+
+/// Back up the daemon's profile, as saved on disk. `requested_profile` must name it.
+async fn create_backup(
+    state: &DaemonState,
+    requested_profile: &str,
+    responses: &mut broadcast::Receiver<CommandResponse>,
+    writer: &mut OwnedWriteHalf,
+) -> BorgResult<()> {
+    let profile = Profile::open_or_create(&state.profile_name).await?;
+    if profile.name() != requested_profile {
+        bail!(
+            "The daemon backs up profile {}, not {}",
+            profile.name(),
+            requested_profile
+        );
+    }
+    let _operation_guard = state.operation_lock.lock().await;
+    let cancel = state.start_operation();
+    let mut backup = profile
+        .create_backup_with_notification(state.progress_channel.clone(), cancel)
+        .await?;
+    loop {
+        tokio::select! {
+            response = responses.recv() => {
+                if let Ok(response) = response {
+                    write_message(writer, &ControlReply::Response(Box::new(response))).await?;
+                }
+            }
+            res = &mut backup => {
+                res?;
+                break;
+            }
+        }
+    }
+    // Pass along whatever arrived while the backup was finishing up
+    while let Ok(response) = responses.try_recv() {
+        write_message(writer, &ControlReply::Response(Box::new(response))).await?;
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use std::os::unix::fs::PermissionsExt;
+
+    use super::{check_private_dir, ControlReply, ControlRequest};
+    use crate::borgtui::{Command, CommandResponse};
+    use crate::profiles::Profile;
+
+    fn profile(name: &str) -> Profile {
+        serde_json::from_value(serde_json::json!({"name": name, "backup_paths": [], "repos": []}))
+            .unwrap()
+    }
+
+    /// Send `message` through JSON and back, like the socket does.
+    fn round_trip<T: serde::Serialize + serde::de::DeserializeOwned>(message: &T) -> T {
+        let line = serde_json::to_string(message).unwrap();
+        assert!(!line.contains('\n'), "Messages are newline delimited");
+        serde_json::from_str(&line).unwrap()
+    }
+
+    #[test]
+    fn requests_round_trip() {
+        assert!(matches!(
+            round_trip(&ControlRequest::Subscribe),
+            ControlRequest::Subscribe
+        ));
+        assert!(matches!(
+            round_trip(&ControlRequest::Status),
+            ControlRequest::Status
+        ));
+        let request = ControlRequest::Command(Box::new(Command::CreateBackup(profile("laptop"))));
+        match round_trip(&request) {
+            ControlRequest::Command(command) => match *command {
+                Command::CreateBackup(profile) => assert_eq!(profile.name(), "laptop"),
+                other => panic!("Unexpected command {:?}", other),
+            },
+            other => panic!("Unexpected request {:?}", other),
+        }
+        let request = ControlRequest::Command(Box::new(Command::Cancel(profile("laptop"), true)));
+        match round_trip(&request) {
+            ControlRequest::Command(command) => {
+                assert!(matches!(*command, Command::Cancel(_, true)))
+            }
+            other => panic!("Unexpected request {:?}", other),
+        }
+    }
+
+    #[test]
+    fn replies_round_trip() {
+        let reply = ControlReply::Response(Box::new(CommandResponse::Info(
+            "Backing up\nline two".to_string(),
+        )));
+        match round_trip(&reply) {
+            ControlReply::Response(response) => match *response {
+                CommandResponse::Info(message) => assert_eq!(message, "Backing up\nline two"),
+                other => panic!("Unexpected response {:?}", other),
+            },
+            other => panic!("Unexpected reply {:?}", other),
+        }
+        assert!(matches!(
+            round_trip(&ControlReply::Done),
+            ControlReply::Done
+        ));
+        match round_trip(&ControlReply::Failed("no".to_string())) {
+            ControlReply::Failed(e) => assert_eq!(e, "no"),
+            other => panic!("Unexpected reply {:?}", other),
+        }
+    }
+
+    #[test]
+    fn socket_dirs_must_be_private() {
+        let dir = std::env::temp_dir().join(format!("borgtui-control-{}", std::process::id()));
+        // Safety: getuid never fails
+        let uid = unsafe { libc::getuid() };
+        let sockets = dir.join("borgtui");
+        std::fs::create_dir_all(&sockets).unwrap();
+        std::fs::set_permissions(&sockets, std::fs::Permissions::from_mode(0o700)).unwrap();
+        check_private_dir(&sockets, uid).unwrap();
+        assert!(check_private_dir(&sockets, uid + 1).is_err());
+
+        std::fs::set_permissions(&sockets, std::fs::Permissions::from_mode(0o755)).unwrap();
+        assert!(check_private_dir(&sockets, uid).is_err());
+
+        let link = dir.join("link");
+        std::os::unix::fs::symlink(&sockets, &link).unwrap();
+        std::fs::set_permissions(&sockets, std::fs::Permissions::from_mode(0o700)).unwrap();
+        assert!(check_private_dir(&link, uid).is_err());
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+}
@@ -0,0 +1,374 @@
+use std::collections::{hash_map::RandomState, HashMap};
+use std::hash::{BuildHasher, Hasher};
+use std::time::Duration;
+
+use chrono::{DateTime, Local};
+use tokio::sync::{broadcast, mpsc};
+use tracing::{error, info, warn};
+
+use crate::{
+    borgtui::CommandResponse,
+    cancel::{is_cancelled, CancelToken},
+    control::{self, DaemonState},
+    history::{self, RunKind, RunOutcome, RunRecord},
+    profiles::{Profile, Repository, Schedule, ScheduleInterval},
+    types::{BorgResult, CommandResponseSender},
+};
+
+/// When the daemon last started each operation on a repository. Covers runs that never
+/// made it into the history, like a backup aborted by a failing hook.
+type Attempts = HashMap<(RunKind, String), DateTime<Local>>;
+
+/// How often to look for due operations. Due-ness is judged with the wall clock and the
+/// run history, so runs missed while the machine was suspended happen on the next tick.
+const TICK: Duration = Duration::from_secs(60);
+/// How long to wait before retrying a failed operation, unless its interval is shorter
+const RETRY_DELAY: Duration = Duration::from_secs(60 * 60);
+/// How many progress messages a slow control socket subscriber can fall behind by
+const SUBSCRIBER_BACKLOG: usize = 1024;
+
+#[derive(Debug, Clone, Copy)]
+enum ScheduledOperation {
+    Backup,
+    Prune,
+    Compact,
+    Check,
+}
+
+impl ScheduledOperation {
+    const ALL: [ScheduledOperation; 4] = [
+        ScheduledOperation::Backup,
+        ScheduledOperation::Prune,
+        ScheduledOperation::Compact,
+        ScheduledOperation::Check,
+    ];
+
+    fn run_kind(self) -> RunKind {
+        match self {
+            ScheduledOperation::Backup => RunKind::Create,
+            ScheduledOperation::Prune => RunKind::Prune,
+            ScheduledOperation::Compact => RunKind::Compact,
+            ScheduledOperation::Check => RunKind::Check,
+        }
+    }
+
+    /// Read-only repositories are skipped by operations which write to them
+    fn writes(self) -> bool {
+        !matches!(self, ScheduledOperation::Check)
+    }
+
+    fn interval(self, schedule: &Schedule) -> Option<ScheduleInterval> {
+        match self {
+            ScheduledOperation::Backup => schedule.backup,
+            ScheduledOperation::Prune => schedule.prune,
+            ScheduledOperation::Compact => schedule.compact,
+            ScheduledOperation::Check => schedule.check,
+        }
+    }
+}
+
+/// Run the profile's scheduled operations and serve requests on its control socket until
+/// `shutdown` is cancelled.
+pub(crate) async fn run(
+    profile_name: Option<String>,
+    progress_channel: CommandResponseSender,
+    shutdown: CancelToken,
+) -> BorgResult<()> {
+    let profile = Profile::open_or_create(&profile_name).await?;
+    if profile.schedule().is_empty() {
+        warn!(
+            "{} has no schedule, only serving control socket requests. Add a \"schedule\" section to the profile (see `borgtui config-path`)",
+            profile
+        );
+    }
+
+    // Log everything like the other subcommands do, and share it with socket subscribers
+    let (daemon_progress_send, mut daemon_progress_recv) =
+        mpsc::channel::<CommandResponse>(progress_channel.max_capacity());
+    let (responses, _) = broadcast::channel(SUBSCRIBER_BACKLOG);
+    let responses_clone = responses.clone();
+    tokio::spawn(async move {
+        while let Some(response) = daemon_progress_recv.recv().await {
+            // Only fails when nobody is subscribed
+            let _ = responses_clone.send(response.clone());
+            if let Err(e) = progress_channel.send(response).await {
+                error!("Failed to log daemon progress: {}", e);
+            }
+        }
+    });
+    let state = DaemonState::new(
+        profile_name.clone(),
+        daemon_progress_send,
+        responses,
+        shutdown.clone(),
+    );
+    control::serve(profile.name(), state.clone()).await?;
+
+    info!("Starting scheduler for {}", profile);
+    let mut attempts = Attempts::new();
+    loop {
+        // Reload every tick to pick up edits to the profile
+        match Profile::open_or_create(&profile_name).await {
+            Ok(profile) => run_due_operations(&profile, &mut attempts, &state).await,
+            Err(e) => error!("Failed to load profile: {}", e),
+        }
+        tokio::select! {
+            _ = tokio::time::sleep(TICK) => {}
+            _ = shutdown.cancelled() => break,
+        }
+    }
+    // Wait for a control socket operation to wrap up
+    let _operation_guard = state.operation_lock.lock().await;
+    info!("Stopped scheduler for {}", profile);
+    Ok(())
+}
+
+/// Run every operation that's due, one at a time (including those requested over the
+/// control socket) so operations never contend for a repository's lock.
+async fn run_due_operations(profile: &Profile, attempts: &mut Attempts, state: &DaemonState) {
+    let schedule = profile.schedule();
+    let history = match history::load_history().await {
+        Ok(history) => history,
+        Err(e) => {
+            error!("Failed to load run history, not scheduling anything: {}", e);
+            return;
+        }
+    };
+    for operation in ScheduledOperation::ALL {
+        let Some(interval) = operation.interval(&schedule) else {
+            continue;
+        };
+        let now = Local::now();
+        let due_repos = profile
+            .active_repositories()
+            .filter(|repo| !(operation.writes() && repo.read_only()))
+            .filter(|repo| {
+                is_due(
+                    &history,
+                    attempts,
+                    operation.run_kind(),
+                    &repo.path(),
+                    interval,
+                    now,
+                )
+            })
+            .collect::<Vec<_>>();
+        if due_repos.is_empty() {
+            continue;
+        }
+        tokio::select! {
+            _ = jitter(schedule.jitter_seconds) => {}
+            _ = state.shutdown.cancelled() => return,
+        }
+        let _operation_guard = state.operation_lock.lock().await;
+        if state.shutdown.is_cancelled() {
+            return;
+        }
+        let cancel = state.start_operation();
+        let started_at = Local::now();
+        for repo in &due_repos {
+            attempts.insert((operation.run_kind(), repo.path()), started_at);
+        }
+        info!("Running scheduled {:?} for {}", operation, profile);
+        match run_operation(
+            operation,
+            profile,
+            &due_repos,
+            &state.progress_channel,
+            &cancel,
+        )
+        .await
+        {
+            Err(e) if is_cancelled(&e) => {
+                info!("Cancelled scheduled {:?} for {}", operation, profile)
+            }
+            Err(e) => error!("Scheduled {:?} for {} failed: {}", operation, profile, e),
+            Ok(()) => {}
+        }
+    }
+}
+
+async fn run_operation(
+    operation: ScheduledOperation,
+    profile: &Profile,
+    due_repos: &[&Repository],
+    progress_channel: &CommandResponseSender,
+    cancel: &CancelToken,
+) -> BorgResult<()> {
+    match operation {
+        // Backups cover the whole profile, so everything is backed up if any repo is due
+        ScheduledOperation::Backup => {
+            profile
+                .create_backup_with_notification(progress_channel.clone(), cancel.clone())
+                .await?
+                .await?
+        }
+        ScheduledOperation::Prune => {
+            for repo in due_repos {
+                cancel.check()?;
+                if let Err(e) = repo
+                    .prune(
+                        profile.prune_options_for(repo),
+                        &profile.archive_filter_for(repo),
+                        progress_channel.clone(),
+                        cancel.clone(),
+                    )
+                    .await
+                {
+                    error!("Failed to prune {}: {}", repo, e);
+                }
+            }
+        }
+        ScheduledOperation::Compact => {
+            for repo in due_repos {
+                cancel.check()?;
+                if let Err(e) = repo.compact(progress_channel.clone(), cancel.clone()).await {
+                    error!("Failed to compact {}: {}", repo, e);
+                }
+            }
+        }
+        ScheduledOperation::Check => {
+            let repo_paths = due_repos.iter().map(|repo| repo.path()).collect::<Vec<_>>();
+            let failed = profile
+                .check_repositories(Some(&repo_paths), progress_channel.clone(), cancel.clone())
+                .await?;
+            for repo in failed {
+                error!("Verification failed for repository: {}", repo);
+            }
+        }
+    }
+    Ok(())
+}
+
+/// When `kind` last succeeded on `repo_path`, and when it was last attempted (successfully or not).
+fn last_runs(
+    history: &[RunRecord],
+    attempts: &Attempts,
+    kind: RunKind,
+    repo_path: &str,
+) -> (Option<DateTime<Local>>, Option<DateTime<Local>>) {
+    let mut runs = history
+        .iter()
+        .rev()
+        .filter(|record| record.kind == kind && record.repository == repo_path);
+    let last_recorded = runs.clone().next().map(|record| record.started_at);
+    let last_success = runs
+        .find(|record| record.outcome == RunOutcome::Success)
+        .map(|record| record.started_at);
+    let last_attempted = attempts.get(&(kind, repo_path.to_string())).copied();
+    (last_success, last_recorded.max(last_attempted))
+}
+
+/// Due once `interval` has passed since the last successful run. Failed runs are retried after
+/// `RETRY_DELAY` (or `interval`, if that's shorter) instead of waiting out a whole interval.
+fn is_due(
+    history: &[RunRecord],
+    attempts: &Attempts,
+    kind: RunKind,
+    repo_path: &str,
+    interval: ScheduleInterval,
+    now: DateTime<Local>,
+) -> bool {
+    let (last_success, last_attempt) = last_runs(history, attempts, kind, repo_path);
+    let has_passed = |since: Option<DateTime<Local>>, duration: Duration| match since {
+        Some(since) => now.signed_duration_since(since).num_seconds() >= duration.as_secs() as i64,
+        None => true,
+    };
+    let interval = interval.as_duration();
+    has_passed(last_success, interval) && has_passed(last_attempt, interval.min(RETRY_DELAY))
+}
+
+async fn jitter(max_seconds: u64) {
+    if max_seconds == 0 {
+        return;
+    }
+    let random = RandomState::new().build_hasher().finish();
+    let delay = Duration::from_secs(random % (max_seconds + 1));
+    info!("Waiting {}s before the scheduled run", delay.as_secs());
+    tokio::time::sleep(delay).await;
+}
+
+#[cfg(test)]
+mod tests {
+    use anyhow::anyhow;
+    use chrono::{DateTime, Duration, Local};
+
+    use super::{is_due, Attempts};
+    use crate::history::{RunKind, RunRecord};
+
+    const REPO: &str = "/backups/repo";
+
+    fn run(started_at: DateTime<Local>, succeeded: bool) -> RunRecord {
+        let result = if succeeded {
+            Ok(())
+        } else {
+            Err(anyhow!("failed"))
+        };
+        RunRecord::new(RunKind::Create, REPO.to_string(), started_at, &result)
+    }
+
+    #[test]
+    fn due_once_the_interval_passes_since_the_last_success() {
+        let now = Local::now();
+        let interval = "24H".parse().unwrap();
+        let attempts = Attempts::new();
+        let is_due = |history: &[RunRecord]| {
+            is_due(history, &attempts, RunKind::Create, REPO, interval, now)
+        };
+        assert!(is_due(&[]));
+        assert!(!is_due(&[run(now - Duration::hours(1), true)]));
+        assert!(is_due(&[run(now - Duration::hours(25), true)]));
+        // Other repositories and operations don't count
+        let mut other = run(now - Duration::hours(1), true);
+        other.repository = "/backups/other".to_string();
+        assert!(is_due(&[other]));
+        let mut prune = run(now - Duration::hours(1), true);
+        prune.kind = RunKind::Prune;
+        assert!(is_due(&[prune]));
+    }
+
+    #[test]
+    fn failures_are_retried_before_the_next_interval() {
+        let now = Local::now();
+        let interval = "24H".parse().unwrap();
+        let attempts = Attempts::new();
+        let is_due = |history: &[RunRecord]| {
+            is_due(history, &attempts, RunKind::Create, REPO, interval, now)
+        };
+        let succeeded = run(now - Duration::hours(30), true);
+        assert!(!is_due(&[
+            succeeded.clone(),
+            run(now - Duration::minutes(10), false)
+        ]));
+        assert!(is_due(&[succeeded, run(now - Duration::hours(2), false)]));
+        // A recent failure doesn't make a recent success due again
+        assert!(!is_due(&[
+            run(now - Duration::hours(3), true),
+            run(now - Duration::hours(2), false)
+        ]));
+    }
+
+    #[test]
+    fn attempts_missing_from_the_history_delay_retries() {
+        let now = Local::now();
+        let interval = "24H".parse().unwrap();
+        let mut attempts = Attempts::new();
+        attempts.insert(
+            (RunKind::Create, REPO.to_string()),
+            now - Duration::minutes(10),
+        );
+        assert!(!is_due(
+            &[],
+            &attempts,
+            RunKind::Create,
+            REPO,
+            interval,
+            now
+        ));
+        attempts.insert(
+            (RunKind::Create, REPO.to_string()),
+            now - Duration::hours(2),
+        );
+        assert!(is_due(&[], &attempts, RunKind::Create, REPO, interval, now));
+    }
+}
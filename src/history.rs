@@ -0,0 +1,379 @@
+use std::collections::HashSet;
+use std::path::{Path, PathBuf};
+
+use anyhow::anyhow;
+use chrono::{DateTime, Local};
+use serde::{Deserialize, Serialize};
+use tokio::io::AsyncWriteExt;
+
+use crate::cancel::is_cancelled;
+use crate::types::{BorgResult, PrettyBytes};
+
+const HISTORY_FILE_NAME: &str = "history.jsonl";
+
+/// The history file is trimmed to `MAX_HISTORY_RECORDS` once it grows past this, so it stays
+/// cheap for the daemon to re-read every tick.
+const MAX_HISTORY_BYTES: u64 = 2 * 1024 * 1024;
+
+/// How many of the newest records trimming keeps (see `trim_history` for the others it keeps).
+const MAX_HISTORY_RECORDS: usize = 5000;
+
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq, Hash)]
+#[serde(rename_all = "snake_case")]
+pub(crate) enum RunKind {
+    Create,
+    Prune,
+    Compact,
+    Check,
+    Delete,
+}
+
+impl std::fmt::Display for RunKind {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let name = match self {
+            RunKind::Create => "create",
+            RunKind::Prune => "prune",
+            RunKind::Compact => "compact",
+            RunKind::Check => "check",
+            RunKind::Delete => "delete",
+        };
+        write!(f, "{}", name)
+    }
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub(crate) enum RunOutcome {
+    Success,
+    Failure,
+    Cancelled,
+}
+
+impl std::fmt::Display for RunOutcome {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            RunOutcome::Success => write!(f, "success"),
+            RunOutcome::Failure => write!(f, "failure"),
+            RunOutcome::Cancelled => write!(f, "cancelled"),
+        }
+    }
+}
+
+/// The last stats a backup reported before it finished.
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) struct RunStats {
+    pub(crate) original_size: u64,
+    pub(crate) compressed_size: u64,
+    pub(crate) deduplicated_size: u64,
+    pub(crate) num_files: u64,
+}
+
+impl std::fmt::Display for RunStats {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "{} files, {} original, {} deduplicated",
+            self.num_files,
+            PrettyBytes(self.original_size),
+            PrettyBytes(self.deduplicated_size)
+        )
+    }
+}
+
+/// A single create, prune, compact, check or archive deletion run against one repository.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub(crate) struct RunRecord {
+    pub(crate) kind: RunKind,
+    pub(crate) repository: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub(crate) archive: Option<String>,
+    pub(crate) started_at: DateTime<Local>,
+    pub(crate) finished_at: DateTime<Local>,
+    pub(crate) outcome: RunOutcome,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub(crate) stats: Option<RunStats>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub(crate) error: Option<String>,
+}
+
+impl RunRecord {
+    pub(crate) fn new(
+        kind: RunKind,
+        repository: String,
+        started_at: DateTime<Local>,
+        result: &BorgResult<()>,
+    ) -> Self {
+        Self {
+            kind,
+            repository,
+            archive: None,
+            started_at,
+            finished_at: Local::now(),
+            outcome: match result {
+                Ok(_) => RunOutcome::Success,
+                Err(e) if is_cancelled(e) => RunOutcome::Cancelled,
+                Err(_) => RunOutcome::Failure,
+            },
+            stats: None,
+            error: result.as_ref().err().map(|e| e.to_string()),
+        }
+    }
+
+    /// The error if the run failed, otherwise its stats (if any).
+    pub(crate) fn details(&self) -> String {
+        match (&self.error, &self.stats) {
+            (Some(error), _) => error.clone(),
+            (None, Some(stats)) => stats.to_string(),
+            (None, None) => String::new(),
+        }
+    }
+
+    pub(crate) fn duration(&self) -> chrono::Duration {
+        self.finished_at - self.started_at
+    }
+}
+
+/// Where run records are kept. One JSON record per line, oldest first.
+pub(crate) fn history_path() -> BorgResult<PathBuf> {
+    let mut path = dirs::data_dir().ok_or_else(|| anyhow!("Failed to find data directory"))?;
+    path.push("borgtui");
+    path.push(HISTORY_FILE_NAME);
+    Ok(path)
+}
+
+async fn append_record(path: &Path, record: &RunRecord) -> BorgResult<()> {
+    if let Some(parent) = path.parent() {
+        tokio::fs::create_dir_all(parent).await?;
+    }
+    let mut line = serde_json::to_string(record)?;
+    line.push('\n');
+    let mut file = tokio::fs::OpenOptions::new()
+        .create(true)
+        .append(true)
+        .open(path)
+        .await?;
+    file.write_all(line.as_bytes()).await?;
+    if file.metadata().await?.len() > MAX_HISTORY_BYTES {
+        drop(file);
+        trim_history_file(path, MAX_HISTORY_RECORDS).await?;
+    }
+    Ok(())
+}
+
+/// Rewrite the history file with only the records `trim_history` keeps. The trimmed history
+/// is written next to it and renamed over it, so readers never see a partial file.
+async fn trim_history_file(path: &Path, max_records: usize) -> BorgResult<()> {
+    let records = trim_history(load_history_from(path).await?, max_records);
+    let mut contents = String::new();
+    for record in &records {
+        contents.push_str(&serde_json::to_string(record)?);
+        contents.push('\n');
+    }
+    let trimmed_path = path.with_extension("jsonl.trimmed");
+    tokio::fs::write(&trimmed_path, contents).await?;
+    tokio::fs::rename(&trimmed_path, path).await?;
+    Ok(())
+}
+
+/// The newest `max_records` records, along with the newest record and newest successful record
+/// of each kind of run on each repository, so schedules and statuses outlive the trimming.
+fn trim_history(records: Vec<RunRecord>, max_records: usize) -> Vec<RunRecord> {
+    let keep_from = records.len().saturating_sub(max_records);
+    let mut seen = HashSet::new();
+    let mut seen_success = HashSet::new();
+    let mut kept = records
+        .into_iter()
+        .enumerate()
+        .rev()
+        .filter(|(index, record)| {
+            let key = (record.kind, record.repository.clone());
+            let newest = seen.insert(key.clone());
+            let newest_success = record.outcome == RunOutcome::Success && seen_success.insert(key);
+            *index >= keep_from || newest || newest_success
+        })
+        .map(|(_, record)| record)
+        .collect::<Vec<_>>();
+    kept.reverse();
+    kept
+}
+
+/// Persist `record`, logging instead of failing since history is best effort.
+pub(crate) async fn record_run(record: RunRecord) {
+    let result = match history_path() {
+        Ok(path) => append_record(&path, &record).await,
+        Err(e) => Err(e),
+    };
+    if let Err(e) = result {
+        tracing::error!(
+            "Failed to record {} of {} in history: {}",
+            record.kind,
+            record.repository,
+            e
+        );
+    }
+}
+
+/// Load every run record, oldest first. Lines that can't be parsed are skipped.
+pub(crate) async fn load_history() -> BorgResult<Vec<RunRecord>> {
+    load_history_from(&history_path()?).await
+}
+
+async fn load_history_from(path: &Path) -> BorgResult<Vec<RunRecord>> {
+    if !tokio::fs::try_exists(path).await? {
+        return Ok(Vec::new());
+    }
+    let contents = tokio::fs::read_to_string(path).await?;
+    Ok(parse_history(&contents))
+}
+
+fn parse_history(contents: &str) -> Vec<RunRecord> {
+    contents
+        .lines()
+        .filter(|line| !line.trim().is_empty())
+        .filter_map(|line| match serde_json::from_str(line) {
+            Ok(record) => Some(record),
+            Err(e) => {
+                tracing::warn!("Skipping malformed history record: {}", e);
+                None
+            }
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use chrono::TimeZone;
+
+    use super::*;
+    use crate::cancel::Cancelled;
+
+    fn record(kind: RunKind, repository: &str, outcome: RunOutcome, hour: u32) -> RunRecord {
+        let started_at = Local.with_ymd_and_hms(2024, 5, 6, hour, 0, 0).unwrap();
+        RunRecord {
+            kind,
+            repository: repository.to_string(),
+            archive: None,
+            started_at,
+            finished_at: started_at + chrono::Duration::minutes(5),
+            outcome,
+            stats: None,
+            error: None,
+        }
+    }
+
+    #[test]
+    fn records_round_trip_through_json() {
+        let mut original = record(RunKind::Create, "/repo", RunOutcome::Success, 1);
+        original.archive = Some("2024-05-06T01:00:00".to_string());
+        original.stats = Some(RunStats {
+            original_size: 100,
+            compressed_size: 50,
+            deduplicated_size: 10,
+            num_files: 3,
+        });
+        let line = serde_json::to_string(&original).unwrap();
+        assert!(!line.contains('\n'));
+        let parsed: RunRecord = serde_json::from_str(&line).unwrap();
+        assert_eq!(parsed.kind, original.kind);
+        assert_eq!(parsed.repository, original.repository);
+        assert_eq!(parsed.archive, original.archive);
+        assert_eq!(parsed.started_at, original.started_at);
+        assert_eq!(parsed.finished_at, original.finished_at);
+        assert_eq!(parsed.outcome, original.outcome);
+        assert_eq!(parsed.stats, original.stats);
+        assert_eq!(parsed.error, None);
+        // Empty optional fields are left out
+        let bare = serde_json::to_string(&record(RunKind::Check, "/repo", RunOutcome::Failure, 1));
+        assert!(!bare.unwrap().contains("archive"));
+    }
+
+    #[test]
+    fn malformed_and_blank_lines_are_skipped() {
+        let good = serde_json::to_string(&record(RunKind::Prune, "/repo", RunOutcome::Success, 1))
+            .unwrap();
+        let contents = format!("{good}\n\n   \n{{\"kind\": \"create\"\nnot json\n{good}\n");
+        let records = parse_history(&contents);
+        assert_eq!(records.len(), 2);
+        assert!(records.iter().all(|record| record.kind == RunKind::Prune));
+        assert!(parse_history("").is_empty());
+    }
+
+    #[test]
+    fn outcomes_and_errors_come_from_the_result() {
+        let started_at = Local::now();
+        let new = |result: BorgResult<()>| {
+            RunRecord::new(RunKind::Check, "/repo".to_string(), started_at, &result)
+        };
+
+        let success = new(Ok(()));
+        assert_eq!(success.outcome, RunOutcome::Success);
+        assert_eq!(success.error, None);
+        assert!(success.finished_at >= started_at);
+
+        let failure = new(Err(anyhow!("repository is locked")));
+        assert_eq!(failure.outcome, RunOutcome::Failure);
+        assert_eq!(failure.error.as_deref(), Some("repository is locked"));
+        assert_eq!(failure.details(), "repository is locked");
+
+        let cancelled = new(Err(Cancelled.into()));
+        assert_eq!(cancelled.outcome, RunOutcome::Cancelled);
+        assert_eq!(cancelled.error.as_deref(), Some("Cancelled"));
+    }
+
+    #[test]
+    fn trimming_keeps_what_schedules_need() {
+        let mut records = vec![
+            record(RunKind::Check, "/repo", RunOutcome::Success, 1),
+            record(RunKind::Check, "/repo", RunOutcome::Failure, 2),
+            record(RunKind::Compact, "/other", RunOutcome::Success, 3),
+        ];
+        records.extend(
+            (4..10).map(|hour| record(RunKind::Create, "/repo", RunOutcome::Success, hour)),
+        );
+
+        let trimmed = trim_history(records, 2);
+        let kept = trimmed
+            .iter()
+            .map(|record| (record.kind, record.started_at.format("%H").to_string()))
+            .collect::<Vec<_>>();
+        assert_eq!(
+            kept,
+            [
+                // The newest successful and newest check, then the only compact
+                (RunKind::Check, "01".to_string()),
+                (RunKind::Check, "02".to_string()),
+                (RunKind::Compact, "03".to_string()),
+                // The newest two records
+                (RunKind::Create, "08".to_string()),
+                (RunKind::Create, "09".to_string()),
+            ]
+        );
+    }
+
+    #[tokio::test]
+    async fn large_history_files_are_trimmed() {
+        let dir = std::env::temp_dir().join(format!("borgtui-history-{}", std::process::id()));
+        let path = dir.join(HISTORY_FILE_NAME);
+        let mut contents = String::new();
+        let line = serde_json::to_string(&record(RunKind::Create, "/repo", RunOutcome::Success, 1))
+            .unwrap();
+        while contents.len() as u64 <= MAX_HISTORY_BYTES {
+            contents.push_str(&line);
+            contents.push('\n');
+        }
+        tokio::fs::create_dir_all(&dir).await.unwrap();
+        tokio::fs::write(&path, contents).await.unwrap();
+
+        append_record(
+            &path,
+            &record(RunKind::Check, "/repo", RunOutcome::Success, 2),
+        )
+        .await
+        .unwrap();
+        let records = load_history_from(&path).await.unwrap();
+        assert_eq!(records.len(), MAX_HISTORY_RECORDS);
+        assert_eq!(records.last().unwrap().kind, RunKind::Check);
+        assert!(!path.with_extension("jsonl.trimmed").exists());
+        tokio::fs::remove_dir_all(&dir).await.unwrap();
+    }
+}
@@ -0,0 +1,258 @@
+use std::process::Stdio;
+use std::time::Duration;
+
+use anyhow::anyhow;
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    borgtui::CommandResponse,
+    types::{send_error, send_info, BorgResult, CommandResponseSender},
+};
+
+/// Shell commands run around backups and checks. Each command is run with `sh -c`.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub(crate) struct Hooks {
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    before_backup: Vec<String>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    after_backup: Vec<String>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    on_error: Vec<String>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    before_check: Vec<String>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    after_check: Vec<String>,
+    /// How long a single hook command may run before it's killed
+    #[serde(default = "default_hook_timeout_seconds")]
+    timeout_seconds: u64,
+}
+
+// Necessary for serde(default)
+const fn default_hook_timeout_seconds() -> u64 {
+    300
+}
+
+impl Default for Hooks {
+    fn default() -> Self {
+        Self {
+            before_backup: vec![],
+            after_backup: vec![],
+            on_error: vec![],
+            before_check: vec![],
+            after_check: vec![],
+            timeout_seconds: default_hook_timeout_seconds(),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) enum HookKind {
+    BeforeBackup,
+    AfterBackup,
+    OnError,
+    BeforeCheck,
+    AfterCheck,
+}
+
+impl std::fmt::Display for HookKind {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let name = match self {
+            HookKind::BeforeBackup => "before_backup",
+            HookKind::AfterBackup => "after_backup",
+            HookKind::OnError => "on_error",
+            HookKind::BeforeCheck => "before_check",
+            HookKind::AfterCheck => "after_check",
+        };
+        write!(f, "{}", name)
+    }
+}
+
+/// What the hook is running for. Exposed to hooks as `BORGTUI_*` environment variables.
+#[derive(Debug, Clone, Default)]
+pub(crate) struct HookContext {
+    pub(crate) profile: String,
+    /// Newline separated in `BORGTUI_REPOSITORIES`
+    pub(crate) repositories: Vec<String>,
+    pub(crate) archive: Option<String>,
+    pub(crate) error: Option<String>,
+}
+
+impl HookContext {
+    fn env(&self, kind: HookKind) -> Vec<(&'static str, String)> {
+        let mut env = vec![
+            ("BORGTUI_HOOK", kind.to_string()),
+            ("BORGTUI_PROFILE", self.profile.clone()),
+            ("BORGTUI_REPOSITORIES", self.repositories.join("\n")),
+        ];
+        if let [repository] = self.repositories.as_slice() {
+            env.push(("BORGTUI_REPOSITORY", repository.clone()));
+        }
+        if let Some(archive) = &self.archive {
+            env.push(("BORGTUI_ARCHIVE", archive.clone()));
+        }
+        if let Some(error) = &self.error {
+            env.push(("BORGTUI_ERROR", error.clone()));
+        }
+        env
+    }
+}
+
+impl Hooks {
+    pub(crate) fn is_empty(&self) -> bool {
+        *self == Self::default()
+    }
+
+    fn commands(&self, kind: HookKind) -> &[String] {
+        match kind {
+            HookKind::BeforeBackup => &self.before_backup,
+            HookKind::AfterBackup => &self.after_backup,
+            HookKind::OnError => &self.on_error,
+            HookKind::BeforeCheck => &self.before_check,
+            HookKind::AfterCheck => &self.after_check,
+        }
+    }
+
+    /// Run every hook of `kind` in order, stopping at the first one that fails or times out.
+    pub(crate) async fn run(
+        &self,
+        kind: HookKind,
+        context: &HookContext,
+        progress_channel: &CommandResponseSender,
+    ) -> BorgResult<()> {
+        for command in self.commands(kind) {
+            send_info!(
+                progress_channel,
+                format!("Running {} hook: {}", kind, command),
+                "Failed to send hook start info: {}"
+            );
+            let child = tokio::process::Command::new("sh")
+                .arg("-c")
+                .arg(command)
+                .envs(context.env(kind))
+                .stdin(Stdio::null())
+                .stdout(Stdio::piped())
+                .stderr(Stdio::piped())
+                // Anything the hook starts joins its process group, so it can be killed too
+                .process_group(0)
+                .kill_on_drop(true)
+                .spawn()?;
+            let process_group = child.id();
+            let timeout = Duration::from_secs(self.timeout_seconds);
+            let output = match tokio::time::timeout(timeout, child.wait_with_output()).await {
+                Ok(output) => output?,
+                Err(_) => {
+                    if let Some(process_group) = process_group {
+                        // Safety: The group is the hook's, which was spawned as its leader
+                        unsafe { libc::kill(-(process_group as libc::pid_t), libc::SIGKILL) };
+                    }
+                    return Err(anyhow!(
+                        "{} hook `{}` timed out after {}s",
+                        kind,
+                        command,
+                        self.timeout_seconds
+                    ));
+                }
+            };
+            let stdout = String::from_utf8_lossy(&output.stdout);
+            let stderr = String::from_utf8_lossy(&output.stderr);
+            if !output.status.success() {
+                for line in stdout.lines().filter(|line| !line.is_empty()) {
+                    send_info!(progress_channel, format!("[{}] {}", kind, line));
+                }
+                for line in stderr.lines().filter(|line| !line.is_empty()) {
+                    send_error!(progress_channel, format!("[{}] {}", kind, line));
+                }
+                return Err(anyhow!(
+                    "{} hook `{}` failed with {}",
+                    kind,
+                    command,
+                    output.status
+                ));
+            }
+            for line in stdout.lines().chain(stderr.lines()) {
+                if !line.is_empty() {
+                    send_info!(progress_channel, format!("[{}] {}", kind, line));
+                }
+            }
+        }
+        Ok(())
+    }
+
+    /// Run the `on_error` hooks, reporting (rather than returning) any failure.
+    pub(crate) async fn run_on_error(
+        &self,
+        context: &HookContext,
+        error: &anyhow::Error,
+        progress_channel: &CommandResponseSender,
+    ) {
+        let context = HookContext {
+            error: Some(error.to_string()),
+            ..context.clone()
+        };
+        if let Err(e) = self
+            .run(HookKind::OnError, &context, progress_channel)
+            .await
+        {
+            send_error!(progress_channel, format!("Failed to run hook: {}", e));
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn hooks_default_to_empty_and_round_trip() {
+        let hooks: Hooks = serde_json::from_str("{}").unwrap();
+        assert!(hooks.is_empty());
+
+        let hooks: Hooks =
+            serde_json::from_str(r#"{"before_backup": ["pg_dumpall > /tmp/db.sql"]}"#).unwrap();
+        assert!(!hooks.is_empty());
+        let reloaded: Hooks =
+            serde_json::from_str(&serde_json::to_string(&hooks).unwrap()).unwrap();
+        assert_eq!(reloaded, hooks);
+    }
+
+    #[tokio::test]
+    async fn hooks_see_their_context_and_report_failure() {
+        let hooks: Hooks = serde_json::from_str(
+            r#"{"before_backup": ["test \"$BORGTUI_PROFILE:$BORGTUI_ARCHIVE\" = dev:dev-1"]}"#,
+        )
+        .unwrap();
+        let (send, _recv) = tokio::sync::mpsc::channel(16);
+        let mut context = HookContext {
+            profile: "dev".to_string(),
+            archive: Some("dev-1".to_string()),
+            ..Default::default()
+        };
+        assert!(hooks
+            .run(HookKind::BeforeBackup, &context, &send)
+            .await
+            .is_ok());
+        context.profile = "prod".to_string();
+        assert!(hooks
+            .run(HookKind::BeforeBackup, &context, &send)
+            .await
+            .is_err());
+    }
+
+    #[tokio::test]
+    async fn timed_out_hooks_take_their_children_with_them() {
+        let marker = std::env::temp_dir().join(format!("borgtui-hook-{}", std::process::id()));
+        let hooks = Hooks {
+            before_backup: vec![format!("(sleep 2; touch '{}') & wait", marker.display())],
+            timeout_seconds: 1,
+            ..Default::default()
+        };
+        let (send, _recv) = tokio::sync::mpsc::channel(16);
+        let error = hooks
+            .run(HookKind::BeforeBackup, &HookContext::default(), &send)
+            .await
+            .unwrap_err();
+        assert!(error.to_string().contains("timed out"), "{}", error);
+        tokio::time::sleep(Duration::from_secs(2)).await;
+        assert!(!marker.exists());
+    }
+}
@@ -4,7 +4,6 @@ use std::sync::{Arc, Mutex};
 use std::thread::JoinHandle;
 
 use anyhow::{anyhow, bail, Context};
-use backends::borg_provider::hack_unmount;
 use chrono::Duration;
 use notify::Watcher;
 use profiles::{BorgV1OptionsBuilder, RepositoryKind, RepositoryOptions};
@@ -17,14 +16,28 @@ use types::{BackupCreationProgress, CommandResponseSender};
 use walkdir::WalkDir;
 
 use crate::borgtui::{BorgTui, Command, CommandResponse};
-use crate::cli::Action;
+use crate::cancel::{cancel_on_ctrl_c, CancelToken};
+use crate::cli::{Action, OutputFormat};
+use crate::control::{ControlClient, ControlReply, ControlRequest};
+use crate::history::RunRecord;
 use crate::profiles::{Encryption, Profile, Repository};
-use crate::types::{send_error, send_info, BorgResult, PrettyBytes};
+use crate::types::{
+    send_error, send_info, ArchiveDiff, ArchiveSummary, BorgResult, PrettyBytes, PrunePreview,
+    RepositoryArchivesSummary,
+};
 
 mod backends;
 mod borgtui;
+mod cancel;
 mod cli;
+mod control;
+mod daemon;
+mod history;
+mod hooks;
+mod mounts;
 mod profiles;
+mod serve;
+mod tarball;
 mod types;
 
 const QUEUE_SIZE: usize = 1000;
@@ -78,11 +91,29 @@ fn determine_directory_size(
     }
 }
 
+/// List the repository's archives and hand them to the TUI.
+async fn send_archive_list(repo: &Repository, command_response_send: &CommandResponseSender) {
+    match repo.list_archives_with_stats().await {
+        Ok(res) => {
+            if let Err(e) = command_response_send
+                .send(CommandResponse::ListArchiveResult(res))
+                .await
+            {
+                error!("Failed to send ListArchiveResult for {}: {}", repo, e);
+            }
+        }
+        Err(e) => {
+            error!("Failed to list archives for {}: {}", repo, e);
+        }
+    }
+}
+
 /// Returns Ok(true) to exit the program.
 async fn handle_tui_command(
     command: Command,
     command_response_send: CommandResponseSender,
     directory_finder: Arc<Mutex<DirectoryFinder>>,
+    cancel: &mut CancelToken,
 ) -> BorgResult<bool> {
     match command {
         Command::CreateBackup(profile) => {
@@ -91,7 +122,27 @@ async fn handle_tui_command(
                 format!("Starting backup of profile {}", &profile),
                 "Failed to send backup start signal: {}"
             );
-            profile.create_backup(command_response_send).await?;
+            profile
+                .create_backup(command_response_send, cancel.clone())
+                .await?;
+            Ok(false)
+        }
+        Command::Cancel(profile, checkpoint) => {
+            send_info!(
+                command_response_send,
+                "Cancelling running operations...".to_string()
+            );
+            cancel.cancel(checkpoint);
+            // Operations started from now on get a fresh token
+            *cancel = CancelToken::default();
+            if let Some(mut daemon) = ControlClient::connect(profile.name()).await? {
+                daemon
+                    .run_until_done(
+                        &ControlRequest::Command(Box::new(Command::Cancel(profile, checkpoint))),
+                        &command_response_send,
+                    )
+                    .await?;
+            }
             Ok(false)
         }
         Command::UpdateProfileAndSave(mut profile, op, signal_success) => {
@@ -103,7 +154,7 @@ async fn handle_tui_command(
                 "Failed to send 'Saved profile' message: {}"
             );
             command_response_send
-                .send(CommandResponse::ProfileUpdated(profile))
+                .send(CommandResponse::ProfileUpdated(Box::new(profile)))
                 .await?;
             signal_success.store(true, Ordering::SeqCst);
             Ok(false)
@@ -129,45 +180,127 @@ async fn handle_tui_command(
             Ok(false)
         }
         Command::ListArchives(repo) => {
+            tokio::spawn(async move { send_archive_list(&repo, &command_response_send).await });
+            Ok(false)
+        }
+        Command::ListArchiveDirectory(repo, archive, directory) => {
             tokio::spawn(async move {
-                match repo.list_archives().await {
-                    Ok(res) => {
-                        if let Err(e) = command_response_send
-                            .send(CommandResponse::ListArchiveResult(res))
-                            .await
-                        {
-                            error!("Failed to send ListArchiveResult for {}: {}", repo, e);
-                        }
-                    }
-                    Err(e) => {
-                        error!("Failed to list archives for {}: {}", repo, e);
-                    }
+                match repo.list_archive_directory(archive, directory).await {
+                    Ok(listing) => log_on_error!(
+                        command_response_send
+                            .send(CommandResponse::ArchiveDirectoryListing(listing))
+                            .await,
+                        "Failed to send ArchiveDirectoryListing: {}"
+                    ),
+                    Err(e) => send_error!(
+                        command_response_send,
+                        format!("Failed to list archive directory: {}", e)
+                    ),
+                }
+            });
+            Ok(false)
+        }
+        Command::Restore(repo, archive, paths, target) => {
+            tokio::spawn(async move {
+                if let Err(e) = repo
+                    .restore(
+                        archive.clone(),
+                        &paths,
+                        target.clone(),
+                        None,
+                        command_response_send.clone(),
+                    )
+                    .await
+                {
+                    send_error!(command_response_send, format!("Failed to restore: {}", e))
+                } else {
+                    send_info!(
+                        command_response_send,
+                        format!("Restored {} to {}", archive, target.to_string_lossy())
+                    );
+                }
+            });
+            Ok(false)
+        }
+        Command::DiffArchives(repo, first, second) => {
+            tokio::spawn(async move {
+                match repo.diff(first, second).await {
+                    Ok(diff) => log_on_error!(
+                        command_response_send
+                            .send(CommandResponse::ArchiveDiff(diff))
+                            .await,
+                        "Failed to send ArchiveDiff: {}"
+                    ),
+                    Err(e) => send_error!(
+                        command_response_send,
+                        format!("Failed to diff archives: {}", e)
+                    ),
                 }
             });
             Ok(false)
         }
-        Command::CheckRepository(repo) => {
+        Command::CheckProfile(profile) => {
+            let cancel = cancel.clone();
             tokio::spawn(async move {
                 send_info!(
                     command_response_send,
-                    format!("Checking {}", repo),
+                    format!("Checking {}", profile),
                     "Failed to send start checking info: {}"
                 );
                 log_on_error!(
-                    repo.check(command_response_send).await,
+                    profile
+                        .check_repositories(None, command_response_send, cancel)
+                        .await,
                     "Failed to check: {}"
                 );
             });
             Ok(false)
         }
+        Command::DeleteArchive(repo, archive) => {
+            let cancel = cancel.clone();
+            tokio::spawn(async move {
+                if let Err(e) = repo
+                    .delete_archive(archive.clone(), command_response_send.clone(), cancel)
+                    .await
+                {
+                    send_error!(command_response_send, format!("Failed to delete: {}", e));
+                } else {
+                    send_info!(command_response_send, format!("Deleted {}", archive));
+                }
+                send_archive_list(&repo, &command_response_send).await;
+            });
+            Ok(false)
+        }
+        Command::RenameArchive(repo, archive, new_name) => {
+            tokio::spawn(async move {
+                if let Err(e) = repo
+                    .rename_archive(
+                        archive.clone(),
+                        new_name.clone(),
+                        command_response_send.clone(),
+                    )
+                    .await
+                {
+                    send_error!(command_response_send, format!("Failed to rename: {}", e));
+                } else {
+                    send_info!(
+                        command_response_send,
+                        format!("Renamed {} to {}", archive, new_name)
+                    );
+                }
+                send_archive_list(&repo, &command_response_send).await;
+            });
+            Ok(false)
+        }
         Command::Compact(repo) => {
+            let cancel = cancel.clone();
             tokio::spawn(async move {
                 send_info!(
                     command_response_send,
                     format!("Compacting {}", repo),
                     "Failed to send start compacting info: {}"
                 );
-                if let Err(e) = repo.compact(command_response_send.clone()).await {
+                if let Err(e) = repo.compact(command_response_send.clone(), cancel).await {
                     send_error!(command_response_send, format!("Failed to compact: {}", e));
                 } else {
                     send_info!(command_response_send, format!("Compacted {}", repo));
@@ -175,7 +308,8 @@ async fn handle_tui_command(
             });
             Ok(false)
         }
-        Command::Prune(repo, prune_options) => {
+        Command::Prune(repo, prune_options, archive_filter) => {
+            let cancel = cancel.clone();
             tokio::spawn(async move {
                 send_info!(
                     command_response_send,
@@ -184,7 +318,12 @@ async fn handle_tui_command(
                 );
 
                 if let Err(e) = repo
-                    .prune(prune_options, command_response_send.clone())
+                    .prune(
+                        prune_options,
+                        &archive_filter,
+                        command_response_send.clone(),
+                        cancel,
+                    )
                     .await
                 {
                     send_error!(command_response_send, format!("Failed to prune: {}", e))
@@ -194,6 +333,46 @@ async fn handle_tui_command(
             });
             Ok(false)
         }
+        Command::LoadHistory => {
+            tokio::spawn(async move {
+                match history::load_history().await {
+                    Ok(records) => log_on_error!(
+                        command_response_send
+                            .send(CommandResponse::History(records))
+                            .await,
+                        "Failed to send History: {}"
+                    ),
+                    Err(e) => send_error!(
+                        command_response_send,
+                        format!("Failed to load history: {}", e)
+                    ),
+                }
+            });
+            Ok(false)
+        }
+        Command::PruneDryRun(repo, prune_options, archive_filter) => {
+            tokio::spawn(async move {
+                match repo.prune_dry_run(prune_options, &archive_filter).await {
+                    Ok(preview) => log_on_error!(
+                        command_response_send
+                            .send(CommandResponse::PrunePreview(preview))
+                            .await,
+                        "Failed to send PrunePreview: {}"
+                    ),
+                    // The TUI waits on every repo's preview, so it needs to hear about failures too
+                    Err(e) => log_on_error!(
+                        command_response_send
+                            .send(CommandResponse::PrunePreviewFailed(
+                                repo.path(),
+                                e.to_string()
+                            ))
+                            .await,
+                        "Failed to send PrunePreviewFailed: {}"
+                    ),
+                }
+            });
+            Ok(false)
+        }
         Command::GetDirectorySuggestionsFor(directory) => {
             // TODO: This blocks command handling, right?
             tokio::task::spawn_blocking(move || {
@@ -247,7 +426,7 @@ async fn handle_tui_command(
         Command::Unmount(mountpoint) => {
             // TODO: Properly join all of this.
             tokio::spawn(async move {
-                match hack_unmount(PathBuf::from(mountpoint.clone())).await {
+                match mounts::unmount(Path::new(&mountpoint)).await {
                     Ok(_) => {
                         send_info!(
                             command_response_send,
@@ -282,7 +461,7 @@ fn watch_profile_for_changes(
                     match Profile::blocking_open_path(profile_path_clone.clone()) {
                         Ok(profile) => {
                             if let Err(e) = response_send
-                                .blocking_send(CommandResponse::ProfileUpdated(profile))
+                                .blocking_send(CommandResponse::ProfileUpdated(Box::new(profile)))
                             {
                                 error!("Failed to send update profile message: {}", e)
                             }
@@ -312,6 +491,26 @@ async fn setup_tui(profile: Option<String>, watch_profile: bool) -> BorgResult<J
     let (command_send, mut command_recv) = mpsc::channel::<Command>(QUEUE_SIZE);
     let (response_send, response_recv) = mpsc::channel::<CommandResponse>(QUEUE_SIZE);
 
+    // Show progress from a daemon running the same profile
+    if let Some(daemon) = ControlClient::connect(profile.name()).await? {
+        attach_to_daemon(daemon, response_send.clone());
+    }
+
+    // Show mounts left by earlier runs, cleaning up any that died with them
+    match mounts::clean_stale_mounts().await {
+        Ok(mounts) => {
+            for mount in mounts {
+                response_send
+                    .send(CommandResponse::MountResult(
+                        mount.source().to_string(),
+                        mount.mountpoint.to_string_lossy().to_string(),
+                    ))
+                    .await?;
+            }
+        }
+        Err(e) => error!("Failed to clean up stale mounts: {}", e),
+    }
+
     // Profile watcher (sends updates when the config file is manually edited)
     if watch_profile {
         watch_profile_for_changes(profile.profile_path()?, response_send.clone())?;
@@ -323,6 +522,7 @@ async fn setup_tui(profile: Option<String>, watch_profile: bool) -> BorgResult<J
         error!("Failed to add exclude patterns: {}", e);
     }
     let dir_finder = Arc::new(Mutex::new(dir_finder));
+    let mut cancel = CancelToken::default();
     let res = std::thread::spawn(move || {
         let mut tui = BorgTui::new(profile, command_send, response_recv);
         if let Err(e) = tui.run() {
@@ -330,7 +530,14 @@ async fn setup_tui(profile: Option<String>, watch_profile: bool) -> BorgResult<J
         }
     });
     while let Some(command) = command_recv.recv().await {
-        match handle_tui_command(command, response_send.clone(), dir_finder.clone()).await {
+        match handle_tui_command(
+            command,
+            response_send.clone(),
+            dir_finder.clone(),
+            &mut cancel,
+        )
+        .await
+        {
             Ok(true) => return Ok(res),
             Err(e) => {
                 error!("Failed to handle tui command: {}", e);
@@ -342,6 +549,35 @@ async fn setup_tui(profile: Option<String>, watch_profile: bool) -> BorgResult<J
     Ok(res)
 }
 
+fn attach_to_daemon(mut daemon: ControlClient, response_send: CommandResponseSender) {
+    tokio::spawn(async move {
+        log_on_error!(
+            daemon.send(&ControlRequest::Subscribe).await,
+            "Failed to subscribe to the daemon: {}"
+        );
+        send_info!(response_send, "Attached to the running daemon".to_string());
+        loop {
+            match daemon.next_reply().await {
+                Ok(Some(ControlReply::Response(response))) => {
+                    log_on_error!(
+                        response_send.send(*response).await,
+                        "Failed to forward daemon progress: {}"
+                    )
+                }
+                Ok(Some(_)) => {}
+                Ok(None) => {
+                    send_info!(response_send, "The daemon exited".to_string());
+                    return;
+                }
+                Err(e) => {
+                    send_error!(response_send, format!("Lost the daemon connection: {}", e));
+                    return;
+                }
+            }
+        }
+    });
+}
+
 async fn handle_command_response(command_response_recv: mpsc::Receiver<CommandResponse>) {
     let mut command_response_recv = command_response_recv;
     while let Some(message) = command_response_recv.recv().await {
@@ -365,6 +601,17 @@ async fn handle_command_response(command_response_recv: mpsc::Receiver<CommandRe
                 BackupCreationProgress::Finished => {
                     info!("Finished backup for {}", msg.repository)
                 }
+                BackupCreationProgress::Cancelled { checkpoint } => {
+                    info!(
+                        "Cancelled backup for {}{}",
+                        msg.repository,
+                        if checkpoint {
+                            " (keeping a checkpoint archive)"
+                        } else {
+                            ""
+                        }
+                    )
+                }
             },
             CommandResponse::CheckProgress(check_progress) => {
                 info!("[{}] {}", check_progress.repo_loc, check_progress.message);
@@ -392,6 +639,21 @@ async fn handle_command_response(command_response_recv: mpsc::Receiver<CommandRe
             CommandResponse::MountResult(_, _) => {
                 error!("Received MountResult in non-interactive!")
             }
+            CommandResponse::ArchiveDirectoryListing(_) => {
+                error!("Received ArchiveDirectoryListing in non-interactive!")
+            }
+            CommandResponse::ArchiveDiff(_) => {
+                error!("Received ArchiveDiff in non-interactive!")
+            }
+            CommandResponse::PrunePreview(_) => {
+                error!("Received PrunePreview in non-interactive!")
+            }
+            CommandResponse::PrunePreviewFailed(_, _) => {
+                error!("Received PrunePreviewFailed in non-interactive!")
+            }
+            CommandResponse::History(_) => {
+                error!("Received History in non-interactive!")
+            }
             CommandResponse::Error(error_message) => error!(error_message),
             CommandResponse::ProfileUpdated(_profile) => info!("Profile updated."),
         }
@@ -437,10 +699,88 @@ fn print_repo_list(repos: &[Repository]) {
     }
 }
 
+/// Like `print_repo_list`, followed by what each repository backs up.
+fn print_repo_sources(profile: &Profile) {
+    for repo in profile.repositories() {
+        print_repo_list(std::slice::from_ref(repo));
+        for path in profile.backup_paths_for(repo) {
+            println!("    {}", path.display());
+        }
+        for pattern in profile.exclude_patterns_for(repo) {
+            println!("    exclude {}", pattern);
+        }
+        if profile.exclude_caches_for(repo) {
+            println!("    exclude caches");
+        }
+    }
+}
+
+fn print_archive_diff(diff: &ArchiveDiff) {
+    for entry in &diff.entries {
+        println!(
+            "{:<16} {:>20} {}",
+            entry.change.to_string(),
+            entry.size_change(),
+            entry.path
+        );
+    }
+    println!("{}", diff.summary());
+}
+
+fn print_prune_preview(preview: &PrunePreview) {
+    println!(
+        "{}: {} to remove, {} to keep",
+        preview.repository,
+        preview.num_removed(),
+        preview.decisions.len() - preview.num_removed()
+    );
+    for decision in &preview.decisions {
+        println!(
+            "{:<8} {:<24} {}",
+            if decision.keep { "keep" } else { "remove" },
+            decision.reason.as_deref().unwrap_or("-"),
+            decision.archive
+        );
+    }
+}
+
+fn print_run_record(record: &RunRecord) {
+    println!(
+        "{} {:>6}s {:<8} {:<8} {} {} {}",
+        record.started_at.format("%Y-%m-%d %H:%M:%S"),
+        record.duration().num_seconds(),
+        record.kind.to_string(),
+        record.outcome.to_string(),
+        record.repository,
+        record.archive.as_deref().unwrap_or(""),
+        record.details()
+    );
+}
+
+fn print_json<T: serde::Serialize>(value: &T) -> BorgResult<()> {
+    println!("{}", serde_json::to_string_pretty(value)?);
+    Ok(())
+}
+
+/// Returned by `status` when repositories are stale or unreachable. Unlike `status`'s other
+/// errors, this makes borgtui exit non-zero.
+#[derive(Debug)]
+struct UnhealthyRepositories(usize);
+
+impl std::fmt::Display for UnhealthyRepositories {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{} repositories are stale or unreachable", self.0)
+    }
+}
+
+impl std::error::Error for UnhealthyRepositories {}
+
 async fn handle_action(
     action: Action,
     profile_name: Option<String>,
+    output: OutputFormat,
     command_response_send: CommandResponseSender,
+    cancel: CancelToken,
 ) -> BorgResult<()> {
     match action {
         Action::Init {
@@ -474,14 +814,40 @@ async fn handle_action(
             info!("Initialized Repository '{}' in {}", location, profile);
             Ok(())
         }
-        Action::Create => {
+        Action::Create { .. } => {
             let profile = Profile::open_or_create(&profile_name).await?;
+            if let Some(mut daemon) = ControlClient::connect(profile.name()).await? {
+                info!("Asking the running daemon to back up {}", profile);
+                let request =
+                    ControlRequest::Command(Box::new(Command::CreateBackup(profile.clone())));
+                let backup = daemon.run_until_done(&request, &command_response_send);
+                tokio::pin!(backup);
+                return tokio::select! {
+                    res = &mut backup => res,
+                    _ = cancel.cancelled() => {
+                        if let Some(mut daemon) = ControlClient::connect(profile.name()).await? {
+                            let checkpoint = cancel.wants_checkpoint();
+                            daemon
+                                .run_until_done(
+                                    &ControlRequest::Command(Box::new(Command::Cancel(
+                                        profile.clone(),
+                                        checkpoint,
+                                    ))),
+                                    &command_response_send,
+                                )
+                                .await?;
+                        }
+                        backup.await?;
+                        cancel.check()
+                    }
+                };
+            }
             info!("Creating backup for profile {}", profile);
             let handle = profile
-                .create_backup_with_notification(command_response_send)
+                .create_backup_with_notification(command_response_send, cancel.clone())
                 .await?;
             handle.await?;
-            Ok(())
+            cancel.check()
         }
         Action::Add { directory } => {
             let mut profile = Profile::open_or_create(&profile_name).await?;
@@ -579,24 +945,39 @@ async fn handle_action(
         } => {
             let profile = Profile::open_or_create(&profile_name).await?;
             let timeout_duration_secs = profile.action_timeout_seconds() as i64;
+            let mut json_output = Vec::new();
             for repo in profile.active_repositories().filter(|repo| {
                 repository
                     .as_ref()
                     .map(|rr| rr.as_str() == repo.path.as_str())
                     .unwrap_or(true)
             }) {
+                // A repo which can't be listed is reported without stopping the others
                 let list_archives_per_repo = match tokio::time::timeout(
                     Duration::seconds(timeout_duration_secs).to_std().unwrap(),
-                    repo.list_archives(),
+                    repo.list_archives_with_stats(),
                 )
                 .await
                 {
-                    Ok(list_archive_result) => list_archive_result?,
-                    Err(_timeout_error) => {
-                        error!(
-                            "Timeout ({}s) while attempting to list repo {}",
-                            timeout_duration_secs, repo
-                        );
+                    Ok(list_archive_result) => list_archive_result
+                        .map_err(|e| format!("Failed to list repo {}: {}", repo.path(), e)),
+                    Err(_timeout_error) => Err(format!(
+                        "Timeout ({}s) while attempting to list repo {}",
+                        timeout_duration_secs,
+                        repo.path()
+                    )),
+                };
+                let list_archives_per_repo = match list_archives_per_repo {
+                    Ok(list_archives_per_repo) => list_archives_per_repo,
+                    Err(message) => {
+                        error!("{}", message);
+                        if output == OutputFormat::Json {
+                            json_output.push(RepositoryArchivesSummary {
+                                repository: repo.summary(),
+                                archives: Vec::new(),
+                                error: Some(message),
+                            });
+                        }
                         continue;
                     }
                 };
@@ -604,15 +985,42 @@ async fn handle_action(
                 if !all {
                     to_skip = list_archives_per_repo.archives.len().saturating_sub(count);
                 }
-                for archives in list_archives_per_repo.archives.iter().skip(to_skip) {
-                    info!("{}::{}", repo.path(), archives.name);
+                let archives = list_archives_per_repo.archives.iter().skip(to_skip);
+                match output {
+                    OutputFormat::Text => {
+                        for archive in archives {
+                            let details = archive.details();
+                            if details.is_empty() {
+                                info!("{}::{}", repo.path(), archive.name);
+                            } else {
+                                info!("{}::{} ({})", repo.path(), archive.name, details);
+                            }
+                        }
+                    }
+                    OutputFormat::Json => json_output.push(RepositoryArchivesSummary {
+                        repository: repo.summary(),
+                        archives: archives.map(ArchiveSummary::from).collect(),
+                        error: None,
+                    }),
                 }
             }
+            if output == OutputFormat::Json {
+                print_json(&json_output)?;
+            }
             Ok(())
         }
         Action::ListRepos => {
             let profile = Profile::open_or_create(&profile_name).await?;
-            print_repo_list(profile.repositories());
+            match output {
+                OutputFormat::Text => print_repo_sources(&profile),
+                OutputFormat::Json => print_json(
+                    &profile
+                        .repositories()
+                        .iter()
+                        .map(|repo| profile.repository_sources_summary(repo))
+                        .collect::<Vec<_>>(),
+                )?,
+            }
             Ok(())
         }
         Action::LsRepo { repository } => {
@@ -629,8 +1037,19 @@ async fn handle_action(
                     // Exact match or close enough
                     1 => {
                         let repo = prefix_matches[0];
-                        let repo_info = serde_json::to_string_pretty(repo)?;
-                        println!("{}", repo_info);
+                        match output {
+                            OutputFormat::Text => print_json(repo)?,
+                            OutputFormat::Json => {
+                                print_json(&profile.repository_sources_summary(repo))?
+                            }
+                        }
+                    }
+                    // The json output must stay parseable, so don't print suggestions
+                    0 if output == OutputFormat::Json => {
+                        bail!("Repository '{}' not found.", repository)
+                    }
+                    _ if output == OutputFormat::Json => {
+                        bail!("Repository '{}' is ambiguous.", repository)
                     }
                     // No dice
                     0 => {
@@ -654,7 +1073,12 @@ async fn handle_action(
                     }
                 }
             } else {
-                print_repo_list(repos);
+                match output {
+                    OutputFormat::Text => print_repo_list(repos),
+                    OutputFormat::Json => {
+                        print_json(&repos.iter().map(Repository::summary).collect::<Vec<_>>())?
+                    }
+                }
             }
             Ok(())
         }
@@ -664,6 +1088,41 @@ async fn handle_action(
             info!("Updated config for {}", profile);
             Ok(())
         }
+        Action::ValidateConfig { path } => {
+            let path = match path {
+                Some(path) => path,
+                None => {
+                    let name = profile_name
+                        .as_deref()
+                        .unwrap_or(Profile::DEFAULT_PROFILE_NAME);
+                    let paths = Profile::profile_paths_for_name(name)?;
+                    if let [used, ignored @ ..] = paths.as_slice() {
+                        for ignored in ignored {
+                            warn!(
+                                "Ignoring {} as {} takes precedence",
+                                ignored.display(),
+                                used.display()
+                            );
+                        }
+                    }
+                    paths
+                        .into_iter()
+                        .next()
+                        .ok_or_else(|| anyhow!("There's no profile named {}", name))?
+                }
+            };
+            let profile = Profile::blocking_open_path(&path)?;
+            let problems = profile.validate();
+            match output {
+                OutputFormat::Text => problems.iter().for_each(|problem| println!("{}", problem)),
+                OutputFormat::Json => print_json(&problems)?,
+            }
+            if !problems.is_empty() {
+                bail!("Found {} problem(s) in {}", problems.len(), path.display());
+            }
+            info!("{} is valid", path.display());
+            Ok(())
+        }
         Action::SetPassword {
             repo,
             passphrase_loc,
@@ -678,70 +1137,67 @@ async fn handle_action(
         }
         Action::Compact => {
             let profile = Profile::open_or_create(&profile_name).await?;
-            for repo in profile.active_repositories() {
-                repo.compact(command_response_send.clone()).await?;
+            for repo in profile.writable_repositories() {
+                repo.compact(command_response_send.clone(), cancel.clone())
+                    .await?;
                 info!("Finished compacting {}", repo);
             }
             Ok(())
         }
-        Action::Prune => {
+        Action::Prune { dry_run: true } => {
             let profile = Profile::open_or_create(&profile_name).await?;
-            for repo in profile.active_repositories() {
-                repo.prune(profile.prune_options(), command_response_send.clone())
-                    .await?;
+            let mut previews = Vec::new();
+            for repo in profile.writable_repositories() {
+                previews.push(
+                    repo.prune_dry_run(
+                        profile.prune_options_for(repo),
+                        &profile.archive_filter_for(repo),
+                    )
+                    .await?,
+                );
+            }
+            match output {
+                OutputFormat::Json => print_json(&previews)?,
+                OutputFormat::Text => previews.iter().for_each(print_prune_preview),
+            }
+            Ok(())
+        }
+        Action::Prune { dry_run: false } => {
+            let profile = Profile::open_or_create(&profile_name).await?;
+            for repo in profile.writable_repositories() {
+                repo.prune(
+                    profile.prune_options_for(repo),
+                    &profile.archive_filter_for(repo),
+                    command_response_send.clone(),
+                    cancel.clone(),
+                )
+                .await?;
                 info!("Finished pruning {}", repo);
             }
             Ok(())
         }
         Action::Check { only_these_repos } => {
             let profile = Profile::open_or_create(&profile_name).await?;
-            let check_semaphore = Arc::new(Semaphore::new(0));
-            let successful = Arc::new(AtomicBool::new(true));
-            for repo in profile.active_repositories() {
-                let should_check = only_these_repos
-                    .as_ref()
-                    .map(|repos_to_check| repos_to_check.contains(&repo.path()))
-                    .unwrap_or(true);
-                if !should_check {
-                    tracing::info!("Skipping verification of {}", repo.path());
-                    check_semaphore.add_permits(1);
-                    continue;
+            let failed = profile
+                .check_repositories(
+                    only_these_repos.as_deref(),
+                    command_response_send.clone(),
+                    cancel.clone(),
+                )
+                .await?;
+            for repo in &failed {
+                tracing::error!("Verification failed for repository: {}", repo);
+                if let Err(e) = show_notification(
+                    &format!("Verification Failed for {}!", repo),
+                    "Please check BorgTUI's logs for more information.",
+                    EXTENDED_NOTIFICATION_DURATION,
+                )
+                .await
+                {
+                    error!("Failed to show notification popup: {}", e);
                 }
-                tracing::info!("Starting verification of {}", repo.path());
-                let successful_clone = successful.clone();
-                let check_semaphore_clone = check_semaphore.clone();
-                let repo_clone = repo.clone();
-                let progress_channel = command_response_send.clone();
-                // TODO: do this spawn inside of the provider
-                tokio::spawn(async move {
-                    let res = match repo_clone.check(progress_channel).await {
-                        Ok(res) => res,
-                        Err(e) => {
-                            error!("Verification failed: {e}");
-                            false
-                        }
-                    };
-                    if !res {
-                        tracing::error!("Verification failed for repository: {}", repo_clone);
-                        log_on_error!(
-                            show_notification(
-                                &format!("Verification Failed for {}!", repo_clone),
-                                "Please check BorgTUI's logs for more information.",
-                                EXTENDED_NOTIFICATION_DURATION,
-                            )
-                            .await,
-                            "Failed to show notification popup: {}"
-                        );
-                    }
-
-                    successful_clone.fetch_and(res, Ordering::SeqCst);
-                    check_semaphore_clone.add_permits(1);
-                });
             }
-            let _ = check_semaphore
-                .acquire_many(profile.num_active_repositories() as u32)
-                .await?;
-            let title = if successful.load(Ordering::SeqCst) {
+            let title = if failed.is_empty() {
                 "Backup Verification Successful!"
             } else {
                 "Backup Verification FAILED!"
@@ -751,7 +1207,10 @@ async fn handle_action(
             show_notification(title, &message, EXTENDED_NOTIFICATION_DURATION).await?;
             Ok(())
         }
-        Action::Repair { only_these_repos } => {
+        Action::Repair {
+            dry_run,
+            only_these_repos,
+        } => {
             let profile = Profile::open_or_create(&profile_name).await?;
             let repair_semaphore = Arc::new(Semaphore::new(0));
             let successful = Arc::new(AtomicBool::new(true));
@@ -770,9 +1229,10 @@ async fn handle_action(
                 let repair_semaphore_clone = repair_semaphore.clone();
                 let repo_clone = repo.clone();
                 let progress_channel = command_response_send.clone();
+                let cancel = cancel.clone();
                 // TODO: do this spawn inside of the provider
                 tokio::spawn(async move {
-                    let res = match repo_clone.repair(progress_channel).await {
+                    let res = match repo_clone.repair(dry_run, progress_channel, cancel).await {
                         Ok(res) => res,
                         Err(e) => {
                             error!("Repair failed: {e}");
@@ -780,10 +1240,11 @@ async fn handle_action(
                         }
                     };
                     if !res {
-                        tracing::error!("Repair failed for repository: {}", repo_clone);
+                        let operation = if dry_run { "Repair dry run" } else { "Repair" };
+                        tracing::error!("{} failed for repository: {}", operation, repo_clone);
                         log_on_error!(
                             show_notification(
-                                &format!("Repair failed for {}!", repo_clone),
+                                &format!("{} failed for {}!", operation, repo_clone),
                                 "Please check BorgTUI's logs for more information.",
                                 EXTENDED_NOTIFICATION_DURATION,
                             )
@@ -799,10 +1260,11 @@ async fn handle_action(
             let _ = repair_semaphore
                 .acquire_many(profile.num_active_repositories() as u32)
                 .await?;
-            let title = if successful.load(Ordering::SeqCst) {
-                "Backup Repair Successful!"
-            } else {
-                "Backup Repair FAILED!"
+            let title = match (dry_run, successful.load(Ordering::SeqCst)) {
+                (true, true) => "Backup Repair Dry Run Finished",
+                (true, false) => "Backup Repair Dry Run Found Problems!",
+                (false, true) => "Backup Repair Successful!",
+                (false, false) => "Backup Repair FAILED!",
             };
             let message = format!("Profile: {}", profile.name());
             info!("{}", message);
@@ -836,6 +1298,8 @@ async fn handle_action(
         } => {
             let profile = Profile::open_or_create(&profile_name).await?;
             let repo = profile.find_repo_from_mount_src(&repository_path)?;
+            // A stale mount would otherwise stop the mountpoint from being reused
+            mounts::clean_stale_mounts().await?;
             repo.mount(repository_path, mountpoint.clone()).await?;
             if !do_not_open_in_gui_file_manager {
                 if let Err(e) = open_path_in_gui_file_manager(mountpoint) {
@@ -844,8 +1308,158 @@ async fn handle_action(
             }
             Ok(())
         }
+        Action::Restore {
+            archive,
+            paths,
+            target,
+            strip_components,
+        } => {
+            let profile = Profile::open_or_create(&profile_name).await?;
+            let repo = profile.find_repo_from_mount_src(&archive)?;
+            repo.restore(
+                archive.clone(),
+                &paths,
+                target.clone(),
+                strip_components,
+                command_response_send,
+            )
+            .await?;
+            info!("Restored {} to {}", archive, target.to_string_lossy());
+            Ok(())
+        }
+        Action::ExportTar {
+            archive,
+            paths,
+            output_file,
+        } => {
+            let profile = Profile::open_or_create(&profile_name).await?;
+            let repo = profile.find_repo_from_mount_src(&archive)?;
+            repo.export_tar(archive.clone(), &paths, &output_file, cancel)
+                .await?;
+            info!("Exported {} to {}", archive, output_file.to_string_lossy());
+            Ok(())
+        }
+        Action::ImportTar {
+            tarball,
+            repository,
+        } => {
+            let profile = Profile::open_or_create(&profile_name).await?;
+            let repos = match repository {
+                Some(repository) => vec![profile.find_repo_from_mount_src(&repository)?],
+                None => profile.writable_repositories().cloned().collect(),
+            };
+            if repos.is_empty() {
+                bail!("{} has no writable repositories to import into", profile);
+            }
+            if repos.len() > 1 && tarball::is_stdio(&tarball) {
+                bail!("A tarball from stdin can only be imported into one repository, pick it with --repository");
+            }
+            let now = chrono::Local::now();
+            for repo in repos {
+                let archive_name = profile.archive_name_for(&repo, &now)?;
+                info!(
+                    "Importing {} into {}::{}",
+                    tarball.to_string_lossy(),
+                    repo.path(),
+                    archive_name
+                );
+                repo.import_tar(
+                    archive_name.clone(),
+                    tarball.clone(),
+                    command_response_send.clone(),
+                    cancel.clone(),
+                )
+                .await?;
+                info!("Created {}::{}", repo.path(), archive_name);
+            }
+            Ok(())
+        }
+        Action::Delete { archive } => {
+            let profile = Profile::open_or_create(&profile_name).await?;
+            let repo = profile.find_repo_from_mount_src(&archive)?;
+            repo.delete_archive(archive.clone(), command_response_send, cancel)
+                .await?;
+            info!("Deleted {}", archive);
+            Ok(())
+        }
+        Action::Rename { archive, new_name } => {
+            let profile = Profile::open_or_create(&profile_name).await?;
+            let repo = profile.find_repo_from_mount_src(&archive)?;
+            repo.rename_archive(archive.clone(), new_name.clone(), command_response_send)
+                .await?;
+            info!("Renamed {} to {}::{}", archive, repo.path(), new_name);
+            Ok(())
+        }
+        Action::Diff { first, second } => {
+            let profile = Profile::open_or_create(&profile_name).await?;
+            let repo = profile.find_repo_from_mount_src(&first)?;
+            let diff = repo.diff(first, second).await?;
+            match output {
+                OutputFormat::Text => print_archive_diff(&diff),
+                OutputFormat::Json => print_json(&diff)?,
+            }
+            Ok(())
+        }
+        Action::Daemon => daemon::run(profile_name, command_response_send, cancel).await,
+        Action::Serve {
+            repository_path,
+            bind,
+            token,
+            webdav,
+        } => {
+            let profile = Profile::open_or_create(&profile_name).await?;
+            let repo = profile.find_repo_from_mount_src(&repository_path)?;
+            let token = match token {
+                Some(token) => token,
+                None => serve::random_token()?,
+            };
+            let options = serve::ServeOptions {
+                bind,
+                token,
+                webdav,
+            };
+            serve::serve(repo, repository_path, options, cancel).await
+        }
+        Action::Status => {
+            let profile = Profile::open_or_create(&profile_name).await?;
+            let statuses = match ControlClient::connect(profile.name()).await? {
+                Some(mut daemon) => {
+                    daemon.send(&ControlRequest::Status).await?;
+                    match daemon.next_reply().await? {
+                        Some(ControlReply::Status(statuses)) => statuses,
+                        Some(ControlReply::Failed(e)) => anyhow::bail!(e),
+                        _ => anyhow::bail!("Unexpected reply from the daemon"),
+                    }
+                }
+                None => profile.repository_statuses().await?,
+            };
+            match output {
+                OutputFormat::Text => statuses.iter().for_each(|status| println!("{}", status)),
+                OutputFormat::Json => print_json(&statuses)?,
+            }
+            let num_unhealthy = statuses
+                .iter()
+                .filter(|status| !status.is_healthy())
+                .count();
+            if num_unhealthy > 0 {
+                return Err(UnhealthyRepositories(num_unhealthy).into());
+            }
+            Ok(())
+        }
+        Action::History { repository, limit } => {
+            let mut records = history::load_history().await?;
+            if let Some(repository) = repository {
+                records.retain(|record| record.repository == repository);
+            }
+            let records = &records[records.len().saturating_sub(limit)..];
+            match output {
+                OutputFormat::Text => records.iter().for_each(print_run_record),
+                OutputFormat::Json => print_json(&records)?,
+            }
+            Ok(())
+        }
         Action::Umount { mountpoint } => {
-            hack_unmount(mountpoint.clone()).await?;
+            mounts::unmount(&mountpoint).await?;
             info!("Successfully unmounted {}", mountpoint.to_string_lossy());
             Ok(())
         }
@@ -918,13 +1532,28 @@ fn main() -> BorgResult<()> {
     let file_appender = tracing_appender::rolling::hourly("/tmp", "borgtui.log");
     let (non_blocking, _guard) = tracing_appender::non_blocking(file_appender);
     let subscriber = FmtSubscriber::builder().with_max_level(tracing::Level::DEBUG);
-    if is_noninteractive {
+    let stdout_is_output = args.output == OutputFormat::Json
+        || args
+            .action
+            .as_ref()
+            .is_some_and(|action| action.writes_to_stdout());
+    if is_noninteractive && stdout_is_output {
+        // Keep stdout clean for the json document or tarball
+        tracing::subscriber::set_global_default(subscriber.with_writer(std::io::stderr).finish())
+            .with_context(|| "setting default subscriber failed")?;
+    } else if is_noninteractive {
         tracing::subscriber::set_global_default(subscriber.finish())
             .with_context(|| "setting default subscriber failed")?;
     } else {
         tracing::subscriber::set_global_default(subscriber.with_writer(non_blocking).finish())
             .with_context(|| "setting default subscriber failed")?;
     }
+    // Rustic reports through the log crate (e.g. what a repair dry run would fix). Its debug
+    // logs, and those of the crates below it, would drown out ours.
+    tracing_log::LogTracer::builder()
+        .with_max_level(tracing_log::log::LevelFilter::Info)
+        .init()
+        .with_context(|| "setting the log crate's logger failed")?;
 
     let mut tui_join_handle = None;
     tokio::runtime::Builder::new_multi_thread()
@@ -933,12 +1562,30 @@ fn main() -> BorgResult<()> {
         .block_on(async {
             let res = match args.action {
                 Some(action) => {
+                    let cancel = CancelToken::default();
+                    if action.is_cancellable() {
+                        let checkpoint = matches!(
+                            action,
+                            Action::Create {
+                                checkpoint_on_cancel: true
+                            }
+                        );
+                        tokio::spawn(cancel_on_ctrl_c(cancel.clone(), checkpoint));
+                    }
                     let (send, recv) = mpsc::channel::<CommandResponse>(QUEUE_SIZE);
                     let handle = tokio::spawn(async move { handle_command_response(recv).await });
-                    if let Err(e) = handle_action(action, args.borgtui_profile, send).await {
-                        error!("Error handling CLI action: {}", e)
-                    };
-                    handle.await
+                    let fails_with_exit_status = action.fails_with_exit_status();
+                    let action_result =
+                        handle_action(action, args.borgtui_profile, args.output, send, cancel)
+                            .await;
+                    let res = handle.await;
+                    if let Err(e) = action_result {
+                        error!("Error handling CLI action: {}", e);
+                        if fails_with_exit_status || e.is::<UnhealthyRepositories>() {
+                            std::process::exit(1);
+                        }
+                    }
+                    res
                 }
                 None => {
                     match setup_tui(args.borgtui_profile, args.watch_profile).await {
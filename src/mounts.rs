@@ -0,0 +1,311 @@
+use std::ffi::OsString;
+use std::os::unix::ffi::OsStringExt;
+use std::path::{Path, PathBuf};
+
+use anyhow::{anyhow, bail};
+use chrono::{DateTime, Local};
+use serde::{Deserialize, Serialize};
+use tokio::sync::Mutex;
+
+use crate::profiles::{Repository, RepositoryKind};
+use crate::types::BorgResult;
+
+const MOUNTS_FILE_NAME: &str = "mounts.jsonl";
+const MOUNT_LOG_FILE_NAME: &str = "mounts.log";
+
+/// Held while the registry is read and rewritten so concurrent updates aren't lost.
+static REGISTRY_LOCK: Mutex<()> = Mutex::const_new(());
+
+/// A repository or archive BorgTUI mounted.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub(crate) struct MountRecord {
+    pub(crate) repository: String,
+    /// The archive (repo::archive), or None if the whole repository is mounted
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub(crate) archive: Option<String>,
+    pub(crate) mountpoint: PathBuf,
+    pub(crate) kind: RepositoryKind,
+    /// The process serving the mount. It's BorgTUI's own for rustic, and unknown for borg
+    /// since `borg mount` daemonizes.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub(crate) pid: Option<u32>,
+    pub(crate) mounted_at: DateTime<Local>,
+}
+
+impl MountRecord {
+    pub(crate) fn new(
+        repo: &Repository,
+        given_repository_path: &str,
+        mountpoint: &Path,
+        pid: Option<u32>,
+    ) -> BorgResult<Self> {
+        Ok(Self {
+            repository: repo.path(),
+            archive: given_repository_path
+                .contains("::")
+                .then(|| given_repository_path.to_string()),
+            mountpoint: std::path::absolute(mountpoint)?,
+            kind: repo.kind(),
+            pid,
+            mounted_at: Local::now(),
+        })
+    }
+
+    /// What's mounted, the archive or the repository
+    pub(crate) fn source(&self) -> &str {
+        self.archive.as_deref().unwrap_or(&self.repository)
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum MountState {
+    Mounted,
+    /// Still mounted, but nothing is serving it anymore
+    Stale,
+    Unmounted,
+}
+
+/// Where mount records are kept. One JSON record per line, oldest first.
+pub(crate) fn mounts_path() -> BorgResult<PathBuf> {
+    let mut path = dirs::data_dir().ok_or_else(|| anyhow!("Failed to find data directory"))?;
+    path.push("borgtui");
+    path.push(MOUNTS_FILE_NAME);
+    Ok(path)
+}
+
+/// Where restic mounts write their errors. Restic outlives BorgTUI, so its stderr can't be
+/// a pipe BorgTUI reads.
+pub(crate) fn mount_log_path() -> BorgResult<PathBuf> {
+    let mut path = mounts_path()?;
+    path.set_file_name(MOUNT_LOG_FILE_NAME);
+    Ok(path)
+}
+
+/// Open the mount log for appending, to be handed to a mount process as its stderr.
+pub(crate) async fn open_mount_log() -> BorgResult<std::fs::File> {
+    let path = mount_log_path()?;
+    if let Some(parent) = path.parent() {
+        tokio::fs::create_dir_all(parent).await?;
+    }
+    let file = tokio::fs::OpenOptions::new()
+        .create(true)
+        .append(true)
+        .open(&path)
+        .await?;
+    Ok(file.into_std().await)
+}
+
+/// Load every mount record. Lines that can't be parsed are skipped.
+pub(crate) async fn load_mounts() -> BorgResult<Vec<MountRecord>> {
+    let path = mounts_path()?;
+    if !tokio::fs::try_exists(&path).await? {
+        return Ok(Vec::new());
+    }
+    let contents = tokio::fs::read_to_string(&path).await?;
+    Ok(contents
+        .lines()
+        .filter(|line| !line.trim().is_empty())
+        .filter_map(|line| match serde_json::from_str(line) {
+            Ok(record) => Some(record),
+            Err(e) => {
+                tracing::warn!("Skipping malformed mount record: {}", e);
+                None
+            }
+        })
+        .collect())
+}
+
+async fn save_mounts(records: &[MountRecord]) -> BorgResult<()> {
+    let path = mounts_path()?;
+    if let Some(parent) = path.parent() {
+        tokio::fs::create_dir_all(parent).await?;
+    }
+    let mut contents = String::new();
+    for record in records {
+        contents.push_str(&serde_json::to_string(record)?);
+        contents.push('\n');
+    }
+    tokio::fs::write(&path, contents).await?;
+    Ok(())
+}
+
+/// Record a new mount, replacing whatever was recorded at its mountpoint before.
+pub(crate) async fn register_mount(record: MountRecord) -> BorgResult<()> {
+    let _registry_guard = REGISTRY_LOCK.lock().await;
+    let mut records = load_mounts().await?;
+    records.retain(|existing| existing.mountpoint != record.mountpoint);
+    records.push(record);
+    save_mounts(&records).await
+}
+
+pub(crate) async fn forget_mount(mountpoint: &Path) -> BorgResult<()> {
+    let mountpoint = std::path::absolute(mountpoint)?;
+    let _registry_guard = REGISTRY_LOCK.lock().await;
+    let mut records = load_mounts().await?;
+    let num_records = records.len();
+    records.retain(|record| record.mountpoint != mountpoint);
+    if records.len() != num_records {
+        save_mounts(&records).await?;
+    }
+    Ok(())
+}
+
+/// Forget a mount whose process exited, logging instead of failing.
+pub(crate) async fn forget_finished_mount(mountpoint: PathBuf) {
+    if let Err(e) = forget_mount(&mountpoint).await {
+        tracing::error!(
+            "Failed to forget mount {}: {}",
+            mountpoint.to_string_lossy(),
+            e
+        );
+    }
+}
+
+/// Undo the escaping /proc/self/mountinfo applies to spaces, tabs, newlines and backslashes
+fn unescape_mountinfo_path(path: &str) -> PathBuf {
+    let mut unescaped = Vec::with_capacity(path.len());
+    let bytes = path.as_bytes();
+    let mut i = 0;
+    while i < bytes.len() {
+        let octal = bytes
+            .get(i + 1..i + 4)
+            .filter(|_| bytes[i] == b'\\')
+            .and_then(|octal| std::str::from_utf8(octal).ok())
+            .and_then(|octal| u8::from_str_radix(octal, 8).ok());
+        match octal {
+            Some(byte) => {
+                unescaped.push(byte);
+                i += 4;
+            }
+            None => {
+                unescaped.push(bytes[i]);
+                i += 1;
+            }
+        }
+    }
+    PathBuf::from(OsString::from_vec(unescaped))
+}
+
+/// Whether something is mounted at `mountpoint`.
+pub(crate) async fn is_mountpoint(mountpoint: &Path) -> BorgResult<bool> {
+    let mountpoint = std::path::absolute(mountpoint)?;
+    let mountinfo = tokio::fs::read_to_string("/proc/self/mountinfo").await?;
+    Ok(mountinfo
+        .lines()
+        // The mount point is the fifth field
+        .filter_map(|line| line.split(' ').nth(4))
+        .any(|path| unescape_mountinfo_path(path) == mountpoint))
+}
+
+fn is_process_alive(pid: u32) -> bool {
+    // Safety: Signal 0 only checks whether the process exists
+    let res = unsafe { libc::kill(pid as libc::pid_t, 0) };
+    res == 0 || std::io::Error::last_os_error().raw_os_error() == Some(libc::EPERM)
+}
+
+async fn mount_state(record: &MountRecord) -> BorgResult<MountState> {
+    if !is_mountpoint(&record.mountpoint).await? {
+        return Ok(MountState::Unmounted);
+    }
+    // FUSE mounts whose process died fail with "Transport endpoint is not connected"
+    let endpoint_gone = matches!(
+        tokio::fs::metadata(&record.mountpoint).await,
+        Err(e) if e.raw_os_error() == Some(libc::ENOTCONN)
+    );
+    let process_gone = record.pid.is_some_and(|pid| !is_process_alive(pid));
+    if endpoint_gone || process_gone {
+        Ok(MountState::Stale)
+    } else {
+        Ok(MountState::Mounted)
+    }
+}
+
+async fn fusermount_unmount(mountpoint: &Path, lazy: bool) -> BorgResult<()> {
+    let flags = if lazy { "-uz" } else { "-u" };
+    let output = tokio::process::Command::new("fusermount")
+        .arg(flags)
+        .arg(mountpoint)
+        .output()
+        .await?;
+    if !output.status.success() {
+        bail!(
+            "fusermount {} {} failed: {}",
+            flags,
+            mountpoint.to_string_lossy(),
+            String::from_utf8_lossy(&output.stderr).trim()
+        );
+    }
+    Ok(())
+}
+
+/// Unmount `mountpoint` through the provider which mounted it, falling back to
+/// `fusermount -u` if it's still mounted afterwards.
+pub(crate) async fn unmount(mountpoint: &Path) -> BorgResult<()> {
+    let mountpoint = std::path::absolute(mountpoint)?;
+    let record = load_mounts()
+        .await?
+        .into_iter()
+        .find(|record| record.mountpoint == mountpoint);
+    if record.is_none() && !is_mountpoint(&mountpoint).await? {
+        bail!("Nothing is mounted at {}", mountpoint.to_string_lossy());
+    }
+    if let Some(record) = &record {
+        if let Err(e) = record
+            .kind
+            .backup_provider()
+            .unmount(mountpoint.clone())
+            .await
+        {
+            tracing::warn!(
+                "The {} provider failed to unmount {}: {}",
+                record.kind,
+                mountpoint.to_string_lossy(),
+                e
+            );
+        }
+    }
+    if is_mountpoint(&mountpoint).await? {
+        tracing::info!(
+            "Unmounting {} with fusermount",
+            mountpoint.to_string_lossy()
+        );
+        fusermount_unmount(&mountpoint, false).await?;
+    }
+    forget_mount(&mountpoint).await
+}
+
+/// Forget mounts which were unmounted behind BorgTUI's back and lazily unmount those whose
+/// process died (like rustic mounts of a BorgTUI that exited). Returns the mounts left.
+pub(crate) async fn clean_stale_mounts() -> BorgResult<Vec<MountRecord>> {
+    let _registry_guard = REGISTRY_LOCK.lock().await;
+    let records = load_mounts().await?;
+    let num_records = records.len();
+    let mut live = Vec::new();
+    for record in records {
+        match mount_state(&record).await? {
+            MountState::Mounted => live.push(record),
+            MountState::Unmounted => {
+                tracing::info!(
+                    "Forgetting {} mount at {}, it's no longer mounted",
+                    record.source(),
+                    record.mountpoint.to_string_lossy()
+                );
+            }
+            MountState::Stale => {
+                tracing::warn!(
+                    "Cleaning up stale mount of {} at {}",
+                    record.source(),
+                    record.mountpoint.to_string_lossy()
+                );
+                if let Err(e) = fusermount_unmount(&record.mountpoint, true).await {
+                    tracing::error!("Failed to clean up stale mount: {}", e);
+                    live.push(record);
+                }
+            }
+        }
+    }
+    if live.len() != num_records {
+        save_mounts(&live).await?;
+    }
+    Ok(live)
+}
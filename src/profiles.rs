@@ -1,10 +1,12 @@
 use std::{
+    collections::{BTreeMap, HashMap, HashSet},
     io::Write,
-    num::NonZeroU16,
+    num::{NonZeroU16, NonZeroU32, NonZeroU64},
     os::unix::prelude::PermissionsExt,
     path::{Path, PathBuf},
+    process::Stdio,
     str::FromStr,
-    sync::Arc,
+    sync::{Arc, LazyLock},
     time::Instant,
 };
 
@@ -13,16 +15,26 @@ use crate::{
         backup_provider::BackupProvider, borg_provider::BorgProvider,
         restic_provider::ResticProvider,
     },
+    borgtui::CommandResponse,
+    cancel::{is_cancelled, CancelToken, Cancelled},
     cli::PassphraseSource,
+    history::{self, RunKind, RunOutcome, RunRecord, RunStats},
+    hooks::{HookContext, HookKind, Hooks},
+    mounts,
+    tarball::{self, TarballWriter},
     types::{
-        log_on_error, show_notification, BorgResult, CommandResponseSender, RepositoryArchives,
-        SHORT_NOTIFICATION_DURATION,
+        log_on_error, send_error, send_info, show_notification, Archive, ArchiveDiff,
+        ArchiveDirectoryListing, BackupCreateProgress, BackupCreationProgress, BorgResult,
+        CommandResponseSender, PrunePreview, RepositoryArchives, RepositorySourcesSummary,
+        RepositoryStatus, RepositorySummary, SHORT_NOTIFICATION_DURATION,
     },
 };
 use anyhow::anyhow;
 use anyhow::{bail, Context};
+use itertools::Itertools;
 use keyring::Entry;
 use std::fs;
+use tokio::io::AsyncWrite;
 use tracing::info;
 
 #[cfg(feature = "rustic")]
@@ -74,10 +86,26 @@ pub(crate) enum Encryption {
     Raw(Passphrase),
     Keyring,
     Keyfile(PathBuf),
+    /// Run this command (program followed by its arguments) and use the first line it prints
+    Command(Vec<String>),
 }
 
 impl Encryption {
+    /// A short name for where the passphrase comes from. Never contains the passphrase.
+    pub(crate) fn source_name(&self) -> &'static str {
+        match self {
+            Encryption::None => "none",
+            Encryption::Raw(_) => "raw",
+            Encryption::Keyring => "keyring",
+            Encryption::Keyfile(_) => "keyfile",
+            Encryption::Command(_) => "command",
+        }
+    }
+
     pub(crate) fn from_passphrase_loc(passphrase_loc: PassphraseSource) -> BorgResult<Self> {
+        if let Some(passcommand) = passphrase_loc.passcommand_argv()? {
+            return Ok(Encryption::Command(passcommand));
+        }
         if passphrase_loc.raw {
             if let Some(borg_passphrase) = passphrase_loc.borg_passphrase {
                 return Ok(Encryption::Raw(borg_passphrase));
@@ -124,6 +152,17 @@ impl std::fmt::Display for RepositoryKind {
     }
 }
 
+impl RepositoryKind {
+    pub(crate) fn backup_provider(self) -> Box<dyn BackupProvider> {
+        match self {
+            RepositoryKind::Borg => Box::new(BorgProvider {}),
+            #[cfg(feature = "rustic")]
+            RepositoryKind::Rustic => Box::new(RusticProvider {}),
+            RepositoryKind::Restic => Box::new(ResticProvider {}),
+        }
+    }
+}
+
 impl FromStr for RepositoryKind {
     type Err = anyhow::Error;
 
@@ -178,17 +217,13 @@ impl ToLatestRepository for RepositoryV1 {
             encryption: self.encryption.clone(),
             disabled: self.disabled,
             config,
+            prune_options: None,
+            backup_overrides: BackupOverrides::default(),
             lock: Default::default(),
         }
     }
 }
 
-impl ToLatestRepository for Repository {
-    fn to_latest(&self) -> Repository {
-        self.clone()
-    }
-}
-
 #[derive(Serialize, Deserialize, Debug, Clone)]
 pub(crate) struct Repository {
     pub(crate) path: String,
@@ -197,10 +232,54 @@ pub(crate) struct Repository {
     #[serde(default)]
     disabled: bool,
     config: RepositoryOptions,
+    /// Overrides the profile's prune options for this repository
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    prune_options: Option<PruneOptions>,
+    #[serde(default, skip_serializing_if = "BackupOverrides::is_empty")]
+    backup_overrides: BackupOverrides,
     #[serde(skip)]
     pub(crate) lock: Arc<Mutex<()>>,
 }
 
+/// Changes to the profile's backup paths and excludes for a single repository.
+#[derive(Serialize, Deserialize, Debug, Clone, Default, PartialEq)]
+pub(crate) struct BackupOverrides {
+    /// Backed up to this repository on top of the profile's backup paths
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    extra_paths: Vec<PathBuf>,
+    /// Profile backup paths which aren't backed up to this repository
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    skip_paths: Vec<PathBuf>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    extra_excludes: Vec<String>,
+    /// Profile exclude patterns which don't apply to this repository
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    skip_excludes: Vec<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    exclude_caches: Option<bool>,
+}
+
+impl BackupOverrides {
+    pub(crate) fn is_empty(&self) -> bool {
+        *self == Self::default()
+    }
+}
+
+/// `items` without those in `skip`, followed by `extra` (ignoring any already present).
+fn apply_overrides<T: Clone + PartialEq>(items: &[T], extra: &[T], skip: &[T]) -> Vec<T> {
+    let mut result = items
+        .iter()
+        .filter(|item| !skip.contains(item))
+        .cloned()
+        .collect::<Vec<_>>();
+    for item in extra {
+        if !result.contains(item) {
+            result.push(item.clone());
+        }
+    }
+    result
+}
+
 impl std::fmt::Display for Repository {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
         write!(f, "Repository<{}>", self.path)
@@ -216,10 +295,272 @@ fn get_keyring_entry(repo_path: &str) -> BorgResult<Entry> {
     })
 }
 
-#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+/// Borg's pattern style prefixes, e.g. `re:` in `re:^/home/[^/]+/\.cache`
+const BORG_PATTERN_STYLES: [&str; 6] = ["fm:", "sh:", "re:", "pp:", "pf:", "pm:"];
+
+/// Why `repo`'s backend would reject `pattern` as an exclude pattern, if it would.
+///
+/// Borg turns any pattern into a regex (fnmatch by default, or the style its prefix names),
+/// so its patterns are never wrong. Restic and rustic use globs, which fail on a `[` that's
+/// never closed or a trailing `\`, and don't know borg's prefixes.
+fn exclude_pattern_problem(repo: &Repository, pattern: &str) -> Option<String> {
+    if matches!(repo.kind(), RepositoryKind::Borg) {
+        return None;
+    }
+    let kind = repo.repo_kind_name();
+    if let Some(style) = BORG_PATTERN_STYLES
+        .iter()
+        .find(|style| pattern.starts_with(*style))
+    {
+        return Some(format!(
+            "uses borg's `{}` pattern style, which {} ({}) would match literally",
+            style, kind, repo.path
+        ));
+    }
+    let mut chars = pattern.chars();
+    while let Some(c) = chars.next() {
+        match c {
+            '\\' if chars.next().is_none() => {
+                return Some(format!("ends with a lone `\\`, which {} rejects", kind))
+            }
+            '[' => {
+                // The first character is part of the class even if it's a `]`
+                let mut class = chars.by_ref().skip_while(|c| *c == '^' || *c == '!');
+                let closed = class.next().is_some() && class.any(|c| c == ']');
+                if !closed {
+                    return Some(format!(
+                        "has a `[` which is never closed, which {} rejects",
+                        kind
+                    ));
+                }
+            }
+            _ => {}
+        }
+    }
+    None
+}
+
+/// Passphrases printed by passcommands, kept for the lifetime of the process so commands
+/// which ask for something through their own window (e.g. gpg's pinentry) only do so once.
+/// Passcommands can't prompt on the terminal: their stdin is closed, since the TUI owns it.
+static PASSCOMMAND_CACHE: LazyLock<std::sync::Mutex<HashMap<Vec<String>, Passphrase>>> =
+    LazyLock::new(Default::default);
+
+/// Run `command` and use the first line it prints as the passphrase, like `pass show` prints.
+///
+/// Passphrases are needed from sync code (e.g. when building a borg command), so this blocks.
+/// On a runtime worker the runtime is told first, so it moves the worker's other tasks away.
+pub(crate) fn passcommand_passphrase(command: &[String]) -> BorgResult<Passphrase> {
+    match tokio::runtime::Handle::try_current() {
+        Ok(handle) if handle.runtime_flavor() == tokio::runtime::RuntimeFlavor::MultiThread => {
+            tokio::task::block_in_place(|| run_passcommand(command))
+        }
+        _ => run_passcommand(command),
+    }
+}
+
+fn run_passcommand(command: &[String]) -> BorgResult<Passphrase> {
+    // Held while the command runs so repos sharing a passcommand don't run it concurrently
+    let mut cache = PASSCOMMAND_CACHE
+        .lock()
+        .map_err(|_| anyhow!("A passcommand panicked earlier, restart BorgTUI"))?;
+    if let Some(passphrase) = cache.get(command) {
+        return Ok(passphrase.clone());
+    }
+    let (program, args) = command
+        .split_first()
+        .ok_or_else(|| anyhow!("The passcommand is empty"))?;
+    let command_str = command.join(" ");
+    let output = std::process::Command::new(program)
+        .args(args)
+        .stdin(Stdio::null())
+        .output()
+        .with_context(|| format!("Failed to run passcommand `{}`", command_str))?;
+    if !output.status.success() {
+        bail!(
+            "Passcommand `{}` failed with {}: {}",
+            command_str,
+            output.status,
+            String::from_utf8_lossy(&output.stderr).trim()
+        );
+    }
+    let stdout = String::from_utf8(output.stdout)
+        .with_context(|| format!("Passcommand `{}` printed invalid UTF-8", command_str))?;
+    let passphrase = stdout.lines().next().unwrap_or_default();
+    if passphrase.is_empty() {
+        bail!("Passcommand `{}` didn't print a passphrase", command_str);
+    }
+    let passphrase = Passphrase::from(passphrase);
+    cache.insert(command.to_vec(), passphrase.clone());
+    Ok(passphrase)
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, Default, PartialEq)]
 pub(crate) struct BorgV1Options {
     pub(crate) rsh: Option<String>,
     pub(crate) remote_path: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub(crate) compression: Option<BorgCompression>,
+    /// Don't cross filesystem boundaries when backing up
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    pub(crate) one_file_system: bool,
+    /// Limit uploads to the repository to this many kiB/s
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub(crate) upload_ratelimit: Option<NonZeroU64>,
+    /// How often borg saves a checkpoint archive during a backup (borg defaults to 30 minutes)
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub(crate) checkpoint_interval_seconds: Option<NonZeroU64>,
+    /// Only store numeric user and group ids
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    pub(crate) numeric_ids: bool,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub(crate) chunker_params: Option<BorgChunkerParams>,
+}
+
+/// A borg `--compression` spec like "zstd,10", "lz4" or "auto,lzma,6".
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+#[serde(try_from = "String", into = "String")]
+pub(crate) struct BorgCompression(String);
+
+impl BorgCompression {
+    fn check_spec(spec: &str) -> BorgResult<()> {
+        let (algorithm, rest) = match spec.split_once(',') {
+            Some((algorithm, rest)) => (algorithm, Some(rest)),
+            None => (spec, None),
+        };
+        let check_level = |level: &str, levels: std::ops::RangeInclusive<u8>| -> BorgResult<()> {
+            match level.parse::<u8>() {
+                Ok(level) if levels.contains(&level) => Ok(()),
+                _ => bail!(
+                    "Invalid {algorithm} level '{level}'. Expected {} to {}",
+                    levels.start(),
+                    levels.end()
+                ),
+            }
+        };
+        match (algorithm, rest) {
+            ("none" | "lz4" | "zstd" | "zlib" | "lzma", None) => Ok(()),
+            ("zstd", Some(level)) => check_level(level, 1..=22),
+            ("zlib" | "lzma", Some(level)) => check_level(level, 0..=9),
+            ("auto", Some(rest)) => Self::check_spec(rest),
+            ("obfuscate", Some(rest)) => {
+                let (level, rest) = rest
+                    .split_once(',')
+                    .ok_or_else(|| anyhow!("obfuscate needs a level and a compression"))?;
+                match level.parse::<u8>() {
+                    Ok(1..=6 | 110..=123) => Self::check_spec(rest),
+                    _ => bail!("Invalid obfuscate level '{level}'. Expected 1 to 6 or 110 to 123"),
+                }
+            }
+            _ => bail!("Unknown compression '{spec}'. Expected e.g. lz4, zstd,3, zlib,6, lzma,6 or auto,zstd,3"),
+        }
+    }
+}
+
+impl std::fmt::Display for BorgCompression {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}", self.0)
+    }
+}
+
+impl FromStr for BorgCompression {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        // Not added as context, since serde only shows the outermost error
+        Self::check_spec(s)
+            .map_err(|e| anyhow!("Invalid compression '{s}': {e} (see `borg help compression`)"))?;
+        Ok(BorgCompression(s.to_string()))
+    }
+}
+
+impl TryFrom<String> for BorgCompression {
+    type Error = anyhow::Error;
+
+    fn try_from(value: String) -> Result<Self, Self::Error> {
+        value.parse()
+    }
+}
+
+impl From<BorgCompression> for String {
+    fn from(value: BorgCompression) -> Self {
+        value.0
+    }
+}
+
+/// A borg `--chunker-params` spec like "buzhash,19,23,21,4095", "fixed,4194304" or "default".
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+#[serde(try_from = "String", into = "String")]
+pub(crate) struct BorgChunkerParams(String);
+
+impl BorgChunkerParams {
+    fn check_spec(spec: &str) -> BorgResult<()> {
+        let parts = spec.split(',').collect::<Vec<_>>();
+        let parse = |part: &str, what: &str| -> BorgResult<u64> {
+            part.parse::<u64>()
+                .map_err(|_| anyhow!("Invalid {what} '{part}'. Expected a number"))
+        };
+        match parts.as_slice() {
+            ["default"] => Ok(()),
+            ["fixed", block_size] | ["fixed", block_size, _] => {
+                if parse(block_size, "block size")? < 64 {
+                    bail!("The block size must be at least 64 bytes");
+                }
+                if let [_, _, header_size] = parts.as_slice() {
+                    parse(header_size, "header size")?;
+                }
+                Ok(())
+            }
+            ["buzhash", min, max, mask, window] | [min, max, mask, window] => {
+                let min = parse(min, "minimum chunk size exponent")?;
+                let max = parse(max, "maximum chunk size exponent")?;
+                let mask = parse(mask, "hash mask bits")?;
+                let window = parse(window, "hash window size")?;
+                if !(6..=23).contains(&min) || !(6..=23).contains(&max) {
+                    bail!("The chunk size exponents must be from 6 to 23");
+                }
+                if !(min <= mask && mask <= max) {
+                    bail!("The hash mask bits must be between the chunk size exponents");
+                }
+                if window % 2 == 0 {
+                    bail!("The hash window size must be odd");
+                }
+                Ok(())
+            }
+            _ => bail!("Expected e.g. buzhash,19,23,21,4095, fixed,4194304 or default"),
+        }
+    }
+}
+
+impl std::fmt::Display for BorgChunkerParams {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}", self.0)
+    }
+}
+
+impl FromStr for BorgChunkerParams {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        // Not added as context, since serde only shows the outermost error
+        Self::check_spec(s)
+            .map_err(|e| anyhow!("Invalid chunker params '{s}': {e} (see `borg help create`)"))?;
+        Ok(BorgChunkerParams(s.to_string()))
+    }
+}
+
+impl TryFrom<String> for BorgChunkerParams {
+    type Error = anyhow::Error;
+
+    fn try_from(value: String) -> Result<Self, Self::Error> {
+        value.parse()
+    }
+}
+
+impl From<BorgChunkerParams> for String {
+    fn from(value: BorgChunkerParams) -> Self {
+        value.0
+    }
 }
 
 pub(crate) struct BorgV1OptionsBuilder {
@@ -250,15 +591,78 @@ impl BorgV1OptionsBuilder {
         BorgV1Options {
             rsh: self.rsh,
             remote_path: self.remote_path,
+            ..Default::default()
         }
     }
 }
 
+/// Rustic stores compression and pack sizes in the repository's config, so they're applied
+/// when the repository is initialized or backed up to.
+#[cfg(feature = "rustic")]
 #[derive(Serialize, Deserialize, Debug, Clone, Default, PartialEq)]
-pub(crate) struct RusticOptions {}
+pub(crate) struct RusticOptions {
+    /// Passed to the backend, e.g. {"timeout": "5min"} for a REST server
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub(crate) backend_options: BTreeMap<String, String>,
+    /// Defaults to ~/.cache/borgtui/rustic
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub(crate) cache_dir: Option<PathBuf>,
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    pub(crate) no_cache: bool,
+    /// The zstd level, from -7 to 22 (0 is zstd's default)
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub(crate) compression: Option<i32>,
+    /// The target size of data pack files
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub(crate) pack_size_mib: Option<NonZeroU32>,
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    pub(crate) read_only: bool,
+}
 
 #[derive(Serialize, Deserialize, Debug, Clone, Default, PartialEq)]
-pub(crate) struct ResticOptions {}
+pub(crate) struct ResticOptions {
+    /// Passed with `-o`, e.g. {"s3.storage-class": "STANDARD_IA"}
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub(crate) backend_options: BTreeMap<String, String>,
+    /// Defaults to restic's own cache directory
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub(crate) cache_dir: Option<PathBuf>,
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    pub(crate) no_cache: bool,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub(crate) compression: Option<ResticCompression>,
+    /// The target size of pack files, from 4 to 128
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub(crate) pack_size_mib: Option<NonZeroU32>,
+    /// Also runs restic's read commands with `--no-lock`, so repositories on read-only storage
+    /// can be read
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    pub(crate) read_only: bool,
+}
+
+/// Restic's `--compression` modes
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub(crate) enum ResticCompression {
+    Auto,
+    Off,
+    Fastest,
+    Better,
+    Max,
+}
+
+impl std::fmt::Display for ResticCompression {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let mode = match self {
+            ResticCompression::Auto => "auto",
+            ResticCompression::Off => "off",
+            ResticCompression::Fastest => "fastest",
+            ResticCompression::Better => "better",
+            ResticCompression::Max => "max",
+        };
+        write!(f, "{mode}")
+    }
+}
 
 #[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
 pub(crate) enum RepositoryOptions {
@@ -306,10 +710,16 @@ impl Repository {
             encryption,
             config,
             disabled: false,
+            prune_options: None,
+            backup_overrides: BackupOverrides::default(),
             lock: Default::default(),
         }
     }
 
+    pub(crate) fn has_backup_overrides(&self) -> bool {
+        !self.backup_overrides.is_empty()
+    }
+
     pub(crate) fn get_passphrase(&self) -> BorgResult<Option<Passphrase>> {
         match &self.encryption {
             Encryption::None => Ok(None),
@@ -325,6 +735,9 @@ impl Repository {
                     .to_string();
                 Ok(Some(Passphrase(passphrase)))
             }
+            Encryption::Command(command) => passcommand_passphrase(command)
+                .with_context(|| format!("Failed to get the passphrase for repository {}", self))
+                .map(Some),
         }
     }
 
@@ -361,6 +774,23 @@ impl Repository {
         self.disabled
     }
 
+    /// Read-only repositories are skipped by backups, and refuse to be pruned or compacted.
+    pub(crate) fn read_only(&self) -> bool {
+        match &self.config {
+            RepositoryOptions::BorgV1(_) => false,
+            #[cfg(feature = "rustic")]
+            RepositoryOptions::Rustic(options) => options.read_only,
+            RepositoryOptions::Restic(options) => options.read_only,
+        }
+    }
+
+    fn check_writable(&self) -> BorgResult<()> {
+        if self.read_only() {
+            bail!("Refusing to modify {}: it's read-only", self);
+        }
+        Ok(())
+    }
+
     pub(crate) fn path(&self) -> String {
         self.path.clone()
     }
@@ -373,13 +803,11 @@ impl Repository {
         self.config.borg_options()
     }
 
-    #[allow(unused)]
     #[cfg(feature = "rustic")]
     pub(crate) fn rustic_options(&self) -> BorgResult<RusticOptions> {
         self.config.rustic_options()
     }
 
-    #[allow(unused)]
     pub(crate) fn restic_options(&self) -> BorgResult<ResticOptions> {
         self.config.restic_options()
     }
@@ -388,6 +816,235 @@ impl Repository {
         self.backup_provider().list_archives(self).await
     }
 
+    /// List the archives along with their sizes and file counts, which can be slow.
+    /// The archives are still returned if their stats can't be found.
+    pub(crate) async fn list_archives_with_stats(&self) -> BorgResult<RepositoryArchives> {
+        let provider = self.backup_provider();
+        let mut archives = provider.list_archives(self).await?;
+        if let Err(e) = provider.add_archive_stats(self, &mut archives).await {
+            tracing::warn!("{}", e);
+        }
+        Ok(archives)
+    }
+
+    /// How fresh this repository's backups are. `history` is used for the last check result.
+    /// Listing the archives gives up after `timeout`, reporting the repository as unreachable.
+    pub(crate) async fn status(
+        &self,
+        max_age: Option<KeepWithin>,
+        history: &[RunRecord],
+        timeout: std::time::Duration,
+    ) -> RepositoryStatus {
+        let path = self.path();
+        let last_check = history
+            .iter()
+            .rev()
+            .find(|record| record.kind == RunKind::Check && record.repository == path);
+        let mut status = RepositoryStatus {
+            path,
+            disabled: self.disabled,
+            newest_archive: None,
+            newest_archive_age_seconds: None,
+            last_check: last_check.map(|record| record.finished_at.to_rfc3339()),
+            last_check_successful: last_check.map(|record| record.outcome == RunOutcome::Success),
+            stale: false,
+            error: None,
+        };
+        if self.disabled {
+            return status;
+        }
+        let archives = match tokio::time::timeout(timeout, self.list_archives()).await {
+            Ok(Ok(archives)) => archives.archives,
+            Ok(Err(e)) => {
+                status.error = Some(e.to_string());
+                return status;
+            }
+            Err(_) => {
+                status.error = Some(format!(
+                    "Timeout ({}s) while attempting to list archives",
+                    timeout.as_secs()
+                ));
+                return status;
+            }
+        };
+        let newest = archives.iter().max_by_key(|archive| archive.creation_date);
+        if let Some(newest) = newest {
+            let age = chrono::Local::now().naive_local() - newest.creation_date;
+            status.newest_archive = Some(newest.name.clone());
+            status.newest_archive_age_seconds = Some(age.num_seconds());
+        }
+        // Read-only repositories aren't backed up to, so they're never stale
+        if let Some(max_age) = max_age.filter(|_| !self.read_only()) {
+            status.stale = match status.newest_archive_age_seconds {
+                Some(age_seconds) => age_seconds as u64 > max_age.as_duration().as_secs(),
+                None => true,
+            };
+        }
+        status
+    }
+
+    pub(crate) async fn list_archive_directory(
+        &self,
+        archive_spec: String,
+        directory: String,
+    ) -> BorgResult<ArchiveDirectoryListing> {
+        let mut entries = self
+            .backup_provider()
+            .list_archive_directory(self, archive_spec.clone(), directory.clone())
+            .await?;
+        // Directories first, then alphabetical
+        entries.sort_by(|left, right| {
+            right
+                .is_dir
+                .cmp(&left.is_dir)
+                .then_with(|| left.path.cmp(&right.path))
+        });
+        Ok(ArchiveDirectoryListing {
+            archive: archive_spec,
+            directory,
+            entries,
+        })
+    }
+
+    pub(crate) async fn read_archive_file(
+        &self,
+        archive_spec: String,
+        path: String,
+        writer: &mut (dyn AsyncWrite + Send + Unpin),
+    ) -> BorgResult<()> {
+        self.backup_provider()
+            .read_archive_file(self, archive_spec, path, writer)
+            .await
+    }
+
+    /// Export the archive named by `archive_spec` as a tarball at `output` ("-" for stdout),
+    /// compressed to match its extension and limited to `paths` if any are given.
+    pub(crate) async fn export_tar(
+        &self,
+        archive_spec: String,
+        paths: &[PathBuf],
+        output: &Path,
+        cancel: CancelToken,
+    ) -> BorgResult<()> {
+        let mut writer = TarballWriter::create(output).await?;
+        let result = match self
+            .backup_provider()
+            .export_tar(self, archive_spec, paths, writer.writer(), cancel)
+            .await
+        {
+            Ok(()) => writer.finish().await,
+            Err(e) => Err(e),
+        };
+        if result.is_err() && !tarball::is_stdio(output) {
+            // Don't leave a truncated tarball behind
+            if let Err(e) = tokio::fs::remove_file(output).await {
+                tracing::warn!(
+                    "Failed to remove incomplete tarball {}: {}",
+                    output.to_string_lossy(),
+                    e
+                );
+            }
+        }
+        result
+    }
+
+    /// Create the archive `archive_name` from the tarball at `tarball` ("-" for stdin).
+    pub(crate) async fn import_tar(
+        &self,
+        archive_name: String,
+        tarball: PathBuf,
+        progress_channel: CommandResponseSender,
+        cancel: CancelToken,
+    ) -> BorgResult<()> {
+        self.check_writable()?;
+        let started_at = chrono::Local::now();
+        let result = self
+            .backup_provider()
+            .import_tar(
+                self,
+                archive_name.clone(),
+                tarball,
+                progress_channel,
+                cancel,
+            )
+            .await;
+        let mut record = RunRecord::new(RunKind::Create, self.path(), started_at, &result);
+        record.archive = Some(archive_name);
+        history::record_run(record).await;
+        result
+    }
+
+    pub(crate) async fn diff(
+        &self,
+        first_archive_spec: String,
+        second_archive_spec: String,
+    ) -> BorgResult<ArchiveDiff> {
+        for archive_spec in [&first_archive_spec, &second_archive_spec] {
+            self.archive_name_in(archive_spec)?;
+        }
+        let mut entries = self
+            .backup_provider()
+            .diff(
+                self,
+                first_archive_spec.clone(),
+                second_archive_spec.clone(),
+            )
+            .await?;
+        entries.sort_by(|left, right| left.path.cmp(&right.path));
+        Ok(ArchiveDiff {
+            first: first_archive_spec,
+            second: second_archive_spec,
+            entries,
+        })
+    }
+
+    /// The archive part of `archive_spec` ("repo::archive"), failing if it's not in this
+    /// repository.
+    fn archive_name_in<'a>(&self, archive_spec: &'a str) -> BorgResult<&'a str> {
+        match archive_spec.split_once("::") {
+            Some((repo_path, archive_name)) if repo_path == self.path => Ok(archive_name),
+            _ => bail!("{} is not an archive in {}", archive_spec, self),
+        }
+    }
+
+    /// Delete the archive named by `archive_spec` ("repo::archive").
+    pub(crate) async fn delete_archive(
+        &self,
+        archive_spec: String,
+        progress_channel: CommandResponseSender,
+        cancel: CancelToken,
+    ) -> BorgResult<()> {
+        self.check_writable()?;
+        let archive_name = self.archive_name_in(&archive_spec)?.to_string();
+        let started_at = chrono::Local::now();
+        let result = self
+            .backup_provider()
+            .delete_archive(self, archive_spec, progress_channel, cancel)
+            .await;
+        let mut record = RunRecord::new(RunKind::Delete, self.path(), started_at, &result);
+        record.archive = Some(archive_name);
+        history::record_run(record).await;
+        result
+    }
+
+    /// Rename the archive named by `archive_spec` ("repo::archive") to `new_name`.
+    pub(crate) async fn rename_archive(
+        &self,
+        archive_spec: String,
+        new_name: String,
+        progress_channel: CommandResponseSender,
+    ) -> BorgResult<()> {
+        self.check_writable()?;
+        let archive_name = self.archive_name_in(&archive_spec)?;
+        if archive_name == new_name {
+            bail!("{} is already named {}", archive_spec, new_name);
+        }
+        check_archive_name(&new_name)?;
+        self.backup_provider()
+            .rename_archive(self, archive_spec, new_name, progress_channel)
+            .await
+    }
+
     pub(crate) async fn init(&self) -> BorgResult<()> {
         self.backup_provider()
             .init_repo(self.path(), self.get_passphrase()?, self.config.clone())
@@ -400,6 +1057,12 @@ impl Repository {
         given_repository_path: String,
         mountpoint: PathBuf,
     ) -> BorgResult<()> {
+        if mounts::is_mountpoint(&mountpoint).await? {
+            bail!(
+                "Something is already mounted at {}",
+                mountpoint.to_string_lossy()
+            );
+        }
         self.backup_provider()
             .mount(self, given_repository_path, mountpoint)
             .await?;
@@ -409,32 +1072,187 @@ impl Repository {
     pub(crate) async fn prune(
         &self,
         prune_options: PruneOptions,
+        archive_filter: &ArchiveFilter,
         progress_channel: CommandResponseSender,
+        cancel: CancelToken,
     ) -> BorgResult<()> {
+        self.check_writable()?;
+        self.check_prune_options(&prune_options)?;
+        self.report_unprunable_archives(archive_filter, &progress_channel)
+            .await;
         info!("Starting to prune {}", self);
-        self.backup_provider()
-            .prune(self, prune_options, progress_channel)
-            .await
+        let started_at = chrono::Local::now();
+        let result = self
+            .backup_provider()
+            .prune(
+                self,
+                prune_options,
+                archive_filter,
+                progress_channel,
+                cancel,
+            )
+            .await;
+        history::record_run(RunRecord::new(
+            RunKind::Prune,
+            self.path(),
+            started_at,
+            &result,
+        ))
+        .await;
+        result
+    }
+
+    pub(crate) async fn prune_dry_run(
+        &self,
+        prune_options: PruneOptions,
+        archive_filter: &ArchiveFilter,
+    ) -> BorgResult<PrunePreview> {
+        self.check_prune_options(&prune_options)?;
+        let mut decisions = self
+            .backup_provider()
+            .prune_dry_run(self, prune_options, archive_filter)
+            .await?;
+        // Show what would be removed first
+        decisions.sort_by_key(|decision| decision.keep);
+        Ok(PrunePreview {
+            repository: self.path(),
+            decisions,
+        })
     }
 
-    pub(crate) async fn compact(&self, progress_channel: CommandResponseSender) -> BorgResult<()> {
-        self.backup_provider().compact(self, progress_channel).await
+    /// Say how many archives `archive_filter` keeps prune away from, so archives named with an
+    /// older archive name format don't pile up unnoticed.
+    async fn report_unprunable_archives(
+        &self,
+        archive_filter: &ArchiveFilter,
+        progress_channel: &CommandResponseSender,
+    ) {
+        if archive_filter == &ArchiveFilter::default() {
+            return;
+        }
+        let archives = match self.list_archives().await {
+            Ok(archives) => archives.archives,
+            Err(e) => {
+                tracing::warn!("Failed to list {} before pruning: {}", self, e);
+                return;
+            }
+        };
+        let unprunable = archives
+            .iter()
+            .filter(|archive| !archive_filter.matches(self, archive))
+            .collect::<Vec<_>>();
+        if let Some(example) = unprunable.first() {
+            send_info!(
+                progress_channel,
+                format!(
+                    "Not pruning {} archives in {} which the profile's archive_name_format wouldn't create, like {}",
+                    unprunable.len(),
+                    self.path(),
+                    example.name
+                )
+            );
+        }
     }
 
-    pub(crate) async fn check(&self, progress_channel: CommandResponseSender) -> BorgResult<bool> {
-        self.backup_provider().check(self, progress_channel).await
+    fn check_prune_options(&self, prune_options: &PruneOptions) -> BorgResult<()> {
+        if prune_options.is_empty() {
+            bail!("Refusing to prune {}: no retention rules are set", self);
+        }
+        Ok(())
     }
 
-    pub(crate) async fn repair(&self, progress_channel: CommandResponseSender) -> BorgResult<bool> {
-        self.backup_provider().repair(self, progress_channel).await
+    pub(crate) async fn compact(
+        &self,
+        progress_channel: CommandResponseSender,
+        cancel: CancelToken,
+    ) -> BorgResult<()> {
+        self.check_writable()?;
+        let started_at = chrono::Local::now();
+        let result = self
+            .backup_provider()
+            .compact(self, progress_channel, cancel)
+            .await;
+        history::record_run(RunRecord::new(
+            RunKind::Compact,
+            self.path(),
+            started_at,
+            &result,
+        ))
+        .await;
+        result
+    }
+
+    pub(crate) async fn check(
+        &self,
+        progress_channel: CommandResponseSender,
+        cancel: CancelToken,
+    ) -> BorgResult<bool> {
+        let started_at = chrono::Local::now();
+        let result = self
+            .backup_provider()
+            .check(self, progress_channel, cancel)
+            .await;
+        let outcome = match &result {
+            Ok(true) => Ok(()),
+            Ok(false) => Err(anyhow!("Verification failed")),
+            Err(e) if is_cancelled(e) => Err(Cancelled.into()),
+            Err(e) => Err(anyhow!("{}", e)),
+        };
+        history::record_run(RunRecord::new(
+            RunKind::Check,
+            self.path(),
+            started_at,
+            &outcome,
+        ))
+        .await;
+        result
+    }
+
+    pub(crate) async fn repair(
+        &self,
+        dry_run: bool,
+        progress_channel: CommandResponseSender,
+        cancel: CancelToken,
+    ) -> BorgResult<bool> {
+        if !dry_run {
+            self.check_writable()?;
+        }
+        self.backup_provider()
+            .repair(self, dry_run, progress_channel, cancel)
+            .await
+    }
+
+    pub(crate) async fn restore(
+        &self,
+        archive_spec: String,
+        include_paths: &[PathBuf],
+        target_directory: PathBuf,
+        strip_components: Option<u32>,
+        progress_channel: CommandResponseSender,
+    ) -> BorgResult<()> {
+        info!("Starting to restore {} from {}", archive_spec, self);
+        self.backup_provider()
+            .restore(
+                self,
+                archive_spec,
+                include_paths,
+                target_directory,
+                strip_components,
+                progress_channel,
+            )
+            .await
     }
 
     pub(crate) fn backup_provider(&self) -> Box<dyn BackupProvider> {
+        self.kind().backup_provider()
+    }
+
+    pub(crate) fn kind(&self) -> RepositoryKind {
         match self.config {
-            RepositoryOptions::BorgV1(_) => Box::new(BorgProvider {}),
+            RepositoryOptions::BorgV1(_) => RepositoryKind::Borg,
             #[cfg(feature = "rustic")]
-            RepositoryOptions::Rustic(_) => Box::new(RusticProvider {}),
-            RepositoryOptions::Restic(_) => Box::new(ResticProvider {}),
+            RepositoryOptions::Rustic(_) => RepositoryKind::Rustic,
+            RepositoryOptions::Restic(_) => RepositoryKind::Restic,
         }
     }
 
@@ -446,65 +1264,703 @@ impl Repository {
             RepositoryOptions::Restic(_) => "Restic",
         }
     }
+
+    pub(crate) fn summary(&self) -> RepositorySummary {
+        RepositorySummary {
+            path: self.path.clone(),
+            kind: self.repo_kind_name().to_lowercase(),
+            disabled: self.disabled,
+            encryption: self.encryption.source_name().to_string(),
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) enum KeepWithinUnit {
+    Hour,
+    Day,
+    Week,
+    Month,
+    Year,
+}
+
+impl KeepWithinUnit {
+    fn suffix(&self) -> char {
+        match self {
+            KeepWithinUnit::Hour => 'H',
+            KeepWithinUnit::Day => 'd',
+            KeepWithinUnit::Week => 'w',
+            KeepWithinUnit::Month => 'm',
+            KeepWithinUnit::Year => 'y',
+        }
+    }
+
+    fn seconds(&self) -> u64 {
+        const DAY: u64 = 24 * 60 * 60;
+        match self {
+            KeepWithinUnit::Hour => 60 * 60,
+            KeepWithinUnit::Day => DAY,
+            KeepWithinUnit::Week => 7 * DAY,
+            // Same approximations as borg
+            KeepWithinUnit::Month => 31 * DAY,
+            KeepWithinUnit::Year => 365 * DAY,
+        }
+    }
+}
+
+/// A span of time like "7d", used for `keep_within` and `max_age`. Uses borg's units (H, d, w, m, y).
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+#[serde(try_from = "String", into = "String")]
+pub(crate) struct KeepWithin {
+    pub(crate) quantifier: NonZeroU16,
+    pub(crate) unit: KeepWithinUnit,
+}
+
+impl KeepWithin {
+    pub(crate) fn as_duration(&self) -> std::time::Duration {
+        std::time::Duration::from_secs(self.quantifier.get() as u64 * self.unit.seconds())
+    }
+}
+
+impl std::fmt::Display for KeepWithin {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}{}", self.quantifier, self.unit.suffix())
+    }
+}
+
+/// Parse a span of time like "7d" into its quantifier and unit. `what` names the setting in errors.
+fn parse_time_span(s: &str, what: &str) -> BorgResult<(NonZeroU16, KeepWithinUnit)> {
+    let s = s.trim();
+    let unit = match s.chars().last() {
+        Some('H') | Some('h') => KeepWithinUnit::Hour,
+        Some('d') => KeepWithinUnit::Day,
+        Some('w') => KeepWithinUnit::Week,
+        Some('m') => KeepWithinUnit::Month,
+        Some('y') => KeepWithinUnit::Year,
+        _ => bail!("Invalid {what} '{s}'. Expected a number followed by H, d, w, m or y (e.g. 7d)"),
+    };
+    let quantifier = s[..s.len() - 1]
+        .parse::<NonZeroU16>()
+        .with_context(|| format!("Invalid {what} '{s}'. Expected a positive number followed by H, d, w, m or y (e.g. 7d)"))?;
+    Ok((quantifier, unit))
+}
+
+impl FromStr for KeepWithin {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let (quantifier, unit) = parse_time_span(s, "keep_within")?;
+        Ok(KeepWithin { quantifier, unit })
+    }
+}
+
+impl TryFrom<String> for KeepWithin {
+    type Error = anyhow::Error;
+
+    fn try_from(value: String) -> Result<Self, Self::Error> {
+        value.parse()
+    }
 }
 
-#[derive(Serialize, Deserialize, Debug, Clone, Copy)]
+impl From<KeepWithin> for String {
+    fn from(value: KeepWithin) -> Self {
+        value.to_string()
+    }
+}
+
+/// Retention rules. Each rule is optional, and rules which are unset are not applied.
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
 pub(crate) struct PruneOptions {
-    pub(crate) keep_daily: NonZeroU16,
-    pub(crate) keep_weekly: NonZeroU16,
-    pub(crate) keep_monthly: NonZeroU16,
-    pub(crate) keep_yearly: NonZeroU16,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub(crate) keep_last: Option<NonZeroU16>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub(crate) keep_within: Option<KeepWithin>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub(crate) keep_hourly: Option<NonZeroU16>,
+    #[serde(default)]
+    pub(crate) keep_daily: Option<NonZeroU16>,
+    #[serde(default)]
+    pub(crate) keep_weekly: Option<NonZeroU16>,
+    #[serde(default)]
+    pub(crate) keep_monthly: Option<NonZeroU16>,
+    #[serde(default)]
+    pub(crate) keep_yearly: Option<NonZeroU16>,
+}
+
+impl PruneOptions {
+    /// True if no retention rule is set. Pruning with no rules would delete everything.
+    pub(crate) fn is_empty(&self) -> bool {
+        self.keep_last.is_none()
+            && self.keep_within.is_none()
+            && self.keep_hourly.is_none()
+            && self.keep_daily.is_none()
+            && self.keep_weekly.is_none()
+            && self.keep_monthly.is_none()
+            && self.keep_yearly.is_none()
+    }
+}
+
+/// How often a scheduled operation runs, e.g. "24H". Same units as `keep_within`.
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+#[serde(try_from = "String", into = "String")]
+pub(crate) struct ScheduleInterval {
+    quantifier: NonZeroU16,
+    unit: KeepWithinUnit,
+}
+
+impl ScheduleInterval {
+    pub(crate) fn as_duration(&self) -> std::time::Duration {
+        std::time::Duration::from_secs(self.quantifier.get() as u64 * self.unit.seconds())
+    }
+}
+
+impl std::fmt::Display for ScheduleInterval {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}{}", self.quantifier, self.unit.suffix())
+    }
+}
+
+impl FromStr for ScheduleInterval {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let (quantifier, unit) = parse_time_span(s, "schedule interval")?;
+        Ok(ScheduleInterval { quantifier, unit })
+    }
+}
+
+impl TryFrom<String> for ScheduleInterval {
+    type Error = anyhow::Error;
+
+    fn try_from(value: String) -> Result<Self, Self::Error> {
+        value.parse()
+    }
+}
+
+impl From<ScheduleInterval> for String {
+    fn from(value: ScheduleInterval) -> Self {
+        value.to_string()
+    }
+}
+
+/// How often `borgtui daemon` runs each operation, e.g. "24H" or "7d". Unset operations aren't scheduled.
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub(crate) struct Schedule {
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub(crate) backup: Option<ScheduleInterval>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub(crate) prune: Option<ScheduleInterval>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub(crate) compact: Option<ScheduleInterval>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub(crate) check: Option<ScheduleInterval>,
+    /// Wait a random amount of time up to this before each scheduled operation
+    #[serde(default)]
+    pub(crate) jitter_seconds: u64,
+}
+
+impl Schedule {
+    pub(crate) fn is_empty(&self) -> bool {
+        *self == Self::default()
+    }
 }
 
 impl Default for PruneOptions {
     fn default() -> Self {
         Self {
-            keep_daily: NonZeroU16::new(64).unwrap(),
-            keep_weekly: NonZeroU16::new(128).unwrap(),
-            keep_monthly: NonZeroU16::new(64).unwrap(),
-            keep_yearly: NonZeroU16::new(32).unwrap(),
+            keep_last: None,
+            keep_within: None,
+            keep_hourly: None,
+            keep_daily: NonZeroU16::new(64),
+            keep_weekly: NonZeroU16::new(128),
+            keep_monthly: NonZeroU16::new(64),
+            keep_yearly: NonZeroU16::new(32),
+        }
+    }
+}
+
+/// How `{now}` formats the backup time. Rustic mounts also name snapshot directories with it.
+pub(crate) const ARCHIVE_TIME_FORMAT: &str = "%Y-%m-%d:%H:%M:%S";
+const DEFAULT_ARCHIVE_NAME_FORMAT: &str = "{profile}-{now}";
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+enum ArchiveNamePart {
+    Literal(String),
+    Profile,
+    Hostname,
+    User,
+    Kind,
+    /// The backup time in this strftime format
+    Now(String),
+}
+
+/// What the archive name placeholders are filled in with.
+struct ArchiveNameValues {
+    profile: String,
+    hostname: String,
+    user: String,
+    kind: String,
+}
+
+impl ArchiveNameValues {
+    fn local(profile: &str, repo: &Repository) -> Self {
+        Self {
+            profile: profile.to_string(),
+            // Short like borg's {hostname}, e.g. "laptop" for "laptop.example.com"
+            hostname: local_hostname()
+                .split('.')
+                .next()
+                .unwrap_or_default()
+                .to_string(),
+            user: local_user(),
+            kind: repo.repo_kind_name().to_lowercase(),
+        }
+    }
+}
+
+fn local_hostname() -> String {
+    let mut buf = [0u8; 256];
+    // Safety: gethostname writes at most buf.len() bytes into buf
+    if unsafe { libc::gethostname(buf.as_mut_ptr() as *mut libc::c_char, buf.len()) } != 0 {
+        return "localhost".to_string();
+    }
+    let len = buf.iter().position(|b| *b == 0).unwrap_or(buf.len());
+    String::from_utf8_lossy(&buf[..len]).into_owned()
+}
+
+fn local_user() -> String {
+    if let Ok(user) = std::env::var("USER").or_else(|_| std::env::var("LOGNAME")) {
+        return user;
+    }
+    // Safety: getuid never fails
+    let uid = unsafe { libc::getuid() };
+    // Safety: passwd is plain old data, and getpwuid_r only writes to passwd and buf (which
+    // passwd's strings then point into), setting result to &passwd on success
+    unsafe {
+        let mut passwd: libc::passwd = std::mem::zeroed();
+        let mut buf = [0 as libc::c_char; 1024];
+        let mut result = std::ptr::null_mut();
+        if libc::getpwuid_r(uid, &mut passwd, buf.as_mut_ptr(), buf.len(), &mut result) == 0
+            && !result.is_null()
+        {
+            return std::ffi::CStr::from_ptr(passwd.pw_name)
+                .to_string_lossy()
+                .into_owned();
         }
     }
+    uid.to_string()
+}
+
+/// How archives are named, e.g. "{hostname}-{profile}-{now:%Y-%m-%d}". The placeholders are
+/// {profile}, {hostname}, {user}, {kind} (borg, restic or rustic) and {now} or {now:FORMAT}.
+/// Formats are checked with example values when the profile is loaded, which catches most bad
+/// formats early. Each name is checked again when it's rendered, since a hostname or user can
+/// still make it invalid.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+#[serde(try_from = "String", into = "String")]
+pub(crate) struct ArchiveNameFormat {
+    format: String,
+    parts: Vec<ArchiveNamePart>,
+}
+
+impl Default for ArchiveNameFormat {
+    fn default() -> Self {
+        DEFAULT_ARCHIVE_NAME_FORMAT
+            .parse()
+            .expect("the default archive name format is valid")
+    }
 }
 
-#[derive(Debug, Clone)]
+impl ArchiveNameFormat {
+    pub(crate) fn is_default(&self) -> bool {
+        self.format == DEFAULT_ARCHIVE_NAME_FORMAT
+    }
+
+    fn has(&self, part: &ArchiveNamePart) -> bool {
+        self.parts.contains(part)
+    }
+
+    fn render(&self, values: &ArchiveNameValues, now: &chrono::DateTime<chrono::Local>) -> String {
+        self.parts
+            .iter()
+            .map(|part| match part {
+                ArchiveNamePart::Literal(literal) => literal.clone(),
+                ArchiveNamePart::Profile => values.profile.clone(),
+                ArchiveNamePart::Hostname => values.hostname.clone(),
+                ArchiveNamePart::User => values.user.clone(),
+                ArchiveNamePart::Kind => values.kind.clone(),
+                ArchiveNamePart::Now(format) => now.format(format).to_string(),
+            })
+            .collect()
+    }
+
+    /// A glob matching every name this format renders for `values`, whatever the time.
+    fn glob(&self, values: &ArchiveNameValues) -> String {
+        let mut glob = String::new();
+        for part in &self.parts {
+            match part {
+                ArchiveNamePart::Literal(literal) => glob.push_str(&glob::Pattern::escape(literal)),
+                ArchiveNamePart::Profile => glob.push_str(&glob::Pattern::escape(&values.profile)),
+                ArchiveNamePart::Hostname => {
+                    glob.push_str(&glob::Pattern::escape(&values.hostname))
+                }
+                ArchiveNamePart::User => glob.push_str(&glob::Pattern::escape(&values.user)),
+                ArchiveNamePart::Kind => glob.push_str(&glob::Pattern::escape(&values.kind)),
+                ArchiveNamePart::Now(format) => push_time_glob(&mut glob, format),
+            }
+        }
+        glob
+    }
+
+    /// Fail if names rendered with `values` would be rejected by borg or break rustic mounts.
+    fn check_rendered(&self, values: &ArchiveNameValues) -> BorgResult<()> {
+        check_archive_name(&self.render(values, &chrono::Local::now()))
+    }
+}
+
+/// Add a glob matching any time rendered with the strftime `format`. Numbers only match digits
+/// (e.g. "[0-9][0-9][0-9][0-9]" for %Y), so the glob for "dev-{now}" doesn't also match the
+/// archives of a "dev-laptop" profile.
+fn push_time_glob(glob: &mut String, format: &str) {
+    use chrono::format::{Item, Numeric, Pad};
+    for item in chrono::format::StrftimeItems::new(format) {
+        let digits = match item {
+            Item::Literal(literal) | Item::Space(literal) => {
+                glob.push_str(&glob::Pattern::escape(literal));
+                continue;
+            }
+            Item::OwnedLiteral(literal) | Item::OwnedSpace(literal) => {
+                glob.push_str(&glob::Pattern::escape(&literal));
+                continue;
+            }
+            Item::Numeric(numeric, pad) => match numeric {
+                Numeric::Year | Numeric::IsoYear => Some((4, pad)),
+                Numeric::Ordinal => Some((3, pad)),
+                Numeric::Quarter | Numeric::NumDaysFromSun | Numeric::WeekdayFromMon => {
+                    Some((1, pad))
+                }
+                Numeric::YearDiv100
+                | Numeric::YearMod100
+                | Numeric::IsoYearDiv100
+                | Numeric::IsoYearMod100
+                | Numeric::Month
+                | Numeric::Day
+                | Numeric::WeekFromSun
+                | Numeric::WeekFromMon
+                | Numeric::IsoWeek
+                | Numeric::Hour
+                | Numeric::Hour12
+                | Numeric::Minute
+                | Numeric::Second => Some((2, pad)),
+                Numeric::Timestamp => Some((1, Pad::None)),
+                // Nanoseconds depend on the precision
+                _ => None,
+            },
+            // Names like %B, fractions and time zones
+            _ => None,
+        };
+        match digits {
+            Some((width, Pad::Zero)) => glob.push_str(&"[0-9]".repeat(width)),
+            Some((width, Pad::Space)) => {
+                glob.push_str(&"[ 0-9]".repeat(width - 1));
+                glob.push_str("[0-9]");
+            }
+            // Unpadded numbers are at least one digit
+            Some((_, Pad::None)) => glob.push_str("[0-9]*"),
+            None if glob.ends_with('*') => {}
+            None => glob.push('*'),
+        }
+    }
+}
+
+/// Fail if borg would reject `name` as an archive name or it would break rustic mounts.
+pub(crate) fn check_archive_name(name: &str) -> BorgResult<()> {
+    if name.is_empty() {
+        bail!("Archive names can't be empty")
+    }
+    if name.contains('/') {
+        bail!("Archive names can't contain '/', borg rejects them and rustic mounts treat it as a directory")
+    }
+    if name.contains("::") {
+        bail!("Archive names can't contain \"::\", borg uses it to separate the repository from the archive")
+    }
+    if name.chars().any(char::is_control) {
+        bail!("Archive names can't contain control characters like newlines")
+    }
+    if name.trim() != name {
+        bail!("Archive names can't start or end with whitespace")
+    }
+    Ok(())
+}
+
+impl std::fmt::Display for ArchiveNameFormat {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}", self.format)
+    }
+}
+
+impl FromStr for ArchiveNameFormat {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let mut parts = Vec::new();
+        let mut literal = String::new();
+        let mut chars = s.chars();
+        while let Some(c) = chars.next() {
+            let rest = chars.as_str();
+            match c {
+                // Doubled braces are literal braces, like format!
+                '{' | '}' if rest.starts_with(c) => {
+                    literal.push(c);
+                    chars.next();
+                }
+                '}' => bail!(
+                    "Unmatched '}}' in archive name format '{s}'. Write '}}}}' for a literal brace"
+                ),
+                '{' => {
+                    let Some((placeholder, rest)) = rest.split_once('}') else {
+                        bail!("Unclosed '{{' in archive name format '{s}'. Write '{{{{' for a literal brace")
+                    };
+                    let part = match placeholder.split_once(':') {
+                        None if placeholder == "profile" => ArchiveNamePart::Profile,
+                        None if placeholder == "hostname" => ArchiveNamePart::Hostname,
+                        None if placeholder == "user" => ArchiveNamePart::User,
+                        None if placeholder == "kind" => ArchiveNamePart::Kind,
+                        None if placeholder == "now" => {
+                            ArchiveNamePart::Now(ARCHIVE_TIME_FORMAT.to_string())
+                        }
+                        Some(("now", format)) => {
+                            if chrono::format::StrftimeItems::new(format)
+                                .any(|item| item == chrono::format::Item::Error)
+                            {
+                                bail!("Invalid date format '{format}' in archive name format '{s}'")
+                            }
+                            ArchiveNamePart::Now(format.to_string())
+                        }
+                        _ => bail!("Unknown placeholder {{{placeholder}}} in archive name format '{s}'. Expected {{profile}}, {{hostname}}, {{user}}, {{kind}}, {{now}} or {{now:FORMAT}}"),
+                    };
+                    if !literal.is_empty() {
+                        parts.push(ArchiveNamePart::Literal(std::mem::take(&mut literal)));
+                    }
+                    parts.push(part);
+                    chars = rest.chars();
+                }
+                c => literal.push(c),
+            }
+        }
+        if !literal.is_empty() {
+            parts.push(ArchiveNamePart::Literal(literal));
+        }
+        if !parts
+            .iter()
+            .any(|part| matches!(part, ArchiveNamePart::Now(_)))
+        {
+            bail!("Archive name format '{s}' needs a {{now}} placeholder so each backup gets a new name")
+        }
+        let format = ArchiveNameFormat {
+            format: s.to_string(),
+            parts,
+        };
+        let example = ArchiveNameValues {
+            profile: "profile".to_string(),
+            hostname: "host".to_string(),
+            user: "user".to_string(),
+            kind: "borg".to_string(),
+        };
+        format.check_rendered(&example)?;
+        Ok(format)
+    }
+}
+
+impl TryFrom<String> for ArchiveNameFormat {
+    type Error = anyhow::Error;
+
+    fn try_from(value: String) -> Result<Self, Self::Error> {
+        value.parse()
+    }
+}
+
+impl From<ArchiveNameFormat> for String {
+    fn from(value: ArchiveNameFormat) -> Self {
+        value.format
+    }
+}
+
+/// The archives a prune may remove: those the profile's archive name format would create on
+/// this host. Keeps profiles and hosts sharing a repository from pruning each other's archives.
+#[derive(Serialize, Deserialize, Debug, Clone, Default, PartialEq, Eq)]
+pub(crate) struct ArchiveFilter {
+    /// Matches the archive names, None if every name matches
+    pub(crate) glob: Option<String>,
+    /// Set if archive names contain the hostname. Restic doesn't name snapshots, but it
+    /// records the host they were made on.
+    pub(crate) hostname: Option<String>,
+}
+
+impl ArchiveFilter {
+    /// Whether prune may remove `archive` from `repo`. Restic snapshots are named after their
+    /// time, so only their host is compared. Borg's names already contain the hostname.
+    pub(crate) fn matches(&self, repo: &Repository, archive: &Archive) -> bool {
+        let glob_matches = matches!(repo.kind(), RepositoryKind::Restic)
+            || self.glob.as_ref().is_none_or(|glob| {
+                glob::Pattern::new(glob).is_ok_and(|glob| glob.matches(&archive.name))
+            });
+        let hostname_matches = matches!(repo.kind(), RepositoryKind::Borg)
+            || self
+                .hostname
+                .as_ref()
+                .is_none_or(|hostname| archive.hostname.as_ref() == Some(hostname));
+        glob_matches && hostname_matches
+    }
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
 pub(crate) enum ProfileOperation {
     AddBackupPath(PathBuf),
 }
 
-// Necessary for serde(default)
-const fn default_action_timeout_seconds() -> u64 {
-    30
+// Necessary for serde(default)
+const fn default_action_timeout_seconds() -> u64 {
+    30
+}
+
+// Necessary for serde(default)
+const fn default_exclude_caches() -> bool {
+    true
+}
+
+/// A repository from any version of the config. Not a `serde(untagged)` enum of the versions,
+/// since those fail with "data did not match any variant" rather than what's actually wrong.
+#[derive(Deserialize)]
+struct RepositoryVersion {
+    path: String,
+    encryption: Encryption,
+    #[serde(default)]
+    disabled: bool,
+    /// Replaced `rsh` and `kind` in V2
+    config: Option<RepositoryOptions>,
+    #[serde(default)]
+    prune_options: Option<PruneOptions>,
+    #[serde(default)]
+    backup_overrides: BackupOverrides,
+    #[serde(default)]
+    rsh: Option<String>,
+    #[serde(default = "default_repository_kind")]
+    kind: RepositoryKind,
+}
+
+impl ToLatestRepository for RepositoryVersion {
+    fn to_latest(&self) -> Repository {
+        match &self.config {
+            Some(config) => Repository {
+                path: self.path.clone(),
+                encryption: self.encryption.clone(),
+                disabled: self.disabled,
+                config: config.clone(),
+                prune_options: self.prune_options,
+                backup_overrides: self.backup_overrides.clone(),
+                lock: Default::default(),
+            },
+            None => RepositoryV1 {
+                path: self.path.clone(),
+                rsh: self.rsh.clone(),
+                encryption: self.encryption.clone(),
+                disabled: self.disabled,
+                kind: self.kind,
+            }
+            .to_latest(),
+        }
+    }
+}
+
+impl RepositoryVersion {
+    fn deserialize_latest<'de, D>(deserializer: D) -> Result<Vec<Repository>, D::Error>
+    where
+        D: serde::Deserializer<'de>,
+    {
+        let repos = Vec::<RepositoryVersion>::deserialize(deserializer)?;
+        Ok(repos.iter().map(ToLatestRepository::to_latest).collect())
+    }
+}
+
+/// Adds `permits` to `semaphore` when dropped.
+struct AddPermitsOnDrop {
+    semaphore: Arc<Semaphore>,
+    permits: usize,
 }
 
-// Necessary for serde(default)
-const fn default_exclude_caches() -> bool {
-    true
+impl AddPermitsOnDrop {
+    /// Close the semaphore instead, telling waiters nothing is coming.
+    fn abandon(self) {
+        self.semaphore.close();
+    }
 }
 
-#[derive(Serialize, Deserialize, Debug, Clone)]
-#[serde(untagged)]
-enum RepositoryVersion {
-    // Order matters!
-    V2(Repository),
-    V1(RepositoryV1),
+impl Drop for AddPermitsOnDrop {
+    fn drop(&mut self) {
+        self.semaphore.add_permits(self.permits);
+    }
 }
 
-impl RepositoryVersion {
-    fn deserialize<'de, D>(deserializer: D) -> Result<Vec<Repository>, D::Error>
-    where
-        D: serde::Deserializer<'de>,
-    {
-        let repos = Vec::<RepositoryVersion>::deserialize(deserializer)?;
-        let mut result = Vec::new();
-        for repo in repos {
-            match repo {
-                RepositoryVersion::V1(repo) => result.push(repo.to_latest()),
-                RepositoryVersion::V2(repo) => result.push(repo.to_latest()),
+/// Forward a repository's backup progress to `progress_channel`, remembering its last stats
+/// and first error. The backup is over once the provider drops every copy of the returned
+/// sender, at which point the handle resolves to the run's history record.
+fn forward_backup_progress(
+    repository: String,
+    archive_name: String,
+    progress_channel: CommandResponseSender,
+) -> (CommandResponseSender, tokio::task::JoinHandle<RunRecord>) {
+    let (backup_send, mut backup_recv) =
+        tokio::sync::mpsc::channel::<CommandResponse>(progress_channel.max_capacity());
+    let started_at = chrono::Local::now();
+    let forwarder = tokio::spawn(async move {
+        let mut stats = None;
+        let mut first_error = None;
+        let mut cancelled = false;
+        while let Some(response) = backup_recv.recv().await {
+            match &response {
+                CommandResponse::CreateProgress(BackupCreateProgress {
+                    create_progress:
+                        BackupCreationProgress::InProgress {
+                            original_size,
+                            compressed_size,
+                            deduplicated_size,
+                            num_files,
+                            ..
+                        },
+                    ..
+                }) => {
+                    stats = Some(RunStats {
+                        original_size: *original_size,
+                        compressed_size: *compressed_size,
+                        deduplicated_size: *deduplicated_size,
+                        num_files: *num_files,
+                    })
+                }
+                CommandResponse::CreateProgress(BackupCreateProgress {
+                    create_progress: BackupCreationProgress::Cancelled { .. },
+                    ..
+                }) => cancelled = true,
+                CommandResponse::Error(message) => {
+                    first_error.get_or_insert_with(|| message.clone());
+                }
+                _ => {}
+            }
+            if let Err(e) = progress_channel.send(response).await {
+                tracing::error!("Failed to forward backup progress: {}", e);
             }
         }
-        Ok(result)
-    }
+        let result = match first_error {
+            Some(error) => Err(anyhow!(error)),
+            None if cancelled => Err(Cancelled.into()),
+            None => Ok(()),
+        };
+        let mut record = RunRecord::new(RunKind::Create, repository, started_at, &result);
+        record.archive = Some(archive_name);
+        record.stats = stats;
+        record
+    });
+    (backup_send, forwarder)
 }
 
 #[derive(Serialize, Deserialize, Debug, Clone)]
@@ -519,10 +1975,91 @@ pub(crate) struct Profile {
     prune_options: PruneOptions,
     #[serde(default = "default_action_timeout_seconds")]
     action_timeout_seconds: u64,
-    #[serde(deserialize_with = "RepositoryVersion::deserialize")]
+    #[serde(default, skip_serializing_if = "Hooks::is_empty")]
+    hooks: Hooks,
+    /// `borgtui status` fails when an active repository's newest archive is older than this
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    max_age: Option<KeepWithin>,
+    #[serde(default, skip_serializing_if = "Schedule::is_empty")]
+    schedule: Schedule,
+    #[serde(default, skip_serializing_if = "ArchiveNameFormat::is_default")]
+    archive_name_format: ArchiveNameFormat,
+    #[serde(deserialize_with = "RepositoryVersion::deserialize_latest")]
     repos: Vec<Repository>,
 }
 
+/// The file formats profiles can be written in, chosen by the profile's extension.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) enum ProfileFormat {
+    Json,
+    Toml,
+    Yaml,
+}
+
+/// Prefix a deserialization error with the field it happened in, e.g. "repos[1].encryption".
+fn describe_deserialize_error<E: std::fmt::Display>(
+    error: serde_path_to_error::Error<E>,
+) -> String {
+    if error.path().iter().next().is_none() {
+        error.inner().to_string()
+    } else {
+        format!("{}: {}", error.path(), error.inner())
+    }
+}
+
+impl ProfileFormat {
+    /// Profile extensions, in the order they're looked for
+    const EXTENSIONS: [(&'static str, ProfileFormat); 4] = [
+        ("json", ProfileFormat::Json),
+        ("toml", ProfileFormat::Toml),
+        ("yaml", ProfileFormat::Yaml),
+        ("yml", ProfileFormat::Yaml),
+    ];
+
+    pub(crate) fn from_path(path: &Path) -> BorgResult<Self> {
+        let extension = path
+            .extension()
+            .and_then(|extension| extension.to_str())
+            .unwrap_or_default();
+        Self::EXTENSIONS
+            .iter()
+            .find(|(known, _)| *known == extension)
+            .map(|(_, format)| *format)
+            .ok_or_else(|| {
+                anyhow!(
+                    "Can't tell the format of profile {}, expected a .json, .toml, .yaml or .yml extension",
+                    path.display()
+                )
+            })
+    }
+
+    fn parse(self, contents: &str) -> BorgResult<Profile> {
+        let profile = match self {
+            ProfileFormat::Json => {
+                serde_path_to_error::deserialize(&mut serde_json::Deserializer::from_str(contents))
+                    .map_err(describe_deserialize_error)
+            }
+            ProfileFormat::Toml => {
+                serde_path_to_error::deserialize(toml::Deserializer::new(contents))
+                    .map_err(describe_deserialize_error)
+            }
+            // serde_yaml's errors already include the field
+            ProfileFormat::Yaml => serde_yaml::from_str(contents).map_err(|e| e.to_string()),
+        };
+        profile.map_err(|e| anyhow!(e))
+    }
+
+    fn serialize(self, profile: &Profile) -> BorgResult<String> {
+        match self {
+            ProfileFormat::Json => profile.serialize(),
+            ProfileFormat::Toml => toml::to_string_pretty(profile)
+                .with_context(|| format!("Failed to serialize profile {} as TOML", profile.name())),
+            ProfileFormat::Yaml => serde_yaml::to_string(profile)
+                .with_context(|| format!("Failed to serialize profile {} as YAML", profile.name())),
+        }
+    }
+}
+
 impl std::fmt::Display for Profile {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
         write!(f, "Profile<{}>", self.name)
@@ -552,6 +2089,10 @@ impl Profile {
             prune_options: Default::default(),
             repos: vec![],
             action_timeout_seconds: default_action_timeout_seconds(),
+            hooks: Hooks::default(),
+            max_age: None,
+            schedule: Schedule::default(),
+            archive_name_format: ArchiveNameFormat::default(),
         }
     }
 
@@ -577,30 +2118,32 @@ impl Profile {
         if !profile_path.exists() {
             return Ok(None);
         }
-        let profile = tokio::fs::read_to_string(profile_path)
+        let profile = tokio::fs::read_to_string(&profile_path)
             .await
             .with_context(|| format!("Failed to read profile {}", name))?;
-        serde_json::from_str(&profile)
-            .with_context(|| format!("Failed to deserialize profile {}", name))
-            .map(Some)
+        Self::parse(&profile_path, &profile).map(Some)
     }
 
     pub(crate) fn blocking_open_path<P: AsRef<Path>>(path: P) -> BorgResult<Self> {
         let profile = std::fs::read_to_string(path.as_ref()).with_context(|| {
             format!("Failed to read profile {}", path.as_ref().to_string_lossy())
         })?;
-        serde_json::from_str(&profile).with_context(|| {
-            format!(
-                "Failed to deserialize profile {}",
-                path.as_ref().to_string_lossy()
-            )
-        })
+        Self::parse(path.as_ref(), &profile)
+    }
+
+    fn parse(path: &Path, contents: &str) -> BorgResult<Self> {
+        // Not added as context so the field and line of the error are shown with it
+        ProfileFormat::from_path(path)?
+            .parse(contents)
+            .map_err(|e| anyhow!("Failed to deserialize profile {}: {}", path.display(), e))
     }
 
     pub(crate) fn find_repo_from_mount_src(&self, repo_or_archive: &str) -> BorgResult<Repository> {
-        let repo_name = match repo_or_archive.find("::") {
-            Some(loc) => repo_or_archive[..loc].to_string(),
-            None => repo_or_archive.to_string(),
+        // Archive names can't contain "::" (see ArchiveNameFormat), so the last one separates
+        // the repository from the archive
+        let repo_name = match repo_or_archive.rsplit_once("::") {
+            Some((repo_name, _archive)) => repo_name,
+            None => repo_or_archive,
         };
         tracing::debug!("Figured repo name is: {}", repo_name);
         self.active_repositories()
@@ -612,18 +2155,20 @@ impl Profile {
     pub(crate) async fn create_backup_with_notification(
         &self,
         progress_channel: CommandResponseSender,
+        cancel: CancelToken,
     ) -> BorgResult<tokio::task::JoinHandle<()>> {
         let completion_semaphore = Arc::new(Semaphore::new(0));
-        let num_active_repos = self.num_active_repos();
+        let num_backup_repos = self.writable_repositories().count();
         let self_name = format!("{}", self);
         let completion_semaphore_clone = completion_semaphore.clone();
+        let cancel_clone = cancel.clone();
         let join_handle = tokio::spawn(async move {
             let start_time = Instant::now();
             if let Err(e) = completion_semaphore_clone
-                .acquire_many(num_active_repos as u32)
+                .acquire_many(num_backup_repos as u32)
                 .await
             {
-                tracing::error!("Failed to wait on completion semaphore: {}", e);
+                tracing::debug!("Backup never started, skipping notification: {}", e);
             } else {
                 let elapsed_duration = start_time.elapsed();
                 let nicely_formatted = format!(
@@ -632,10 +2177,15 @@ impl Profile {
                     elapsed_duration.as_secs() / 60 % 60,
                     elapsed_duration.as_secs() % 60
                 );
-                tracing::info!("Completed backup for {} in {}", self_name, nicely_formatted);
+                let summary = if cancel_clone.is_cancelled() {
+                    "Backup cancelled"
+                } else {
+                    "Backup complete"
+                };
+                tracing::info!("{} for {} after {}", summary, self_name, nicely_formatted);
                 log_on_error!(
                     show_notification(
-                        &format!("Backup complete for {}", self_name),
+                        &format!("{} for {}", summary, self_name),
                         &format!("Completed in {}", nicely_formatted),
                         SHORT_NOTIFICATION_DURATION
                     )
@@ -644,7 +2194,7 @@ impl Profile {
                 );
             }
         });
-        self.create_backup_internal(progress_channel, completion_semaphore)
+        self.create_backup_internal(progress_channel, completion_semaphore, cancel)
             .await?;
         Ok(join_handle)
     }
@@ -652,8 +2202,9 @@ impl Profile {
     pub(crate) async fn create_backup(
         &self,
         progress_channel: CommandResponseSender,
+        cancel: CancelToken,
     ) -> BorgResult<()> {
-        self.create_backup_internal(progress_channel, Arc::new(Semaphore::new(0)))
+        self.create_backup_internal(progress_channel, Arc::new(Semaphore::new(0)), cancel)
             .await
     }
 
@@ -661,33 +2212,239 @@ impl Profile {
         &self,
         progress_channel: CommandResponseSender,
         completion_semaphore: Arc<Semaphore>,
+        cancel: CancelToken,
     ) -> BorgResult<()> {
-        let archive_name = format!(
-            "{}-{}",
-            self.name(),
-            chrono::Local::now().format("%Y-%m-%d:%H:%M:%S")
-        );
-        for repo in self.active_repositories() {
+        // Waiters are released however the backup ends, even if it never starts or the task
+        // finishing it panics
+        let release_waiters = AddPermitsOnDrop {
+            semaphore: completion_semaphore,
+            permits: self.writable_repositories().count(),
+        };
+        if let Some(repo) = self
+            .writable_repositories()
+            .find(|repo| self.backup_paths_for(repo).is_empty())
+        {
+            release_waiters.abandon();
+            bail!(
+                "Nothing to back up to {}: it has no backup paths once its backup_overrides are applied",
+                repo
+            );
+        }
+        let now = chrono::Local::now();
+        let archive_names = match self
+            .writable_repositories()
+            .map(|repo| self.archive_name_for(repo, &now))
+            .collect::<BorgResult<Vec<_>>>()
+        {
+            Ok(archive_names) => archive_names,
+            Err(e) => {
+                release_waiters.abandon();
+                return Err(e);
+            }
+        };
+        let hook_context = HookContext {
+            profile: self.name().to_string(),
+            repositories: self
+                .writable_repositories()
+                .map(|repo| repo.path())
+                .collect(),
+            // Unset when {kind} names the archive differently in each repository
+            archive: archive_names.iter().all_equal_value().ok().cloned(),
+            error: None,
+        };
+        if let Err(e) = self
+            .hooks
+            .run(HookKind::BeforeBackup, &hook_context, &progress_channel)
+            .await
+        {
+            self.hooks
+                .run_on_error(&hook_context, &e, &progress_channel)
+                .await;
+            release_waiters.abandon();
+            return Err(e.context("Aborting backup"));
+        }
+        if let Err(e) = cancel.check() {
+            release_waiters.abandon();
+            return Err(e);
+        }
+
+        let mut backups = Vec::new();
+        for (repo, archive_name) in self.writable_repositories().zip(archive_names) {
+            let (backup_send, forwarder) = forward_backup_progress(
+                repo.path(),
+                archive_name.clone(),
+                progress_channel.clone(),
+            );
             let backup_provider = repo.backup_provider();
-            backup_provider
+            let start_result = backup_provider
                 .create_backup(
-                    archive_name.clone(),
-                    self.backup_paths(),
-                    self.exclude_patterns(),
-                    self.exclude_caches(),
+                    archive_name,
+                    &self.backup_paths_for(repo),
+                    &self.exclude_patterns_for(repo),
+                    self.exclude_caches_for(repo),
                     repo.clone(),
-                    progress_channel.clone(),
-                    completion_semaphore.clone(),
+                    backup_send,
+                    Arc::new(Semaphore::new(0)),
+                    cancel.clone(),
                 )
-                .await?;
+                .await;
+            let failed_to_start = start_result.is_err();
+            backups.push((forwarder, start_result));
+            if failed_to_start {
+                break;
+            }
         }
-        Ok(())
+        let start_error = backups
+            .iter()
+            .find_map(|(_, start_result)| start_result.as_ref().err())
+            .map(|e| anyhow!("{}", e));
+
+        let hooks = self.hooks.clone();
+        tokio::spawn(async move {
+            let _release_waiters = release_waiters;
+            let mut first_error = None;
+            for (forwarder, start_result) in backups {
+                let mut record = match forwarder.await {
+                    Ok(record) => record,
+                    Err(e) => {
+                        tracing::error!("Failed to join backup forwarder: {}", e);
+                        let error = match start_result {
+                            Err(start_error) => start_error.to_string(),
+                            Ok(()) => format!("Lost track of the backup's progress: {}", e),
+                        };
+                        first_error.get_or_insert(error);
+                        continue;
+                    }
+                };
+                if let Err(e) = start_result {
+                    record.outcome = RunOutcome::Failure;
+                    record.error = Some(e.to_string());
+                }
+                if first_error.is_none() {
+                    first_error = record.error.clone();
+                }
+                history::record_run(record).await;
+            }
+            let result = match first_error {
+                Some(error) => Err(anyhow!(error)),
+                None => {
+                    hooks
+                        .run(HookKind::AfterBackup, &hook_context, &progress_channel)
+                        .await
+                }
+            };
+            if let Err(e) = result {
+                if cancel.is_cancelled() {
+                    send_info!(progress_channel, "Backup cancelled".to_string());
+                } else {
+                    send_error!(progress_channel, format!("Backup failed: {}", e));
+                }
+                hooks
+                    .run_on_error(&hook_context, &e, &progress_channel)
+                    .await;
+            }
+        });
+        match start_error {
+            Some(e) => Err(e),
+            None => Ok(()),
+        }
+    }
+
+    /// Verify the active repositories (or only `only_these_repos`), running the check hooks
+    /// around them. Returns the repositories that failed verification.
+    pub(crate) async fn check_repositories(
+        &self,
+        only_these_repos: Option<&[String]>,
+        progress_channel: CommandResponseSender,
+        cancel: CancelToken,
+    ) -> BorgResult<Vec<Repository>> {
+        let mut repos = Vec::new();
+        for repo in self.active_repositories() {
+            let should_check = only_these_repos
+                .map(|repos_to_check| repos_to_check.contains(&repo.path()))
+                .unwrap_or(true);
+            if should_check {
+                repos.push(repo.clone());
+            } else {
+                tracing::info!("Skipping verification of {}", repo.path());
+            }
+        }
+        let hook_context = HookContext {
+            profile: self.name().to_string(),
+            repositories: repos.iter().map(|repo| repo.path()).collect(),
+            ..Default::default()
+        };
+        if let Err(e) = self
+            .hooks
+            .run(HookKind::BeforeCheck, &hook_context, &progress_channel)
+            .await
+        {
+            self.hooks
+                .run_on_error(&hook_context, &e, &progress_channel)
+                .await;
+            return Err(e.context("Aborting check"));
+        }
+
+        let mut checks = tokio::task::JoinSet::new();
+        for repo in repos {
+            tracing::info!("Starting verification of {}", repo.path());
+            let progress_channel = progress_channel.clone();
+            let cancel = cancel.clone();
+            checks.spawn(async move {
+                let successful = match repo.check(progress_channel, cancel).await {
+                    Ok(successful) => successful,
+                    Err(e) if is_cancelled(&e) => false,
+                    Err(e) => {
+                        tracing::error!("Verification failed: {e}");
+                        false
+                    }
+                };
+                (repo, successful)
+            });
+        }
+        let mut failed = Vec::new();
+        while let Some(res) = checks.join_next().await {
+            let (repo, successful) = res?;
+            if !successful {
+                failed.push(repo);
+            }
+        }
+
+        let result = if cancel.is_cancelled() {
+            Err(Cancelled.into())
+        } else if failed.is_empty() {
+            self.hooks
+                .run(HookKind::AfterCheck, &hook_context, &progress_channel)
+                .await
+        } else {
+            Err(anyhow!(
+                "Verification failed for {}",
+                failed.iter().map(|repo| repo.path()).join(", ")
+            ))
+        };
+        if let Err(e) = result {
+            if cancel.is_cancelled() {
+                send_info!(progress_channel, "Check cancelled".to_string());
+            } else {
+                send_error!(progress_channel, format!("Check failed: {}", e));
+            }
+            self.hooks
+                .run_on_error(&hook_context, &e, &progress_channel)
+                .await;
+            cancel.check()?;
+        }
+        Ok(failed)
     }
 
     pub(crate) fn active_repositories(&self) -> impl Iterator<Item = &Repository> {
         self.repositories().iter().filter(|repo| !repo.disabled)
     }
 
+    /// The active repositories that are backed up to, pruned and compacted.
+    pub(crate) fn writable_repositories(&self) -> impl Iterator<Item = &Repository> {
+        self.active_repositories().filter(|repo| !repo.read_only())
+    }
+
     pub(crate) fn num_active_repositories(&self) -> usize {
         self.active_repositories().count()
     }
@@ -708,24 +2465,97 @@ impl Profile {
         self.repos.len()
     }
 
-    pub(crate) fn num_active_repos(&self) -> usize {
-        self.active_repositories().count()
+    /// How fresh each repository's backups are, see `borgtui status`.
+    pub(crate) async fn repository_statuses(&self) -> BorgResult<Vec<RepositoryStatus>> {
+        let history = history::load_history().await?;
+        let timeout = std::time::Duration::from_secs(self.action_timeout_seconds);
+        let mut statuses = Vec::new();
+        for repo in self.repositories() {
+            statuses.push(repo.status(self.max_age, &history, timeout).await);
+        }
+        Ok(statuses)
+    }
+
+    pub(crate) fn schedule(&self) -> Schedule {
+        self.schedule
     }
 
     pub(crate) fn action_timeout_seconds(&self) -> u64 {
         self.action_timeout_seconds
     }
 
-    pub(crate) fn prune_options(&self) -> PruneOptions {
-        self.prune_options
+    /// The prune options for `repo`: its own if it overrides them, otherwise the profile's.
+    pub(crate) fn prune_options_for(&self, repo: &Repository) -> PruneOptions {
+        repo.prune_options.unwrap_or(self.prune_options)
+    }
+
+    /// The name of the archive a backup started at `now` creates in `repo`.
+    pub(crate) fn archive_name_for(
+        &self,
+        repo: &Repository,
+        now: &chrono::DateTime<chrono::Local>,
+    ) -> BorgResult<String> {
+        let values = ArchiveNameValues::local(self.name(), repo);
+        let name = self.archive_name_format.render(&values, now);
+        check_archive_name(&name).with_context(|| {
+            format!(
+                "archive_name_format '{}' renders the invalid name {:?} for {}",
+                self.archive_name_format, name, repo
+            )
+        })?;
+        Ok(name)
+    }
+
+    /// The archives in `repo` this profile prunes.
+    pub(crate) fn archive_filter_for(&self, repo: &Repository) -> ArchiveFilter {
+        let values = ArchiveNameValues::local(self.name(), repo);
+        let glob = self.archive_name_format.glob(&values);
+        ArchiveFilter {
+            glob: (glob != "*").then_some(glob),
+            hostname: self
+                .archive_name_format
+                .has(&ArchiveNamePart::Hostname)
+                .then(local_hostname),
+        }
     }
 
     pub(crate) fn exclude_patterns(&self) -> &[String] {
         &self.exclude_patterns
     }
 
-    pub(crate) fn exclude_caches(&self) -> bool {
-        self.exclude_caches
+    /// The profile's backup paths, less those `repo` skips, plus its extra paths.
+    pub(crate) fn backup_paths_for(&self, repo: &Repository) -> Vec<PathBuf> {
+        let overrides = &repo.backup_overrides;
+        apply_overrides(
+            &self.backup_paths,
+            &overrides.extra_paths,
+            &overrides.skip_paths,
+        )
+    }
+
+    /// The profile's exclude patterns, less those `repo` skips, plus its extra excludes.
+    pub(crate) fn exclude_patterns_for(&self, repo: &Repository) -> Vec<String> {
+        let overrides = &repo.backup_overrides;
+        apply_overrides(
+            &self.exclude_patterns,
+            &overrides.extra_excludes,
+            &overrides.skip_excludes,
+        )
+    }
+
+    pub(crate) fn exclude_caches_for(&self, repo: &Repository) -> bool {
+        repo.backup_overrides
+            .exclude_caches
+            .unwrap_or(self.exclude_caches)
+    }
+
+    pub(crate) fn repository_sources_summary(&self, repo: &Repository) -> RepositorySourcesSummary {
+        RepositorySourcesSummary {
+            repository: repo.summary(),
+            backup_paths: self.backup_paths_for(repo),
+            exclude_patterns: self.exclude_patterns_for(repo),
+            exclude_caches: self.exclude_caches_for(repo),
+        }
     }
 
     pub(crate) fn serialize(&self) -> BorgResult<String> {
@@ -740,16 +2570,32 @@ impl Profile {
         }
     }
 
-    pub(crate) fn profile_path_for_name(name: &str) -> BorgResult<PathBuf> {
+    fn profiles_dir() -> BorgResult<PathBuf> {
         let mut path = dirs::config_dir()
             .ok_or_else(|| anyhow::anyhow!("Failed to get config directory. Is $HOME set?"))?;
         path.push("borgtui");
         path.push("profiles");
-        path.push(name);
-        path.set_extension("json");
         Ok(path)
     }
 
+    /// Every existing file for the profile `name`, in the order they're looked for.
+    pub(crate) fn profile_paths_for_name(name: &str) -> BorgResult<Vec<PathBuf>> {
+        let dir = Self::profiles_dir()?;
+        Ok(ProfileFormat::EXTENSIONS
+            .iter()
+            .map(|(extension, _)| dir.join(format!("{}.{}", name, extension)))
+            .filter(|path| path.exists())
+            .collect())
+    }
+
+    /// The profile's JSON, TOML or YAML file. New profiles are JSON.
+    pub(crate) fn profile_path_for_name(name: &str) -> BorgResult<PathBuf> {
+        match Self::profile_paths_for_name(name)?.into_iter().next() {
+            Some(path) => Ok(path),
+            None => Ok(Self::profiles_dir()?.join(format!("{}.json", name))),
+        }
+    }
+
     pub(crate) fn profile_path(&self) -> BorgResult<PathBuf> {
         Self::profile_path_for_name(&self.name)
     }
@@ -764,12 +2610,130 @@ impl Profile {
                 )
             })?
         }
-        let profile = self.serialize()?;
+        let profile = ProfileFormat::from_path(&profile_path)?.serialize(self)?;
         tokio::fs::write(profile_path, profile)
             .await
             .with_context(|| format!("Failed to write profile {}", self.name))
     }
 
+    /// Problems which would otherwise only show up once a backup runs, described by field.
+    pub(crate) fn validate(&self) -> Vec<String> {
+        let mut problems = Vec::new();
+        for (i, path) in self.backup_paths.iter().enumerate() {
+            if !path.exists() {
+                problems.push(format!(
+                    "backup_paths[{}]: {} doesn't exist",
+                    i,
+                    path.display()
+                ));
+            }
+        }
+        for (i, pattern) in self.exclude_patterns.iter().enumerate() {
+            let problem = self
+                .repos
+                .iter()
+                .filter(|repo| !repo.backup_overrides.skip_excludes.contains(pattern))
+                .find_map(|repo| exclude_pattern_problem(repo, pattern));
+            if let Some(problem) = problem {
+                problems.push(format!(
+                    "exclude_patterns[{}]: {:?} {}",
+                    i, pattern, problem
+                ));
+            }
+        }
+        let mut seen_repos = HashSet::new();
+        for (i, repo) in self.repos.iter().enumerate() {
+            if !seen_repos.insert(repo.path.as_str()) {
+                problems.push(format!(
+                    "repos[{}]: {} is listed more than once",
+                    i, repo.path
+                ));
+            }
+            if let Err(e) = repo.get_passphrase() {
+                problems.push(format!(
+                    "repos[{}].encryption: Can't get the {} passphrase: {:#}",
+                    i,
+                    repo.encryption.source_name(),
+                    e
+                ));
+            }
+            let overrides = &repo.backup_overrides;
+            for (j, path) in overrides.extra_paths.iter().enumerate() {
+                if !path.exists() {
+                    problems.push(format!(
+                        "repos[{}].backup_overrides.extra_paths[{}]: {} doesn't exist",
+                        i,
+                        j,
+                        path.display()
+                    ));
+                }
+            }
+            for (j, path) in overrides.skip_paths.iter().enumerate() {
+                if !self.backup_paths.contains(path) {
+                    problems.push(format!(
+                        "repos[{}].backup_overrides.skip_paths[{}]: {} isn't one of the profile's backup paths",
+                        i,
+                        j,
+                        path.display()
+                    ));
+                }
+            }
+            for (j, pattern) in overrides.extra_excludes.iter().enumerate() {
+                if let Some(problem) = exclude_pattern_problem(repo, pattern) {
+                    problems.push(format!(
+                        "repos[{}].backup_overrides.extra_excludes[{}]: {:?} {}",
+                        i, j, pattern, problem
+                    ));
+                }
+            }
+            for (j, pattern) in overrides.skip_excludes.iter().enumerate() {
+                if !self.exclude_patterns.contains(pattern) {
+                    problems.push(format!(
+                        "repos[{}].backup_overrides.skip_excludes[{}]: {:?} isn't one of the profile's exclude patterns",
+                        i, j, pattern
+                    ));
+                }
+            }
+            match &repo.config {
+                #[cfg(feature = "rustic")]
+                RepositoryOptions::Rustic(options) => {
+                    if let Some(level) = options
+                        .compression
+                        .filter(|level| !(-7..=22).contains(level))
+                    {
+                        problems.push(format!(
+                            "repos[{}].config.Rustic.compression: {} isn't a zstd level from -7 to 22",
+                            i, level
+                        ));
+                    }
+                }
+                RepositoryOptions::Restic(options) => {
+                    if let Some(pack_size) = options
+                        .pack_size_mib
+                        .filter(|size| !(4..=128).contains(&size.get()))
+                    {
+                        problems.push(format!(
+                            "repos[{}].config.Restic.pack_size_mib: Restic only supports pack sizes from 4 to 128 MiB, not {}",
+                            i, pack_size
+                        ));
+                    }
+                }
+                _ => {}
+            }
+            if let Err(e) = self.archive_name_for(repo, &chrono::Local::now()) {
+                problems.push(format!("repos[{}]: {:#}", i, e));
+            }
+            // Read-only repositories aren't backed up to
+            if !repo.disabled && !repo.read_only() && self.backup_paths_for(repo).is_empty() {
+                problems.push(format!(
+                    "repos[{}]: Has no backup paths once its backup_overrides are applied",
+                    i
+                ));
+            }
+        }
+        problems
+    }
+
     pub(crate) fn has_repository(&self, path: &str) -> bool {
         self.repos.iter().any(|r| r.path == path)
     }
@@ -1036,6 +3000,11 @@ mod tests {
         assert!(profile.repositories()[1].rustic_options().is_ok());
     }
 
+    /// A profile with a borg repository (repos[0]) and a restic one (repos[1])
+    fn golden_profile() -> Profile {
+        serde_json::from_str(GOLDEN_V2_CONFIG_WITH_RESTIC).unwrap()
+    }
+
     const GOLDEN_V2_CONFIG_WITH_RESTIC: &str = r#"
 {
   "name": "dev",
@@ -1083,7 +3052,7 @@ mod tests {
 
     #[test]
     fn can_load_new_config_with_restic() {
-        let profile: Profile = serde_json::from_str(GOLDEN_V2_CONFIG_WITH_RESTIC).unwrap();
+        let profile = golden_profile();
         assert_eq!(
             profile.repositories()[0]
                 .borg_options()
@@ -1095,6 +3064,462 @@ mod tests {
         assert!(profile.repositories()[1].restic_options().is_ok());
     }
 
+    #[test]
+    fn can_override_prune_options_per_repository() {
+        let mut profile = golden_profile();
+        let profile_prune_options = profile.prune_options;
+        assert_eq!(profile_prune_options.keep_daily, NonZeroU16::new(2));
+        assert_eq!(profile_prune_options.keep_last, None);
+
+        let repo_prune_options: PruneOptions =
+            serde_json::from_str(r#"{"keep_last": 5, "keep_within": "7d", "keep_daily": null}"#)
+                .unwrap();
+        profile.repos[1].prune_options = Some(repo_prune_options);
+        let reloaded: Profile = serde_json::from_str(&profile.serialize().unwrap()).unwrap();
+        assert_eq!(
+            reloaded.prune_options_for(&reloaded.repos[0]),
+            profile_prune_options
+        );
+        let overridden = reloaded.prune_options_for(&reloaded.repos[1]);
+        assert_eq!(overridden.keep_last, NonZeroU16::new(5));
+        assert_eq!(overridden.keep_within.unwrap().to_string(), "7d");
+        assert_eq!(overridden.keep_daily, None);
+    }
+
+    #[test]
+    fn repositories_can_override_backup_paths_and_excludes() {
+        let mut profile = golden_profile();
+        assert!(!profile.serialize().unwrap().contains("backup_overrides"));
+        profile.repos[1].backup_overrides = serde_json::from_str(
+            r#"{
+                "extra_paths": ["/home/david/Documents"],
+                "skip_paths": ["/home/david/Pictures", "/home/david/programming/collatz"],
+                "skip_excludes": ["**/tmp*"],
+                "exclude_caches": false
+            }"#,
+        )
+        .unwrap();
+        let reloaded: Profile = serde_json::from_str(&profile.serialize().unwrap()).unwrap();
+
+        let unchanged = &reloaded.repos[0];
+        assert_eq!(reloaded.backup_paths_for(unchanged), reloaded.backup_paths);
+        assert_eq!(
+            reloaded.exclude_patterns_for(unchanged),
+            reloaded.exclude_patterns
+        );
+        assert!(reloaded.exclude_caches_for(unchanged));
+
+        let offsite = &reloaded.repos[1];
+        assert_eq!(
+            reloaded.backup_paths_for(offsite),
+            vec![
+                PathBuf::from("/home/david/programming/advent-of-code-2020"),
+                PathBuf::from("/home/david/programming/borgtui"),
+                PathBuf::from("/home/david/Documents"),
+            ]
+        );
+        assert!(reloaded.exclude_patterns_for(offsite).is_empty());
+        assert!(!reloaded.exclude_caches_for(offsite));
+    }
+
+    #[test]
+    fn hooks_are_optional_in_config() {
+        let profile = golden_profile();
+        assert!(profile.hooks.is_empty());
+        assert!(!profile.serialize().unwrap().contains("hooks"));
+    }
+
+    #[test]
+    fn profiles_round_trip_through_every_format() {
+        let profile = golden_profile();
+        for format in [
+            ProfileFormat::Toml,
+            ProfileFormat::Yaml,
+            ProfileFormat::Json,
+        ] {
+            let serialized = format.serialize(&profile).unwrap();
+            let reloaded = format.parse(&serialized).unwrap();
+            assert_eq!(reloaded.serialize().unwrap(), profile.serialize().unwrap());
+        }
+        assert_eq!(
+            ProfileFormat::from_path(Path::new("/tmp/work.yml")).unwrap(),
+            ProfileFormat::Yaml
+        );
+        assert!(ProfileFormat::from_path(Path::new("/tmp/work.ini")).is_err());
+    }
+
+    #[test]
+    fn parse_errors_name_the_field() {
+        let error = ProfileFormat::Toml
+            .parse(
+                r#"
+name = "dev"
+backup_paths = []
+
+[[repos]]
+path = "/hdd1/borg"
+encryption = "Plaintext"
+config = { BorgV1 = {} }
+"#,
+            )
+            .unwrap_err()
+            .to_string();
+        assert!(error.starts_with("repos[0].encryption: "), "{}", error);
+        assert!(error.contains("line 7"), "{}", error);
+    }
+
+    #[test]
+    fn archive_name_formats_render_and_filter() {
+        use chrono::TimeZone;
+        let mut profile = golden_profile();
+        let now = chrono::Local.with_ymd_and_hms(2024, 5, 6, 7, 8, 9).unwrap();
+        // The default matches the names borgtui has always used
+        assert_eq!(
+            profile.archive_name_for(&profile.repos[0], &now).unwrap(),
+            "dev-2024-05-06:07:08:09"
+        );
+        assert_eq!(
+            profile.archive_filter_for(&profile.repos[0]),
+            ArchiveFilter {
+                glob: Some(
+                    "dev-[0-9][0-9][0-9][0-9]-[0-9][0-9]-[0-9][0-9]:[0-9][0-9]:[0-9][0-9]:[0-9][0-9]"
+                        .to_string()
+                ),
+                hostname: None
+            }
+        );
+
+        profile.archive_name_format = "{{{kind}}}-{profile}*{now:%Y}{now:%m}".parse().unwrap();
+        assert_eq!(
+            profile.archive_name_for(&profile.repos[1], &now).unwrap(),
+            "{restic}-dev*202405"
+        );
+        let filter = profile.archive_filter_for(&profile.repos[1]);
+        assert_eq!(
+            filter.glob.as_deref(),
+            Some("{restic}-dev[*][0-9][0-9][0-9][0-9][0-9][0-9]")
+        );
+        assert_eq!(filter.hostname, None);
+
+        profile.archive_name_format = "{hostname}-{now}".parse().unwrap();
+        assert!(profile
+            .archive_filter_for(&profile.repos[0])
+            .hostname
+            .is_some());
+        profile.archive_name_format = "{now:%s-%e}".parse().unwrap();
+        assert_eq!(
+            profile
+                .archive_filter_for(&profile.repos[0])
+                .glob
+                .as_deref(),
+            Some("[0-9]*-[ 0-9][0-9]")
+        );
+
+        for bad in [
+            "{profile}",
+            "{host}-{now}",
+            "{profile-{now}",
+            "{now}}",
+            "{now:%Y/%m}",
+            "{profile}::{now}",
+            "{now:%Q}",
+            " {now}",
+        ] {
+            assert!(bad.parse::<ArchiveNameFormat>().is_err(), "{}", bad);
+        }
+        let error = ProfileFormat::Json
+            .parse(r#"{"name": "dev", "backup_paths": [], "archive_name_format": "{now:%D}", "repos": []}"#)
+            .unwrap_err()
+            .to_string();
+        assert!(error.starts_with("archive_name_format: "), "{}", error);
+    }
+
+    #[test]
+    fn archive_names_are_checked() {
+        for good in ["dev-2024-05-06:07:08:09", "before the secret", "a:b"] {
+            assert!(check_archive_name(good).is_ok(), "{}", good);
+        }
+        for bad in ["", "a/b", "a::b", "a\nb", " padded", "padded "] {
+            assert!(check_archive_name(bad).is_err(), "{:?}", bad);
+        }
+    }
+
+    #[tokio::test]
+    async fn archive_operations_stay_in_their_repository() {
+        let profile = golden_profile();
+        let repo = &profile.repos[0];
+        let (send, _recv) = tokio::sync::mpsc::channel(1);
+        for archive_spec in [
+            format!("{}::a1", profile.repos[1].path()),
+            format!("{}0::a1", repo.path()),
+            format!("{}/::a1", repo.path()),
+            "a1".to_string(),
+        ] {
+            let error = repo
+                .delete_archive(archive_spec.clone(), send.clone(), CancelToken::default())
+                .await
+                .unwrap_err();
+            assert!(
+                error.to_string().contains("is not an archive in"),
+                "{}",
+                error
+            );
+            let error = repo
+                .rename_archive(archive_spec.clone(), "renamed".to_string(), send.clone())
+                .await
+                .unwrap_err();
+            assert!(
+                error.to_string().contains("is not an archive in"),
+                "{}",
+                error
+            );
+        }
+    }
+
+    #[test]
+    fn prune_filters_dont_reach_into_other_profiles() {
+        let dev = golden_profile();
+        let mut dev_laptop = golden_profile();
+        dev_laptop.name = "dev-laptop".to_string();
+        let archive = |name: &str| Archive {
+            name: name.to_string(),
+            ..Default::default()
+        };
+        let ours = archive("dev-2024-05-06:07:08:09");
+        let theirs = archive("dev-laptop-2024-05-06:07:08:09");
+
+        let repo = &dev.repos[0];
+        assert!(dev.archive_filter_for(repo).matches(repo, &ours));
+        assert!(!dev.archive_filter_for(repo).matches(repo, &theirs));
+        assert!(dev_laptop.archive_filter_for(repo).matches(repo, &theirs));
+        assert!(!dev_laptop.archive_filter_for(repo).matches(repo, &ours));
+        assert!(!dev
+            .archive_filter_for(repo)
+            .matches(repo, &archive("dev-manual")));
+    }
+
+    #[test]
+    fn borg_options_parse_and_check_compression() {
+        let options: BorgV1Options = serde_json::from_str(
+            r#"{"rsh": null, "remote_path": null, "compression": "auto,zstd,10", "upload_ratelimit": 500, "one_file_system": true}"#,
+        )
+        .unwrap();
+        assert_eq!(options.compression.unwrap().to_string(), "auto,zstd,10");
+        assert_eq!(options.upload_ratelimit, NonZeroU64::new(500));
+        assert!(options.one_file_system);
+        assert!(!options.numeric_ids);
+        // Unset options are left out of the profile
+        assert_eq!(
+            serde_json::to_string(&BorgV1OptionsBuilder::new().build()).unwrap(),
+            r#"{"rsh":null,"remote_path":null}"#
+        );
+
+        for good in [
+            "none",
+            "lz4",
+            "zstd",
+            "zstd,22",
+            "zlib,0",
+            "obfuscate,3,lzma,6",
+        ] {
+            assert!(good.parse::<BorgCompression>().is_ok(), "{}", good);
+        }
+        for bad in [
+            "",
+            "zstd,23",
+            "lz4,1",
+            "gzip",
+            "auto",
+            "obfuscate,7,lz4",
+            "zlib,x",
+        ] {
+            assert!(bad.parse::<BorgCompression>().is_err(), "{}", bad);
+        }
+    }
+
+    #[test]
+    fn borg_options_check_chunker_params() {
+        let options: BorgV1Options = serde_json::from_str(
+            r#"{"rsh": null, "remote_path": null, "chunker_params": "fixed,4194304"}"#,
+        )
+        .unwrap();
+        assert_eq!(options.chunker_params.unwrap().to_string(), "fixed,4194304");
+        for good in [
+            "default",
+            "buzhash,19,23,21,4095",
+            "10,23,16,4095",
+            "fixed,4096,512",
+        ] {
+            assert!(good.parse::<BorgChunkerParams>().is_ok(), "{}", good);
+        }
+        for bad in [
+            "",
+            "fixed",
+            "fixed,32",
+            "fixed,4096,-1",
+            "buzhash,19,23,21",
+            "buzhash,19,23,24,4095",
+            "buzhash,5,23,21,4095",
+            "buzhash,19,23,21,4096",
+            "rabin,19,23,21,4095",
+        ] {
+            assert!(bad.parse::<BorgChunkerParams>().is_err(), "{}", bad);
+        }
+    }
+
+    #[test]
+    fn restic_options_and_read_only_repos() {
+        let mut profile = golden_profile();
+        profile.repos[1].config = serde_json::from_str(
+            r#"{"Restic": {"backend_options": {"s3.storage-class": "STANDARD_IA"}, "compression": "max", "pack_size_mib": 256, "read_only": true}}"#,
+        )
+        .unwrap();
+        let options = profile.repos[1].restic_options().unwrap();
+        assert_eq!(options.backend_options["s3.storage-class"], "STANDARD_IA");
+        assert_eq!(options.compression, Some(ResticCompression::Max));
+        assert!(!options.no_cache);
+
+        assert!(profile.repos[1].read_only());
+        assert!(!profile.repos[0].read_only());
+        let writable = profile
+            .writable_repositories()
+            .map(|repo| repo.path())
+            .collect::<Vec<_>>();
+        assert_eq!(writable, vec![profile.repos[0].path()]);
+
+        profile.backup_paths = vec![std::env::temp_dir()];
+        for repo in profile.repos.iter_mut() {
+            repo.encryption = Encryption::None;
+        }
+        let problems = profile.validate();
+        assert_eq!(problems.len(), 1, "{:?}", problems);
+        assert!(problems[0].starts_with("repos[1].config.Restic.pack_size_mib: "));
+    }
+
+    #[test]
+    fn validate_finds_bad_globs_and_duplicate_repos() {
+        let mut profile = golden_profile();
+        profile.backup_paths = vec![std::env::temp_dir()];
+        profile.exclude_patterns = vec!["**/tmp*".to_string(), "[unclosed".to_string()];
+        for repo in profile.repos.iter_mut() {
+            repo.encryption = Encryption::None;
+        }
+        profile.repos.push(profile.repos[0].clone());
+        let problems = profile.validate();
+        assert_eq!(problems.len(), 2, "{:?}", problems);
+        assert!(problems[0].starts_with("exclude_patterns[1]: "));
+        assert!(problems[1].starts_with("repos[2]: "));
+    }
+
+    #[test]
+    fn repositories_need_something_to_back_up() {
+        let mut profile = golden_profile();
+        profile.backup_paths = vec![std::env::temp_dir()];
+        profile.repos[1].encryption = Encryption::None;
+        profile.repos[1].backup_overrides.skip_paths = vec![std::env::temp_dir()];
+        let problems = profile.validate();
+        assert_eq!(problems.len(), 1, "{:?}", problems);
+        assert!(problems[0].starts_with("repos[1]: "), "{}", problems[0]);
+
+        // Disabled repositories aren't backed up
+        profile.repos[1].disabled = true;
+        assert!(profile.validate().is_empty());
+    }
+
+    #[test]
+    fn exclude_patterns_are_checked_with_each_backends_syntax() {
+        let profile = golden_profile();
+        let (borg, restic) = (&profile.repos[0], &profile.repos[1]);
+        // Valid for both, even though the glob crate rejects them
+        for pattern in ["a**b", "/home/*/.cache", "[]]", "*.[ch]", "\\*"] {
+            assert_eq!(exclude_pattern_problem(borg, pattern), None, "{}", pattern);
+            assert_eq!(
+                exclude_pattern_problem(restic, pattern),
+                None,
+                "{}",
+                pattern
+            );
+        }
+        // Borg translates anything, restic only takes well formed globs without borg's prefixes
+        for pattern in [
+            "[unclosed",
+            "[^]",
+            "trailing\\",
+            "re:^/home/[^/]+/\\.cache",
+            "sh:**/.git",
+        ] {
+            assert_eq!(exclude_pattern_problem(borg, pattern), None, "{}", pattern);
+            assert!(
+                exclude_pattern_problem(restic, pattern).is_some(),
+                "{}",
+                pattern
+            );
+        }
+    }
+
+    #[test]
+    fn passcommand_prints_the_passphrase() {
+        let mut profile = golden_profile();
+        profile.repos[0].encryption =
+            serde_json::from_str(r#"{"Command": ["printf", "hunter2\\nuser: david\\n"]}"#).unwrap();
+        let passphrase = profile.repos[0].get_passphrase().unwrap().unwrap();
+        assert_eq!(passphrase.inner_ref(), "hunter2");
+
+        profile.repos[1].encryption = Encryption::Command(vec!["false".to_string()]);
+        let error = profile.repos[1].get_passphrase().unwrap_err();
+        assert!(format!("{:#}", error).contains(&profile.repos[1].path));
+    }
+
+    #[tokio::test(flavor = "multi_thread", worker_threads = 1)]
+    async fn passcommands_dont_stall_the_runtime() {
+        let command = vec![
+            "sh".to_string(),
+            "-c".to_string(),
+            "sleep 1; echo hunter3".to_string(),
+        ];
+        let start = Instant::now();
+        let passcommand = tokio::spawn(async move { passcommand_passphrase(&command) });
+        tokio::time::sleep(std::time::Duration::from_millis(100)).await;
+        // Tasks queued on the only worker still run while the passcommand does
+        let other_task = tokio::spawn(async move { start.elapsed() });
+        let waited = other_task.await.unwrap();
+        assert!(
+            waited < std::time::Duration::from_millis(900),
+            "{:?}",
+            waited
+        );
+        let passphrase = passcommand.await.unwrap().unwrap();
+        assert_eq!(passphrase.inner_ref(), "hunter3");
+    }
+
+    #[test]
+    fn schedule_parsing() {
+        let schedule: Schedule =
+            serde_json::from_str(r#"{"backup": "12H", "check": "1m", "jitter_seconds": 600}"#)
+                .unwrap();
+        assert_eq!(
+            schedule.backup.unwrap().as_duration(),
+            std::time::Duration::from_secs(12 * 60 * 60)
+        );
+        assert_eq!(schedule.prune, None);
+        assert_eq!(schedule.check.unwrap().to_string(), "1m");
+        assert!(!schedule.is_empty());
+        assert!(Schedule::default().is_empty());
+        let error = serde_json::from_str::<Schedule>(r#"{"backup": "12"}"#).unwrap_err();
+        assert!(error.to_string().contains("schedule interval"));
+    }
+
+    #[test]
+    fn keep_within_parsing() {
+        let keep_within: KeepWithin = "12H".parse().unwrap();
+        assert_eq!(keep_within.unit, KeepWithinUnit::Hour);
+        assert_eq!(keep_within.as_duration().as_secs(), 12 * 60 * 60);
+        assert_eq!("2w".parse::<KeepWithin>().unwrap().to_string(), "2w");
+        assert!("0d".parse::<KeepWithin>().is_err());
+        assert!("7".parse::<KeepWithin>().is_err());
+        assert!("d".parse::<KeepWithin>().is_err());
+    }
+
     #[test]
     #[cfg(feature = "rustic")]
     fn v1_to_v2_config_yields_same_config_rustic() {
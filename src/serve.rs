@@ -0,0 +1,886 @@
+use std::collections::{HashMap, VecDeque};
+use std::net::SocketAddr;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+use anyhow::bail;
+use chrono::{Local, NaiveDateTime, TimeZone, Utc};
+use tokio::io::{AsyncBufRead, AsyncBufReadExt, AsyncReadExt, AsyncWriteExt, BufReader};
+use tokio::net::tcp::OwnedWriteHalf;
+use tokio::net::{TcpListener, TcpStream};
+use tracing::{error, info, warn};
+
+use crate::{
+    cancel::CancelToken,
+    profiles::Repository,
+    types::{ArchiveEntry, BorgResult, PrettyBytes},
+};
+
+/// How long a client gets to send its request
+const REQUEST_TIMEOUT: Duration = Duration::from_secs(30);
+/// Requests with larger headers are rejected
+const MAX_REQUEST_HEAD_BYTES: usize = 16 * 1024;
+/// WebDAV clients send a body with PROPFIND. It's read and ignored up to this size.
+const MAX_REQUEST_BODY_BYTES: u64 = 64 * 1024;
+/// Shorter tokens are too easy to guess. Random tokens are 32 characters.
+const MIN_TOKEN_LENGTH: usize = 16;
+/// Cached directory listings are dropped, oldest first, once they hold more entries than this
+const MAX_CACHED_LISTING_ENTRIES: usize = 100_000;
+/// Sent with every response. Nothing from an archive gets to run scripts, and the token in
+/// the URL isn't leaked to other sites.
+const SECURITY_HEADERS: [(&str, &str); 3] = [
+    (
+        "Content-Security-Policy",
+        "default-src 'none'; style-src 'unsafe-inline'; img-src 'self'",
+    ),
+    ("X-Content-Type-Options", "nosniff"),
+    ("Referrer-Policy", "no-referrer"),
+];
+
+pub(crate) struct ServeOptions {
+    pub(crate) bind: SocketAddr,
+    /// Every URL starts with /<token>/, so only clients which were given the URL can browse
+    pub(crate) token: String,
+    pub(crate) webdav: bool,
+}
+
+/// A random token for `borgtui serve`.
+pub(crate) fn random_token() -> BorgResult<String> {
+    let mut bytes = [0u8; 16];
+    // Safety: getrandom writes at most bytes.len() bytes into the buffer
+    let res = unsafe { libc::getrandom(bytes.as_mut_ptr().cast(), bytes.len(), 0) };
+    if res < 0 || res as usize != bytes.len() {
+        return Err(std::io::Error::last_os_error().into());
+    }
+    Ok(bytes.iter().map(|byte| format!("{:02x}", byte)).collect())
+}
+
+/// Directory listings keyed by archive and directory path.
+#[derive(Default)]
+struct Listings {
+    listings: HashMap<(String, String), Arc<Vec<ArchiveEntry>>>,
+    /// Keys in the order they were cached
+    order: VecDeque<(String, String)>,
+    /// The number of entries across the cached listings. Empty listings count as one.
+    num_entries: usize,
+}
+
+impl Listings {
+    fn get(&self, key: &(String, String)) -> Option<Arc<Vec<ArchiveEntry>>> {
+        self.listings.get(key).cloned()
+    }
+
+    fn insert(&mut self, key: (String, String), entries: Arc<Vec<ArchiveEntry>>) {
+        if self.listings.contains_key(&key) {
+            return;
+        }
+        self.num_entries += entries.len().max(1);
+        self.listings.insert(key.clone(), entries);
+        self.order.push_back(key);
+        // The newest listing is kept even if it's larger than the limit on its own
+        while self.num_entries > MAX_CACHED_LISTING_ENTRIES && self.order.len() > 1 {
+            let Some(oldest) = self.order.pop_front() else {
+                break;
+            };
+            if let Some(entries) = self.listings.remove(&oldest) {
+                self.num_entries -= entries.len().max(1);
+            }
+        }
+    }
+}
+
+/// A file or directory as clients see it.
+#[derive(Debug, Clone)]
+struct Entry {
+    name: String,
+    is_dir: bool,
+    size: u64,
+    modified: Option<NaiveDateTime>,
+}
+
+impl From<&ArchiveEntry> for Entry {
+    fn from(entry: &ArchiveEntry) -> Self {
+        Self {
+            name: entry.name().to_string(),
+            is_dir: entry.is_dir,
+            size: entry.size,
+            modified: entry.modified,
+        }
+    }
+}
+
+impl Entry {
+    fn directory(name: String, modified: Option<NaiveDateTime>) -> Self {
+        Self {
+            name,
+            is_dir: true,
+            size: 0,
+            modified,
+        }
+    }
+}
+
+/// Where a request path points.
+enum Location {
+    /// The list of archives, when serving a whole repository
+    Archives,
+    /// `path` relative to the root of `archive` ("repo::archive")
+    Archive { archive: String, path: String },
+}
+
+/// The repository's archives (each one a directory), or a single archive's files.
+struct ServedTree {
+    repo: Repository,
+    /// Set when serving a single archive ("repo::archive")
+    archive: Option<String>,
+    /// Archives never change, so their directory listings are only fetched once
+    listings: Mutex<Listings>,
+}
+
+impl ServedTree {
+    fn new(repo: Repository, repo_or_archive: String) -> Self {
+        Self {
+            repo,
+            archive: repo_or_archive.contains("::").then_some(repo_or_archive),
+            listings: Default::default(),
+        }
+    }
+
+    fn source(&self) -> String {
+        self.archive.clone().unwrap_or_else(|| self.repo.path())
+    }
+
+    fn locate(&self, segments: &[String]) -> Location {
+        match (&self.archive, segments) {
+            (Some(archive), _) => Location::Archive {
+                archive: archive.clone(),
+                path: segments.join("/"),
+            },
+            (None, []) => Location::Archives,
+            (None, [name, rest @ ..]) => Location::Archive {
+                archive: format!("{}::{}", self.repo.path(), name),
+                path: rest.join("/"),
+            },
+        }
+    }
+
+    async fn archive_directory(
+        &self,
+        archive: &str,
+        directory: &str,
+    ) -> BorgResult<Arc<Vec<ArchiveEntry>>> {
+        let key = (archive.to_string(), directory.to_string());
+        if let Some(entries) = self.listings.lock().unwrap().get(&key) {
+            return Ok(entries);
+        }
+        let listing = self
+            .repo
+            .list_archive_directory(archive.to_string(), directory.to_string())
+            .await?;
+        let entries = Arc::new(listing.entries);
+        self.listings.lock().unwrap().insert(key, entries.clone());
+        Ok(entries)
+    }
+
+    async fn list(&self, location: &Location) -> BorgResult<Vec<Entry>> {
+        match location {
+            Location::Archives => {
+                let archives = self.repo.list_archives().await?;
+                Ok(archives
+                    .archives
+                    .iter()
+                    .rev()
+                    .map(|archive| {
+                        Entry::directory(archive.name.clone(), Some(archive.creation_date))
+                    })
+                    .collect())
+            }
+            Location::Archive { archive, path } => Ok(self
+                .archive_directory(archive, path)
+                .await?
+                .iter()
+                .map(Entry::from)
+                .collect()),
+        }
+    }
+
+    /// What's at `location`, or None if there's nothing there.
+    async fn stat(&self, location: &Location) -> BorgResult<Option<Entry>> {
+        match location {
+            Location::Archives => Ok(Some(Entry::directory(self.repo.path(), None))),
+            Location::Archive { archive, path } if path.is_empty() => {
+                let name = archive
+                    .rsplit_once("::")
+                    .map_or(archive.as_str(), |(_, name)| name);
+                Ok(Some(Entry::directory(name.to_string(), None)))
+            }
+            Location::Archive { archive, path } => {
+                let (parent, name) = path.rsplit_once('/').unwrap_or(("", path));
+                Ok(self
+                    .archive_directory(archive, parent)
+                    .await?
+                    .iter()
+                    .find(|entry| entry.name() == name)
+                    .map(Entry::from))
+            }
+        }
+    }
+}
+
+fn check_token(token: &str) -> BorgResult<()> {
+    if !token.bytes().all(is_unreserved) {
+        bail!("The token can only contain letters, digits, '-', '.', '_' and '~'");
+    }
+    if token.len() < MIN_TOKEN_LENGTH {
+        bail!(
+            "The token needs at least {} characters so it can't be guessed",
+            MIN_TOKEN_LENGTH
+        );
+    }
+    Ok(())
+}
+
+/// Serve `repo_or_archive` read-only until `cancel` fires.
+pub(crate) async fn serve(
+    repo: Repository,
+    repo_or_archive: String,
+    options: ServeOptions,
+    cancel: CancelToken,
+) -> BorgResult<()> {
+    check_token(&options.token)?;
+    if !options.bind.ip().is_loopback() {
+        warn!(
+            "Serving on {}, which isn't localhost. Anyone on the network with the URL can read your backups!",
+            options.bind
+        );
+    }
+    let listener = TcpListener::bind(options.bind).await?;
+    let tree = Arc::new(ServedTree::new(repo, repo_or_archive));
+    let options = Arc::new(options);
+    let address = listener.local_addr()?;
+    info!(
+        "Serving {} read-only at http://{}/{}/",
+        tree.source(),
+        address,
+        options.token
+    );
+    if options.webdav {
+        info!(
+            "WebDAV clients can connect to dav://{}/{}/",
+            address, options.token
+        );
+    }
+    loop {
+        let accepted = tokio::select! {
+            accepted = listener.accept() => accepted,
+            _ = cancel.cancelled() => break,
+        };
+        match accepted {
+            Ok((stream, peer)) => {
+                let tree = tree.clone();
+                let options = options.clone();
+                tokio::spawn(async move {
+                    if let Err(e) = handle_connection(stream, &tree, &options).await {
+                        warn!("Failed to answer {}: {}", peer, e);
+                    }
+                });
+            }
+            Err(e) => error!("Failed to accept connection: {}", e),
+        }
+    }
+    info!("Stopped serving {}", tree.source());
+    Ok(())
+}
+
+struct Request {
+    method: String,
+    /// The path without its query string, still percent-encoded
+    path: String,
+    /// Header names are lowercase
+    headers: Vec<(String, String)>,
+}
+
+impl Request {
+    fn header(&self, name: &str) -> Option<&str> {
+        self.headers
+            .iter()
+            .find(|(header, _)| header == name)
+            .map(|(_, value)| value.as_str())
+    }
+
+    /// The decoded path segments after the token, or None if the path doesn't start with it.
+    fn segments(&self, token: &str) -> Option<BorgResult<Vec<String>>> {
+        let mut segments = self.path.strip_prefix('/')?.split('/');
+        if !tokens_match(segments.next()?, token) {
+            return None;
+        }
+        Some(
+            segments
+                .filter(|segment| !segment.is_empty())
+                .map(|segment| match percent_decode(segment) {
+                    Some(segment) if segment == "." || segment == ".." => {
+                        bail!("Relative paths aren't allowed")
+                    }
+                    Some(segment) if segment.contains('/') => bail!("Invalid path"),
+                    Some(segment) => Ok(segment),
+                    None => bail!("Invalid percent-encoding in the path"),
+                })
+                .collect(),
+        )
+    }
+}
+
+/// Compare tokens in constant time, so their characters can't be guessed one by one from
+/// how long a 404 takes.
+fn tokens_match(given: &str, token: &str) -> bool {
+    given.len() == token.len()
+        && given
+            .bytes()
+            .zip(token.bytes())
+            .fold(0, |difference, (a, b)| difference | (a ^ b))
+            == 0
+}
+
+/// Read the request head, then discard any body. None if the client hung up first.
+async fn read_request<R: AsyncBufRead + Unpin>(reader: &mut R) -> BorgResult<Option<Request>> {
+    let mut head_bytes = 0;
+    let mut lines = Vec::new();
+    loop {
+        let mut line = String::new();
+        let read = reader.read_line(&mut line).await?;
+        if read == 0 {
+            return Ok(None);
+        }
+        head_bytes += read;
+        if head_bytes > MAX_REQUEST_HEAD_BYTES {
+            bail!("Request headers are too large");
+        }
+        let line = line.trim_end_matches(['\r', '\n']).to_string();
+        if line.is_empty() {
+            break;
+        }
+        lines.push(line);
+    }
+    let mut lines = lines.into_iter();
+    let request_line = lines.next().unwrap_or_default();
+    let mut parts = request_line.split(' ');
+    let (Some(method), Some(target), Some(_version)) = (parts.next(), parts.next(), parts.next())
+    else {
+        bail!("Malformed request line");
+    };
+    let path = target.split_once('?').map_or(target, |(path, _)| path);
+    let headers = lines
+        .filter_map(|line| {
+            line.split_once(':')
+                .map(|(name, value)| (name.trim().to_ascii_lowercase(), value.trim().to_string()))
+        })
+        .collect();
+    let request = Request {
+        method: method.to_string(),
+        path: path.to_string(),
+        headers,
+    };
+    if let Some(length) = request.header("content-length") {
+        let length: u64 = length.parse()?;
+        if length > MAX_REQUEST_BODY_BYTES {
+            bail!("Request body is too large");
+        }
+        tokio::io::copy(&mut reader.take(length), &mut tokio::io::sink()).await?;
+    }
+    Ok(Some(request))
+}
+
+fn reason_phrase(status: u16) -> &'static str {
+    match status {
+        200 => "OK",
+        207 => "Multi-Status",
+        301 => "Moved Permanently",
+        400 => "Bad Request",
+        404 => "Not Found",
+        405 => "Method Not Allowed",
+        408 => "Request Timeout",
+        _ => "Internal Server Error",
+    }
+}
+
+async fn write_head(
+    writer: &mut OwnedWriteHalf,
+    status: u16,
+    headers: &[(&str, String)],
+    content_length: u64,
+) -> BorgResult<()> {
+    let mut head = format!("HTTP/1.1 {} {}\r\n", status, reason_phrase(status));
+    let security_headers = SECURITY_HEADERS
+        .iter()
+        .map(|(name, value)| (*name, value.to_string()));
+    for (name, value) in headers.iter().cloned().chain(security_headers) {
+        head.push_str(&format!("{}: {}\r\n", name, value));
+    }
+    head.push_str(&format!(
+        "Content-Length: {}\r\nConnection: close\r\n\r\n",
+        content_length
+    ));
+    writer.write_all(head.as_bytes()).await?;
+    Ok(())
+}
+
+async fn respond(
+    writer: &mut OwnedWriteHalf,
+    status: u16,
+    headers: &[(&str, String)],
+    body: &[u8],
+    head_only: bool,
+) -> BorgResult<()> {
+    write_head(writer, status, headers, body.len() as u64).await?;
+    if !head_only {
+        writer.write_all(body).await?;
+    }
+    Ok(())
+}
+
+async fn respond_text(
+    writer: &mut OwnedWriteHalf,
+    status: u16,
+    message: &str,
+    head_only: bool,
+) -> BorgResult<()> {
+    let headers = [("Content-Type", "text/plain; charset=utf-8".to_string())];
+    respond(writer, status, &headers, message.as_bytes(), head_only).await
+}
+
+async fn handle_connection(
+    stream: TcpStream,
+    tree: &ServedTree,
+    options: &ServeOptions,
+) -> BorgResult<()> {
+    let (reader, mut writer) = stream.into_split();
+    let mut reader = BufReader::new(reader);
+    let request = match tokio::time::timeout(REQUEST_TIMEOUT, read_request(&mut reader)).await {
+        Ok(Ok(Some(request))) => request,
+        Ok(Ok(None)) => return Ok(()),
+        Ok(Err(e)) => return respond_text(&mut writer, 400, &e.to_string(), false).await,
+        Err(_) => return respond_text(&mut writer, 408, "Request timed out", false).await,
+    };
+    let head_only = request.method == "HEAD";
+    let segments = match request.segments(&options.token) {
+        Some(Ok(segments)) => segments,
+        Some(Err(e)) => return respond_text(&mut writer, 400, &e.to_string(), head_only).await,
+        None => return respond_text(&mut writer, 404, "Not found", head_only).await,
+    };
+    let allow = if options.webdav {
+        "GET, HEAD, OPTIONS, PROPFIND"
+    } else {
+        "GET, HEAD, OPTIONS"
+    };
+    let result = match request.method.as_str() {
+        "GET" | "HEAD" => serve_get(&mut writer, tree, &request, &segments, head_only).await,
+        "PROPFIND" if options.webdav => {
+            serve_propfind(&mut writer, tree, &request, &segments).await
+        }
+        "OPTIONS" => {
+            let mut headers = vec![("Allow", allow.to_string())];
+            if options.webdav {
+                headers.push(("DAV", "1".to_string()));
+            }
+            respond(&mut writer, 200, &headers, b"", false).await
+        }
+        _ => {
+            let headers = [("Allow", allow.to_string())];
+            respond(&mut writer, 405, &headers, b"Read-only", false).await
+        }
+    };
+    if let Err(e) = result {
+        error!("Failed to serve {}: {}", request.path, e);
+        // Fails if the response was already underway, in which case the client sees it cut short
+        let _ = respond_text(&mut writer, 500, &e.to_string(), head_only).await;
+    }
+    Ok(())
+}
+
+async fn serve_get(
+    writer: &mut OwnedWriteHalf,
+    tree: &ServedTree,
+    request: &Request,
+    segments: &[String],
+    head_only: bool,
+) -> BorgResult<()> {
+    let location = tree.locate(segments);
+    let Some(entry) = tree.stat(&location).await? else {
+        return respond_text(writer, 404, "Not found", head_only).await;
+    };
+    if entry.is_dir {
+        // Relative links in the listing only work from inside the directory
+        if !request.path.ends_with('/') {
+            let headers = [("Location", format!("{}/", request.path))];
+            return respond(writer, 301, &headers, b"", head_only).await;
+        }
+        let entries = tree.list(&location).await?;
+        let title = format!("{}/{}", tree.source(), segments.join("/"));
+        let body = directory_listing_html(&title, !segments.is_empty(), &entries);
+        let headers = [("Content-Type", "text/html; charset=utf-8".to_string())];
+        return respond(writer, 200, &headers, body.as_bytes(), head_only).await;
+    }
+    let Location::Archive { archive, path } = location else {
+        unreachable!("The list of archives is a directory");
+    };
+    let headers = [
+        ("Content-Type", content_type(&entry.name).to_string()),
+        ("Last-Modified", http_date(entry.modified)),
+    ];
+    write_head(writer, 200, &headers, entry.size).await?;
+    if !head_only {
+        tree.repo.read_archive_file(archive, path, writer).await?;
+    }
+    Ok(())
+}
+
+async fn serve_propfind(
+    writer: &mut OwnedWriteHalf,
+    tree: &ServedTree,
+    request: &Request,
+    segments: &[String],
+) -> BorgResult<()> {
+    let location = tree.locate(segments);
+    let Some(entry) = tree.stat(&location).await? else {
+        return respond_text(writer, 404, "Not found", false).await;
+    };
+    let href = match (entry.is_dir, request.path.ends_with('/')) {
+        (true, false) => format!("{}/", request.path),
+        _ => request.path.clone(),
+    };
+    let mut body = String::from(
+        "<?xml version=\"1.0\" encoding=\"utf-8\"?>\n<D:multistatus xmlns:D=\"DAV:\">\n",
+    );
+    body.push_str(&propfind_response(&href, &entry));
+    if entry.is_dir && request.header("depth") != Some("0") {
+        for child in tree.list(&location).await? {
+            let mut child_href = format!("{}{}", href, percent_encode(&child.name));
+            if child.is_dir {
+                child_href.push('/');
+            }
+            body.push_str(&propfind_response(&child_href, &child));
+        }
+    }
+    body.push_str("</D:multistatus>\n");
+    let headers = [("Content-Type", "application/xml; charset=utf-8".to_string())];
+    respond(writer, 207, &headers, body.as_bytes(), false).await
+}
+
+fn propfind_response(href: &str, entry: &Entry) -> String {
+    let mut props = format!(
+        "<D:displayname>{}</D:displayname>",
+        escape_markup(&entry.name)
+    );
+    if entry.is_dir {
+        props.push_str("<D:resourcetype><D:collection/></D:resourcetype>");
+    } else {
+        props.push_str(&format!(
+            "<D:resourcetype/><D:getcontentlength>{}</D:getcontentlength><D:getcontenttype>{}</D:getcontenttype>",
+            entry.size,
+            content_type(&entry.name)
+        ));
+    }
+    if entry.modified.is_some() {
+        props.push_str(&format!(
+            "<D:getlastmodified>{}</D:getlastmodified>",
+            http_date(entry.modified)
+        ));
+    }
+    format!(
+        "<D:response><D:href>{}</D:href><D:propstat><D:prop>{}</D:prop><D:status>HTTP/1.1 200 OK</D:status></D:propstat></D:response>\n",
+        escape_markup(href),
+        props
+    )
+}
+
+fn directory_listing_html(title: &str, has_parent: bool, entries: &[Entry]) -> String {
+    let title = escape_markup(title);
+    let mut rows = String::new();
+    if has_parent {
+        rows.push_str("<tr><td><a href=\"../\">../</a></td><td></td><td></td></tr>\n");
+    }
+    for entry in entries {
+        let (href, name, size) = if entry.is_dir {
+            (
+                format!("{}/", percent_encode(&entry.name)),
+                format!("{}/", entry.name),
+                String::new(),
+            )
+        } else {
+            (
+                percent_encode(&entry.name),
+                entry.name.clone(),
+                PrettyBytes(entry.size).to_string(),
+            )
+        };
+        let modified = entry
+            .modified
+            .map(|modified| modified.format("%Y-%m-%d %H:%M:%S").to_string())
+            .unwrap_or_default();
+        rows.push_str(&format!(
+            "<tr><td><a href=\"{}\">{}</a></td><td>{}</td><td>{}</td></tr>\n",
+            escape_markup(&href),
+            escape_markup(&name),
+            size,
+            modified
+        ));
+    }
+    format!(
+        "<!DOCTYPE html>\n<html><head><meta charset=\"utf-8\"><title>{title}</title>\
+         <style>body {{ font-family: monospace; }} td {{ padding: 0 1em; }}</style></head>\n\
+         <body><h1>{title}</h1>\n<table>\n<tr><th>Name</th><th>Size</th><th>Modified</th></tr>\n\
+         {rows}</table></body></html>\n"
+    )
+}
+
+/// Only types browsers can't run scripts from are shown inline, everything else is downloaded.
+fn content_type(name: &str) -> &'static str {
+    let extension = name
+        .rsplit_once('.')
+        .map(|(_, extension)| extension.to_ascii_lowercase())
+        .unwrap_or_default();
+    match extension.as_str() {
+        "txt" | "log" | "md" | "org" | "csv" | "json" | "toml" | "yaml" | "yml" | "conf"
+        | "ini" => "text/plain; charset=utf-8",
+        "png" => "image/png",
+        "jpg" | "jpeg" => "image/jpeg",
+        "gif" => "image/gif",
+        "webp" => "image/webp",
+        "pdf" => "application/pdf",
+        _ => "application/octet-stream",
+    }
+}
+
+/// An RFC 7231 date. Archive times are local.
+fn http_date(time: Option<NaiveDateTime>) -> String {
+    let time = time
+        .and_then(|time| Local.from_local_datetime(&time).earliest())
+        .map(|time| time.with_timezone(&Utc))
+        .unwrap_or_else(Utc::now);
+    time.format("%a, %d %b %Y %H:%M:%S GMT").to_string()
+}
+
+fn is_unreserved(byte: u8) -> bool {
+    byte.is_ascii_alphanumeric() || matches!(byte, b'-' | b'.' | b'_' | b'~')
+}
+
+fn percent_encode(segment: &str) -> String {
+    segment
+        .bytes()
+        .map(|byte| {
+            if is_unreserved(byte) {
+                (byte as char).to_string()
+            } else {
+                format!("%{:02X}", byte)
+            }
+        })
+        .collect()
+}
+
+fn percent_decode(segment: &str) -> Option<String> {
+    let bytes = segment.as_bytes();
+    let mut decoded = Vec::with_capacity(bytes.len());
+    let mut i = 0;
+    while i < bytes.len() {
+        if bytes[i] == b'%' {
+            let hex = bytes.get(i + 1..i + 3)?;
+            if !hex.iter().all(u8::is_ascii_hexdigit) {
+                return None;
+            }
+            decoded.push(u8::from_str_radix(std::str::from_utf8(hex).ok()?, 16).ok()?);
+            i += 3;
+        } else {
+            decoded.push(bytes[i]);
+            i += 1;
+        }
+    }
+    String::from_utf8(decoded).ok()
+}
+
+fn escape_markup(text: &str) -> String {
+    let mut escaped = String::with_capacity(text.len());
+    for c in text.chars() {
+        match c {
+            '&' => escaped.push_str("&amp;"),
+            '<' => escaped.push_str("&lt;"),
+            '>' => escaped.push_str("&gt;"),
+            '"' => escaped.push_str("&quot;"),
+            '\'' => escaped.push_str("&#39;"),
+            c => escaped.push(c),
+        }
+    }
+    escaped
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const TOKEN: &str = "0123456789abcdef";
+
+    fn request(path: &str) -> Request {
+        Request {
+            method: "GET".to_string(),
+            path: path.to_string(),
+            headers: Vec::new(),
+        }
+    }
+
+    fn entry(name: &str, num_entries: usize) -> Arc<Vec<ArchiveEntry>> {
+        Arc::new(
+            (0..num_entries)
+                .map(|i| ArchiveEntry {
+                    path: format!("{name}/{i}"),
+                    is_dir: false,
+                    size: 0,
+                    modified: None,
+                })
+                .collect(),
+        )
+    }
+
+    #[test]
+    fn percent_decoding() {
+        assert_eq!(percent_decode("plain").as_deref(), Some("plain"));
+        assert_eq!(percent_decode("a%20b%2fc").as_deref(), Some("a b/c"));
+        assert_eq!(percent_decode("%C3%A9").as_deref(), Some("é"));
+        assert_eq!(percent_decode("%e9"), None);
+        for bad in ["%", "%4", "%zz", "a%2", "%+1"] {
+            assert_eq!(percent_decode(bad), None, "{}", bad);
+        }
+        let name = "a b/ü&%.txt";
+        assert_eq!(percent_decode(&percent_encode(name)).as_deref(), Some(name));
+    }
+
+    #[test]
+    fn request_segments() {
+        let segments = |path: &str| {
+            request(path)
+                .segments(TOKEN)
+                .map(|segments| segments.map_err(|e| e.to_string()))
+        };
+        assert_eq!(segments(&format!("/{TOKEN}")), Some(Ok(vec![])));
+        assert_eq!(
+            segments(&format!("/{TOKEN}/archive//dir%20one/file.txt")),
+            Some(Ok(vec![
+                "archive".to_string(),
+                "dir one".to_string(),
+                "file.txt".to_string()
+            ]))
+        );
+        for rejected in [
+            "..",
+            ".",
+            "%2e%2e",
+            "%2E",
+            "archive/../..",
+            "a%2Fb",
+            "a%2f..%2fb",
+            "%zz",
+        ] {
+            assert!(
+                matches!(segments(&format!("/{TOKEN}/{rejected}")), Some(Err(_))),
+                "{}",
+                rejected
+            );
+        }
+        for wrong_token in [
+            "/".to_string(),
+            "/archive".to_string(),
+            format!("{TOKEN}/archive"),
+            format!("/{}/archive", &TOKEN[1..]),
+            format!("/{TOKEN}0/archive"),
+            format!("/{}/archive", TOKEN.to_uppercase()),
+            format!("/%{TOKEN}/archive"),
+        ] {
+            assert_eq!(segments(&wrong_token), None, "{}", wrong_token);
+        }
+    }
+
+    #[test]
+    fn tokens_are_checked() {
+        assert!(tokens_match(TOKEN, TOKEN));
+        assert!(!tokens_match(TOKEN, "0123456789abcdeF"));
+        assert!(!tokens_match("", TOKEN));
+        assert!(!tokens_match(&TOKEN[..15], TOKEN));
+
+        assert!(check_token(TOKEN).is_ok());
+        assert!(check_token(&random_token().unwrap()).is_ok());
+        assert!(check_token("short").is_err());
+        assert!(check_token("").is_err());
+        assert!(check_token("0123456789abcdef/").is_err());
+        assert!(check_token("0123456789 abcdef").is_err());
+    }
+
+    #[test]
+    fn markup_is_escaped() {
+        assert_eq!(
+            escape_markup(r#"<a href="x">&'"#),
+            "&lt;a href=&quot;x&quot;&gt;&amp;&#39;"
+        );
+        let sneaky = Entry {
+            name: "<script>\"'&.txt".to_string(),
+            is_dir: false,
+            size: 1,
+            modified: None,
+        };
+        let html = directory_listing_html("<title>", true, std::slice::from_ref(&sneaky));
+        assert!(!html.contains("<script>"));
+        assert!(!html.contains("<title><"));
+        assert!(html.contains("&lt;title&gt;"));
+        assert!(html.contains("href=\"%3Cscript%3E%22%27%26.txt\""));
+        assert!(html.contains(">&lt;script&gt;&quot;&#39;&amp;.txt</a>"));
+
+        let xml = propfind_response("/t/<a>&b", &sneaky);
+        assert!(xml.contains("<D:href>/t/&lt;a&gt;&amp;b</D:href>"));
+        assert!(xml.contains("<D:displayname>&lt;script&gt;&quot;&#39;&amp;.txt</D:displayname>"));
+    }
+
+    #[tokio::test]
+    async fn request_heads_are_parsed() {
+        let mut reader: &[u8] =
+            b"PROPFIND /tok/a%20b?query=1 HTTP/1.1\r\nDepth: 0 \r\nContent-Length: 4\r\n\r\nbody";
+        let request = read_request(&mut reader).await.unwrap().unwrap();
+        assert_eq!(request.method, "PROPFIND");
+        assert_eq!(request.path, "/tok/a%20b");
+        assert_eq!(request.header("depth"), Some("0"));
+        assert_eq!(request.header("Depth"), None);
+        // The body is discarded
+        assert!(reader.is_empty());
+
+        let mut reader: &[u8] = b"GET /\n\n";
+        assert!(read_request(&mut reader).await.is_err());
+        let mut reader: &[u8] = b"";
+        assert!(read_request(&mut reader).await.unwrap().is_none());
+        let mut reader: &[u8] = b"GET / HTTP/1.1\r\nContent-Length: nope\r\n\r\n";
+        assert!(read_request(&mut reader).await.is_err());
+        let mut reader: &[u8] = b"GET / HTTP/1.1\r\nContent-Length: 1000000\r\n\r\n";
+        assert!(read_request(&mut reader).await.is_err());
+        let huge = format!(
+            "GET / HTTP/1.1\r\nX-Padding: {}\r\n\r\n",
+            "a".repeat(MAX_REQUEST_HEAD_BYTES)
+        );
+        assert!(read_request(&mut huge.as_bytes()).await.is_err());
+    }
+
+    #[test]
+    fn listings_are_bounded() {
+        let key = |i: usize| ("repo::archive".to_string(), i.to_string());
+        let mut listings = Listings::default();
+        let per_listing = MAX_CACHED_LISTING_ENTRIES / 4;
+        for i in 0..4 {
+            listings.insert(key(i), entry("dir", per_listing));
+        }
+        assert!(listings.get(&key(0)).is_some());
+        listings.insert(key(4), entry("dir", 1));
+        assert!(listings.get(&key(0)).is_none());
+        assert!(listings.get(&key(1)).is_some());
+        assert!(listings.num_entries <= MAX_CACHED_LISTING_ENTRIES);
+
+        // A listing larger than the limit replaces everything else
+        listings.insert(key(5), entry("dir", MAX_CACHED_LISTING_ENTRIES + 1));
+        assert_eq!(listings.listings.len(), 1);
+        assert!(listings.get(&key(5)).is_some());
+        assert_eq!(listings.order.len(), 1);
+    }
+}
@@ -0,0 +1,155 @@
+use std::io::IsTerminal;
+use std::path::{Path, PathBuf};
+use std::process::Stdio;
+
+use anyhow::bail;
+use tokio::io::{AsyncWrite, AsyncWriteExt};
+use tokio::process::{Child, ChildStdin};
+
+use crate::types::BorgResult;
+
+/// A tarball's compression, going by its extension. Compressing and decompressing is left to
+/// the usual command line tools.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) enum TarCompression {
+    Gzip,
+    Bzip2,
+    Xz,
+    Zstd,
+    Lz4,
+}
+
+impl TarCompression {
+    pub(crate) fn from_path(path: &Path) -> Option<Self> {
+        let name = path.file_name()?.to_string_lossy().to_ascii_lowercase();
+        let compression = match name.rsplit_once('.')?.1 {
+            "tgz" | "gz" => TarCompression::Gzip,
+            "tbz2" | "bz2" => TarCompression::Bzip2,
+            "txz" | "xz" => TarCompression::Xz,
+            "zst" | "zstd" => TarCompression::Zstd,
+            "lz4" => TarCompression::Lz4,
+            _ => return None,
+        };
+        Some(compression)
+    }
+
+    /// The program which compresses stdin to stdout, and decompresses with `-d`
+    pub(crate) fn program(self) -> &'static str {
+        match self {
+            TarCompression::Gzip => "gzip",
+            TarCompression::Bzip2 => "bzip2",
+            TarCompression::Xz => "xz",
+            TarCompression::Zstd => "zstd",
+            TarCompression::Lz4 => "lz4",
+        }
+    }
+
+    /// The shell command decompressing stdin to stdout
+    pub(crate) fn decompress_command(self) -> String {
+        format!("{} -d", self.program())
+    }
+}
+
+/// "-" stands for stdin or stdout
+pub(crate) fn is_stdio(path: &Path) -> bool {
+    path == Path::new("-")
+}
+
+/// Fails if stdin is a terminal. Providers run in their own process group, where reading
+/// from the terminal would stop them, and a tarball is expected to be piped in anyway.
+pub(crate) fn check_piped_stdin() -> BorgResult<()> {
+    if std::io::stdin().is_terminal() {
+        bail!("Expected a tarball to be piped into stdin");
+    }
+    Ok(())
+}
+
+/// What a provider reads the tarball at `path` ("-" for stdin) from.
+pub(crate) fn tarball_stdin(path: &Path) -> BorgResult<Stdio> {
+    if is_stdio(path) {
+        check_piped_stdin()?;
+        return Ok(Stdio::inherit());
+    }
+    Ok(Stdio::from(std::fs::File::open(path)?))
+}
+
+/// The file name providers which store a tarball whole (instead of unpacking it) use.
+pub(crate) fn stored_file_name(path: &Path, archive_name: &str) -> String {
+    match path.file_name() {
+        Some(file_name) if !is_stdio(path) => file_name.to_string_lossy().to_string(),
+        _ => format!("{}.tar", archive_name),
+    }
+}
+
+/// Where an exported tar stream goes, compressed if the file's extension asks for it.
+pub(crate) enum TarballWriter {
+    Stdout(tokio::io::Stdout),
+    File(tokio::fs::File),
+    Compressed {
+        path: PathBuf,
+        compressor: Child,
+        stdin: ChildStdin,
+    },
+}
+
+impl TarballWriter {
+    /// Write to `path`, or stdout if it's "-".
+    pub(crate) async fn create(path: &Path) -> BorgResult<Self> {
+        if is_stdio(path) {
+            return Ok(TarballWriter::Stdout(tokio::io::stdout()));
+        }
+        let file = tokio::fs::File::create(path).await?;
+        let Some(compression) = TarCompression::from_path(path) else {
+            return Ok(TarballWriter::File(file));
+        };
+        let mut compressor = tokio::process::Command::new(compression.program())
+            .stdin(Stdio::piped())
+            .stdout(file.into_std().await)
+            .spawn()?;
+        let stdin = compressor
+            .stdin
+            .take()
+            .expect("The compressor's stdin is piped");
+        Ok(TarballWriter::Compressed {
+            path: path.to_path_buf(),
+            compressor,
+            stdin,
+        })
+    }
+
+    pub(crate) fn writer(&mut self) -> &mut (dyn AsyncWrite + Send + Unpin) {
+        match self {
+            TarballWriter::Stdout(stdout) => stdout,
+            TarballWriter::File(file) => file,
+            TarballWriter::Compressed { stdin, .. } => stdin,
+        }
+    }
+
+    /// Flush everything out, waiting for the compressor to finish.
+    pub(crate) async fn finish(self) -> BorgResult<()> {
+        match self {
+            TarballWriter::Stdout(mut stdout) => stdout.flush().await?,
+            TarballWriter::File(mut file) => {
+                file.flush().await?;
+                file.sync_all().await?;
+            }
+            TarballWriter::Compressed {
+                path,
+                mut compressor,
+                stdin,
+            } => {
+                // Closing stdin tells the compressor it has everything
+                drop(stdin);
+                let status = compressor.wait().await?;
+                if !status.success() {
+                    bail!(
+                        "Failed to compress {}: the compressor exited with {}",
+                        path.to_string_lossy(),
+                        status
+                    );
+                }
+            }
+        }
+        Ok(())
+    }
+}
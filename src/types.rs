@@ -41,6 +41,29 @@ macro_rules! send_check_complete {
 }
 pub(crate) use send_check_complete;
 
+/// Tell the UI a repository's backup was cancelled.
+macro_rules! send_create_cancelled {
+    ($channel:expr, $repo_loc:expr, $checkpoint:expr) => {
+        if let Err(e) = $channel
+            .send(crate::borgtui::CommandResponse::CreateProgress(
+                crate::types::BackupCreateProgress {
+                    repository: $repo_loc,
+                    create_progress: crate::types::BackupCreationProgress::Cancelled {
+                        checkpoint: $checkpoint,
+                    },
+                },
+            ))
+            .await
+        {
+            tracing::error!(
+                "Error occurred while sending backup cancelled message: {}",
+                e
+            );
+        }
+    };
+}
+pub(crate) use send_create_cancelled;
+
 /// Send a CommandResponse::Info in a channel.
 macro_rules! send_info {
     ($channel:expr, $info_message:expr) => {
@@ -64,6 +87,7 @@ pub(crate) type CommandResponseSender = tokio::sync::mpsc::Sender<CommandRespons
 use glob::Pattern;
 use notify_rust::{Notification, Timeout};
 pub(crate) use send_info;
+use serde::{Deserialize, Serialize};
 
 /// Send a CommandResponse::Info in a channel.
 macro_rules! send_error {
@@ -163,7 +187,7 @@ impl<T, const N: usize> FromIterator<T> for RingBuffer<T, N> {
 
 #[cfg(test)]
 mod tests {
-    use super::RingBuffer;
+    use super::{is_direct_child, PrettyDuration, RingBuffer};
 
     #[test]
     fn test_pushes() {
@@ -199,6 +223,28 @@ mod tests {
             (769..=1024).collect::<Vec<_>>()
         );
     }
+
+    #[test]
+    fn test_is_direct_child() {
+        assert!(is_direct_child("", "home"));
+        assert!(is_direct_child("", "/home"));
+        assert!(!is_direct_child("", "home/david"));
+        assert!(is_direct_child("home", "home/david"));
+        assert!(is_direct_child("/home/", "/home/david"));
+        assert!(!is_direct_child("home", "home"));
+        assert!(!is_direct_child("home", "homework/david"));
+        assert!(!is_direct_child("home", "home/david/Documents"));
+    }
+
+    #[test]
+    fn test_pretty_duration() {
+        assert_eq!(PrettyDuration(42).to_string(), "0m 42s");
+        assert_eq!(PrettyDuration(2 * 3600 + 5 * 60).to_string(), "2h 5m");
+        assert_eq!(
+            PrettyDuration(3 * 86400 + 4 * 3600 + 59).to_string(),
+            "3d 4h"
+        );
+    }
 }
 
 #[derive(Clone, Copy, Debug, PartialEq, PartialOrd)]
@@ -315,7 +361,7 @@ pub(crate) async fn show_notification<I: Into<Timeout>>(
     Ok(())
 }
 
-#[derive(Debug, Clone)]
+#[derive(Serialize, Deserialize, Debug, Clone)]
 pub(crate) struct CheckComplete {
     pub(crate) repo_loc: String,
     pub(crate) error: Option<String>,
@@ -327,7 +373,7 @@ impl CheckComplete {
     }
 }
 
-#[derive(Debug, Clone)]
+#[derive(Serialize, Deserialize, Debug, Clone)]
 pub(crate) struct CheckProgress {
     pub(crate) repo_loc: String,
     pub(crate) message: String,
@@ -339,7 +385,7 @@ impl CheckProgress {
     }
 }
 
-#[derive(Debug, Clone)]
+#[derive(Serialize, Deserialize, Debug, Clone)]
 pub(crate) enum BackupCreationProgress {
     InProgress {
         original_size: u64,
@@ -349,9 +395,13 @@ pub(crate) enum BackupCreationProgress {
         current_path: String,
     },
     Finished,
+    /// Stopped early, `checkpoint` is true if borg was asked to save a checkpoint archive
+    Cancelled {
+        checkpoint: bool,
+    },
 }
 
-#[derive(Debug)]
+#[derive(Serialize, Deserialize, Debug, Clone)]
 pub(crate) struct BackupCreateProgress {
     pub(crate) repository: String,
     pub(crate) create_progress: BackupCreationProgress,
@@ -375,13 +425,55 @@ impl BackupCreateProgress {
     }
 }
 
-#[derive(Debug, Clone)]
+/// An archive (or snapshot) in a repository. Everything besides the name and
+/// creation date is only filled in when the backend reports it.
+#[derive(Serialize, Deserialize, Debug, Clone, Default)]
 pub(crate) struct Archive {
     pub(crate) name: String,
     pub(crate) creation_date: chrono::NaiveDateTime,
+    /// Backend specific archive id or snapshot hash
+    pub(crate) id: Option<String>,
+    pub(crate) hostname: Option<String>,
+    pub(crate) username: Option<String>,
+    pub(crate) tags: Vec<String>,
+    pub(crate) original_size: Option<u64>,
+    pub(crate) compressed_size: Option<u64>,
+    pub(crate) deduplicated_size: Option<u64>,
+    pub(crate) num_files: Option<u64>,
+    /// How long the backup took
+    pub(crate) duration: Option<std::time::Duration>,
 }
 
-#[derive(Debug, Clone)]
+impl Archive {
+    /// Short human readable description of the extra metadata, e.g. "host, 1.2 GiB, 10 files".
+    pub(crate) fn details(&self) -> String {
+        let mut details = Vec::new();
+        if let Some(hostname) = &self.hostname {
+            match &self.username {
+                Some(username) => details.push(format!("{username}@{hostname}")),
+                None => details.push(hostname.clone()),
+            }
+        }
+        if !self.tags.is_empty() {
+            details.push(format!("tags: {}", self.tags.join(",")));
+        }
+        if let Some(original_size) = self.original_size {
+            details.push(format!("{}", PrettyBytes(original_size)));
+        }
+        if let Some(deduplicated_size) = self.deduplicated_size {
+            details.push(format!("{} deduplicated", PrettyBytes(deduplicated_size)));
+        }
+        if let Some(num_files) = self.num_files {
+            details.push(format!("{num_files} files"));
+        }
+        if let Some(duration) = self.duration {
+            details.push(format!("took {}s", duration.as_secs()));
+        }
+        details.join(", ")
+    }
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
 pub(crate) struct RepositoryArchives {
     pub(crate) path: String,
     pub(crate) archives: Vec<Archive>,
@@ -393,3 +485,285 @@ impl RepositoryArchives {
         Self { path, archives }
     }
 }
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub(crate) struct ArchiveEntry {
+    /// Path of the entry relative to the archive root (no leading slash)
+    pub(crate) path: String,
+    pub(crate) is_dir: bool,
+    pub(crate) size: u64,
+    pub(crate) modified: Option<chrono::NaiveDateTime>,
+}
+
+impl ArchiveEntry {
+    pub(crate) fn name(&self) -> &str {
+        self.path.rsplit('/').next().unwrap_or(&self.path)
+    }
+}
+
+/// Machine readable description of a repository for `--output json`.
+#[derive(Serialize, Debug, Clone)]
+pub(crate) struct RepositorySummary {
+    pub(crate) path: String,
+    pub(crate) kind: String,
+    pub(crate) disabled: bool,
+    /// Where the passphrase comes from (none, raw, keyring, keyfile, command).
+    pub(crate) encryption: String,
+}
+
+/// What a repository backs up, for `borgtui list-repos --output json`.
+#[derive(Serialize, Debug, Clone)]
+pub(crate) struct RepositorySourcesSummary {
+    #[serde(flatten)]
+    pub(crate) repository: RepositorySummary,
+    pub(crate) backup_paths: Vec<PathBuf>,
+    pub(crate) exclude_patterns: Vec<String>,
+    pub(crate) exclude_caches: bool,
+}
+
+/// How fresh a repository's backups are, for `borgtui status`.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub(crate) struct RepositoryStatus {
+    pub(crate) path: String,
+    pub(crate) disabled: bool,
+    pub(crate) newest_archive: Option<String>,
+    pub(crate) newest_archive_age_seconds: Option<i64>,
+    /// When the last check finished and whether it passed, from the run history
+    pub(crate) last_check: Option<String>,
+    pub(crate) last_check_successful: Option<bool>,
+    /// The newest archive is older than the profile's `max_age` (or there are no archives)
+    pub(crate) stale: bool,
+    /// Set when the archives couldn't be listed
+    pub(crate) error: Option<String>,
+}
+
+impl RepositoryStatus {
+    pub(crate) fn is_healthy(&self) -> bool {
+        self.disabled || (!self.stale && self.error.is_none())
+    }
+}
+
+impl std::fmt::Display for RepositoryStatus {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}: ", self.path)?;
+        if self.disabled {
+            return write!(f, "disabled");
+        }
+        if let Some(error) = &self.error {
+            return write!(f, "ERROR {}", error);
+        }
+        match (&self.newest_archive, self.newest_archive_age_seconds) {
+            (Some(archive), Some(age_seconds)) => write!(
+                f,
+                "newest archive {} ({} old)",
+                archive,
+                PrettyDuration(age_seconds)
+            )?,
+            _ => write!(f, "no archives")?,
+        }
+        match (&self.last_check, self.last_check_successful) {
+            (Some(last_check), Some(true)) => write!(f, ", last check passed {}", last_check)?,
+            (Some(last_check), _) => write!(f, ", last check FAILED {}", last_check)?,
+            _ => write!(f, ", never checked")?,
+        }
+        if self.stale {
+            write!(f, " [STALE]")?;
+        }
+        Ok(())
+    }
+}
+
+/// A number of seconds formatted as its two largest units, e.g. "3d 4h".
+pub(crate) struct PrettyDuration(pub(crate) i64);
+
+impl std::fmt::Display for PrettyDuration {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let seconds = self.0.max(0);
+        let (days, hours, minutes) = (seconds / 86400, seconds / 3600 % 24, seconds / 60 % 60);
+        if days > 0 {
+            write!(f, "{}d {}h", days, hours)
+        } else if hours > 0 {
+            write!(f, "{}h {}m", hours, minutes)
+        } else {
+            write!(f, "{}m {}s", minutes, seconds % 60)
+        }
+    }
+}
+
+#[derive(Serialize, Debug, Clone)]
+pub(crate) struct ArchiveSummary {
+    pub(crate) name: String,
+    pub(crate) creation_date: String,
+    pub(crate) id: Option<String>,
+    pub(crate) hostname: Option<String>,
+    pub(crate) username: Option<String>,
+    pub(crate) tags: Vec<String>,
+    pub(crate) original_size: Option<u64>,
+    pub(crate) compressed_size: Option<u64>,
+    pub(crate) deduplicated_size: Option<u64>,
+    pub(crate) num_files: Option<u64>,
+    pub(crate) duration_seconds: Option<f64>,
+}
+
+impl From<&Archive> for ArchiveSummary {
+    fn from(archive: &Archive) -> Self {
+        Self {
+            name: archive.name.clone(),
+            creation_date: archive
+                .creation_date
+                .format("%Y-%m-%dT%H:%M:%S")
+                .to_string(),
+            id: archive.id.clone(),
+            hostname: archive.hostname.clone(),
+            username: archive.username.clone(),
+            tags: archive.tags.clone(),
+            original_size: archive.original_size,
+            compressed_size: archive.compressed_size,
+            deduplicated_size: archive.deduplicated_size,
+            num_files: archive.num_files,
+            duration_seconds: archive.duration.map(|duration| duration.as_secs_f64()),
+        }
+    }
+}
+
+#[derive(Serialize, Debug, Clone)]
+pub(crate) struct RepositoryArchivesSummary {
+    #[serde(flatten)]
+    pub(crate) repository: RepositorySummary,
+    pub(crate) archives: Vec<ArchiveSummary>,
+    /// Why the archives couldn't be listed, in which case there are none
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub(crate) error: Option<String>,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub(crate) struct ArchiveDirectoryListing {
+    pub(crate) archive: String,
+    pub(crate) directory: String,
+    pub(crate) entries: Vec<ArchiveEntry>,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+#[serde(rename_all = "snake_case")]
+pub(crate) enum ArchiveDiffChange {
+    Added,
+    Removed,
+    Modified,
+    MetadataChanged,
+}
+
+impl Display for ArchiveDiffChange {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let change = match self {
+            ArchiveDiffChange::Added => "added",
+            ArchiveDiffChange::Removed => "removed",
+            ArchiveDiffChange::Modified => "modified",
+            ArchiveDiffChange::MetadataChanged => "metadata changed",
+        };
+        write!(f, "{change}")
+    }
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub(crate) struct ArchiveDiffEntry {
+    /// Relative to the archive root (no leading slash).
+    pub(crate) path: String,
+    pub(crate) change: ArchiveDiffChange,
+    /// Bytes added by this change, if the backend reports it.
+    pub(crate) bytes_added: Option<u64>,
+    /// Bytes removed by this change, if the backend reports it.
+    pub(crate) bytes_removed: Option<u64>,
+}
+
+impl ArchiveDiffEntry {
+    /// Human readable size change, e.g. "+1.2 KiB -300 B".
+    pub(crate) fn size_change(&self) -> String {
+        let added = self
+            .bytes_added
+            .filter(|bytes| *bytes > 0)
+            .map(|bytes| format!("+{}", PrettyBytes(bytes)));
+        let removed = self
+            .bytes_removed
+            .filter(|bytes| *bytes > 0)
+            .map(|bytes| format!("-{}", PrettyBytes(bytes)));
+        added
+            .into_iter()
+            .chain(removed)
+            .collect::<Vec<_>>()
+            .join(" ")
+    }
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub(crate) struct ArchiveDiff {
+    pub(crate) first: String,
+    pub(crate) second: String,
+    pub(crate) entries: Vec<ArchiveDiffEntry>,
+}
+
+impl ArchiveDiff {
+    pub(crate) fn count(&self, change: ArchiveDiffChange) -> usize {
+        self.entries
+            .iter()
+            .filter(|entry| entry.change == change)
+            .count()
+    }
+
+    /// One line summary of the number of changes and bytes added / removed.
+    pub(crate) fn summary(&self) -> String {
+        let bytes_added: u64 = self.entries.iter().filter_map(|e| e.bytes_added).sum();
+        let bytes_removed: u64 = self.entries.iter().filter_map(|e| e.bytes_removed).sum();
+        format!(
+            "{} added, {} removed, {} modified, {} metadata changed (+{} -{})",
+            self.count(ArchiveDiffChange::Added),
+            self.count(ArchiveDiffChange::Removed),
+            self.count(ArchiveDiffChange::Modified),
+            self.count(ArchiveDiffChange::MetadataChanged),
+            PrettyBytes(bytes_added),
+            PrettyBytes(bytes_removed)
+        )
+    }
+}
+
+/// Whether a prune would keep an archive, and why.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub(crate) struct PruneDecision {
+    pub(crate) archive: String,
+    pub(crate) keep: bool,
+    /// The retention rule which kept the archive (e.g. "daily #3")
+    pub(crate) reason: Option<String>,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub(crate) struct PrunePreview {
+    pub(crate) repository: String,
+    pub(crate) decisions: Vec<PruneDecision>,
+}
+
+impl PrunePreview {
+    pub(crate) fn num_removed(&self) -> usize {
+        self.decisions
+            .iter()
+            .filter(|decision| !decision.keep)
+            .count()
+    }
+}
+
+/// Returns true if `path` is directly inside `directory`. Both paths are relative
+/// to the archive root and `directory` is empty for the root itself.
+pub(crate) fn is_direct_child(directory: &str, path: &str) -> bool {
+    let path = path.trim_start_matches('/');
+    let directory = directory.trim_matches('/');
+    let remainder = if directory.is_empty() {
+        path
+    } else {
+        match path
+            .strip_prefix(directory)
+            .and_then(|rest| rest.strip_prefix('/'))
+        {
+            Some(rest) => rest,
+            None => return false,
+        }
+    };
+    !remainder.is_empty() && !remainder.contains('/')
+}